@@ -0,0 +1,76 @@
+use FlagsOf::*;
+
+// Helpers shared by the access flags of classes, fields, methods, inner classes, parameters and
+// modules, which are each parsed into a struct of booleans
+
+// The bits of the mask the JVMS doesn't assign to any flag. They're reserved for future use and
+// the JVM ignores them, so the booleans are parsed from the assigned bits alone (hence
+// from_bits_truncate), but each flags struct keeps these in `reserved` so the class is written
+// back byte for byte.
+pub fn reserved_bits(mask: u16, assigned: u16) -> u16 {
+    mask & !assigned
+}
+
+// What a mask of access flags belongs to. Some bits mean different things on each, e.g. 0x0020
+// is ACC_SUPER on classes but ACC_SYNCHRONIZED on methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagsOf {
+    Class,
+    Field,
+    Method,
+    InnerClass,
+    Parameter,
+    Module,
+    Requires,
+    Exports, // Also opens
+}
+
+// The names the JVMS gives each flag, in bit order, and what they're flags of
+const FLAG_NAMES: &[(u16, &str, &[FlagsOf])] = &[
+    (0x0001, "ACC_PUBLIC", &[Class, Field, Method, InnerClass]),
+    (0x0002, "ACC_PRIVATE", &[Field, Method, InnerClass]),
+    (0x0004, "ACC_PROTECTED", &[Field, Method, InnerClass]),
+    (0x0008, "ACC_STATIC", &[Field, Method, InnerClass]),
+    (
+        0x0010,
+        "ACC_FINAL",
+        &[Class, Field, Method, InnerClass, Parameter],
+    ),
+    (0x0020, "ACC_SUPER", &[Class]),
+    (0x0020, "ACC_SYNCHRONIZED", &[Method]),
+    (0x0020, "ACC_OPEN", &[Module]),
+    (0x0020, "ACC_TRANSITIVE", &[Requires]),
+    (0x0040, "ACC_VOLATILE", &[Field]),
+    (0x0040, "ACC_BRIDGE", &[Method]),
+    (0x0040, "ACC_STATIC_PHASE", &[Requires]),
+    (0x0080, "ACC_TRANSIENT", &[Field]),
+    (0x0080, "ACC_VARARGS", &[Method]),
+    (0x0100, "ACC_NATIVE", &[Method]),
+    (0x0200, "ACC_INTERFACE", &[Class, InnerClass]),
+    (0x0400, "ACC_ABSTRACT", &[Class, Method, InnerClass]),
+    (0x0800, "ACC_STRICT", &[Method]),
+    (
+        0x1000,
+        "ACC_SYNTHETIC",
+        &[
+            Class, Field, Method, InnerClass, Parameter, Module, Requires, Exports,
+        ],
+    ),
+    (0x2000, "ACC_ANNOTATION", &[Class, InnerClass]),
+    (0x4000, "ACC_ENUM", &[Class, Field, InnerClass]),
+    (0x8000, "ACC_MODULE", &[Class]),
+    (
+        0x8000,
+        "ACC_MANDATED",
+        &[Parameter, Module, Requires, Exports],
+    ),
+];
+
+// The names of the flags set in the mask, in bit order. Reserved bits have no name.
+pub fn flag_names(mask: u16, flags_of: FlagsOf) -> Vec<&'static str> {
+    FLAG_NAMES
+        .iter()
+        .filter(|(flag, _, of)| mask & flag != 0 && of.contains(&flags_of))
+        .map(|(_, name, _)| *name)
+        .collect()
+}
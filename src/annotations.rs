@@ -0,0 +1,400 @@
+use std::convert::TryFrom;
+use std::io::Write;
+
+use num_enum::TryFromPrimitive;
+
+use crate::constants::*;
+use crate::error::{ErrorKind, Result};
+use crate::modified_utf8::JavaString;
+use crate::read::*;
+use crate::write::*;
+
+// Annotations as stored in the Runtime*Annotations and AnnotationDefault attributes (JVMS 4.7.16)
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub type_name: String, // A field descriptor, e.g. Ljava/lang/Deprecated;
+    pub elements: Vec<ElementValuePair>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementValuePair {
+    pub name: String,
+    pub value: ElementValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElementValue {
+    Byte(i8),
+    Char(u16),
+    Short(i16),
+    Boolean(bool),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(JavaString),
+    Enum {
+        type_name: String, // A field descriptor
+        const_name: String,
+    },
+    Class(String), // A return descriptor, so void.class is V
+    Annotation(Annotation),
+    Array(Vec<ElementValue>),
+}
+
+impl Annotation {
+    pub fn get(&self, name: &str) -> Option<&ElementValue> {
+        self.elements
+            .iter()
+            .find(|element| element.name == name)
+            .map(|element| &element.value)
+    }
+}
+
+pub fn parse_annotations(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<Vec<Annotation>> {
+    let num_annotations = read_u2(reader)?;
+    let mut annotations = Vec::with_capacity(num_annotations as usize);
+    for i in 0..num_annotations {
+        annotations.push(reader.within(format!("annotation #{}", i), |reader| {
+            parse_annotation(reader, constant_pool)
+        })?);
+    }
+    Ok(annotations)
+}
+
+// One list of annotations per formal parameter
+pub fn parse_parameter_annotations(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<Vec<Vec<Annotation>>> {
+    let num_parameters = read_u1(reader)?;
+    let mut parameters = Vec::with_capacity(num_parameters as usize);
+    for i in 0..num_parameters {
+        parameters.push(reader.within(format!("parameter #{}", i), |reader| {
+            parse_annotations(reader, constant_pool)
+        })?);
+    }
+    Ok(parameters)
+}
+
+fn parse_annotation(reader: &mut ClassReader, constant_pool: &ConstantPool) -> Result<Annotation> {
+    let type_name = constant_pool.utf8(read_u2(reader)?)?;
+    let num_element_value_pairs = read_u2(reader)?;
+    let mut elements = Vec::with_capacity(num_element_value_pairs as usize);
+    for _ in 0..num_element_value_pairs {
+        let name = constant_pool.utf8(read_u2(reader)?)?;
+        let value = reader.within(format!("element {}", name), |reader| {
+            parse_element_value(reader, constant_pool)
+        })?;
+        elements.push(ElementValuePair { name, value });
+    }
+    Ok(Annotation {
+        type_name,
+        elements,
+    })
+}
+
+pub fn parse_element_value(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<ElementValue> {
+    let tag = read_u1(reader)?;
+    Ok(match tag {
+        // The narrower types are stored as Integer constants and truncated, like javac does
+        b'B' => ElementValue::Byte(constant_pool.integer(read_u2(reader)?)? as i8),
+        b'C' => ElementValue::Char(constant_pool.integer(read_u2(reader)?)? as u16),
+        b'S' => ElementValue::Short(constant_pool.integer(read_u2(reader)?)? as i16),
+        b'Z' => ElementValue::Boolean(constant_pool.integer(read_u2(reader)?)? != 0),
+        b'I' => ElementValue::Int(constant_pool.integer(read_u2(reader)?)?),
+        b'J' => ElementValue::Long(constant_pool.long(read_u2(reader)?)?),
+        b'F' => ElementValue::Float(constant_pool.float(read_u2(reader)?)?),
+        b'D' => ElementValue::Double(constant_pool.double(read_u2(reader)?)?),
+        // Unlike ldc, string values point straight at a Utf8 constant
+        b's' => ElementValue::String(constant_pool.java_string(read_u2(reader)?)?.clone()),
+        b'e' => ElementValue::Enum {
+            type_name: constant_pool.utf8(read_u2(reader)?)?,
+            const_name: constant_pool.utf8(read_u2(reader)?)?,
+        },
+        b'c' => ElementValue::Class(constant_pool.utf8(read_u2(reader)?)?),
+        b'@' => ElementValue::Annotation(parse_annotation(reader, constant_pool)?),
+        b'[' => {
+            let num_values = read_u2(reader)?;
+            let mut values = Vec::with_capacity(num_values as usize);
+            for _ in 0..num_values {
+                values.push(parse_element_value(reader, constant_pool)?);
+            }
+            ElementValue::Array(values)
+        }
+        _ => return Err(ErrorKind::BadElementValueTag(tag).into()),
+    })
+}
+
+// Which type in the declaration or code a type annotation applies to (JVMS 4.7.20.1)
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeAnnotationTarget {
+    TypeParameter(u8),
+    Supertype(u16), // Index into the interfaces, or 65535 for the superclass
+    TypeParameterBound {
+        type_parameter_index: u8,
+        bound_index: u8,
+    },
+    Empty, // Field type, method return type or receiver type
+    FormalParameter(u8),
+    Throws(u16), // Index into the Exceptions attribute
+    LocalVariable(Vec<LocalVariableTarget>),
+    Catch(u16),  // Index into the exception table
+    Offset(u16), // Offset of an instanceof, new or method reference instruction
+    TypeArgument {
+        offset: u16,
+        type_argument_index: u8,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalVariableTarget {
+    pub start_pc: u16,
+    pub length: u16,
+    pub index: u16,
+}
+
+#[derive(Debug, Eq, PartialEq, TryFromPrimitive, Clone, Copy)]
+#[repr(u8)]
+pub enum TypePathKind {
+    Array = 0,
+    Nested = 1,
+    WildcardBound = 2,
+    TypeArgument = 3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypePathEntry {
+    pub kind: TypePathKind,
+    pub type_argument_index: u8, // Always 0 unless kind is TypeArgument
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeAnnotation {
+    pub target_type: u8, // Kept as well as the target, since several types share a target layout
+    pub target: TypeAnnotationTarget,
+    pub type_path: Vec<TypePathEntry>,
+    pub annotation: Annotation,
+}
+
+pub fn parse_type_annotations(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<Vec<TypeAnnotation>> {
+    let num_annotations = read_u2(reader)?;
+    let mut annotations = Vec::with_capacity(num_annotations as usize);
+    for i in 0..num_annotations {
+        annotations.push(reader.within(format!("type annotation #{}", i), |reader| {
+            parse_type_annotation(reader, constant_pool)
+        })?);
+    }
+    Ok(annotations)
+}
+
+fn parse_type_annotation(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<TypeAnnotation> {
+    let target_type = read_u1(reader)?;
+    let target = match target_type {
+        0x00 | 0x01 => TypeAnnotationTarget::TypeParameter(read_u1(reader)?),
+        0x10 => TypeAnnotationTarget::Supertype(read_u2(reader)?),
+        0x11 | 0x12 => TypeAnnotationTarget::TypeParameterBound {
+            type_parameter_index: read_u1(reader)?,
+            bound_index: read_u1(reader)?,
+        },
+        0x13..=0x15 => TypeAnnotationTarget::Empty,
+        0x16 => TypeAnnotationTarget::FormalParameter(read_u1(reader)?),
+        0x17 => TypeAnnotationTarget::Throws(read_u2(reader)?),
+        0x40 | 0x41 => {
+            let table_length = read_u2(reader)?;
+            let mut table = Vec::with_capacity(table_length as usize);
+            for _ in 0..table_length {
+                table.push(LocalVariableTarget {
+                    start_pc: read_u2(reader)?,
+                    length: read_u2(reader)?,
+                    index: read_u2(reader)?,
+                });
+            }
+            TypeAnnotationTarget::LocalVariable(table)
+        }
+        0x42 => TypeAnnotationTarget::Catch(read_u2(reader)?),
+        0x43..=0x46 => TypeAnnotationTarget::Offset(read_u2(reader)?),
+        0x47..=0x4B => TypeAnnotationTarget::TypeArgument {
+            offset: read_u2(reader)?,
+            type_argument_index: read_u1(reader)?,
+        },
+        _ => return Err(ErrorKind::BadTypeAnnotationTarget(target_type).into()),
+    };
+
+    let path_length = read_u1(reader)?;
+    let mut type_path = Vec::with_capacity(path_length as usize);
+    for _ in 0..path_length {
+        let kind = read_u1(reader)?;
+        type_path.push(TypePathEntry {
+            kind: TypePathKind::try_from(kind).map_err(|_| ErrorKind::BadTypePathKind(kind))?,
+            type_argument_index: read_u1(reader)?,
+        });
+    }
+
+    Ok(TypeAnnotation {
+        target_type,
+        target,
+        type_path,
+        annotation: parse_annotation(reader, constant_pool)?,
+    })
+}
+
+pub fn write_annotations(
+    annotations: &[Annotation],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_count(annotations.len(), writer)?;
+    for annotation in annotations {
+        write_annotation(annotation, writer, constant_pool)?;
+    }
+    Ok(())
+}
+
+pub fn write_parameter_annotations(
+    parameters: &[Vec<Annotation>],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    let num_parameters =
+        u8::try_from(parameters.len()).map_err(|_| ErrorKind::TooLong(parameters.len()))?;
+    write_u1(num_parameters, writer)?;
+    for annotations in parameters {
+        write_annotations(annotations, writer, constant_pool)?;
+    }
+    Ok(())
+}
+
+fn write_annotation(
+    annotation: &Annotation,
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_u2(
+        constant_pool.add_utf8(annotation.type_name.as_str())?,
+        writer,
+    )?;
+    write_count(annotation.elements.len(), writer)?;
+    for element in &annotation.elements {
+        write_u2(constant_pool.add_utf8(element.name.as_str())?, writer)?;
+        write_element_value(&element.value, writer, constant_pool)?;
+    }
+    Ok(())
+}
+
+pub fn write_element_value(
+    value: &ElementValue,
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    let (tag, index) = match value {
+        ElementValue::Byte(value) => (b'B', constant_pool.add_integer(*value as i32)?),
+        ElementValue::Char(value) => (b'C', constant_pool.add_integer(*value as i32)?),
+        ElementValue::Short(value) => (b'S', constant_pool.add_integer(*value as i32)?),
+        ElementValue::Boolean(value) => (b'Z', constant_pool.add_integer(*value as i32)?),
+        ElementValue::Int(value) => (b'I', constant_pool.add_integer(*value)?),
+        ElementValue::Long(value) => (b'J', constant_pool.add_long(*value)?),
+        ElementValue::Float(value) => (b'F', constant_pool.add_float(*value)?),
+        ElementValue::Double(value) => (b'D', constant_pool.add_double(*value)?),
+        ElementValue::String(value) => (b's', constant_pool.add_utf8(value.clone())?),
+        ElementValue::Enum {
+            type_name,
+            const_name,
+        } => {
+            write_u1(b'e', writer)?;
+            write_u2(constant_pool.add_utf8(type_name.as_str())?, writer)?;
+            write_u2(constant_pool.add_utf8(const_name.as_str())?, writer)?;
+            return Ok(());
+        }
+        ElementValue::Class(descriptor) => (b'c', constant_pool.add_utf8(descriptor.as_str())?),
+        ElementValue::Annotation(annotation) => {
+            write_u1(b'@', writer)?;
+            return write_annotation(annotation, writer, constant_pool);
+        }
+        ElementValue::Array(values) => {
+            write_u1(b'[', writer)?;
+            write_count(values.len(), writer)?;
+            for value in values {
+                write_element_value(value, writer, constant_pool)?;
+            }
+            return Ok(());
+        }
+    };
+    write_u1(tag, writer)?;
+    write_u2(index, writer)?;
+    Ok(())
+}
+
+pub fn write_type_annotations(
+    annotations: &[TypeAnnotation],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_count(annotations.len(), writer)?;
+    for annotation in annotations {
+        write_type_annotation(annotation, writer, constant_pool)?;
+    }
+    Ok(())
+}
+
+fn write_type_annotation(
+    annotation: &TypeAnnotation,
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_u1(annotation.target_type, writer)?;
+    match &annotation.target {
+        TypeAnnotationTarget::TypeParameter(index)
+        | TypeAnnotationTarget::FormalParameter(index) => write_u1(*index, writer)?,
+        TypeAnnotationTarget::Supertype(index)
+        | TypeAnnotationTarget::Throws(index)
+        | TypeAnnotationTarget::Catch(index)
+        | TypeAnnotationTarget::Offset(index) => write_u2(*index, writer)?,
+        TypeAnnotationTarget::TypeParameterBound {
+            type_parameter_index,
+            bound_index,
+        } => {
+            write_u1(*type_parameter_index, writer)?;
+            write_u1(*bound_index, writer)?;
+        }
+        TypeAnnotationTarget::Empty => {}
+        TypeAnnotationTarget::LocalVariable(table) => {
+            write_count(table.len(), writer)?;
+            for entry in table {
+                write_u2(entry.start_pc, writer)?;
+                write_u2(entry.length, writer)?;
+                write_u2(entry.index, writer)?;
+            }
+        }
+        TypeAnnotationTarget::TypeArgument {
+            offset,
+            type_argument_index,
+        } => {
+            write_u2(*offset, writer)?;
+            write_u1(*type_argument_index, writer)?;
+        }
+    }
+
+    let path_length = u8::try_from(annotation.type_path.len())
+        .map_err(|_| ErrorKind::TooLong(annotation.type_path.len()))?;
+    write_u1(path_length, writer)?;
+    for entry in &annotation.type_path {
+        write_u1(entry.kind as u8, writer)?;
+        write_u1(entry.type_argument_index, writer)?;
+    }
+
+    write_annotation(&annotation.annotation, writer, constant_pool)
+}
@@ -1,66 +1,354 @@
-use std::io::{Read, Result};
+use std::convert::TryFrom;
+use std::io::Write;
 
-use crate::attributes::Attribute::{Code, ConstantValue, Empty};
+use bitflags::*;
+
+use crate::access_flags::reserved_bits;
+use crate::annotations::*;
+use crate::attributes::Attribute::{
+    AnnotationDefault, BootstrapMethods, Code, ConstantValue, Deprecated, EnclosingMethod,
+    Exceptions, InnerClasses, LineNumberTable, LocalVariableTable, LocalVariableTypeTable,
+    MethodParameters, Module, ModuleMainClass, ModulePackages, NestHost, NestMembers,
+    PermittedSubclasses, Record, RuntimeInvisibleAnnotations, RuntimeInvisibleParameterAnnotations,
+    RuntimeInvisibleTypeAnnotations, RuntimeVisibleAnnotations, RuntimeVisibleParameterAnnotations,
+    RuntimeVisibleTypeAnnotations, Signature, SourceFile, StackMapTable, Synthetic, Unknown,
+};
+use crate::constants::Constant::*;
 use crate::constants::*;
-use crate::execution::DataType;
-use crate::instructions::Instruction;
+use crate::error::{ErrorKind, Result};
+use crate::inner_classes::{parse_inner_classes, write_inner_classes, InnerClass};
 use crate::instructions::*;
+use crate::modules::{
+    self, parse_module, parse_module_packages, write_module, write_module_packages,
+};
 use crate::read::*;
+use crate::stack_map::*;
+use crate::write::*;
 
 #[derive(Debug)]
 pub struct Exception {
-    start_pc: u16,
-    end_pc: u16,
-    handler_pc: u16,
-    catch_type: Option<String>,
+    pub start_pc: u16,
+    pub end_pc: u16,
+    pub handler_pc: u16,
+    pub catch_type: Option<String>,
 }
 
-fn parse_exception(reader: &mut dyn Read, constant_pool: &Vec<Constant>) -> Result<Exception> {
+fn parse_exception(reader: &mut ClassReader, constant_pool: &ConstantPool) -> Result<Exception> {
     let start_pc = read_u2(reader)?;
     let end_pc = read_u2(reader)?;
     let handler_pc = read_u2(reader)?;
-    let catch_index = read_u2(reader)? as usize;
+    let catch_index = read_u2(reader)?;
 
     Ok(Exception {
         start_pc,
         end_pc,
         handler_pc,
         catch_type: if catch_index > 0 {
-            Some(resolve_utf8(catch_index, constant_pool).unwrap())
+            Some(constant_pool.class_name(catch_index)?)
         } else {
             None
         },
     })
 }
 
+fn write_exception(
+    exception: &Exception,
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_u2(exception.start_pc, writer)?;
+    write_u2(exception.end_pc, writer)?;
+    write_u2(exception.handler_pc, writer)?;
+    let catch_index = match &exception.catch_type {
+        Some(catch_type) => constant_pool.add_class(catch_type)?,
+        None => 0, // Catches everything, used for finally
+    };
+    write_u2(catch_index, writer)?;
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct BootstrapMethod {
-    method_reference: Constant,
-    num_arguments: u16,
-    arguments: Vec<Constant>,
+    pub method_reference: MethodHandle,
+    pub arguments: Vec<LoadableConstant>,
+}
+
+fn parse_bootstrap_method(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<BootstrapMethod> {
+    let method_reference = constant_pool.method_handle(read_u2(reader)?)?;
+    let num_arguments = read_u2(reader)?;
+    let mut arguments = Vec::with_capacity(num_arguments as usize);
+    for _ in 0..num_arguments {
+        arguments.push(constant_pool.loadable(read_u2(reader)?)?);
+    }
+
+    Ok(BootstrapMethod {
+        method_reference,
+        arguments,
+    })
+}
+
+fn write_bootstrap_method(
+    method: &BootstrapMethod,
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_u2(
+        constant_pool.add_method_handle(&method.method_reference)?,
+        writer,
+    )?;
+    write_count(method.arguments.len(), writer)?;
+    for argument in &method.arguments {
+        write_u2(constant_pool.add_loadable(argument)?, writer)?;
+    }
+    Ok(())
+}
+
+fn parse_class_names(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<Vec<String>> {
+    let count = read_u2(reader)?;
+    let mut classes = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        classes.push(constant_pool.class_name(read_u2(reader)?)?);
+    }
+    Ok(classes)
+}
+
+fn write_class_names(
+    classes: &[String],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_count(classes.len(), writer)?;
+    for class in classes {
+        write_u2(constant_pool.add_class(class)?, writer)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct LineNumber {
+    pub start_pc: u16,
+    pub line_number: u16,
+}
+
+fn parse_line_numbers(reader: &mut ClassReader) -> Result<Vec<LineNumber>> {
+    let line_number_table_length = read_u2(reader)?;
+    let mut entries = Vec::with_capacity(line_number_table_length as usize);
+    for _ in 0..line_number_table_length {
+        entries.push(LineNumber {
+            start_pc: read_u2(reader)?,
+            line_number: read_u2(reader)?,
+        });
+    }
+    Ok(entries)
+}
+
+fn write_line_numbers(entries: &[LineNumber], writer: &mut dyn Write) -> Result<()> {
+    write_count(entries.len(), writer)?;
+    for entry in entries {
+        write_u2(entry.start_pc, writer)?;
+        write_u2(entry.line_number, writer)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct LocalVariable {
+    pub start_pc: u16,
+    pub length: u16, // The variable is in scope for [start_pc, start_pc + length)
+    pub name: String,
+    pub descriptor: String, // The generic signature for LocalVariableTypeTable entries
+    pub index: u16,
+}
+
+impl LocalVariable {
+    pub fn in_scope(&self, offset: usize) -> bool {
+        let start = self.start_pc as usize;
+        start <= offset && offset < start + self.length as usize
+    }
+}
+
+fn parse_local_variables(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<Vec<LocalVariable>> {
+    let local_variable_table_length = read_u2(reader)?;
+    let mut entries = Vec::with_capacity(local_variable_table_length as usize);
+    for _ in 0..local_variable_table_length {
+        entries.push(LocalVariable {
+            start_pc: read_u2(reader)?,
+            length: read_u2(reader)?,
+            name: constant_pool.utf8(read_u2(reader)?)?,
+            descriptor: constant_pool.utf8(read_u2(reader)?)?,
+            index: read_u2(reader)?,
+        });
+    }
+    Ok(entries)
+}
+
+fn write_local_variables(
+    entries: &[LocalVariable],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_count(entries.len(), writer)?;
+    for entry in entries {
+        write_u2(entry.start_pc, writer)?;
+        write_u2(entry.length, writer)?;
+        write_u2(constant_pool.add_utf8(entry.name.as_str())?, writer)?;
+        write_u2(constant_pool.add_utf8(entry.descriptor.as_str())?, writer)?;
+        write_u2(entry.index, writer)?;
+    }
+    Ok(())
+}
+
+bitflags! {
+    struct ParameterFlagsBits: u16 {
+        const FINAL = 0x0010;
+        const SYNTHETIC = 0x1000;
+        const MANDATED = 0x8000;
+    }
+}
+
+#[derive(Debug)]
+pub struct ParameterAccessFlags {
+    pub is_final: bool,
+    pub is_synthetic: bool, // Not in the source, e.g. captured variables
+    pub is_mandated: bool,  // Implicit in the source, e.g. the outer instance of an inner class
+    pub reserved: u16,      // Unassigned bits, see access_flags::reserved_bits
+}
+
+impl ParameterAccessFlags {
+    pub fn bits(&self) -> u16 {
+        let mut flags = ParameterFlagsBits::empty();
+        flags.set(ParameterFlagsBits::FINAL, self.is_final);
+        flags.set(ParameterFlagsBits::SYNTHETIC, self.is_synthetic);
+        flags.set(ParameterFlagsBits::MANDATED, self.is_mandated);
+        flags.bits() | self.reserved
+    }
+}
+
+#[derive(Debug)]
+pub struct MethodParameter {
+    pub name: Option<String>, // None if the compiler didn't record one
+    pub access_flags: ParameterAccessFlags,
+}
+
+fn parse_method_parameters(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<Vec<MethodParameter>> {
+    let parameters_count = read_u1(reader)?;
+    let mut parameters = Vec::with_capacity(parameters_count as usize);
+    for _ in 0..parameters_count {
+        let name = match read_u2(reader)? {
+            0 => None,
+            index => Some(constant_pool.utf8(index)?),
+        };
+        let mask = read_u2(reader)?;
+        let flags = ParameterFlagsBits::from_bits_truncate(mask);
+        parameters.push(MethodParameter {
+            name,
+            access_flags: ParameterAccessFlags {
+                is_final: flags.contains(ParameterFlagsBits::FINAL),
+                is_synthetic: flags.contains(ParameterFlagsBits::SYNTHETIC),
+                is_mandated: flags.contains(ParameterFlagsBits::MANDATED),
+                reserved: reserved_bits(mask, ParameterFlagsBits::all().bits()),
+            },
+        });
+    }
+    Ok(parameters)
+}
+
+fn write_method_parameters(
+    parameters: &[MethodParameter],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    let count = u8::try_from(parameters.len()).map_err(|_| ErrorKind::TooLong(parameters.len()))?;
+    write_u1(count, writer)?;
+    for parameter in parameters {
+        let name_index = match &parameter.name {
+            Some(name) => constant_pool.add_utf8(name.as_str())?,
+            None => 0,
+        };
+        write_u2(name_index, writer)?;
+        write_u2(parameter.access_flags.bits(), writer)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct RecordComponent {
+    pub name: String,
+    pub descriptor: String,
+    pub attributes: Vec<Attribute>, // e.g. Signature and annotations
+}
+
+fn parse_record_components(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<Vec<RecordComponent>> {
+    let components_count = read_u2(reader)?;
+    let mut components = Vec::with_capacity(components_count as usize);
+    for i in 0..components_count {
+        components.push(reader.within(format!("record component #{}", i), |reader| {
+            Ok(RecordComponent {
+                name: constant_pool.utf8(read_u2(reader)?)?,
+                descriptor: constant_pool.utf8(read_u2(reader)?)?,
+                attributes: parse_attributes(reader, constant_pool)?,
+            })
+        })?);
+    }
+    Ok(components)
+}
+
+fn write_record_components(
+    components: &[RecordComponent],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_count(components.len(), writer)?;
+    for component in components {
+        write_u2(constant_pool.add_utf8(component.name.as_str())?, writer)?;
+        write_u2(
+            constant_pool.add_utf8(component.descriptor.as_str())?,
+            writer,
+        )?;
+        write_attributes(&component.attributes, writer, constant_pool)?;
+    }
+    Ok(())
 }
 
 // TODO add more attributes
 #[derive(Debug)]
 pub enum Attribute {
-    Empty, // Only used to satisfy Rust's match completeness check
+    // Attributes the parser doesn't understand, kept so they can be written back out unchanged
+    Unknown {
+        name: String,
+        info: Vec<u8>,
+    },
     ConstantValue {
         name: String,
-        value: DataType,
+        value: LoadableConstant, // Only ever a number or a string
     },
     Code {
         name: String,
         max_stack: u16,
         max_locals: u16,
         code_length: u32,
-        code: Vec<Instruction>,
+        code: Bytecode,
         exceptions: Vec<Exception>,
         attributes: Vec<Attribute>,
     },
     StackMapTable {
         name: String,
-        // entries: Vec<StackMapFrame>,
-        // TODO too much work, will revisit if I decide to do verification
+        entries: Vec<StackMapFrame>,
     },
     BootstrapMethods {
         name: String,
@@ -68,66 +356,477 @@ pub enum Attribute {
     },
     NestHost {
         name: String,
-        host_class: Constant,
+        host_class: String,
     },
     NestMembers {
         name: String,
-        num_classes: u16,
-        classes: Vec<Constant>,
+        classes: Vec<String>,
+    },
+    SourceFile {
+        name: String,
+        source_file: String,
+    },
+    LineNumberTable {
+        name: String,
+        entries: Vec<LineNumber>,
+    },
+    LocalVariableTable {
+        name: String,
+        entries: Vec<LocalVariable>,
+    },
+    LocalVariableTypeTable {
+        name: String,
+        entries: Vec<LocalVariable>,
+    },
+    // Only parsed when asked for, since the grammar depends on what the attribute is attached to,
+    // and a malformed signature shouldn't stop the class from loading
+    Signature {
+        name: String,
+        signature: String,
+    },
+    Deprecated {
+        name: String,
+    },
+    Synthetic {
+        name: String,
+    },
+    RuntimeVisibleAnnotations {
+        name: String,
+        annotations: Vec<Annotation>,
     },
+    RuntimeInvisibleAnnotations {
+        name: String,
+        annotations: Vec<Annotation>,
+    },
+    RuntimeVisibleParameterAnnotations {
+        name: String,
+        parameters: Vec<Vec<Annotation>>,
+    },
+    RuntimeInvisibleParameterAnnotations {
+        name: String,
+        parameters: Vec<Vec<Annotation>>,
+    },
+    RuntimeVisibleTypeAnnotations {
+        name: String,
+        annotations: Vec<TypeAnnotation>,
+    },
+    RuntimeInvisibleTypeAnnotations {
+        name: String,
+        annotations: Vec<TypeAnnotation>,
+    },
+    AnnotationDefault {
+        name: String,
+        value: ElementValue,
+    },
+    Module {
+        name: String,
+        module: modules::Module,
+    },
+    ModulePackages {
+        name: String,
+        packages: Vec<String>,
+    },
+    ModuleMainClass {
+        name: String,
+        main_class: String,
+    },
+    Record {
+        name: String,
+        components: Vec<RecordComponent>,
+    },
+    PermittedSubclasses {
+        name: String,
+        classes: Vec<String>,
+    },
+    InnerClasses {
+        name: String,
+        classes: Vec<InnerClass>,
+    },
+    // Only present on local and anonymous classes
+    EnclosingMethod {
+        name: String,
+        class_name: String,
+        method: Option<(String, String)>, // (name, descriptor), None outside of a method body
+    },
+    Exceptions {
+        name: String,
+        exceptions: Vec<String>, // Class names of the checked exceptions in the throws clause
+    },
+    MethodParameters {
+        name: String,
+        parameters: Vec<MethodParameter>,
+    },
+}
+
+pub fn find_signature(attributes: &[Attribute]) -> Option<&str> {
+    attributes.iter().find_map(|attribute| match attribute {
+        Signature { signature, .. } => Some(signature.as_str()),
+        _ => None,
+    })
+}
+
+pub fn is_deprecated(attributes: &[Attribute]) -> bool {
+    attributes
+        .iter()
+        .any(|attribute| matches!(attribute, Deprecated { .. }))
+}
+
+// Both visible and invisible annotations, since tools reading class files see them all
+pub fn find_annotations(attributes: &[Attribute]) -> Vec<&Annotation> {
+    attributes
+        .iter()
+        .flat_map(|attribute| match attribute {
+            RuntimeVisibleAnnotations { annotations, .. }
+            | RuntimeInvisibleAnnotations { annotations, .. } => annotations.iter(),
+            _ => [].iter(),
+        })
+        .collect()
 }
 
 pub fn parse_attributes(
-    reader: &mut dyn Read,
-    constant_pool: &Vec<Constant>,
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
 ) -> Result<Vec<Attribute>> {
     let mut attributes = Vec::new();
     let attributes_count = read_u2(reader)?;
 
-    for _ in 0..attributes_count {
-        let name = resolve_utf8(read_u2(reader)? as usize, constant_pool).unwrap();
+    for i in 0..attributes_count {
+        let name = reader.within(format!("attribute #{}", i), |reader| {
+            constant_pool.utf8(read_u2(reader)?)
+        })?;
+        let attribute = reader.within(format!("attribute {}", name), |reader| {
+            parse_attribute(reader, name, constant_pool)
+        })?;
+        attributes.push(attribute);
+    }
 
-        let length = read_u4(reader)?;
+    Ok(attributes)
+}
 
-        let attribute = match name.as_str() {
-            "ConstantValue" => ConstantValue {
-                name,
-                value: constant_pool[read_u2(reader)? as usize]
-                    .get_constant_value(constant_pool)
-                    .unwrap(),
-            },
-            "Code" => {
-                let max_stack = read_u2(reader)?;
-                let max_locals = read_u2(reader)?;
-                let code_length = read_u4(reader)?;
-                let code = parse_code(read_bytes(code_length as u64, reader)?).unwrap_or(vec![]);
-
-                let exceptions_length = read_u2(reader)?;
-                let mut exceptions = Vec::new();
-                for _ in 0..exceptions_length {
-                    exceptions.push(parse_exception(reader, constant_pool)?);
+fn parse_attribute(
+    reader: &mut ClassReader,
+    name: String,
+    constant_pool: &ConstantPool,
+) -> Result<Attribute> {
+    let length = read_u4(reader)?;
+    let start = reader.offset();
+    let truncated_name = name.clone();
+    let attribute = match name.as_str() {
+        "ConstantValue" => {
+            let index = read_u2(reader)?;
+            let value = match constant_pool.get(index)? {
+                IntInfo { .. }
+                | FloatInfo { .. }
+                | LongInfo { .. }
+                | DoubleInfo { .. }
+                | StringInfo { .. } => constant_pool.loadable(index)?,
+                constant => {
+                    return Err(ErrorKind::WrongConstantKind {
+                        index,
+                        expected: "a constant value",
+                        found: constant.tag(),
+                    }
+                    .into())
                 }
+            };
+            ConstantValue { name, value }
+        }
+        "Code" => {
+            let max_stack = read_u2(reader)?;
+            let max_locals = read_u2(reader)?;
+            let code_length = read_u4(reader)?;
+            let code = parse_code(&read_bytes(code_length as u64, reader)?)?;
 
-                Code {
-                    name,
-                    max_stack,
-                    max_locals,
-                    code_length,
-                    code,
-                    exceptions,
-                    attributes: parse_attributes(reader, constant_pool)?,
-                }
+            let exceptions_length = read_u2(reader)?;
+            let mut exceptions = Vec::new();
+            for _ in 0..exceptions_length {
+                exceptions.push(parse_exception(reader, constant_pool)?);
+            }
+
+            Code {
+                name,
+                max_stack,
+                max_locals,
+                code_length,
+                code,
+                exceptions,
+                attributes: parse_attributes(reader, constant_pool)?,
             }
-            _ => {
-                println!("Unkown attribute: {}", name);
-                // Read anyway, so we can continue
-                read_bytes(length as u64, reader);
-                Empty
+        }
+        "StackMapTable" => StackMapTable {
+            name,
+            entries: parse_stack_map_table(reader, constant_pool)?,
+        },
+        "BootstrapMethods" => {
+            let num_bootstrap_methods = read_u2(reader)?;
+            let mut methods = Vec::with_capacity(num_bootstrap_methods as usize);
+            for i in 0..num_bootstrap_methods {
+                methods.push(reader.within(format!("bootstrap method #{}", i), |reader| {
+                    parse_bootstrap_method(reader, constant_pool)
+                })?);
             }
-        };
+            BootstrapMethods { name, methods }
+        }
+        "NestHost" => NestHost {
+            name,
+            host_class: constant_pool.class_name(read_u2(reader)?)?,
+        },
+        "NestMembers" => NestMembers {
+            name,
+            classes: parse_class_names(reader, constant_pool)?,
+        },
+        "SourceFile" => SourceFile {
+            name,
+            source_file: constant_pool.utf8(read_u2(reader)?)?,
+        },
+        "LineNumberTable" => LineNumberTable {
+            name,
+            entries: parse_line_numbers(reader)?,
+        },
+        "LocalVariableTable" => LocalVariableTable {
+            name,
+            entries: parse_local_variables(reader, constant_pool)?,
+        },
+        "LocalVariableTypeTable" => LocalVariableTypeTable {
+            name,
+            entries: parse_local_variables(reader, constant_pool)?,
+        },
+        "Signature" => Signature {
+            name,
+            signature: constant_pool.utf8(read_u2(reader)?)?,
+        },
+        "Deprecated" => Deprecated { name },
+        "Synthetic" => Synthetic { name },
+        "RuntimeVisibleAnnotations" => RuntimeVisibleAnnotations {
+            name,
+            annotations: parse_annotations(reader, constant_pool)?,
+        },
+        "RuntimeInvisibleAnnotations" => RuntimeInvisibleAnnotations {
+            name,
+            annotations: parse_annotations(reader, constant_pool)?,
+        },
+        "RuntimeVisibleParameterAnnotations" => RuntimeVisibleParameterAnnotations {
+            name,
+            parameters: parse_parameter_annotations(reader, constant_pool)?,
+        },
+        "RuntimeInvisibleParameterAnnotations" => RuntimeInvisibleParameterAnnotations {
+            name,
+            parameters: parse_parameter_annotations(reader, constant_pool)?,
+        },
+        "RuntimeVisibleTypeAnnotations" => RuntimeVisibleTypeAnnotations {
+            name,
+            annotations: parse_type_annotations(reader, constant_pool)?,
+        },
+        "RuntimeInvisibleTypeAnnotations" => RuntimeInvisibleTypeAnnotations {
+            name,
+            annotations: parse_type_annotations(reader, constant_pool)?,
+        },
+        "AnnotationDefault" => AnnotationDefault {
+            name,
+            value: parse_element_value(reader, constant_pool)?,
+        },
+        "Module" => Module {
+            name,
+            module: parse_module(reader, constant_pool)?,
+        },
+        "ModulePackages" => ModulePackages {
+            name,
+            packages: parse_module_packages(reader, constant_pool)?,
+        },
+        "ModuleMainClass" => ModuleMainClass {
+            name,
+            main_class: constant_pool.class_name(read_u2(reader)?)?,
+        },
+        "Record" => Record {
+            name,
+            components: parse_record_components(reader, constant_pool)?,
+        },
+        "PermittedSubclasses" => PermittedSubclasses {
+            name,
+            classes: parse_class_names(reader, constant_pool)?,
+        },
+        "InnerClasses" => InnerClasses {
+            name,
+            classes: parse_inner_classes(reader, constant_pool)?,
+        },
+        "EnclosingMethod" => {
+            let class_name = constant_pool.class_name(read_u2(reader)?)?;
+            let method = match read_u2(reader)? {
+                0 => None,
+                index => Some(constant_pool.name_and_type(index)?),
+            };
+            EnclosingMethod {
+                name,
+                class_name,
+                method,
+            }
+        }
+        "Exceptions" => Exceptions {
+            name,
+            exceptions: parse_class_names(reader, constant_pool)?,
+        },
+        "MethodParameters" => MethodParameters {
+            name,
+            parameters: parse_method_parameters(reader, constant_pool)?,
+        },
+        _ => Unknown {
+            name,
+            info: read_bytes(length as u64, reader)?,
+        },
+    };
 
-        attributes.push(attribute);
+    let read = reader.offset() - start;
+    if read != length as u64 {
+        return Err(ErrorKind::TruncatedAttribute {
+            name: truncated_name,
+            length,
+            read,
+        }
+        .into());
     }
+    Ok(attribute)
+}
 
-    Ok(attributes)
+impl Attribute {
+    pub fn name(&self) -> &str {
+        match self {
+            Unknown { name, .. }
+            | ConstantValue { name, .. }
+            | Code { name, .. }
+            | StackMapTable { name, .. }
+            | BootstrapMethods { name, .. }
+            | NestHost { name, .. }
+            | NestMembers { name, .. }
+            | SourceFile { name, .. }
+            | LineNumberTable { name, .. }
+            | LocalVariableTable { name, .. }
+            | LocalVariableTypeTable { name, .. }
+            | Signature { name, .. }
+            | Deprecated { name }
+            | Synthetic { name }
+            | RuntimeVisibleAnnotations { name, .. }
+            | RuntimeInvisibleAnnotations { name, .. }
+            | RuntimeVisibleParameterAnnotations { name, .. }
+            | RuntimeInvisibleParameterAnnotations { name, .. }
+            | RuntimeVisibleTypeAnnotations { name, .. }
+            | RuntimeInvisibleTypeAnnotations { name, .. }
+            | AnnotationDefault { name, .. }
+            | Module { name, .. }
+            | ModulePackages { name, .. }
+            | ModuleMainClass { name, .. }
+            | Record { name, .. }
+            | PermittedSubclasses { name, .. }
+            | InnerClasses { name, .. }
+            | EnclosingMethod { name, .. }
+            | Exceptions { name, .. }
+            | MethodParameters { name, .. } => name,
+        }
+    }
+}
+
+pub fn write_attributes(
+    attributes: &[Attribute],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_count(attributes.len(), writer)?;
+    for attribute in attributes {
+        write_u2(constant_pool.add_utf8(attribute.name())?, writer)?;
+        // The length comes first, so the contents are written out separately
+        let mut info = Vec::new();
+        write_attribute(attribute, &mut info, constant_pool)?;
+        let length = u32::try_from(info.len()).map_err(|_| ErrorKind::TooLong(info.len()))?;
+        write_u4(length, writer)?;
+        write_bytes(&info, writer)?;
+    }
+    Ok(())
+}
+
+fn write_attribute(
+    attribute: &Attribute,
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    match attribute {
+        Unknown { info, .. } => write_bytes(info, writer)?,
+        ConstantValue { value, .. } => write_u2(constant_pool.add_loadable(value)?, writer)?,
+        Code {
+            max_stack,
+            max_locals,
+            code,
+            exceptions,
+            attributes,
+            ..
+        } => {
+            write_u2(*max_stack, writer)?;
+            write_u2(*max_locals, writer)?;
+            let code = write_code(code)?;
+            write_u4(code.len() as u32, writer)?;
+            write_bytes(&code, writer)?;
+            write_count(exceptions.len(), writer)?;
+            for exception in exceptions {
+                write_exception(exception, writer, constant_pool)?;
+            }
+            write_attributes(attributes, writer, constant_pool)?;
+        }
+        StackMapTable { entries, .. } => write_stack_map_table(entries, writer, constant_pool)?,
+        BootstrapMethods { methods, .. } => {
+            write_count(methods.len(), writer)?;
+            for method in methods {
+                write_bootstrap_method(method, writer, constant_pool)?;
+            }
+        }
+        NestHost { host_class, .. } => write_u2(constant_pool.add_class(host_class)?, writer)?,
+        NestMembers { classes, .. }
+        | PermittedSubclasses { classes, .. }
+        | Exceptions {
+            exceptions: classes,
+            ..
+        } => write_class_names(classes, writer, constant_pool)?,
+        SourceFile { source_file, .. } => {
+            write_u2(constant_pool.add_utf8(source_file.as_str())?, writer)?
+        }
+        LineNumberTable { entries, .. } => write_line_numbers(entries, writer)?,
+        LocalVariableTable { entries, .. } | LocalVariableTypeTable { entries, .. } => {
+            write_local_variables(entries, writer, constant_pool)?
+        }
+        Signature { signature, .. } => {
+            write_u2(constant_pool.add_utf8(signature.as_str())?, writer)?
+        }
+        Deprecated { .. } | Synthetic { .. } => {}
+        RuntimeVisibleAnnotations { annotations, .. }
+        | RuntimeInvisibleAnnotations { annotations, .. } => {
+            write_annotations(annotations, writer, constant_pool)?
+        }
+        RuntimeVisibleParameterAnnotations { parameters, .. }
+        | RuntimeInvisibleParameterAnnotations { parameters, .. } => {
+            write_parameter_annotations(parameters, writer, constant_pool)?
+        }
+        RuntimeVisibleTypeAnnotations { annotations, .. }
+        | RuntimeInvisibleTypeAnnotations { annotations, .. } => {
+            write_type_annotations(annotations, writer, constant_pool)?
+        }
+        AnnotationDefault { value, .. } => write_element_value(value, writer, constant_pool)?,
+        Module { module, .. } => write_module(module, writer, constant_pool)?,
+        ModulePackages { packages, .. } => write_module_packages(packages, writer, constant_pool)?,
+        ModuleMainClass { main_class, .. } => {
+            write_u2(constant_pool.add_class(main_class)?, writer)?
+        }
+        Record { components, .. } => write_record_components(components, writer, constant_pool)?,
+        InnerClasses { classes, .. } => write_inner_classes(classes, writer, constant_pool)?,
+        EnclosingMethod {
+            class_name, method, ..
+        } => {
+            write_u2(constant_pool.add_class(class_name)?, writer)?;
+            let method_index = match method {
+                Some((name, descriptor)) => constant_pool.add_name_and_type(name, descriptor)?,
+                None => 0,
+            };
+            write_u2(method_index, writer)?;
+        }
+        MethodParameters { parameters, .. } => {
+            write_method_parameters(parameters, writer, constant_pool)?
+        }
+    }
+    Ok(())
 }
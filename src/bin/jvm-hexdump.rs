@@ -0,0 +1,204 @@
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+use jvm::constants::ConstantTag;
+use jvm::raw::*;
+
+// Prints each structure in a class file with its offset and length, followed by its bytes, e.g.
+//
+//   0x000a      5    constant #1 MethodRef
+//                        00000a  0a 00 02 00 03                                    |.....|
+//
+// Members and attributes that contain other structures only have their own header dumped, with
+// the structures they contain listed below them.
+fn main() {
+    let paths: Vec<_> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("Usage: jvm-hexdump <class file>...");
+        process::exit(2);
+    }
+
+    let stdout = io::stdout();
+    let out = &mut stdout.lock();
+    for path in &paths {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path, e);
+                process::exit(1);
+            }
+        };
+        let class = match parse_raw_class(&bytes) {
+            Ok(class) => class,
+            Err(e) => {
+                eprintln!("Could not load {}: {}", path, e);
+                process::exit(1);
+            }
+        };
+        let result = writeln!(out, "Classfile {}, {} bytes", path, bytes.len())
+            .and_then(|_| Dumper { class: &class, out }.class());
+        if let Err(e) = result {
+            eprintln!("Could not print {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+struct Dumper<'a, 'b> {
+    class: &'a RawClass<'a>,
+    out: &'b mut dyn Write,
+}
+
+impl Dumper<'_, '_> {
+    fn class(&mut self) -> io::Result<()> {
+        let class = self.class;
+        let header = Span {
+            offset: 0,
+            length: 8,
+        };
+        let version = format!(
+            "header, version {}.{}",
+            class.major_version, class.minor_version
+        );
+        self.structure(0, header, &version, header)?;
+
+        let pool = class.constant_pool_span;
+        let count = class.constant_pool.len();
+        let label = format!("constant pool, {} entries", count);
+        self.structure(0, pool, &label, prefix(pool, 2))?;
+        for (i, constant) in class.constant_pool.iter().enumerate() {
+            if constant.tag == ConstantTag::Placeholder {
+                continue;
+            }
+            let label = format!(
+                "constant #{} {}",
+                i + 1,
+                self.constant(i as u16 + 1, constant)
+            );
+            self.structure(1, constant.span, &label, constant.span)?;
+        }
+
+        let info = Span {
+            offset: pool.end(),
+            length: 6,
+        };
+        let label = format!("class {}", self.name(class.name()));
+        self.structure(0, info, &label, info)?;
+
+        let interfaces = class.interfaces_span;
+        let label = format!("interfaces, {} entries", class.interfaces.len());
+        self.structure(0, interfaces, &label, interfaces)?;
+
+        for (kind, span, members) in [
+            ("field", class.fields_span, &class.fields),
+            ("method", class.methods_span, &class.methods),
+        ] {
+            let label = format!("{}s, {} entries", kind, members.len());
+            self.structure(0, span, &label, prefix(span, 2))?;
+            for member in members {
+                let label = format!(
+                    "{} {}:{}",
+                    kind,
+                    self.name(class.member_name(member)),
+                    self.name(class.member_descriptor(member))
+                );
+                self.structure(1, member.span, &label, prefix(member.span, 8))?;
+                self.attributes(2, &member.attributes)?;
+            }
+        }
+
+        let attributes = class.attributes_span;
+        let label = format!("attributes, {} entries", class.attributes.len());
+        self.structure(0, attributes, &label, prefix(attributes, 2))?;
+        self.attributes(1, &class.attributes)
+    }
+
+    fn attributes(&mut self, depth: usize, attributes: &[RawAttribute]) -> io::Result<()> {
+        for attribute in attributes {
+            let name = self.name(self.class.attribute_name(attribute));
+            let label = format!("attribute {}", name);
+            let code = match name.as_ref() {
+                "Code" => self.class.code(attribute).ok(),
+                _ => None,
+            };
+            match code {
+                Some(code) => {
+                    // The name, length, max stack, max locals and code length
+                    self.structure(depth, attribute.span, &label, prefix(attribute.span, 14))?;
+                    self.structure(depth + 1, code.code_span, "code", code.code_span)?;
+                    let exceptions = code.exception_table_span;
+                    self.structure(depth + 1, exceptions, "exception table", exceptions)?;
+                    let end = code
+                        .attributes
+                        .first()
+                        .map_or(attribute.span.end(), |a| a.span.offset);
+                    let count = Span {
+                        offset: end - 2,
+                        length: 2,
+                    };
+                    let label = format!("attributes, {} entries", code.attributes.len());
+                    self.structure(depth + 1, count, &label, count)?;
+                    self.attributes(depth + 2, &code.attributes)?;
+                }
+                None => self.structure(depth, attribute.span, &label, attribute.span)?,
+            }
+        }
+        Ok(())
+    }
+
+    // A line describing the structure, then the bytes in `dump`
+    fn structure(&mut self, depth: usize, span: Span, label: &str, dump: Span) -> io::Result<()> {
+        writeln!(
+            self.out,
+            "{:#06x} {:>6}  {}{}",
+            span.offset,
+            span.length,
+            "  ".repeat(depth),
+            label
+        )?;
+        for start in dump.range().step_by(16) {
+            let line = &self.class.bytes[start..dump.end().min(start + 16)];
+            let hex: Vec<_> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let text: String = line
+                .iter()
+                .map(|&byte| match byte {
+                    0x20..=0x7e => byte as char,
+                    _ => '.',
+                })
+                .collect();
+            writeln!(
+                self.out,
+                "{:>21}{:06x}  {:<48}  |{}|",
+                "",
+                start,
+                hex.join(" "),
+                text
+            )?;
+        }
+        Ok(())
+    }
+
+    fn constant(&self, index: u16, constant: &RawConstant) -> String {
+        match constant.tag {
+            ConstantTag::Utf8 => format!("Utf8 {:?}", self.name(self.class.utf8(index))),
+            ConstantTag::Class => format!("Class {}", self.name(self.class.class_name(index))),
+            ConstantTag::ConstString => "String".to_string(),
+            tag => format!("{:?}", tag),
+        }
+    }
+
+    fn name<'c>(&self, name: jvm::error::Result<Cow<'c, str>>) -> Cow<'c, str> {
+        name.unwrap_or(Cow::Borrowed("<invalid>"))
+    }
+}
+
+// The first `length` bytes of the span, e.g. a table's count
+fn prefix(span: Span, length: usize) -> Span {
+    Span {
+        offset: span.offset,
+        length: length.min(span.length),
+    }
+}
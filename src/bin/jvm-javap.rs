@@ -0,0 +1,41 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+
+use jvm::class::*;
+use jvm::javap::disassemble;
+
+// Prints each class file given the way `javap -c -v -p` would
+fn main() {
+    let paths: Vec<_> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("Usage: jvm-javap <class file>...");
+        process::exit(2);
+    }
+
+    let stdout = io::stdout();
+    let out = &mut stdout.lock();
+    for path in &paths {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path, e);
+                process::exit(1);
+            }
+        };
+        let class = match parse_class(&mut bytes.as_slice()) {
+            Ok(class) => class,
+            Err(e) => {
+                eprintln!("Could not load {}: {}", path, e);
+                process::exit(1);
+            }
+        };
+        println!("Classfile {}", path);
+        println!("  size {} bytes", bytes.len());
+        if let Err(e) = disassemble(&class, out) {
+            eprintln!("Could not print {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
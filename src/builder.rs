@@ -0,0 +1,1001 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::attributes::{Attribute, Exception};
+use crate::class::{self, write_class, Class};
+use crate::constants::Constant::DynamicInfo;
+use crate::constants::*;
+use crate::descriptors::{parse_field_type, parse_method_descriptor, FieldType};
+use crate::error::{ErrorKind, Result};
+use crate::fields::{self, Field};
+use crate::instructions::Instruction::*;
+use crate::instructions::*;
+use crate::methods::{self, Method};
+
+// Builds classes in code, so the interpreter can be tested without a Java compiler. Constants are
+// added to the class's constant pool as instructions use them, reusing any equal entry.
+//
+// Classes default to version 49 (Java 5), the newest version the JVM verifies without a
+// StackMapTable, which the builder doesn't generate.
+pub struct ClassBuilder {
+    class: Class,
+}
+
+impl ClassBuilder {
+    // A public class extending Object
+    pub fn new(name: &str) -> ClassBuilder {
+        ClassBuilder {
+            class: Class {
+                constant_pool: ConstantPool::default(),
+                minor_version: 0,
+                major_version: 49,
+                access_flags: class::AccessFlags {
+                    visibility: class::Visibility::Public,
+                    is_super: true,
+                    ..Default::default()
+                },
+                name: name.to_string(),
+                super_name: Some("java/lang/Object".to_string()),
+                interfaces: vec![],
+                fields: vec![],
+                methods: vec![],
+                attributes: vec![],
+            },
+        }
+    }
+
+    pub fn version(&mut self, major_version: u16, minor_version: u16) -> &mut Self {
+        self.class.major_version = major_version;
+        self.class.minor_version = minor_version;
+        self
+    }
+
+    pub fn access_flags(&mut self, access_flags: class::AccessFlags) -> &mut Self {
+        self.class.access_flags = access_flags;
+        self
+    }
+
+    // None only for java/lang/Object
+    pub fn super_class(&mut self, super_name: Option<&str>) -> &mut Self {
+        self.class.super_name = super_name.map(str::to_string);
+        self
+    }
+
+    pub fn interface(&mut self, name: &str) -> &mut Self {
+        self.class.interfaces.push(name.to_string());
+        self
+    }
+
+    pub fn field(
+        &mut self,
+        access_flags: fields::AccessFlags,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<&mut Self> {
+        let constant_pool = &mut self.class.constant_pool;
+        self.class.fields.push(Field {
+            access_flags,
+            name: name.to_string(),
+            descriptor: descriptor.to_string(),
+            name_index: constant_pool.add_utf8(name)?,
+            descriptor_index: constant_pool.add_utf8(descriptor)?,
+            field_type: parse_field_type(descriptor)?,
+            attributes: vec![],
+        });
+        Ok(self)
+    }
+
+    // For abstract and native methods, which have no code
+    pub fn abstract_method(
+        &mut self,
+        access_flags: methods::AccessFlags,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<&mut Self> {
+        self.class.methods.push(new_method(
+            &mut self.class.constant_pool,
+            access_flags,
+            name,
+            descriptor,
+        )?);
+        Ok(self)
+    }
+
+    // `emit` is given a CodeBuilder to add the method's instructions to. max_stack and max_locals
+    // are worked out from the instructions once it returns.
+    pub fn method(
+        &mut self,
+        access_flags: methods::AccessFlags,
+        name: &str,
+        descriptor: &str,
+        emit: impl FnOnce(&mut CodeBuilder) -> Result<()>,
+    ) -> Result<&mut Self> {
+        let is_static = access_flags.is_static;
+        let mut method = new_method(
+            &mut self.class.constant_pool,
+            access_flags,
+            name,
+            descriptor,
+        )?;
+
+        let mut code = CodeBuilder::new(&mut self.class.constant_pool);
+        emit(&mut code)?;
+
+        // The receiver and arguments are passed in the first local variables
+        let receiver_slots = if is_static { 0 } else { 1 };
+        let parameter_slots = receiver_slots + method.arg_slots();
+        method.attributes.push(code.finish(parameter_slots)?);
+        self.class.methods.push(method);
+        Ok(self)
+    }
+
+    // Class attributes such as SourceFile
+    pub fn attribute(&mut self, attribute: Attribute) -> &mut Self {
+        self.class.attributes.push(attribute);
+        self
+    }
+
+    pub fn build(self) -> Class {
+        self.class
+    }
+
+    pub fn to_bytes(self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        write_class(&self.class, &mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+fn new_method(
+    constant_pool: &mut ConstantPool,
+    access_flags: methods::AccessFlags,
+    name: &str,
+    descriptor: &str,
+) -> Result<Method> {
+    let (arg_types, return_type) = parse_method_descriptor(descriptor)?;
+    Ok(Method {
+        access_flags,
+        name: name.to_string(),
+        descriptor: descriptor.to_string(),
+        name_index: constant_pool.add_utf8(name)?,
+        descriptor_index: constant_pool.add_utf8(descriptor)?,
+        return_type,
+        arg_types,
+        attributes: vec![],
+    })
+}
+
+// A position in the code, which branches can refer to before it's placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+// The branch instructions, for jumping to a label
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jump {
+    IfEq,
+    IfNe,
+    IfLt,
+    IfGe,
+    IfGt,
+    IfLe,
+    IfICmpEq,
+    IfICmpNe,
+    IfICmpLt,
+    IfICmpGe,
+    IfICmpGt,
+    IfICmpLe,
+    IfACmpEq,
+    IfACmpNe,
+    Goto,
+    Jsr,
+    IfNull,
+    IfNonNull,
+    GotoW,
+    JsrW,
+}
+
+impl Jump {
+    // None if the offset doesn't fit, since only goto_w and jsr_w take 4 byte offsets
+    fn instruction(self, offset: i32) -> Option<Instruction> {
+        let narrow = i16::try_from(offset).ok();
+        Some(match self {
+            Jump::IfEq => IfEq(narrow?),
+            Jump::IfNe => IfNe(narrow?),
+            Jump::IfLt => IfLt(narrow?),
+            Jump::IfGe => IfGe(narrow?),
+            Jump::IfGt => IfGt(narrow?),
+            Jump::IfLe => IfLe(narrow?),
+            Jump::IfICmpEq => IfICmpEq(narrow?),
+            Jump::IfICmpNe => IfICmpNe(narrow?),
+            Jump::IfICmpLt => IfICmpLt(narrow?),
+            Jump::IfICmpGe => IfICmpGe(narrow?),
+            Jump::IfICmpGt => IfICmpGt(narrow?),
+            Jump::IfICmpLe => IfICmpLe(narrow?),
+            Jump::IfACmpEq => IfACmpEq(narrow?),
+            Jump::IfACmpNe => IfACmpNe(narrow?),
+            Jump::Goto => Goto(narrow?),
+            Jump::Jsr => Jsr(narrow?),
+            Jump::IfNull => IfNull(narrow?),
+            Jump::IfNonNull => IfNonNull(narrow?),
+            Jump::GotoW => GotoW(offset),
+            Jump::JsrW => JsrW(offset),
+        })
+    }
+}
+
+// Where a branching instruction goes, filled in once every label has been placed
+enum Target {
+    Jump(Jump, Label),
+    TableSwitch {
+        default: Label,
+        low: i32,
+        targets: Vec<Label>,
+    },
+    LookupSwitch {
+        default: Label,
+        pairs: Vec<(i32, Label)>,
+    },
+}
+
+struct Handler {
+    start: Label,
+    end: Label,
+    handler: Label,
+    catch_type: Option<String>,
+}
+
+pub struct CodeBuilder<'a> {
+    constant_pool: &'a mut ConstantPool,
+    instructions: Vec<Instruction>,
+    targets: Vec<(usize, Target)>, // Keyed by the index of the branching instruction
+    labels: Vec<Option<usize>>,    // The index of the instruction each label is placed before
+    handlers: Vec<Handler>,
+    placed_twice: Option<usize>, // Where a label was placed again, which finish reports
+}
+
+impl CodeBuilder<'_> {
+    fn new(constant_pool: &mut ConstantPool) -> CodeBuilder<'_> {
+        CodeBuilder {
+            constant_pool,
+            instructions: vec![],
+            targets: vec![],
+            labels: vec![],
+            handlers: vec![],
+            placed_twice: None,
+        }
+    }
+
+    // Adds an instruction as is, with any constant pool indices and branch offsets already known
+    pub fn emit(&mut self, instruction: Instruction) -> &mut Self {
+        self.instructions.push(instruction);
+        self
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    // Places the label before the next instruction emitted, or at the end of the code if there
+    // isn't one. A label can only be placed once.
+    pub fn place(&mut self, label: Label) -> &mut Self {
+        if self.labels[label.0].is_some() {
+            self.placed_twice.get_or_insert(self.instructions.len());
+        } else {
+            self.labels[label.0] = Some(self.instructions.len());
+        }
+        self
+    }
+
+    pub fn jump(&mut self, jump: Jump, label: Label) -> &mut Self {
+        self.targets
+            .push((self.instructions.len(), Target::Jump(jump, label)));
+        // Replaced once the offset is known, but it has to be the same size for the layout
+        let placeholder = jump.instruction(0).unwrap();
+        self.emit(placeholder)
+    }
+
+    // Jumps to targets[value - low], or default if the value is out of range
+    pub fn table_switch(&mut self, low: i32, default: Label, targets: &[Label]) -> &mut Self {
+        let target = Target::TableSwitch {
+            default,
+            low,
+            targets: targets.to_vec(),
+        };
+        self.targets.push((self.instructions.len(), target));
+        self.emit(TableSwitch {
+            default: 0,
+            low,
+            offsets: vec![0; targets.len()],
+        })
+    }
+
+    pub fn lookup_switch(&mut self, default: Label, pairs: &[(i32, Label)]) -> &mut Self {
+        // The JVM binary searches the pairs
+        let mut pairs = pairs.to_vec();
+        pairs.sort_by_key(|(key, _)| *key);
+        let placeholder = pairs.iter().map(|(key, _)| (*key, 0)).collect();
+        self.targets.push((
+            self.instructions.len(),
+            Target::LookupSwitch { default, pairs },
+        ));
+        self.emit(LookupSwitch {
+            default: 0,
+            pairs: placeholder,
+        })
+    }
+
+    // Handles exceptions of class catch_type thrown in [start, end), or any exception if it's None.
+    // Handlers are tried in the order they're added.
+    pub fn try_catch(
+        &mut self,
+        start: Label,
+        end: Label,
+        handler: Label,
+        catch_type: Option<&str>,
+    ) -> &mut Self {
+        self.handlers.push(Handler {
+            start,
+            end,
+            handler,
+            catch_type: catch_type.map(str::to_string),
+        });
+        self
+    }
+
+    // Pushes an int using the shortest instruction for it
+    pub fn push_int(&mut self, value: i32) -> Result<&mut Self> {
+        let instruction = match value {
+            -1 => IConstM1,
+            0 => IConst0,
+            1 => IConst1,
+            2 => IConst2,
+            3 => IConst3,
+            4 => IConst4,
+            5 => IConst5,
+            _ => {
+                if let Ok(value) = i8::try_from(value) {
+                    BIPush(value)
+                } else if let Ok(value) = i16::try_from(value) {
+                    SIPush(value)
+                } else {
+                    return self.ldc(&LoadableConstant::Integer(value));
+                }
+            }
+        };
+        Ok(self.emit(instruction))
+    }
+
+    // Uses ldc2_w for longs and doubles, and ldc_w if the constant's index doesn't fit in a byte
+    pub fn ldc(&mut self, constant: &LoadableConstant) -> Result<&mut Self> {
+        let index = self.constant_pool.add_loadable(constant)?;
+        let instruction = match constant {
+            LoadableConstant::Long(_) | LoadableConstant::Double(_) => Ldc2W(index),
+            _ if index <= 0xFF => Ldc(index),
+            _ => LdcW(index),
+        };
+        Ok(self.emit(instruction))
+    }
+
+    pub fn get_static(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<&mut Self> {
+        let index = self
+            .constant_pool
+            .add_field_ref(class_name, name, descriptor)?;
+        Ok(self.emit(GetStatic(index)))
+    }
+
+    pub fn put_static(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<&mut Self> {
+        let index = self
+            .constant_pool
+            .add_field_ref(class_name, name, descriptor)?;
+        Ok(self.emit(PutStatic(index)))
+    }
+
+    pub fn get_field(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<&mut Self> {
+        let index = self
+            .constant_pool
+            .add_field_ref(class_name, name, descriptor)?;
+        Ok(self.emit(GetField(index)))
+    }
+
+    pub fn put_field(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<&mut Self> {
+        let index = self
+            .constant_pool
+            .add_field_ref(class_name, name, descriptor)?;
+        Ok(self.emit(PutField(index)))
+    }
+
+    pub fn invoke_virtual(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<&mut Self> {
+        let index = self
+            .constant_pool
+            .add_method_ref(class_name, name, descriptor)?;
+        Ok(self.emit(InvokeVirtual(index)))
+    }
+
+    pub fn invoke_special(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<&mut Self> {
+        let index = self
+            .constant_pool
+            .add_method_ref(class_name, name, descriptor)?;
+        Ok(self.emit(InvokeSpecial(index)))
+    }
+
+    pub fn invoke_static(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<&mut Self> {
+        let index = self
+            .constant_pool
+            .add_method_ref(class_name, name, descriptor)?;
+        Ok(self.emit(InvokeStatic(index)))
+    }
+
+    pub fn invoke_interface(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<&mut Self> {
+        let (arg_types, _) = parse_method_descriptor(descriptor)?;
+        // The receiver and arguments, in slots, which is redundant but still checked by the JVM
+        let count = 1 + arg_types.iter().map(FieldType::slots).sum::<usize>();
+        let count = u8::try_from(count).map_err(|_| ErrorKind::TooLong(count))?;
+        let index = self
+            .constant_pool
+            .add_interface_method_ref(class_name, name, descriptor)?;
+        Ok(self.emit(InvokeInterface { index, count }))
+    }
+
+    pub fn new_object(&mut self, class_name: &str) -> Result<&mut Self> {
+        let index = self.constant_pool.add_class(class_name)?;
+        Ok(self.emit(New(index)))
+    }
+
+    // The class of the components, which may itself be an array class, e.g. [I
+    pub fn anew_array(&mut self, class_name: &str) -> Result<&mut Self> {
+        let index = self.constant_pool.add_class(class_name)?;
+        Ok(self.emit(ANewArray(index)))
+    }
+
+    pub fn check_cast(&mut self, class_name: &str) -> Result<&mut Self> {
+        let index = self.constant_pool.add_class(class_name)?;
+        Ok(self.emit(CheckCast(index)))
+    }
+
+    pub fn instance_of(&mut self, class_name: &str) -> Result<&mut Self> {
+        let index = self.constant_pool.add_class(class_name)?;
+        Ok(self.emit(InstanceOf(index)))
+    }
+
+    // The class of the whole array, e.g. [[I
+    pub fn multi_anew_array(&mut self, class_name: &str, dimensions: u8) -> Result<&mut Self> {
+        let index = self.constant_pool.add_class(class_name)?;
+        Ok(self.emit(MultiANewArray { index, dimensions }))
+    }
+
+    fn finish(self, parameter_slots: usize) -> Result<Attribute> {
+        let CodeBuilder {
+            constant_pool,
+            mut instructions,
+            targets,
+            labels,
+            handlers,
+            placed_twice,
+        } = self;
+
+        // Branch offsets don't change the size of instructions, so the layout can be worked out
+        // before they're filled in
+        let layout = Bytecode::new(instructions.clone())?;
+        let mut offsets: Vec<usize> = layout.iter().map(|(offset, _)| offset).collect();
+        offsets.push(layout.len());
+        if let Some(i) = placed_twice {
+            return Err(ErrorKind::InvalidInstruction(offsets[i]).into());
+        }
+        let label_offset = |label: Label| labels[label.0].map(|i| offsets[i]);
+
+        for (i, target) in targets {
+            let offset = offsets[i];
+            let invalid = || ErrorKind::InvalidInstruction(offset);
+            let relative = |label: Label| -> Result<i32> {
+                let target = label_offset(label).ok_or_else(invalid)?;
+                Ok(i32::try_from(target as i64 - offset as i64).map_err(|_| invalid())?)
+            };
+            instructions[i] = match target {
+                Target::Jump(jump, label) => {
+                    jump.instruction(relative(label)?).ok_or_else(invalid)?
+                }
+                Target::TableSwitch {
+                    default,
+                    low,
+                    targets,
+                } => TableSwitch {
+                    default: relative(default)?,
+                    low,
+                    offsets: targets.into_iter().map(relative).collect::<Result<_>>()?,
+                },
+                Target::LookupSwitch { default, pairs } => LookupSwitch {
+                    default: relative(default)?,
+                    pairs: pairs
+                        .into_iter()
+                        .map(|(key, label)| Ok((key, relative(label)?)))
+                        .collect::<Result<_>>()?,
+                },
+            };
+        }
+
+        let code = Bytecode::new(instructions)?;
+        if code.len() > u16::MAX as usize {
+            return Err(ErrorKind::TooLong(code.len()).into());
+        }
+
+        let mut exceptions = Vec::with_capacity(handlers.len());
+        for handler in handlers {
+            let pc = |label: Label| {
+                label_offset(label)
+                    .map(|offset| offset as u16)
+                    .ok_or(ErrorKind::InvalidInstruction(code.len()))
+            };
+            exceptions.push(Exception {
+                start_pc: pc(handler.start)?,
+                end_pc: pc(handler.end)?,
+                handler_pc: pc(handler.handler)?,
+                catch_type: handler.catch_type,
+            });
+        }
+
+        let max_stack = max_stack(&code, &exceptions, constant_pool)?;
+        let max_locals = code
+            .iter()
+            .map(|(_, instruction)| local_slots(instruction))
+            .fold(parameter_slots, usize::max);
+
+        Ok(Attribute::Code {
+            name: "Code".to_string(),
+            max_stack: u16::try_from(max_stack).map_err(|_| ErrorKind::TooLong(max_stack))?,
+            max_locals: u16::try_from(max_locals).map_err(|_| ErrorKind::TooLong(max_locals))?,
+            code_length: code.len() as u32,
+            code,
+            exceptions,
+            attributes: vec![],
+        })
+    }
+}
+
+// One past the highest local variable slot the instruction uses
+fn local_slots(instruction: &Instruction) -> usize {
+    match instruction {
+        ILoad(index)
+        | FLoad(index)
+        | ALoad(index)
+        | IStore(index)
+        | FStore(index)
+        | AStore(index)
+        | Ret(index)
+        | IInc { index, .. } => *index as usize + 1,
+        LLoad(index) | DLoad(index) | LStore(index) | DStore(index) => *index as usize + 2,
+        ILoad0 | FLoad0 | ALoad0 | IStore0 | FStore0 | AStore0 => 1,
+        ILoad1 | FLoad1 | ALoad1 | IStore1 | FStore1 | AStore1 | LLoad0 | DLoad0 | LStore0
+        | DStore0 => 2,
+        ILoad2 | FLoad2 | ALoad2 | IStore2 | FStore2 | AStore2 | LLoad1 | DLoad1 | LStore1
+        | DStore1 => 3,
+        ILoad3 | FLoad3 | ALoad3 | IStore3 | FStore3 | AStore3 | LLoad2 | DLoad2 | LStore2
+        | DStore2 => 4,
+        LLoad3 | DLoad3 | LStore3 | DStore3 => 5,
+        _ => 0,
+    }
+}
+
+// The greatest depth the operand stack reaches, in slots, following every path through the code.
+// Like the JVM's verifier, this requires the depth to be the same on every path to an instruction.
+fn max_stack(
+    code: &Bytecode,
+    exceptions: &[Exception],
+    constant_pool: &ConstantPool,
+) -> Result<usize> {
+    // Handlers start with just the exception on the stack
+    let mut pending: Vec<(usize, usize)> = vec![(0, 0)];
+    pending.extend(
+        exceptions
+            .iter()
+            .map(|exception| (exception.handler_pc as usize, 1)),
+    );
+
+    let mut depths = HashMap::new();
+    let mut max = 0;
+    while let Some((offset, depth)) = pending.pop() {
+        match depths.insert(offset, depth) {
+            Some(previous) if previous == depth => continue,
+            Some(_) => return Err(ErrorKind::BadStackDepth(offset).into()),
+            None => {}
+        }
+
+        // Also catches falling off the end of the code
+        let instruction = code
+            .get(offset)
+            .ok_or(ErrorKind::InvalidInstruction(offset))?;
+        let (pops, pushes) = stack_effect(instruction, constant_pool)?;
+        let after = depth
+            .checked_sub(pops)
+            .ok_or(ErrorKind::BadStackDepth(offset))?
+            + pushes;
+        // A handler's exception counts even if its first instruction pops it
+        max = max.max(depth).max(after);
+
+        let branch = |relative: i32| -> Result<usize> {
+            usize::try_from(offset as i64 + relative as i64)
+                .map_err(|_| ErrorKind::InvalidInstruction(offset).into())
+        };
+        let next = code.next_offset(offset);
+        match instruction {
+            IfEq(relative) | IfNe(relative) | IfLt(relative) | IfGe(relative) | IfGt(relative)
+            | IfLe(relative) | IfICmpEq(relative) | IfICmpNe(relative) | IfICmpLt(relative)
+            | IfICmpGe(relative) | IfICmpGt(relative) | IfICmpLe(relative) | IfACmpEq(relative)
+            | IfACmpNe(relative) | IfNull(relative) | IfNonNull(relative) => {
+                pending.push((branch(*relative as i32)?, after));
+                pending.push((next, after));
+            }
+            Goto(relative) => pending.push((branch(*relative as i32)?, after)),
+            GotoW(relative) => pending.push((branch(*relative)?, after)),
+            // The subroutine returns to the next instruction with the stack as it was before
+            Jsr(relative) => {
+                pending.push((branch(*relative as i32)?, after));
+                pending.push((next, depth));
+            }
+            JsrW(relative) => {
+                pending.push((branch(*relative)?, after));
+                pending.push((next, depth));
+            }
+            TableSwitch {
+                default, offsets, ..
+            } => {
+                for relative in offsets.iter().chain(Some(default)) {
+                    pending.push((branch(*relative)?, after));
+                }
+            }
+            LookupSwitch { default, pairs } => {
+                for relative in pairs
+                    .iter()
+                    .map(|(_, relative)| relative)
+                    .chain(Some(default))
+                {
+                    pending.push((branch(*relative)?, after));
+                }
+            }
+            IReturn | LReturn | FReturn | DReturn | AReturn | Return | AThrow | Ret(_) => {}
+            _ => pending.push((next, after)),
+        }
+    }
+    Ok(max)
+}
+
+// Slots taken up by a value with this field descriptor
+fn descriptor_slots(descriptor: &str) -> Result<usize> {
+    Ok(parse_field_type(descriptor)?.slots())
+}
+
+// Slots taken by the arguments and return value of a method with this descriptor
+fn method_slots(descriptor: &str) -> Result<(usize, usize)> {
+    let (arg_types, return_type) = parse_method_descriptor(descriptor)?;
+    let args = arg_types.iter().map(FieldType::slots).sum();
+    Ok((args, return_type.slots()))
+}
+
+// (slots popped, slots pushed)
+fn stack_effect(instruction: &Instruction, constant_pool: &ConstantPool) -> Result<(usize, usize)> {
+    Ok(match instruction {
+        Nop | IInc { .. } | Goto(_) | GotoW(_) | Ret(_) | Return => (0, 0),
+        AConstNull | IConstM1 | IConst0 | IConst1 | IConst2 | IConst3 | IConst4 | IConst5
+        | FConst0 | FConst1 | FConst2 | BIPush(_) | SIPush(_) | Ldc(_) | LdcW(_) | ILoad(_)
+        | FLoad(_) | ALoad(_) | ILoad0 | ILoad1 | ILoad2 | ILoad3 | FLoad0 | FLoad1 | FLoad2
+        | FLoad3 | ALoad0 | ALoad1 | ALoad2 | ALoad3 | New(_) | Jsr(_) | JsrW(_) => (0, 1),
+        LConst0 | LConst1 | DConst0 | DConst1 | Ldc2W(_) | LLoad(_) | DLoad(_) | LLoad0
+        | LLoad1 | LLoad2 | LLoad3 | DLoad0 | DLoad1 | DLoad2 | DLoad3 => (0, 2),
+        IALoad | FALoad | AALoad | BALoad | CALoad | SALoad => (2, 1),
+        LALoad | DALoad => (2, 2),
+        IStore(_)
+        | FStore(_)
+        | AStore(_)
+        | IStore0
+        | IStore1
+        | IStore2
+        | IStore3
+        | FStore0
+        | FStore1
+        | FStore2
+        | FStore3
+        | AStore0
+        | AStore1
+        | AStore2
+        | AStore3
+        | Pop
+        | IfEq(_)
+        | IfNe(_)
+        | IfLt(_)
+        | IfGe(_)
+        | IfGt(_)
+        | IfLe(_)
+        | IfNull(_)
+        | IfNonNull(_)
+        | TableSwitch { .. }
+        | LookupSwitch { .. }
+        | IReturn
+        | FReturn
+        | AReturn
+        | AThrow
+        | MonitorEnter
+        | MonitorExit => (1, 0),
+        LStore(_) | DStore(_) | LStore0 | LStore1 | LStore2 | LStore3 | DStore0 | DStore1
+        | DStore2 | DStore3 | Pop2 | IfICmpEq(_) | IfICmpNe(_) | IfICmpLt(_) | IfICmpGe(_)
+        | IfICmpGt(_) | IfICmpLe(_) | IfACmpEq(_) | IfACmpNe(_) | LReturn | DReturn => (2, 0),
+        IAStore | FAStore | AAStore | BAStore | CAStore | SAStore => (3, 0),
+        LAStore | DAStore => (4, 0),
+        Dup => (1, 2),
+        DupX1 => (2, 3),
+        DupX2 => (3, 4),
+        Dup2 => (2, 4),
+        Dup2X1 => (3, 5),
+        Dup2X2 => (4, 6),
+        Swap => (2, 2),
+        IAdd | FAdd | ISub | FSub | IMul | FMul | IDiv | FDiv | IRem | FRem | IShl | IShr
+        | IUShr | IAnd | IOr | IXor | FCmpL | FCmpG => (2, 1),
+        LAdd | DAdd | LSub | DSub | LMul | DMul | LDiv | DDiv | LRem | DRem | LAnd | LOr | LXor => {
+            (4, 2)
+        }
+        INeg | FNeg | I2F | F2I | I2B | I2C | I2S | NewArray(_) | ANewArray(_) | ArrayLength
+        | CheckCast(_) | InstanceOf(_) => (1, 1),
+        LNeg | DNeg | L2D | D2L => (2, 2),
+        LShl | LShr | LUShr => (3, 2),
+        I2L | I2D | F2L | F2D => (1, 2),
+        L2I | L2F | D2I | D2F => (2, 1),
+        LCmp | DCmpL | DCmpG => (4, 1),
+        GetStatic(index) => (
+            0,
+            descriptor_slots(&constant_pool.field_ref(*index)?.descriptor)?,
+        ),
+        PutStatic(index) => (
+            descriptor_slots(&constant_pool.field_ref(*index)?.descriptor)?,
+            0,
+        ),
+        GetField(index) => (
+            1,
+            descriptor_slots(&constant_pool.field_ref(*index)?.descriptor)?,
+        ),
+        PutField(index) => (
+            1 + descriptor_slots(&constant_pool.field_ref(*index)?.descriptor)?,
+            0,
+        ),
+        InvokeVirtual(index) => {
+            let (args, result) = method_slots(&constant_pool.method_ref(*index)?.descriptor)?;
+            (1 + args, result)
+        }
+        InvokeSpecial(index) => {
+            let (args, result) = method_slots(&constant_pool.any_method_ref(*index)?.descriptor)?;
+            (1 + args, result)
+        }
+        InvokeStatic(index) => method_slots(&constant_pool.any_method_ref(*index)?.descriptor)?,
+        InvokeInterface { index, .. } => {
+            let reference = constant_pool.interface_method_ref(*index)?;
+            let (args, result) = method_slots(&reference.descriptor)?;
+            (1 + args, result)
+        }
+        InvokeDynamic(index) => match constant_pool.get(*index)? {
+            DynamicInfo {
+                tag: ConstantTag::InvokeDynamic,
+                name_and_type_index,
+                ..
+            } => method_slots(&constant_pool.name_and_type(*name_and_type_index)?.1)?,
+            constant => {
+                return Err(ErrorKind::WrongConstantKind {
+                    index: *index,
+                    expected: "InvokeDynamic",
+                    found: constant.tag(),
+                }
+                .into())
+            }
+        },
+        MultiANewArray { dimensions, .. } => (*dimensions as usize, 1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn static_method() -> methods::AccessFlags {
+        methods::AccessFlags {
+            is_static: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn placing_a_label_twice_is_an_error() {
+        let mut builder = ClassBuilder::new("Labels");
+        let result = builder.method(static_method(), "run", "()V", |code| {
+            let label = code.new_label();
+            code.place(label).emit(Nop).place(label).emit(Return);
+            Ok(())
+        });
+        assert!(matches!(
+            result.map(|_| ()).unwrap_err().kind,
+            ErrorKind::InvalidInstruction(1)
+        ));
+    }
+
+    #[test]
+    fn invokedynamic_needs_an_invokedynamic_constant() {
+        let mut builder = ClassBuilder::new("Indy");
+        let result = builder.method(static_method(), "run", "()V", |code| {
+            // A Dynamic constant has the same layout, but is loaded with ldc instead
+            let index = code.constant_pool.add_dynamic(0, "value", "I")?;
+            code.emit(InvokeDynamic(index)).emit(Return);
+            Ok(())
+        });
+        assert!(matches!(
+            result.map(|_| ()).unwrap_err().kind,
+            ErrorKind::WrongConstantKind {
+                expected: "InvokeDynamic",
+                found: ConstantTag::Dynamic,
+                ..
+            }
+        ));
+    }
+
+    // max_stack and max_locals of a method built with `emit`
+    fn code_sizes(
+        access_flags: methods::AccessFlags,
+        descriptor: &str,
+        emit: impl FnOnce(&mut CodeBuilder) -> Result<()>,
+    ) -> (u16, u16) {
+        let mut builder = ClassBuilder::new("Sizes");
+        builder
+            .method(access_flags, "run", descriptor, emit)
+            .unwrap();
+        match &builder.build().methods[0].attributes[0] {
+            Attribute::Code {
+                max_stack,
+                max_locals,
+                ..
+            } => (*max_stack, *max_locals),
+            attribute => panic!("Expected Code, found {:?}", attribute),
+        }
+    }
+
+    #[test]
+    fn max_stack_is_the_deepest_of_any_branch() {
+        // if (x == 0) { long + long } else { int }, where longs take two entries
+        let sizes = code_sizes(static_method(), "(I)V", |code| {
+            let other = code.new_label();
+            let end = code.new_label();
+            code.emit(ILoad0)
+                .jump(Jump::IfEq, other)
+                .emit(IConst1)
+                .emit(Pop)
+                .jump(Jump::Goto, end)
+                .place(other)
+                .emit(LConst1)
+                .emit(LConst1)
+                .emit(LAdd)
+                .emit(Pop2)
+                .place(end)
+                .emit(Return);
+            Ok(())
+        });
+        assert_eq!(sizes, (4, 1));
+    }
+
+    #[test]
+    fn max_stack_carries_the_depth_across_jumps() {
+        // The jump leaves an int on the stack, which is still there after the label
+        let sizes = code_sizes(static_method(), "(I)I", |code| {
+            let label = code.new_label();
+            code.emit(IConst1)
+                .emit(ILoad0)
+                .jump(Jump::IfEq, label)
+                .emit(IConst2)
+                .emit(IAdd)
+                .place(label)
+                .emit(IConst3)
+                .emit(IConst4)
+                .emit(IAdd)
+                .emit(IAdd)
+                .emit(IReturn);
+            Ok(())
+        });
+        assert_eq!(sizes, (3, 1));
+    }
+
+    #[test]
+    fn different_depths_at_a_label_are_an_error() {
+        let mut builder = ClassBuilder::new("Sizes");
+        let result = builder.method(static_method(), "run", "(I)V", |code| {
+            let label = code.new_label();
+            code.emit(IConst1)
+                .emit(ILoad0)
+                .jump(Jump::IfEq, label)
+                .emit(Pop)
+                .place(label)
+                .emit(Return);
+            Ok(())
+        });
+        assert!(matches!(
+            result.map(|_| ()).unwrap_err().kind,
+            ErrorKind::BadStackDepth(_)
+        ));
+    }
+
+    #[test]
+    fn handlers_start_with_the_exception_on_the_stack() {
+        let sizes = code_sizes(static_method(), "()V", |code| {
+            let start = code.new_label();
+            let end = code.new_label();
+            let handler = code.new_label();
+            code.try_catch(start, end, handler, None)
+                .place(start)
+                .emit(Nop)
+                .place(end)
+                .emit(Return)
+                .place(handler)
+                .emit(AStore0)
+                .emit(Return);
+            Ok(())
+        });
+        assert_eq!(sizes, (1, 1));
+    }
+
+    #[test]
+    fn max_locals_counts_parameters_and_every_local_on_any_branch() {
+        // The receiver, a long and a double take 5 slots
+        let parameters_only = code_sizes(methods::AccessFlags::default(), "(JD)V", |code| {
+            code.emit(Return);
+            Ok(())
+        });
+        assert_eq!(parameters_only, (0, 5));
+
+        // A long stored after a branch takes 2 slots, and a wide store reaches past 255
+        let sizes = code_sizes(static_method(), "(I)V", |code| {
+            let label = code.new_label();
+            code.emit(ILoad0)
+                .jump(Jump::IfEq, label)
+                .emit(LConst0)
+                .emit(LStore(5))
+                .place(label)
+                .emit(IConst0)
+                .emit(IStore(300))
+                .emit(Return);
+            Ok(())
+        });
+        assert_eq!(sizes, (2, 301));
+    }
+}
@@ -1,25 +1,172 @@
-use std::io::{Read, Result};
+use std::io::{Read, Write};
 
-use crate::attributes::Attribute::Code;
-use crate::attributes::{parse_attributes, Attribute};
-use crate::constants::Constant::{FieldInfo, NameAndTypeInfo};
+use bitflags::*;
+
+use crate::access_flags::reserved_bits;
+use crate::annotations::Annotation;
+use crate::attributes::Attribute::SourceFile;
+use crate::attributes::{
+    find_annotations, find_signature, is_deprecated, parse_attributes, write_attributes, Attribute,
+    RecordComponent,
+};
 use crate::constants::*;
+use crate::error::{ErrorKind, Result};
 use crate::execution::DataType;
 use crate::fields::*;
-use crate::instructions::Instruction;
+use crate::inner_classes::InnerClass;
+use crate::instructions::Bytecode;
 use crate::methods::*;
+use crate::modules::ModuleDescriptor;
 use crate::read::*;
+use crate::signatures::*;
+use crate::write::*;
+
+#[derive(Debug, Default)]
+pub enum Visibility {
+    #[default]
+    Package,
+    Public,
+}
+
+bitflags! {
+    struct AccessFlagsBits: u16 {
+        const PUBLIC = 0x0001;
+        const FINAL = 0x0010;
+        const SUPER = 0x0020;
+        const INTERFACE = 0x0200;
+        const ABSTRACT = 0x0400;
+        const SYNTHETIC = 0x1000;
+        const ANNOTATION = 0x2000;
+        const ENUM = 0x4000;
+        const MODULE = 0x8000;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AccessFlags {
+    pub visibility: Visibility,
+    pub is_final: bool,
+    pub is_super: bool, // Always treated as set since Java 8, only matters for invokespecial
+    pub is_interface: bool,
+    pub is_abstract: bool,
+    pub is_synthetic: bool,
+    pub is_annotation: bool,
+    pub is_enum: bool,
+    pub is_module: bool,
+    pub reserved: u16, // Unassigned bits, see access_flags::reserved_bits
+}
+
+impl AccessFlags {
+    pub fn bits(&self) -> u16 {
+        let mut flags = AccessFlagsBits::empty();
+        flags.set(
+            AccessFlagsBits::PUBLIC,
+            matches!(self.visibility, Visibility::Public),
+        );
+        flags.set(AccessFlagsBits::FINAL, self.is_final);
+        flags.set(AccessFlagsBits::SUPER, self.is_super);
+        flags.set(AccessFlagsBits::INTERFACE, self.is_interface);
+        flags.set(AccessFlagsBits::ABSTRACT, self.is_abstract);
+        flags.set(AccessFlagsBits::SYNTHETIC, self.is_synthetic);
+        flags.set(AccessFlagsBits::ANNOTATION, self.is_annotation);
+        flags.set(AccessFlagsBits::ENUM, self.is_enum);
+        flags.set(AccessFlagsBits::MODULE, self.is_module);
+        flags.bits() | self.reserved
+    }
+}
+
+fn parse_access_flags(mask: u16, major_version: u16) -> Result<AccessFlags> {
+    let flags = AccessFlagsBits::from_bits_truncate(mask);
+
+    // See JVMS 4.1 for which combinations are allowed. Before Java 6 interfaces didn't have to be
+    // marked abstract, as they're abstract regardless; is_abstract is left as it was in the file,
+    // so such classes are written back unchanged.
+    let valid = if flags.contains(AccessFlagsBits::MODULE) {
+        flags == AccessFlagsBits::MODULE
+    } else if flags.contains(AccessFlagsBits::INTERFACE) {
+        (flags.contains(AccessFlagsBits::ABSTRACT) || major_version < 50)
+            && !flags
+                .intersects(AccessFlagsBits::FINAL | AccessFlagsBits::SUPER | AccessFlagsBits::ENUM)
+    } else {
+        !flags.contains(AccessFlagsBits::ANNOTATION)
+            && !flags.contains(AccessFlagsBits::FINAL | AccessFlagsBits::ABSTRACT)
+    };
+    if !valid {
+        return Err(ErrorKind::InvalidAccessFlags(mask).into());
+    }
+
+    let visibility = if flags.contains(AccessFlagsBits::PUBLIC) {
+        Visibility::Public
+    } else {
+        Visibility::Package
+    };
+
+    Ok(AccessFlags {
+        visibility,
+        is_final: flags.contains(AccessFlagsBits::FINAL),
+        is_super: flags.contains(AccessFlagsBits::SUPER),
+        is_interface: flags.contains(AccessFlagsBits::INTERFACE),
+        is_abstract: flags.contains(AccessFlagsBits::ABSTRACT),
+        is_synthetic: flags.contains(AccessFlagsBits::SYNTHETIC),
+        is_annotation: flags.contains(AccessFlagsBits::ANNOTATION),
+        is_enum: flags.contains(AccessFlagsBits::ENUM),
+        is_module: flags.contains(AccessFlagsBits::MODULE),
+        reserved: reserved_bits(mask, AccessFlagsBits::all().bits()),
+    })
+}
+
+// Which class file versions parse_class will accept
+#[derive(Debug, Clone)]
+pub struct VersionPolicy {
+    pub oldest_major: u16,
+    pub newest_major: u16,
+    // Classes compiled with --enable-preview have a minor version of 65535, and depend on features
+    // specific to exactly their major version, so only those for newest_major are allowed
+    pub allow_preview: bool,
+}
+
+impl Default for VersionPolicy {
+    // Everything up to Java 17, which is the newest format the interpreter understands
+    fn default() -> Self {
+        VersionPolicy {
+            oldest_major: 45,
+            newest_major: 61,
+            allow_preview: false,
+        }
+    }
+}
+
+impl VersionPolicy {
+    fn check(&self, major: u16, minor: u16) -> Result<()> {
+        let is_preview = major >= 56 && minor == 0xFFFF;
+        if major < self.oldest_major
+            || major > self.newest_major
+            || (is_preview && !(self.allow_preview && major == self.newest_major))
+        {
+            return Err(ErrorKind::UnsupportedVersion {
+                major,
+                minor,
+                oldest: self.oldest_major,
+                newest: self.newest_major,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug)]
 pub struct Class {
-    constant_pool: Vec<Constant>,
+    pub constant_pool: ConstantPool,
+    pub minor_version: u16,
+    pub major_version: u16,
+    pub access_flags: AccessFlags,
     pub name: String,
-    super_name: String,
-    // access_flags: TODO implement this
-    interfaces: Vec<String>,
-    fields: Vec<Field>,
-    methods: Vec<Method>,
-    attributes: Vec<Attribute>,
+    pub super_name: Option<String>, // None for java/lang/Object and module-info
+    pub interfaces: Vec<String>,
+    pub fields: Vec<Field>,
+    pub methods: Vec<Method>,
+    pub attributes: Vec<Attribute>,
 }
 
 // TODO see if there's any other impl opportunities
@@ -27,142 +174,204 @@ impl Class {
     // TODO consider if I want to make it an option
     // imo no, because failures aren't recoverable and should never ocur
     // But other places want it as an option
-    pub fn get_constant(&self, i: usize) -> Option<&Constant> {
-        self.constant_pool.get(i - 1)
+    pub fn get_constant(&self, i: u16) -> Option<&Constant> {
+        self.constant_pool.get(i).ok()
     }
 
-    pub fn get_code(&self, method_name: &str) -> Option<&Vec<Instruction>> {
-        self.methods
+    // None if the class doesn't declare the method, or it's abstract or native
+    pub fn get_code(&self, name: &str, descriptor: &str) -> Option<&Bytecode> {
+        self.get_method(name, descriptor)
+            .and_then(|method| method.get_code())
+    }
+
+    // None if the class isn't generic
+    pub fn signature(&self) -> Option<Result<ClassSignature>> {
+        find_signature(&self.attributes).map(parse_class_signature)
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        is_deprecated(&self.attributes)
+    }
+
+    pub fn annotations(&self) -> Vec<&Annotation> {
+        find_annotations(&self.attributes)
+    }
+
+    // None unless this is a module-info class
+    pub fn module_descriptor(&self) -> Option<ModuleDescriptor> {
+        let mut module = None;
+        let mut packages: &[String] = &[];
+        let mut main_class = None;
+        for attribute in &self.attributes {
+            match attribute {
+                Attribute::Module { module: m, .. } => module = Some(m),
+                Attribute::ModulePackages { packages: p, .. } => packages = p,
+                Attribute::ModuleMainClass { main_class: c, .. } => main_class = Some(c.as_str()),
+                _ => {}
+            }
+        }
+        module.map(|module| ModuleDescriptor::new(module, packages, main_class))
+    }
+
+    // None unless this is a record class
+    pub fn record_components(&self) -> Option<&[RecordComponent]> {
+        self.attributes
             .iter()
-            .filter(|method| method.name == method_name)
-            .filter_map(|method| {
-                method
-                    .attributes
-                    .iter()
-                    .filter_map(|attribute| match attribute {
-                        Code {
-                            name,
-                            max_stack,
-                            max_locals,
-                            code_length,
-                            code,
-                            exceptions,
-                            attributes,
-                        } => Some(code),
-                        _ => None,
-                    })
-                    .next()
+            .find_map(|attribute| match attribute {
+                Attribute::Record { components, .. } => Some(components.as_slice()),
+                _ => None,
             })
-            .next()
     }
 
-    pub fn get_constant_value(&self, index: usize) -> Option<DataType> {
-        let constant = match self.get_constant(index) {
-            Some(c) => c,
-            None => return None,
-        };
-        constant.get_constant_value(&self.constant_pool)
+    // None unless this is a sealed class or interface
+    pub fn permitted_subclasses(&self) -> Option<&[String]> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::PermittedSubclasses { classes, .. } => Some(classes.as_slice()),
+                _ => None,
+            })
     }
 
-    pub fn get_method_ref_from_constant(&self, index: usize) -> Option<(String, &Constant)> {
-        let (class_index, name_and_type_index) = match self.get_constant(index) {
-            Some(FieldInfo {
-                tag,
-                class_index,
-                name_and_type_index,
-            }) => (*class_index as usize, *name_and_type_index as usize),
-            _ => return None,
-        };
+    // Every nested class referenced by this class, which includes this class itself if it's nested
+    pub fn inner_classes(&self) -> &[InnerClass] {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::InnerClasses { classes, .. } => Some(classes.as_slice()),
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
 
-        let class_name = resolve_utf8(class_index, &self.constant_pool).unwrap();
-        Some((
-            class_name,
-            self.get_constant(name_and_type_index).unwrap(),
-        ))
-    }
-
-    pub fn has_method(&self, name: String) -> bool {
-        self.methods.iter().any(|method| method.name == name)
-    }
-
-    pub fn get_method(&self, name_and_type: &Constant) -> Option<&Method> {
-        match name_and_type {
-            NameAndTypeInfo {
-                tag,
-                name_index,
-                descriptor_index,
-            } => self
-                .methods
-                .iter()
-                .filter(|method| {
-                    method.name == resolve_utf8(*name_index as usize, &self.constant_pool).unwrap()
-                })
-                .last(),
-            _ => None,
-        }
+    // The entry describing this class, if it's nested
+    pub fn inner_class_entry(&self) -> Option<&InnerClass> {
+        self.inner_classes()
+            .iter()
+            .find(|inner| inner.inner_class == self.name)
+    }
+
+    // (class name, (method name, descriptor)) for local and anonymous classes
+    pub fn enclosing_method(&self) -> Option<(&str, Option<(&str, &str)>)> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::EnclosingMethod {
+                    class_name, method, ..
+                } => Some((
+                    class_name.as_str(),
+                    method
+                        .as_ref()
+                        .map(|(name, descriptor)| (name.as_str(), descriptor.as_str())),
+                )),
+                _ => None,
+            })
+    }
+
+    pub fn source_file(&self) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                SourceFile { source_file, .. } => Some(source_file.as_str()),
+                _ => None,
+            })
+    }
+
+    pub fn get_constant_value(&self, index: u16) -> Option<DataType> {
+        self.get_constant(index)?.get_constant_value()
+    }
+
+    // The method ref or interface method ref at the index, resolved in this class's constant pool
+    pub fn get_method_ref_from_constant(&self, index: u16) -> Result<MemberRef> {
+        self.constant_pool.any_method_ref(index)
+    }
+
+    // The field a field ref refers to, resolved in this class's constant pool
+    pub fn get_field_ref_from_constant(&self, index: u16) -> Result<MemberRef> {
+        self.constant_pool.field_ref(index)
+    }
+
+    // Methods are identified by name and descriptor together, since overloads share a name
+    pub fn has_method(&self, name: &str, descriptor: &str) -> bool {
+        self.method_index(name, descriptor).is_some()
+    }
+
+    pub fn get_method(&self, name: &str, descriptor: &str) -> Option<&Method> {
+        self.method_index(name, descriptor)
+            .map(|index| &self.methods[index])
+    }
+
+    // Index into methods of the method this class declares, not counting inherited ones
+    pub fn method_index(&self, name: &str, descriptor: &str) -> Option<usize> {
+        self.methods
+            .iter()
+            .position(|method| method.name == name && method.descriptor == descriptor)
     }
 }
 
-fn parse_interfaces(reader: &mut dyn Read, constant_pool: &Vec<Constant>) -> Result<Vec<String>> {
+fn parse_interfaces(reader: &mut ClassReader, constant_pool: &ConstantPool) -> Result<Vec<String>> {
     let mut interfaces = Vec::new();
     let interfaces_count = read_u2(reader)?;
 
     for _ in 0..interfaces_count {
-        interfaces.push(resolve_utf8(read_u2(reader)? as usize, constant_pool).unwrap());
+        interfaces.push(constant_pool.class_name(read_u2(reader)?)?);
     }
 
     Ok(interfaces)
 }
 
 pub fn parse_class(reader: &mut dyn Read) -> Result<Class> {
-    // Read first 4 bytes as magic value and check if it's valid
-    let magic = read_u4(reader)?;
-    if magic != 0xCAFEBABE {
-        println!("{:x?}", magic);
-        // TODO return Err(SomeError);
-    }
+    parse_class_with_policy(reader, &VersionPolicy::default())
+}
+
+pub fn parse_class_with_policy(reader: &mut dyn Read, policy: &VersionPolicy) -> Result<Class> {
+    let reader = &mut ClassReader::new(reader);
 
-    // Read next 4 bytes as version number
-    // Ignore this since we don't care if the class is valid so long as it works
-    let minor_version = read_u2(reader)?;
-    let major_version = read_u2(reader)?;
+    let (minor_version, major_version) = reader.within("header", |reader| {
+        // Read first 4 bytes as magic value and check if it's valid
+        let magic = read_u4(reader)?;
+        if magic != 0xCAFEBABE {
+            return Err(ErrorKind::BadMagic(magic).into());
+        }
+
+        // Read next 4 bytes as version number
+        let minor = read_u2(reader)?;
+        let major = read_u2(reader)?;
+        policy.check(major, minor)?;
+        Ok((minor, major))
+    })?;
 
     // Read constant pool
-    let constant_pool = parse_constant_pool(reader)?;
-    for i in 1..=constant_pool.len() {
-        println!("Constant {}\n\t{:?}", i, constant_pool[i - 1]);
-    }
+    let mut constant_pool = parse_constant_pool(reader)?;
 
-    let access_flags = read_u2(reader)?; // TODO parse
+    let (access_flags, this_class, super_class) = reader.within("class", |reader| {
+        let access_flags = parse_access_flags(read_u2(reader)?, major_version)?;
 
-    let this_class = resolve_utf8(read_u2(reader)? as usize, &constant_pool).unwrap();
-    println!("This: {:?}", this_class);
-    let super_class_index = read_u2(reader)?;
-    let super_class = match super_class_index {
-        0 => "N/A".to_string(), // This class must be Object, with no superclass
-        _ => resolve_utf8(super_class_index as usize, &constant_pool).unwrap(),
-    };
-    println!("Super: {:?}", super_class);
+        let this_class = constant_pool.class_name(read_u2(reader)?)?;
+        let super_class_index = read_u2(reader)?;
+        let super_class = match super_class_index {
+            0 => None,
+            _ => Some(constant_pool.class_name(super_class_index)?),
+        };
+        Ok((access_flags, this_class, super_class))
+    })?;
 
-    let interfaces = parse_interfaces(reader, &constant_pool)?;
-    println!("Interfaces {:?}", interfaces);
+    let interfaces = reader.within("interfaces", |reader| {
+        parse_interfaces(reader, &constant_pool)
+    })?;
 
     let fields = parse_fields(reader, &constant_pool)?;
-    println!("Fields {:?}", fields);
 
     let methods = parse_methods(reader, &constant_pool)?;
-    for i in 0..methods.len() {
-        println!("Method {}\n\t{:?}", i, methods[i]);
-    }
 
     let attributes = parse_attributes(reader, &constant_pool)?;
-    for i in 0..attributes.len() {
-        println!("Attribute {}\n\t{:?}", i, attributes[i]);
-    }
-    println!();
+    constant_pool.stop_recording();
 
     Ok(Class {
         constant_pool,
+        minor_version,
+        major_version,
+        access_flags,
         name: this_class,
         super_name: super_class,
         interfaces,
@@ -171,3 +380,177 @@ pub fn parse_class(reader: &mut dyn Read) -> Result<Class> {
         attributes,
     })
 }
+
+// Writes the class back out in the class file format. Constants are looked up in a copy of the
+// class's constant pool and only appended when missing, so a class that hasn't been modified is
+// written back byte for byte.
+pub fn write_class(class: &Class, writer: &mut dyn Write) -> Result<()> {
+    // Everything after the constant pool can add to it, so is written first
+    let mut constant_pool = class.constant_pool.clone();
+    let body = &mut Vec::new();
+    write_u2(class.access_flags.bits(), body)?;
+    write_u2(constant_pool.add_class(&class.name)?, body)?;
+    let super_class_index = match &class.super_name {
+        Some(super_name) => constant_pool.add_class(super_name)?,
+        None => 0,
+    };
+    write_u2(super_class_index, body)?;
+    write_count(class.interfaces.len(), body)?;
+    for interface in &class.interfaces {
+        write_u2(constant_pool.add_class(interface)?, body)?;
+    }
+    write_fields(&class.fields, body, &mut constant_pool)?;
+    write_methods(&class.methods, body, &mut constant_pool)?;
+    write_attributes(&class.attributes, body, &mut constant_pool)?;
+
+    write_u4(0xCAFEBABE, writer)?;
+    write_u2(class.minor_version, writer)?;
+    write_u2(class.major_version, writer)?;
+    write_constant_pool(&constant_pool, writer)?;
+    write_bytes(body, writer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::ClassBuilder;
+    use crate::instructions::Instruction;
+    use crate::{fields, methods};
+
+    fn rewrite(bytes: &[u8]) -> Vec<u8> {
+        let class = parse_class(&mut &bytes[..]).unwrap();
+        let mut written = vec![];
+        write_class(&class, &mut written).unwrap();
+        written
+    }
+
+    #[test]
+    fn writes_back_the_bytes_it_parsed() {
+        let mut builder = ClassBuilder::new("com/acme/Counter");
+        builder
+            .field(fields::AccessFlags::default(), "count", "J")
+            .unwrap()
+            .method(
+                methods::AccessFlags {
+                    is_static: true,
+                    ..Default::default()
+                },
+                "add",
+                "(II)I",
+                |code| {
+                    code.emit(Instruction::ILoad0)
+                        .emit(Instruction::ILoad1)
+                        .emit(Instruction::IAdd)
+                        .emit(Instruction::IReturn);
+                    Ok(())
+                },
+            )
+            .unwrap();
+        let bytes = builder.to_bytes().unwrap();
+        assert_eq!(rewrite(&bytes), bytes);
+    }
+
+    #[test]
+    fn keeps_reserved_access_flag_bits() {
+        // Like the JDK's LambdaForm$Holder, whose flags are ACC_FINAL | ACC_SUPER | 0x0002
+        let mut builder = ClassBuilder::new("java/lang/invoke/LambdaForm$Holder");
+        builder
+            .access_flags(AccessFlags {
+                is_final: true,
+                is_super: true,
+                reserved: 0x0002,
+                ..Default::default()
+            })
+            .field(
+                fields::AccessFlags {
+                    reserved: 0x0800,
+                    ..Default::default()
+                },
+                "x",
+                "I",
+            )
+            .unwrap()
+            .abstract_method(
+                methods::AccessFlags {
+                    is_native: true,
+                    reserved: 0x2000,
+                    ..Default::default()
+                },
+                "f",
+                "()V",
+            )
+            .unwrap();
+        let bytes = builder.to_bytes().unwrap();
+
+        let class = parse_class(&mut &bytes[..]).unwrap();
+        assert_eq!(class.access_flags.bits(), 0x0032);
+        assert!(class.access_flags.is_final && class.access_flags.is_super);
+        assert_eq!(class.fields[0].access_flags.bits(), 0x0800);
+        assert_eq!(class.methods[0].access_flags.bits(), 0x2100);
+        assert_eq!(rewrite(&bytes), bytes);
+    }
+
+    #[test]
+    fn interfaces_only_need_to_be_abstract_from_java_6() {
+        let interface = |major_version| {
+            let mut builder = ClassBuilder::new("Old");
+            builder.version(major_version, 0).access_flags(AccessFlags {
+                is_interface: true,
+                ..Default::default()
+            });
+            builder.to_bytes().unwrap()
+        };
+
+        let bytes = interface(49);
+        let class = parse_class(&mut &bytes[..]).unwrap();
+        assert!(class.access_flags.is_interface && !class.access_flags.is_abstract);
+        assert_eq!(rewrite(&bytes), bytes);
+
+        let bytes = interface(50);
+        assert!(matches!(
+            parse_class(&mut &bytes[..]).unwrap_err().kind,
+            ErrorKind::InvalidAccessFlags(0x0200)
+        ));
+    }
+
+    #[test]
+    fn only_previews_of_the_newest_version_are_allowed() {
+        let policy = VersionPolicy {
+            allow_preview: true,
+            ..Default::default()
+        };
+        assert!(policy.check(61, 0xFFFF).is_ok());
+        assert!(policy.check(60, 0xFFFF).is_err());
+        assert!(policy.check(60, 0).is_ok());
+        assert!(VersionPolicy::default().check(61, 0xFFFF).is_err());
+    }
+
+    #[test]
+    fn keeps_names_with_unpaired_surrogates() {
+        let mut builder = ClassBuilder::new("com/acme/Names");
+        builder
+            .field(fields::AccessFlags::default(), "fff", "I")
+            .unwrap()
+            .abstract_method(
+                methods::AccessFlags {
+                    is_abstract: true,
+                    ..Default::default()
+                },
+                "mmm",
+                "()V",
+            )
+            .unwrap();
+        let mut bytes = builder.to_bytes().unwrap();
+        // Rename both to a lone high surrogate, which is the same length in modified UTF-8
+        for name in [b"fff", b"mmm"] {
+            let utf8 = [&[1, 0, 3][..], name].concat();
+            let at = bytes.windows(6).position(|window| window == utf8).unwrap();
+            bytes[at + 3..at + 6].copy_from_slice(&[0xED, 0xA0, 0x80]);
+        }
+
+        let class = parse_class(&mut &bytes[..]).unwrap();
+        assert_eq!(class.fields[0].name, "\u{FFFD}");
+        assert_eq!(rewrite(&bytes), bytes);
+    }
+}
@@ -0,0 +1,78 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use log::info;
+
+use crate::class::{parse_class, Class};
+use crate::class_path::{internal_name, ClassPath};
+use crate::error::{ErrorKind, Result};
+use crate::logging::CLASS_LOAD;
+
+// Loads classes from a class path on demand. Each class is only loaded once, and stays loaded
+// for as long as the loader, so it can be shared by every frame executing its methods.
+pub struct ClassLoader {
+    class_path: ClassPath,
+    classes: RefCell<HashMap<String, Rc<Class>>>,
+}
+
+impl ClassLoader {
+    pub fn new(class_path: ClassPath) -> ClassLoader {
+        ClassLoader {
+            class_path,
+            classes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn class_path(&self) -> &ClassPath {
+        &self.class_path
+    }
+
+    // The class with the binary name, e.g. com/acme/Util, loading it if it hasn't been already
+    pub fn load(&self, name: &str) -> Result<Rc<Class>> {
+        let name = internal_name(name);
+        if let Some(class) = self.classes.borrow().get(name.as_ref()) {
+            return Ok(Rc::clone(class));
+        }
+
+        let (entry, bytes) = self
+            .class_path
+            .locate(&name)?
+            .ok_or_else(|| ErrorKind::ClassNotFound(name.to_string()))?;
+        let class = parse_class(&mut &bytes[..])?;
+        // e.g. a class file in the wrong directory
+        if class.name != name {
+            return Err(ErrorKind::WrongClassName {
+                expected: name.to_string(),
+                found: class.name,
+            }
+            .into());
+        }
+        info!(target: CLASS_LOAD, "Loaded {} from {}", name, entry);
+
+        let class = Rc::new(class);
+        self.classes
+            .borrow_mut()
+            .insert(name.into_owned(), Rc::clone(&class));
+        Ok(class)
+    }
+
+    // Adds a class that wasn't loaded from the class path, replacing any already loaded with its
+    // name
+    pub fn define(&self, class: Class) -> Rc<Class> {
+        info!(target: CLASS_LOAD, "Defined {}", class.name);
+        let class = Rc::new(class);
+        self.classes
+            .borrow_mut()
+            .insert(class.name.clone(), Rc::clone(&class));
+        class
+    }
+
+    // The class if it's been loaded, without trying to load it
+    pub fn loaded(&self, name: &str) -> Option<Rc<Class>> {
+        self.classes
+            .borrow()
+            .get(internal_name(name).as_ref())
+            .cloned()
+    }
+}
@@ -0,0 +1,194 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::jar::Jar;
+use crate::raw::parse_raw_class;
+
+// Borrowed from in-memory entries, and read into a buffer of its own otherwise
+pub type ClassBytes<'a> = Cow<'a, [u8]>;
+
+// Where classes are found, searched in order like the JVM's -classpath. Classes are looked up by
+// binary name in internal form, e.g. com/acme/Util, or with dots, e.g. com.acme.Util.
+#[derive(Debug, Clone, Default)]
+pub struct ClassPath {
+    entries: Vec<ClassPathEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ClassPathEntry {
+    // The root of a package hierarchy, so com/acme/Util is read from com/acme/Util.class
+    Directory(PathBuf),
+    // A single class file, found by the name it declares
+    File { name: String, path: PathBuf },
+    // Class file bytes keyed by binary name in internal form
+    Memory(HashMap<String, Vec<u8>>),
+    Jar(Arc<Jar>),
+}
+
+impl ClassPath {
+    pub fn new() -> ClassPath {
+        ClassPath::default()
+    }
+
+    // A list of paths separated the way the platform separates PATH, i.e. ':' or ';'. Paths
+    // that are files are read as class files or jars, and anything else as a directory.
+    pub fn parse(paths: &str) -> Result<ClassPath> {
+        let mut class_path = ClassPath::new();
+        class_path.add_paths(paths)?;
+        Ok(class_path)
+    }
+
+    // Adds each path in a list like parse accepts
+    pub fn add_paths(&mut self, paths: &str) -> Result<&mut Self> {
+        for path in env::split_paths(paths) {
+            self.add_path(path)?;
+        }
+        Ok(self)
+    }
+
+    // A class file or jar, told apart by their contents, or otherwise a directory
+    pub fn add_path(&mut self, path: impl Into<PathBuf>) -> Result<&mut Self> {
+        let path = path.into();
+        if !path.is_file() {
+            return Ok(self.add_directory(path));
+        }
+        let mut magic = [0; 4];
+        fs::File::open(&path)?.read_exact(&mut magic)?;
+        match u32::from_be_bytes(magic) {
+            0xCAFEBABE => self.add_file(path),
+            _ => self.add_jar(path),
+        }
+    }
+
+    pub fn entries(&self) -> &[ClassPathEntry] {
+        &self.entries
+    }
+
+    pub fn add(&mut self, entry: ClassPathEntry) -> &mut Self {
+        self.entries.push(entry);
+        self
+    }
+
+    // Directories that don't exist are kept, as they might be created later
+    pub fn add_directory(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.add(ClassPathEntry::Directory(path.into()))
+    }
+
+    // Reads the class file now to find out which class it declares
+    pub fn add_file(&mut self, path: impl Into<PathBuf>) -> Result<&mut Self> {
+        let path = path.into();
+        let bytes = fs::read(&path)?;
+        let name = parse_raw_class(&bytes)?.name()?.into_owned();
+        Ok(self.add(ClassPathEntry::File { name, path }))
+    }
+
+    pub fn add_bytes(&mut self, classes: HashMap<String, Vec<u8>>) -> &mut Self {
+        self.add(ClassPathEntry::Memory(classes))
+    }
+
+    // Also adds what the Class-Path in the jar's manifest lists, after the jar itself. Like the
+    // JVM, entries that don't exist are skipped, as are jars that are already on the class path.
+    pub fn add_jar(&mut self, path: impl Into<PathBuf>) -> Result<&mut Self> {
+        let jar = Arc::new(Jar::open(path)?);
+        self.add(ClassPathEntry::Jar(Arc::clone(&jar)));
+
+        let directory = jar.path().parent().unwrap_or_else(|| Path::new(""));
+        for relative in jar
+            .manifest()
+            .map_or(vec![], |manifest| manifest.class_path())
+        {
+            let path = directory.join(relative);
+            if relative.ends_with('/') {
+                self.add_directory(path);
+            } else if path.is_file() && !self.has_jar(&path) {
+                self.add_path(path)?;
+            }
+        }
+        Ok(self)
+    }
+
+    fn has_jar(&self, path: &Path) -> bool {
+        let path = path.canonicalize().ok();
+        self.entries.iter().any(|entry| match entry {
+            ClassPathEntry::Jar(jar) => jar.path().canonicalize().ok() == path,
+            _ => false,
+        })
+    }
+
+    // The bytes of the first class file for the class, or None if no entry has it
+    pub fn find(&self, name: &str) -> io::Result<Option<ClassBytes<'_>>> {
+        Ok(self.locate(name)?.map(|(_, bytes)| bytes))
+    }
+
+    // Like find, but also says which entry the class was found in
+    pub fn locate(&self, name: &str) -> io::Result<Option<(&ClassPathEntry, ClassBytes<'_>)>> {
+        let name = internal_name(name);
+        for entry in &self.entries {
+            if let Some(bytes) = entry.find(&name)? {
+                return Ok(Some((entry, bytes)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl ClassPathEntry {
+    pub fn find(&self, name: &str) -> io::Result<Option<ClassBytes<'_>>> {
+        match self {
+            ClassPathEntry::Directory(directory) => {
+                read_if_exists(&directory.join(format!("{}.class", name)))
+            }
+            ClassPathEntry::File {
+                name: declared,
+                path,
+            } if declared == name => read_if_exists(path),
+            ClassPathEntry::File { .. } => Ok(None),
+            ClassPathEntry::Memory(classes) => {
+                Ok(classes.get(name).map(|bytes| Cow::Borrowed(&bytes[..])))
+            }
+            ClassPathEntry::Jar(jar) => match jar.find_class(name) {
+                Ok(bytes) => Ok(bytes.map(Cow::Owned)),
+                // Otherwise nothing says which jar is broken
+                Err(e) => Err(io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", jar.path().display(), e),
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ClassPathEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClassPathEntry::Directory(path) | ClassPathEntry::File { path, .. } => {
+                write!(f, "{}", path.display())
+            }
+            ClassPathEntry::Memory(_) => write!(f, "memory"),
+            ClassPathEntry::Jar(jar) => write!(f, "{}", jar.path().display()),
+        }
+    }
+}
+
+// Binary names are accepted with either separator, e.g. from the command line
+pub fn internal_name(name: &str) -> Cow<'_, str> {
+    match name.contains('.') {
+        true => Cow::Owned(name.replace('.', "/")),
+        false => Cow::Borrowed(name),
+    }
+}
+
+fn read_if_exists(path: &Path) -> io::Result<Option<ClassBytes<'static>>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(Cow::Owned(bytes))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
@@ -1,16 +1,21 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
-use std::io::{Read, Result};
-use std::str;
+use std::fmt;
+use std::io::Write;
 
 use num_enum::TryFromPrimitive;
 
 use Constant::*;
 use ConstantTag::*;
 
+use crate::error::{ClassFormatError, ErrorKind, Result};
 use crate::execution::DataType;
+use crate::modified_utf8::JavaString;
 use crate::read::*;
+use crate::write::*;
 
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive, Clone, Copy)]
+#[derive(Debug, Eq, PartialEq, Hash, TryFromPrimitive, Clone, Copy)]
 #[repr(u8)]
 pub enum ConstantTag {
     Class = 7,
@@ -33,14 +38,39 @@ pub enum ConstantTag {
     Placeholder,
 }
 
-#[derive(Debug)]
+// The kind of a method handle, which says what bytecode behaviour it stands for (JVMS 5.4.3.5)
+#[derive(Debug, Eq, PartialEq, Hash, TryFromPrimitive, Clone, Copy)]
+#[repr(u8)]
+pub enum ReferenceKind {
+    GetField = 1,
+    GetStatic = 2,
+    PutField = 3,
+    PutStatic = 4,
+    InvokeVirtual = 5,
+    InvokeStatic = 6,
+    InvokeSpecial = 7,
+    NewInvokeSpecial = 8,
+    InvokeInterface = 9,
+}
+
+#[derive(Debug, Clone)]
 pub enum Constant {
     Empty,
     ClassInfo {
         tag: ConstantTag,
         name_index: u16,
     },
-    FieldInfo {
+    FieldRefInfo {
+        tag: ConstantTag,
+        class_index: u16,
+        name_and_type_index: u16,
+    },
+    MethodRefInfo {
+        tag: ConstantTag,
+        class_index: u16,
+        name_and_type_index: u16,
+    },
+    InterfaceMethodRefInfo {
         tag: ConstantTag,
         class_index: u16,
         name_and_type_index: u16,
@@ -72,11 +102,11 @@ pub enum Constant {
     },
     Utf8Info {
         tag: ConstantTag,
-        value: String,
+        value: JavaString,
     },
     MethodHandleInfo {
         tag: ConstantTag,
-        kind: u8, // TODO see if I need an enum for this
+        kind: ReferenceKind,
         index: u16,
     },
     MethodTypeInfo {
@@ -99,139 +129,849 @@ pub enum Constant {
 }
 
 impl Constant {
-    pub fn get_constant_value(&self, constant_pool: &Vec<Constant>) -> Option<DataType> {
+    pub fn tag(&self) -> ConstantTag {
         match self {
-            IntInfo { tag, value } => Some(DataType::Integer(*value)),
-            FloatInfo { tag, value } => Some(DataType::Float(*value)),
-            LongInfo { tag, value } => Some(DataType::Long(*value)),
-            DoubleInfo { tag, value } => Some(DataType::Double(*value)),
-            StringInfo { tag, index } => {
-                // TODO implement string data types
-                None
-                // ConstantValue::String(resolve_utf8(index as usize, constant_pool).unwrap())
-            }
-            _ => None,
+            Empty => Placeholder,
+            ClassInfo { tag, .. }
+            | FieldRefInfo { tag, .. }
+            | MethodRefInfo { tag, .. }
+            | InterfaceMethodRefInfo { tag, .. }
+            | StringInfo { tag, .. }
+            | IntInfo { tag, .. }
+            | FloatInfo { tag, .. }
+            | LongInfo { tag, .. }
+            | DoubleInfo { tag, .. }
+            | NameAndTypeInfo { tag, .. }
+            | Utf8Info { tag, .. }
+            | MethodHandleInfo { tag, .. }
+            | MethodTypeInfo { tag, .. }
+            | DynamicInfo { tag, .. }
+            | ModuleInfo { tag, .. }
+            | PackageInfo { tag, .. } => *tag,
         }
     }
 
-    // pub fn get_method_name_and_type(
-    //     &self,
-    //     constant_pool: &Vec<Constant>,
-    // ) -> Option<(String, String, String)> {
-    //     let (class_index, name_and_type_index) = match self {
-    //         FieldInfo {
-    //             tag,
-    //             class_index,
-    //             name_and_type_index,
-    //         } => (class_index, name_and_type_index),
-    //         _ => return None,
-    //     };
-    // }
+    // The value of a numeric constant. Strings are None, as pushing one needs a java/lang/String
+    // object, which the interpreter can't create yet.
+    pub fn get_constant_value(&self) -> Option<DataType> {
+        match self {
+            IntInfo { value, .. } => Some(DataType::Integer(*value)),
+            FloatInfo { value, .. } => Some(DataType::Float(*value)),
+            LongInfo { value, .. } => Some(DataType::Long(*value)),
+            DoubleInfo { value, .. } => Some(DataType::Double(*value)),
+            _ => None,
+        }
+    }
 }
 
-pub fn parse_constant_pool(reader: &mut dyn Read) -> Result<Vec<Constant>> {
+pub fn parse_constant_pool(reader: &mut ClassReader) -> Result<ConstantPool> {
     let mut pool = Vec::new();
     let constant_pool_count = read_u2(reader)?;
-    println!("Constant pool count {}", constant_pool_count);
 
     // Insert a placeholder for double-width constants Long, Double
     let mut skip = false;
 
-    for _ in 1..constant_pool_count {
+    for i in 1..constant_pool_count {
         if skip {
             pool.push(Empty);
             skip = false;
             continue;
         }
-        let tag_num = read_u1(reader)?;
-        let tag = ConstantTag::try_from(tag_num).unwrap();
-        let constant = match tag {
-            Class => ClassInfo {
-                tag,
-                name_index: read_u2(reader)?,
-            },
-            FieldRef | MethodRef | InterfaceMethodRef => FieldInfo {
+        let constant = reader.within(format!("constant #{}", i), parse_constant)?;
+        let tag = constant.tag();
+        pool.push(constant);
+        if tag == Double || tag == Long {
+            // Skip next entry and insert placeholder, per spec
+            skip = true;
+        }
+    }
+
+    let mut constant_pool = ConstantPool::new(pool);
+    constant_pool.recording = true;
+    Ok(constant_pool)
+}
+
+fn parse_constant(reader: &mut ClassReader) -> Result<Constant> {
+    let tag_num = read_u1(reader)?;
+    let tag = ConstantTag::try_from(tag_num).map_err(|_| ErrorKind::BadConstantTag(tag_num))?;
+    let constant = match tag {
+        Class => ClassInfo {
+            tag,
+            name_index: read_u2(reader)?,
+        },
+        FieldRef => FieldRefInfo {
+            tag,
+            class_index: read_u2(reader)?,
+            name_and_type_index: read_u2(reader)?,
+        },
+        MethodRef => MethodRefInfo {
+            tag,
+            class_index: read_u2(reader)?,
+            name_and_type_index: read_u2(reader)?,
+        },
+        InterfaceMethodRef => InterfaceMethodRefInfo {
+            tag,
+            class_index: read_u2(reader)?,
+            name_and_type_index: read_u2(reader)?,
+        },
+        ConstString => StringInfo {
+            tag,
+            index: read_u2(reader)?,
+        },
+        Integer => IntInfo {
+            tag,
+            value: read_u4(reader)? as i32,
+        },
+        Float => FloatInfo {
+            tag,
+            value: f32::from_bits(read_u4(reader)?),
+        },
+        Long => LongInfo {
+            tag,
+            value: read_u8(reader)? as i64,
+        },
+        Double => DoubleInfo {
+            tag,
+            value: f64::from_bits(read_u8(reader)?),
+        },
+        NameAndType => NameAndTypeInfo {
+            tag,
+            name_index: read_u2(reader)?,
+            descriptor_index: read_u2(reader)?,
+        },
+        Utf8 => {
+            let length = read_u2(reader)? as u64;
+            let bytes = read_bytes(length, reader)?;
+            Utf8Info {
                 tag,
-                class_index: read_u2(reader)?,
-                name_and_type_index: read_u2(reader)?,
-            },
-            ConstString => StringInfo {
+                value: JavaString::from_modified_utf8(&bytes)?,
+            }
+        }
+        MethodHandle => {
+            let kind = read_u1(reader)?;
+            MethodHandleInfo {
                 tag,
+                kind: ReferenceKind::try_from(kind)
+                    .map_err(|_| ErrorKind::BadReferenceKind(kind))?,
                 index: read_u2(reader)?,
-            },
-            Integer => IntInfo {
-                tag,
-                value: read_u4(reader)? as i32,
-            },
-            Float => FloatInfo {
-                tag,
-                value: f32::from_bits(read_u4(reader)?),
-            },
-            Long => LongInfo {
-                tag,
-                value: read_u8(reader)? as i64,
-            },
-            Double => DoubleInfo {
-                tag,
-                value: f64::from_bits(read_u8(reader)?),
-            },
-            NameAndType => NameAndTypeInfo {
-                tag,
-                name_index: read_u2(reader)?,
-                descriptor_index: read_u2(reader)?,
-            },
-            Utf8 => {
-                let length = read_u2(reader)? as u64;
-                let bytes = read_bytes(length, reader)?;
-                Utf8Info {
-                    tag,
-                    value: str::from_utf8(bytes.as_slice()).unwrap().to_string(),
+            }
+        }
+        MethodType => MethodTypeInfo {
+            tag,
+            descriptor_index: read_u2(reader)?,
+        },
+        Dynamic | InvokeDynamic => DynamicInfo {
+            tag,
+            bootstrap_method_attr_index: read_u2(reader)?,
+            name_and_type_index: read_u2(reader)?,
+        },
+        Module => ModuleInfo {
+            tag,
+            name_index: read_u2(reader)?,
+        },
+        Package => PackageInfo {
+            tag,
+            name_index: read_u2(reader)?,
+        },
+        Placeholder => return Err(ErrorKind::BadConstantTag(tag_num).into()),
+    };
+
+    Ok(constant)
+}
+
+pub fn write_constant_pool(constant_pool: &ConstantPool, writer: &mut dyn Write) -> Result<()> {
+    // The count includes the unused constant 0
+    write_u2(constant_pool.len() as u16 + 1, writer)?;
+    for (_, constant) in constant_pool.iter() {
+        write_constant(constant, writer)?;
+    }
+    Ok(())
+}
+
+fn write_constant(constant: &Constant, writer: &mut dyn Write) -> Result<()> {
+    if let Empty = constant {
+        return Ok(());
+    }
+    write_u1(constant.tag() as u8, writer)?;
+    match constant {
+        Empty => {}
+        ClassInfo { name_index, .. }
+        | ModuleInfo { name_index, .. }
+        | PackageInfo { name_index, .. } => write_u2(*name_index, writer)?,
+        FieldRefInfo {
+            class_index,
+            name_and_type_index,
+            ..
+        }
+        | MethodRefInfo {
+            class_index,
+            name_and_type_index,
+            ..
+        }
+        | InterfaceMethodRefInfo {
+            class_index,
+            name_and_type_index,
+            ..
+        } => {
+            write_u2(*class_index, writer)?;
+            write_u2(*name_and_type_index, writer)?;
+        }
+        StringInfo { index, .. } => write_u2(*index, writer)?,
+        IntInfo { value, .. } => write_u4(*value as u32, writer)?,
+        FloatInfo { value, .. } => write_u4(value.to_bits(), writer)?,
+        LongInfo { value, .. } => write_u8(*value as u64, writer)?,
+        DoubleInfo { value, .. } => write_u8(value.to_bits(), writer)?,
+        NameAndTypeInfo {
+            name_index,
+            descriptor_index,
+            ..
+        } => {
+            write_u2(*name_index, writer)?;
+            write_u2(*descriptor_index, writer)?;
+        }
+        Utf8Info { value, .. } => {
+            let bytes = value.to_modified_utf8();
+            write_count(bytes.len(), writer)?;
+            write_bytes(&bytes, writer)?;
+        }
+        MethodHandleInfo { kind, index, .. } => {
+            write_u1(*kind as u8, writer)?;
+            write_u2(*index, writer)?;
+        }
+        MethodTypeInfo {
+            descriptor_index, ..
+        } => write_u2(*descriptor_index, writer)?,
+        DynamicInfo {
+            bootstrap_method_attr_index,
+            name_and_type_index,
+            ..
+        } => {
+            write_u2(*bootstrap_method_attr_index, writer)?;
+            write_u2(*name_and_type_index, writer)?;
+        }
+    }
+    Ok(())
+}
+
+fn wrong_kind(index: u16, expected: &'static str, found: &Constant) -> ClassFormatError {
+    ErrorKind::WrongConstantKind {
+        index,
+        expected,
+        found: found.tag(),
+    }
+    .into()
+}
+
+// A field, method or interface method reference with its class and name and type resolved
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberRef {
+    pub tag: ConstantTag,
+    pub class_name: String,
+    pub name: String,
+    pub descriptor: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodHandle {
+    pub kind: ReferenceKind,
+    pub reference: MemberRef,
+}
+
+// A constant that can be pushed by ldc or passed as a static argument to a bootstrap method, with
+// all of its references resolved
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadableConstant {
+    Integer(i32),
+    Float(f32),
+    Long(i64),
+    Double(f64),
+    String(JavaString),
+    Class(String),
+    MethodType(String),
+    MethodHandle(MethodHandle),
+    Dynamic {
+        bootstrap_method_attr_index: u16,
+        name: String,
+        descriptor: String,
+    },
+}
+
+// Identifies a constant by its contents, so adding a constant that's already in the pool can
+// reuse it. Floating point values are compared by their bits, so NaNs and -0.0 are kept distinct.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Utf8(JavaString),
+    Class(u16),
+    String(u16),
+    Integer(i32),
+    Float(u32),
+    Long(i64),
+    Double(u64),
+    NameAndType(u16, u16),
+    MemberRef(ConstantTag, u16, u16),
+    MethodHandle(ReferenceKind, u16),
+    MethodType(u16),
+    Dynamic(ConstantTag, u16, u16),
+    Module(u16),
+    Package(u16),
+}
+
+fn constant_key(constant: &Constant) -> Option<ConstantKey> {
+    Some(match constant {
+        Empty => return None,
+        Utf8Info { value, .. } => ConstantKey::Utf8(value.clone()),
+        ClassInfo { name_index, .. } => ConstantKey::Class(*name_index),
+        StringInfo { index, .. } => ConstantKey::String(*index),
+        IntInfo { value, .. } => ConstantKey::Integer(*value),
+        FloatInfo { value, .. } => ConstantKey::Float(value.to_bits()),
+        LongInfo { value, .. } => ConstantKey::Long(*value),
+        DoubleInfo { value, .. } => ConstantKey::Double(value.to_bits()),
+        NameAndTypeInfo {
+            name_index,
+            descriptor_index,
+            ..
+        } => ConstantKey::NameAndType(*name_index, *descriptor_index),
+        FieldRefInfo {
+            tag,
+            class_index,
+            name_and_type_index,
+        }
+        | MethodRefInfo {
+            tag,
+            class_index,
+            name_and_type_index,
+        }
+        | InterfaceMethodRefInfo {
+            tag,
+            class_index,
+            name_and_type_index,
+        } => ConstantKey::MemberRef(*tag, *class_index, *name_and_type_index),
+        MethodHandleInfo { kind, index, .. } => ConstantKey::MethodHandle(*kind, *index),
+        MethodTypeInfo {
+            descriptor_index, ..
+        } => ConstantKey::MethodType(*descriptor_index),
+        DynamicInfo {
+            tag,
+            bootstrap_method_attr_index,
+            name_and_type_index,
+        } => ConstantKey::Dynamic(*tag, *bootstrap_method_attr_index, *name_and_type_index),
+        ModuleInfo { name_index, .. } => ConstantKey::Module(*name_index),
+        PackageInfo { name_index, .. } => ConstantKey::Package(*name_index),
+    })
+}
+
+// Indexed from 1 like in the class file, with Long and Double constants followed by an Empty
+// entry. The accessors check the kind of constant at the index, and resolve any indices it holds.
+// The add_ methods return the index of an equal constant if there is one, and append otherwise.
+//
+// Some compilers put the same constant in the pool more than once, e.g. older versions of javac
+// for classes used as catch types. While a class is being parsed, the pool records which copy each
+// reference used, and adding the constant again hands the copies back out in the same order. That
+// way writing an unmodified class refers to the same indices it was read with.
+#[derive(Clone, Default)]
+pub struct ConstantPool {
+    constants: Vec<Constant>,
+    indices: HashMap<ConstantKey, u16>, // The first index of each distinct constant
+    duplicates: HashSet<u16>,           // Indices of constants that appear more than once
+    recording: bool,
+    uses: RefCell<HashMap<ConstantKey, VecDeque<u16>>>,
+}
+
+impl fmt::Debug for ConstantPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.constants).finish()
+    }
+}
+
+impl ConstantPool {
+    pub fn new(constants: Vec<Constant>) -> ConstantPool {
+        let mut indices = HashMap::new();
+        let mut duplicates = HashSet::new();
+        for (i, constant) in constants.iter().enumerate() {
+            if let Some(key) = constant_key(constant) {
+                let index = i as u16 + 1;
+                let first = *indices.entry(key).or_insert(index);
+                if first != index {
+                    duplicates.insert(first);
+                    duplicates.insert(index);
                 }
             }
-            MethodHandle => MethodHandleInfo {
-                tag,
-                kind: read_u1(reader)?,
-                index: read_u2(reader)?,
-            },
-            MethodType => MethodTypeInfo {
-                tag,
-                descriptor_index: read_u2(reader)?,
-            },
-            Dynamic | InvokeDynamic => DynamicInfo {
+        }
+        ConstantPool {
+            constants,
+            indices,
+            duplicates,
+            ..ConstantPool::default()
+        }
+    }
+
+    // Called once the class has been parsed, so later lookups aren't mistaken for references
+    pub fn stop_recording(&mut self) {
+        self.recording = false;
+    }
+
+    // One less than the constant_pool_count in the class file, as there's no constant 0
+    pub fn len(&self) -> usize {
+        self.constants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.constants.is_empty()
+    }
+
+    // Constants along with their indices
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &Constant)> {
+        self.constants
+            .iter()
+            .enumerate()
+            .map(|(i, constant)| (i as u16 + 1, constant))
+    }
+
+    pub fn get(&self, index: u16) -> Result<&Constant> {
+        match (index as usize)
+            .checked_sub(1)
+            .and_then(|i| self.constants.get(i))
+        {
+            Some(constant) => Ok(constant),
+            None => Err(ErrorKind::InvalidConstantIndex(index).into()),
+        }
+    }
+
+    // Like get, for following a reference. Every accessor below resolves each index it's given or
+    // finds exactly once, in the same order the add_ methods add them.
+    fn resolve(&self, index: u16) -> Result<&Constant> {
+        let constant = self.get(index)?;
+        if self.recording && self.duplicates.contains(&index) {
+            let key = constant_key(constant).expect("duplicates are never Empty");
+            self.uses
+                .borrow_mut()
+                .entry(key)
+                .or_default()
+                .push_back(index);
+        }
+        Ok(constant)
+    }
+
+    pub fn java_string(&self, index: u16) -> Result<&JavaString> {
+        match self.resolve(index)? {
+            Utf8Info { value, .. } => Ok(value),
+            constant => Err(wrong_kind(index, "Utf8", constant)),
+        }
+    }
+
+    // Names can't usefully contain unpaired surrogates, so don't need to be lossless
+    pub fn utf8(&self, index: u16) -> Result<String> {
+        Ok(self.java_string(index)?.to_string_lossy())
+    }
+
+    pub fn class_name(&self, index: u16) -> Result<String> {
+        match self.resolve(index)? {
+            ClassInfo { name_index, .. } => self.utf8(*name_index),
+            constant => Err(wrong_kind(index, "Class", constant)),
+        }
+    }
+
+    pub fn module_name(&self, index: u16) -> Result<String> {
+        match self.resolve(index)? {
+            ModuleInfo { name_index, .. } => self.utf8(*name_index),
+            constant => Err(wrong_kind(index, "Module", constant)),
+        }
+    }
+
+    // In internal form, e.g. java/lang
+    pub fn package_name(&self, index: u16) -> Result<String> {
+        match self.resolve(index)? {
+            PackageInfo { name_index, .. } => self.utf8(*name_index),
+            constant => Err(wrong_kind(index, "Package", constant)),
+        }
+    }
+
+    // (name, descriptor)
+    pub fn name_and_type(&self, index: u16) -> Result<(String, String)> {
+        match self.resolve(index)? {
+            NameAndTypeInfo {
+                name_index,
+                descriptor_index,
+                ..
+            } => Ok((self.utf8(*name_index)?, self.utf8(*descriptor_index)?)),
+            constant => Err(wrong_kind(index, "NameAndType", constant)),
+        }
+    }
+
+    pub fn field_ref(&self, index: u16) -> Result<MemberRef> {
+        self.member_ref(index, &[FieldRef], "FieldRef")
+    }
+
+    pub fn method_ref(&self, index: u16) -> Result<MemberRef> {
+        self.member_ref(index, &[MethodRef], "MethodRef")
+    }
+
+    pub fn interface_method_ref(&self, index: u16) -> Result<MemberRef> {
+        self.member_ref(index, &[InterfaceMethodRef], "InterfaceMethodRef")
+    }
+
+    // invokestatic and invokespecial can refer to either kind of method
+    pub fn any_method_ref(&self, index: u16) -> Result<MemberRef> {
+        self.member_ref(
+            index,
+            &[MethodRef, InterfaceMethodRef],
+            "MethodRef or InterfaceMethodRef",
+        )
+    }
+
+    fn member_ref(
+        &self,
+        index: u16,
+        tags: &[ConstantTag],
+        expected: &'static str,
+    ) -> Result<MemberRef> {
+        match self.resolve(index)? {
+            FieldRefInfo {
                 tag,
-                bootstrap_method_attr_index: read_u2(reader)?,
-                name_and_type_index: read_u2(reader)?,
-            },
-            Module => ModuleInfo {
+                class_index,
+                name_and_type_index,
+            }
+            | MethodRefInfo {
                 tag,
-                name_index: read_u2(reader)?,
-            },
-            Package => PackageInfo {
+                class_index,
+                name_and_type_index,
+            }
+            | InterfaceMethodRefInfo {
                 tag,
-                name_index: read_u2(reader)?,
-            },
-            _ => Empty,
+                class_index,
+                name_and_type_index,
+            } if tags.contains(tag) => {
+                let (name, descriptor) = self.name_and_type(*name_and_type_index)?;
+                Ok(MemberRef {
+                    tag: *tag,
+                    class_name: self.class_name(*class_index)?,
+                    name,
+                    descriptor,
+                })
+            }
+            constant => Err(wrong_kind(index, expected, constant)),
+        }
+    }
+
+    pub fn integer(&self, index: u16) -> Result<i32> {
+        match self.resolve(index)? {
+            IntInfo { value, .. } => Ok(*value),
+            constant => Err(wrong_kind(index, "Integer", constant)),
+        }
+    }
+
+    pub fn float(&self, index: u16) -> Result<f32> {
+        match self.resolve(index)? {
+            FloatInfo { value, .. } => Ok(*value),
+            constant => Err(wrong_kind(index, "Float", constant)),
+        }
+    }
+
+    pub fn long(&self, index: u16) -> Result<i64> {
+        match self.resolve(index)? {
+            LongInfo { value, .. } => Ok(*value),
+            constant => Err(wrong_kind(index, "Long", constant)),
+        }
+    }
+
+    pub fn double(&self, index: u16) -> Result<f64> {
+        match self.resolve(index)? {
+            DoubleInfo { value, .. } => Ok(*value),
+            constant => Err(wrong_kind(index, "Double", constant)),
+        }
+    }
+
+    // The value of a String constant, which unlike names must be lossless
+    pub fn string(&self, index: u16) -> Result<JavaString> {
+        match self.resolve(index)? {
+            StringInfo {
+                index: utf8_index, ..
+            } => Ok(self.java_string(*utf8_index)?.clone()),
+            constant => Err(wrong_kind(index, "String", constant)),
+        }
+    }
+
+    pub fn method_handle(&self, index: u16) -> Result<MethodHandle> {
+        let (kind, reference_index) = match self.resolve(index)? {
+            MethodHandleInfo {
+                kind,
+                index: reference_index,
+                ..
+            } => (*kind, *reference_index),
+            constant => return Err(wrong_kind(index, "MethodHandle", constant)),
         };
-        pool.push(constant);
-        if tag == Double || tag == Long {
-            // Skip next entry and insert placeholder, per spec
-            skip = true;
+
+        // The kind decides what the handle may refer to, see JVMS 4.4.8
+        let reference = match kind {
+            ReferenceKind::GetField
+            | ReferenceKind::GetStatic
+            | ReferenceKind::PutField
+            | ReferenceKind::PutStatic => self.field_ref(reference_index)?,
+            ReferenceKind::InvokeVirtual | ReferenceKind::NewInvokeSpecial => {
+                self.method_ref(reference_index)?
+            }
+            ReferenceKind::InvokeStatic | ReferenceKind::InvokeSpecial => {
+                self.any_method_ref(reference_index)?
+            }
+            ReferenceKind::InvokeInterface => self.interface_method_ref(reference_index)?,
+        };
+        Ok(MethodHandle { kind, reference })
+    }
+
+    pub fn loadable(&self, index: u16) -> Result<LoadableConstant> {
+        // These resolve the index themselves
+        match self.get(index)? {
+            StringInfo { .. } => return Ok(LoadableConstant::String(self.string(index)?)),
+            ClassInfo { .. } => return Ok(LoadableConstant::Class(self.class_name(index)?)),
+            MethodHandleInfo { .. } => {
+                return Ok(LoadableConstant::MethodHandle(self.method_handle(index)?))
+            }
+            _ => {}
         }
+
+        Ok(match self.resolve(index)? {
+            IntInfo { value, .. } => LoadableConstant::Integer(*value),
+            FloatInfo { value, .. } => LoadableConstant::Float(*value),
+            LongInfo { value, .. } => LoadableConstant::Long(*value),
+            DoubleInfo { value, .. } => LoadableConstant::Double(*value),
+            MethodTypeInfo {
+                descriptor_index, ..
+            } => LoadableConstant::MethodType(self.utf8(*descriptor_index)?),
+            DynamicInfo {
+                tag: Dynamic,
+                bootstrap_method_attr_index,
+                name_and_type_index,
+            } => {
+                let (name, descriptor) = self.name_and_type(*name_and_type_index)?;
+                LoadableConstant::Dynamic {
+                    bootstrap_method_attr_index: *bootstrap_method_attr_index,
+                    name,
+                    descriptor,
+                }
+            }
+            constant => return Err(wrong_kind(index, "a loadable constant", constant)),
+        })
     }
 
-    Ok(pool)
-}
+    fn add(&mut self, constant: Constant) -> Result<u16> {
+        let key = constant_key(&constant).expect("Empty is never added");
+        let reused = self
+            .uses
+            .get_mut()
+            .get_mut(&key)
+            .and_then(VecDeque::pop_front);
+        if let Some(index) = reused.or_else(|| self.indices.get(&key).copied()) {
+            return Ok(index);
+        }
+
+        // The count written to the class file is one more than the number of entries
+        let wide = matches!(constant, LongInfo { .. } | DoubleInfo { .. });
+        let needed = if wide { 2 } else { 1 };
+        if self.constants.len() + needed >= u16::MAX as usize {
+            return Err(ErrorKind::ConstantPoolFull.into());
+        }
+
+        let index = self.constants.len() as u16 + 1;
+        self.constants.push(constant);
+        if wide {
+            self.constants.push(Empty);
+        }
+        self.indices.insert(key, index);
+        Ok(index)
+    }
+
+    pub fn add_utf8(&mut self, value: impl Into<JavaString>) -> Result<u16> {
+        self.add(Utf8Info {
+            tag: Utf8,
+            value: value.into(),
+        })
+    }
+
+    // Like add_utf8, for a name read from the Utf8 constant at `index`. utf8 replaces unpaired
+    // surrogates, so if the name is still what that decoded to, the original constant is added.
+    pub fn add_utf8_from(&mut self, index: u16, value: &str) -> Result<u16> {
+        match self.java_string(index) {
+            Ok(original) if original.to_string_lossy() == value => {
+                let original = original.clone();
+                self.add_utf8(original)
+            }
+            _ => self.add_utf8(value),
+        }
+    }
+
+    pub fn add_class(&mut self, name: &str) -> Result<u16> {
+        let name_index = self.add_utf8(name)?;
+        self.add(ClassInfo {
+            tag: Class,
+            name_index,
+        })
+    }
+
+    pub fn add_module(&mut self, name: &str) -> Result<u16> {
+        let name_index = self.add_utf8(name)?;
+        self.add(ModuleInfo {
+            tag: Module,
+            name_index,
+        })
+    }
+
+    pub fn add_package(&mut self, name: &str) -> Result<u16> {
+        let name_index = self.add_utf8(name)?;
+        self.add(PackageInfo {
+            tag: Package,
+            name_index,
+        })
+    }
+
+    pub fn add_name_and_type(&mut self, name: &str, descriptor: &str) -> Result<u16> {
+        let name_index = self.add_utf8(name)?;
+        let descriptor_index = self.add_utf8(descriptor)?;
+        self.add(NameAndTypeInfo {
+            tag: NameAndType,
+            name_index,
+            descriptor_index,
+        })
+    }
+
+    pub fn add_field_ref(&mut self, class_name: &str, name: &str, descriptor: &str) -> Result<u16> {
+        let (class_index, name_and_type_index) =
+            self.add_member_parts(class_name, name, descriptor)?;
+        self.add(FieldRefInfo {
+            tag: FieldRef,
+            class_index,
+            name_and_type_index,
+        })
+    }
+
+    pub fn add_method_ref(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<u16> {
+        let (class_index, name_and_type_index) =
+            self.add_member_parts(class_name, name, descriptor)?;
+        self.add(MethodRefInfo {
+            tag: MethodRef,
+            class_index,
+            name_and_type_index,
+        })
+    }
+
+    pub fn add_interface_method_ref(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<u16> {
+        let (class_index, name_and_type_index) =
+            self.add_member_parts(class_name, name, descriptor)?;
+        self.add(InterfaceMethodRefInfo {
+            tag: InterfaceMethodRef,
+            class_index,
+            name_and_type_index,
+        })
+    }
+
+    fn add_member_parts(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<(u16, u16)> {
+        Ok((
+            self.add_class(class_name)?,
+            self.add_name_and_type(name, descriptor)?,
+        ))
+    }
+
+    pub fn add_member_ref(&mut self, reference: &MemberRef) -> Result<u16> {
+        let MemberRef {
+            tag,
+            class_name,
+            name,
+            descriptor,
+        } = reference;
+        match tag {
+            FieldRef => self.add_field_ref(class_name, name, descriptor),
+            InterfaceMethodRef => self.add_interface_method_ref(class_name, name, descriptor),
+            _ => self.add_method_ref(class_name, name, descriptor),
+        }
+    }
+
+    pub fn add_integer(&mut self, value: i32) -> Result<u16> {
+        self.add(IntInfo {
+            tag: Integer,
+            value,
+        })
+    }
+
+    pub fn add_float(&mut self, value: f32) -> Result<u16> {
+        self.add(FloatInfo { tag: Float, value })
+    }
+
+    pub fn add_long(&mut self, value: i64) -> Result<u16> {
+        self.add(LongInfo { tag: Long, value })
+    }
+
+    pub fn add_double(&mut self, value: f64) -> Result<u16> {
+        self.add(DoubleInfo { tag: Double, value })
+    }
+
+    pub fn add_string(&mut self, value: impl Into<JavaString>) -> Result<u16> {
+        let index = self.add_utf8(value)?;
+        self.add(StringInfo {
+            tag: ConstString,
+            index,
+        })
+    }
+
+    pub fn add_method_type(&mut self, descriptor: &str) -> Result<u16> {
+        let descriptor_index = self.add_utf8(descriptor)?;
+        self.add(MethodTypeInfo {
+            tag: MethodType,
+            descriptor_index,
+        })
+    }
+
+    pub fn add_method_handle(&mut self, handle: &MethodHandle) -> Result<u16> {
+        let index = self.add_member_ref(&handle.reference)?;
+        self.add(MethodHandleInfo {
+            tag: MethodHandle,
+            kind: handle.kind,
+            index,
+        })
+    }
+
+    pub fn add_dynamic(
+        &mut self,
+        bootstrap_method_attr_index: u16,
+        name: &str,
+        descriptor: &str,
+    ) -> Result<u16> {
+        let name_and_type_index = self.add_name_and_type(name, descriptor)?;
+        self.add(DynamicInfo {
+            tag: Dynamic,
+            bootstrap_method_attr_index,
+            name_and_type_index,
+        })
+    }
 
-// TODO change this into a Result when I figure out error handling
-pub fn resolve_utf8(index: usize, constant_pool: &Vec<Constant>) -> Option<String> {
-    match &constant_pool[index - 1] {
-        Constant::Utf8Info { tag, value } => Some(value.to_string()),
-        Constant::ClassInfo { tag, name_index } => {
-            resolve_utf8(*name_index as usize, constant_pool)
+    pub fn add_loadable(&mut self, constant: &LoadableConstant) -> Result<u16> {
+        match constant {
+            LoadableConstant::Integer(value) => self.add_integer(*value),
+            LoadableConstant::Float(value) => self.add_float(*value),
+            LoadableConstant::Long(value) => self.add_long(*value),
+            LoadableConstant::Double(value) => self.add_double(*value),
+            LoadableConstant::String(value) => self.add_string(value.clone()),
+            LoadableConstant::Class(name) => self.add_class(name),
+            LoadableConstant::MethodType(descriptor) => self.add_method_type(descriptor),
+            LoadableConstant::MethodHandle(handle) => self.add_method_handle(handle),
+            LoadableConstant::Dynamic {
+                bootstrap_method_attr_index,
+                name,
+                descriptor,
+            } => self.add_dynamic(*bootstrap_method_attr_index, name, descriptor),
         }
-        a => {
-            println!("{} {:?}", index, a);
-            None
-        } // TODO throw an actual error at some point
     }
 }
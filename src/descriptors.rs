@@ -0,0 +1,194 @@
+use std::fmt;
+
+use pest::iterators::Pair;
+use pest::Parser;
+
+use crate::error::{ErrorKind, Result};
+
+// Field and method descriptors (JVMS 4.3), the erased types the JVM links and executes against
+
+#[derive(Parser)]
+#[grammar = "descriptor.pest"]
+struct DescriptorParser;
+
+// Arrays may have at most this many dimensions (JVMS 4.3.2)
+pub const MAX_DIMENSIONS: usize = 255;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FieldType {
+    Byte,
+    Char,
+    Double,
+    Float,
+    Int,
+    Long,
+    Ref(String), // Class name in internal form, e.g. java/lang/String
+    Short,
+    Bool,
+    Array(Box<FieldType>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ReturnType {
+    Return(FieldType),
+    Void,
+}
+
+impl FieldType {
+    // Number of local variable or operand stack slots a value of this type takes up
+    pub fn slots(&self) -> usize {
+        match self {
+            FieldType::Long | FieldType::Double => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn is_reference(&self) -> bool {
+        matches!(self, FieldType::Ref(_) | FieldType::Array(_))
+    }
+
+    // 0 unless this is an array type
+    pub fn dimensions(&self) -> usize {
+        match self {
+            FieldType::Array(element) => 1 + element.dimensions(),
+            _ => 0,
+        }
+    }
+
+    // The type with every array dimension removed, e.g. int for int[][]
+    pub fn element_type(&self) -> &FieldType {
+        match self {
+            FieldType::Array(element) => element.element_type(),
+            _ => self,
+        }
+    }
+
+    // e.g. [Ljava/lang/String;
+    pub fn descriptor(&self) -> String {
+        match self {
+            FieldType::Byte => "B".to_string(),
+            FieldType::Char => "C".to_string(),
+            FieldType::Double => "D".to_string(),
+            FieldType::Float => "F".to_string(),
+            FieldType::Int => "I".to_string(),
+            FieldType::Long => "J".to_string(),
+            FieldType::Ref(class) => format!("L{};", class),
+            FieldType::Short => "S".to_string(),
+            FieldType::Bool => "Z".to_string(),
+            FieldType::Array(element) => format!("[{}", element.descriptor()),
+        }
+    }
+}
+
+impl ReturnType {
+    pub fn slots(&self) -> usize {
+        match self {
+            ReturnType::Return(field_type) => field_type.slots(),
+            ReturnType::Void => 0,
+        }
+    }
+
+    pub fn descriptor(&self) -> String {
+        match self {
+            ReturnType::Return(field_type) => field_type.descriptor(),
+            ReturnType::Void => "V".to_string(),
+        }
+    }
+}
+
+fn invalid(descriptor: &str) -> ErrorKind {
+    ErrorKind::InvalidDescriptor(descriptor.to_string())
+}
+
+fn parse_rule(rule: Rule, descriptor: &str) -> Result<Pair<'_, Rule>> {
+    let mut pairs = DescriptorParser::parse(rule, descriptor).map_err(|_| invalid(descriptor))?;
+    Ok(pairs.next().ok_or_else(|| invalid(descriptor))?)
+}
+
+pub fn parse_field_type(descriptor: &str) -> Result<FieldType> {
+    let field_type = parse_field(first_inner(parse_rule(Rule::field_descriptor, descriptor)?));
+    check_dimensions(&field_type, descriptor)?;
+    Ok(field_type)
+}
+
+// The argument types and return type
+pub fn parse_method_descriptor(descriptor: &str) -> Result<(Vec<FieldType>, ReturnType)> {
+    let mut arg_types = vec![];
+    let mut return_type = ReturnType::Void;
+    for pair in parse_rule(Rule::method, descriptor)?.into_inner() {
+        match pair.as_rule() {
+            Rule::field => {
+                let arg_type = parse_field(pair);
+                check_dimensions(&arg_type, descriptor)?;
+                arg_types.push(arg_type);
+            }
+            Rule::result => {
+                if let Some(field) = pair.into_inner().next() {
+                    let field_type = parse_field(field);
+                    check_dimensions(&field_type, descriptor)?;
+                    return_type = ReturnType::Return(field_type);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok((arg_types, return_type))
+}
+
+fn check_dimensions(field_type: &FieldType, descriptor: &str) -> Result<()> {
+    if field_type.dimensions() > MAX_DIMENSIONS {
+        return Err(invalid(descriptor).into());
+    }
+    Ok(())
+}
+
+// The grammar guarantees the structure of each pair, so walking it can't fail
+fn first_inner(pair: Pair<Rule>) -> Pair<Rule> {
+    pair.into_inner().next().unwrap()
+}
+
+fn parse_field(pair: Pair<Rule>) -> FieldType {
+    let inner = first_inner(pair);
+    match inner.as_rule() {
+        Rule::base => match inner.as_str() {
+            "B" => FieldType::Byte,
+            "C" => FieldType::Char,
+            "D" => FieldType::Double,
+            "F" => FieldType::Float,
+            "I" => FieldType::Int,
+            "J" => FieldType::Long,
+            "S" => FieldType::Short,
+            _ => FieldType::Bool,
+        },
+        Rule::reference => FieldType::Ref(first_inner(inner).as_str().to_string()),
+        _ => FieldType::Array(Box::new(parse_field(first_inner(inner)))),
+    }
+}
+
+// Types are displayed as they'd be written in Java source, e.g. java.lang.String[]
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldType::Byte => write!(f, "byte"),
+            FieldType::Char => write!(f, "char"),
+            FieldType::Double => write!(f, "double"),
+            FieldType::Float => write!(f, "float"),
+            FieldType::Int => write!(f, "int"),
+            FieldType::Long => write!(f, "long"),
+            FieldType::Ref(class) => write!(f, "{}", class.replace('/', ".")),
+            FieldType::Short => write!(f, "short"),
+            FieldType::Bool => write!(f, "boolean"),
+            FieldType::Array(element) => write!(f, "{}[]", element),
+        }
+    }
+}
+
+impl fmt::Display for ReturnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReturnType::Return(field_type) => write!(f, "{}", field_type),
+            ReturnType::Void => write!(f, "void"),
+        }
+    }
+}
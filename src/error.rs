@@ -0,0 +1,195 @@
+use std::fmt;
+use std::io;
+
+use crate::constants::ConstantTag;
+
+pub type Result<T> = std::result::Result<T, ClassFormatError>;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    // The class file ended early, or the underlying reader or writer failed
+    Io(io::Error),
+    BadMagic(u32),
+    UnsupportedVersion {
+        major: u16,
+        minor: u16,
+        oldest: u16,
+        newest: u16,
+    },
+    BadConstantTag(u8),
+    BadReferenceKind(u8),
+    InvalidConstantIndex(u16),
+    InvalidUtf8,
+    WrongConstantKind {
+        index: u16,
+        expected: &'static str,
+        found: ConstantTag,
+    },
+    // The attribute's contents didn't match its declared length
+    TruncatedAttribute {
+        name: String,
+        length: u32,
+        read: u64,
+    },
+    InvalidDescriptor(String),
+    InvalidSignature(String),
+    InvalidAccessFlags(u16),
+    // Offsets below are within the method's code array
+    BadOpcode {
+        opcode: u8,
+        offset: usize,
+    },
+    TruncatedInstruction(usize),
+    InvalidInstruction(usize),
+    BadStackMapFrameType(u8),
+    BadVerificationTypeTag(u8),
+    BadElementValueTag(u8),
+    BadTypeAnnotationTarget(u8),
+    BadTypePathKind(u8),
+    // The operand stack underflows or has different depths on different paths to this code offset
+    BadStackDepth(usize),
+    // Only raised when loading classes from a class path
+    ClassNotFound(String),
+    WrongClassName {
+        expected: String,
+        found: String,
+    },
+    // Only raised when writing
+    ConstantPoolFull,
+    TooLong(usize), // A table or array had more entries than its length field can hold
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                write!(f, "unexpected end of class file")
+            }
+            ErrorKind::Io(e) => write!(f, "i/o failed: {}", e),
+            ErrorKind::BadMagic(magic) => write!(f, "bad magic value {:#010x}", magic),
+            ErrorKind::UnsupportedVersion {
+                major,
+                minor,
+                oldest,
+                newest,
+            } => write!(
+                f,
+                "unsupported class file version {}.{}, supported major versions are {} to {}",
+                major, minor, oldest, newest
+            ),
+            ErrorKind::BadConstantTag(tag) => write!(f, "bad constant tag {}", tag),
+            ErrorKind::BadReferenceKind(kind) => write!(f, "bad method handle kind {}", kind),
+            ErrorKind::InvalidConstantIndex(index) => {
+                write!(f, "constant pool index {} is out of range", index)
+            }
+            ErrorKind::InvalidUtf8 => write!(f, "invalid modified utf8 string"),
+            ErrorKind::WrongConstantKind {
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "constant {} should be {} but is {:?}",
+                index, expected, found
+            ),
+            ErrorKind::TruncatedAttribute { name, length, read } => write!(
+                f,
+                "attribute {} declares {} bytes but {} were parsed",
+                name, length, read
+            ),
+            ErrorKind::InvalidDescriptor(descriptor) => {
+                write!(f, "invalid descriptor {:?}", descriptor)
+            }
+            ErrorKind::InvalidSignature(signature) => {
+                write!(f, "invalid generic signature {:?}", signature)
+            }
+            ErrorKind::InvalidAccessFlags(flags) => {
+                write!(f, "invalid access flags {:#06x}", flags)
+            }
+            ErrorKind::BadOpcode { opcode, offset } => {
+                write!(f, "bad opcode {} at code offset {}", opcode, offset)
+            }
+            ErrorKind::TruncatedInstruction(offset) => {
+                write!(f, "truncated instruction at code offset {}", offset)
+            }
+            ErrorKind::InvalidInstruction(offset) => {
+                write!(
+                    f,
+                    "invalid operands for instruction at code offset {}",
+                    offset
+                )
+            }
+            ErrorKind::BadStackMapFrameType(frame_type) => {
+                write!(f, "bad stack map frame type {}", frame_type)
+            }
+            ErrorKind::BadVerificationTypeTag(tag) => {
+                write!(f, "bad verification type tag {}", tag)
+            }
+            ErrorKind::BadElementValueTag(tag) => {
+                write!(f, "bad element value tag {:?}", *tag as char)
+            }
+            ErrorKind::BadTypeAnnotationTarget(target_type) => {
+                write!(f, "bad type annotation target type {:#04x}", target_type)
+            }
+            ErrorKind::BadTypePathKind(kind) => write!(f, "bad type path kind {}", kind),
+            ErrorKind::BadStackDepth(offset) => {
+                write!(f, "inconsistent operand stack at code offset {}", offset)
+            }
+            ErrorKind::ClassNotFound(name) => {
+                write!(f, "class {} not found on the class path", name)
+            }
+            ErrorKind::WrongClassName { expected, found } => {
+                write!(f, "class file for {} declares class {}", expected, found)
+            }
+            ErrorKind::ConstantPoolFull => write!(f, "constant pool is full"),
+            ErrorKind::TooLong(length) => write!(f, "{} entries is too many to write", length),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ClassFormatError {
+    pub kind: ErrorKind,
+    // Byte offset into the class file the error was detected at, if it came from parsing
+    pub offset: Option<u64>,
+    // The structure being parsed, outermost first, e.g. "method #2 > attribute Code"
+    pub structure: String,
+}
+
+impl From<ErrorKind> for ClassFormatError {
+    fn from(kind: ErrorKind) -> Self {
+        ClassFormatError {
+            kind,
+            offset: None,
+            structure: String::new(),
+        }
+    }
+}
+
+impl From<io::Error> for ClassFormatError {
+    fn from(e: io::Error) -> Self {
+        ErrorKind::Io(e).into()
+    }
+}
+
+impl fmt::Display for ClassFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at byte {:#x}", offset)?;
+        }
+        if !self.structure.is_empty() {
+            write!(f, " while parsing {}", self.structure)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ClassFormatError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
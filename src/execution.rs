@@ -1,9 +1,19 @@
+use std::rc::Rc;
+
+use log::{debug, trace};
 use DataType::*;
 
 use crate::class::*;
+use crate::descriptors::FieldType;
+use crate::heap::ObjectRef;
 use crate::instructions::Instruction::*;
-use crate::instructions::*;
+use crate::logging::{INTERPRETER, INVOKE};
 use crate::methods::Method;
+use crate::runtime::{
+    describe_method, JavaResult, Runtime, Throwable, ABSTRACT_METHOD_ERROR,
+    INCOMPATIBLE_CLASS_CHANGE_ERROR, INTERNAL_ERROR, NO_SUCH_METHOD_ERROR, NULL_POINTER_EXCEPTION,
+    UNSATISFIED_LINK_ERROR, VERIFY_ERROR,
+};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum DataType {
@@ -15,34 +25,66 @@ pub enum DataType {
     Char(char),
     Bool(bool),
     ReturnAddress, // TODO work out how to represent this
-    Reference,     // TODO work out how these work
-    Void,          // Used as return value of void methods
-    Placeholder,   // Used for double-width types Long, Double
+    Reference(ObjectRef),
+    Null,
+    Void,        // Used as return value of void methods
+    Placeholder, // Used for double-width types Long, Double
+}
+
+impl DataType {
+    // The value fields of the type start with. Booleans, bytes, chars and shorts are ints until
+    // they're stored in an array.
+    pub fn default_for(field_type: &FieldType) -> DataType {
+        match field_type {
+            FieldType::Long => Long(0),
+            FieldType::Float => Float(0.0),
+            FieldType::Double => Double(0.0),
+            FieldType::Ref(_) | FieldType::Array(_) => Null,
+            _ => Integer(0),
+        }
+    }
+
+    // The object this refers to, or a NullPointerException with the message `action` gives,
+    // e.g. Cannot read field "x". Nothing verifies the value is a reference, so it's a
+    // VerifyError if it isn't.
+    pub fn dereference(self, action: impl FnOnce() -> String) -> JavaResult<ObjectRef> {
+        match self {
+            Reference(object) => Ok(object),
+            Null => Err(Throwable::new(NULL_POINTER_EXCEPTION, action())),
+            value => Err(Throwable::new(
+                VERIFY_ERROR,
+                format!("Expected a reference, found {:?}", value),
+            )),
+        }
+    }
 }
 
 pub struct Frame<'a> {
     // TODO account for that stupid double-wide scheme
     local_variables: Vec<DataType>,
     operand_stack: Vec<DataType>,
+    // Where the classes the method refers to are linked and initialized
+    runtime: &'a Runtime,
     // TODO dynamic linking - reference to constant pool
-    class: &'a Class,
+    class: Rc<Class>,
     ip: usize,
-    code: &'a Vec<Instruction>,
-    method_name: &'a str,
+    method: usize, // Index into class.methods
 }
 
-impl Frame<'_> {
-    pub fn exec(&mut self) -> DataType {
-        println!("Code {:?}", self.code);
-        while self.ip < self.code.len() {
-            let op = self.code.get(self.ip).unwrap();
-            println!("IP {} OP {:?}", self.ip, op);
+impl<'a> Frame<'a> {
+    // The value the method returns, or what it throws
+    pub fn exec(&mut self) -> JavaResult<DataType> {
+        // A handle of our own lets the code stay borrowed while the frame changes
+        let class = Rc::clone(&self.class);
+        let code = class.methods[self.method].get_code().unwrap();
+        trace!(target: INTERPRETER, "Code {:?}", code);
+        while let Some(op) = code.get(self.ip) {
+            trace!(target: INTERPRETER, "IP {} ({}) OP {:?}", self.ip, self.source_position(), op);
             let mut jumped = false;
 
             match op {
                 Nop => {}
-                // TODO implement null references
-                AConstNull => self.operand_stack.push(Reference),
+                AConstNull => self.operand_stack.push(Null),
                 IConstM1 => self.operand_stack.push(Integer(-1)),
                 IConst0 => self.operand_stack.push(Integer(0)),
                 IConst1 => self.operand_stack.push(Integer(1)),
@@ -55,28 +97,79 @@ impl Frame<'_> {
                 FConst0 => self.operand_stack.push(Float(0.0)),
                 FConst1 => self.operand_stack.push(Float(1.0)),
                 FConst2 => self.operand_stack.push(Float(2.0)),
-                LDC => {
-                    let index = self.code[self.ip + 1] as usize;
-                    let constant = self.class.get_constant_value(index).unwrap();
+                BIPush(value) => self.operand_stack.push(Integer(*value as i32)),
+                SIPush(value) => self.operand_stack.push(Integer(*value as i32)),
+                Ldc(index) | LdcW(index) | Ldc2W(index) => {
+                    let constant = self.class.get_constant_value(*index).unwrap();
                     self.operand_stack.push(constant);
                 }
+                ILoad(index) | LLoad(index) | FLoad(index) | DLoad(index) | ALoad(index) => self
+                    .operand_stack
+                    .push(self.local_variables[*index as usize]),
                 ILoad0 => self.operand_stack.push(self.local_variables[0]),
                 ILoad1 => self.operand_stack.push(self.local_variables[1]),
                 ILoad2 => self.operand_stack.push(self.local_variables[2]),
                 ILoad3 => self.operand_stack.push(self.local_variables[3]),
+                LLoad0 => self.operand_stack.push(self.local_variables[0]),
                 LLoad1 => self.operand_stack.push(self.local_variables[1]),
+                LLoad2 => self.operand_stack.push(self.local_variables[2]),
+                LLoad3 => self.operand_stack.push(self.local_variables[3]),
                 ALoad0 => self.operand_stack.push(self.local_variables[0]),
                 ALoad1 => self.operand_stack.push(self.local_variables[1]),
-                IStore1 => {
-                    let value = self.operand_stack.pop().unwrap();
-                    self.store_local(1, value);
-                },
-                Dup => self
-                    .operand_stack
-                    .push(self.operand_stack.last().copied().unwrap()),
+                ALoad2 => self.operand_stack.push(self.local_variables[2]),
+                ALoad3 => self.operand_stack.push(self.local_variables[3]),
+                IStore(index) => {
+                    let value = self.pop()?;
+                    self.store_local(*index as usize, value);
+                }
+                IStore0 | IStore1 | IStore2 | IStore3 => {
+                    let index = match op {
+                        IStore0 => 0,
+                        IStore1 => 1,
+                        IStore2 => 2,
+                        _ => 3,
+                    };
+                    let value = self.pop()?;
+                    self.store_local(index, value);
+                }
+                AStore(index) => {
+                    let value = self.pop()?;
+                    self.store_local(*index as usize, value);
+                }
+                AStore0 | AStore1 | AStore2 | AStore3 => {
+                    let index = match op {
+                        AStore0 => 0,
+                        AStore1 => 1,
+                        AStore2 => 2,
+                        _ => 3,
+                    };
+                    let value = self.pop()?;
+                    self.store_local(index, value);
+                }
+                LStore(index) => {
+                    let value = self.pop()?;
+                    self.store_local(*index as usize, value);
+                }
+                LStore0 | LStore1 | LStore2 | LStore3 => {
+                    let index = match op {
+                        LStore0 => 0,
+                        LStore1 => 1,
+                        LStore2 => 2,
+                        _ => 3,
+                    };
+                    let value = self.pop()?;
+                    self.store_local(index, value);
+                }
+                Pop => {
+                    self.pop()?;
+                }
+                Dup => {
+                    let value = self.pop()?;
+                    self.operand_stack.extend([value, value]);
+                }
                 IAdd => {
-                    let a = self.operand_stack.pop().unwrap();
-                    let b = self.operand_stack.pop().unwrap();
+                    let a = self.pop()?;
+                    let b = self.pop()?;
                     let sum = match (a, b) {
                         (Integer(a_val), Integer(b_val)) => Some(a_val + b_val),
                         _ => None,
@@ -84,113 +177,305 @@ impl Frame<'_> {
                     self.operand_stack.push(Integer(sum.unwrap()));
                 }
                 LAdd => {
-                    let a = self.operand_stack.pop().unwrap();
-                    let b = self.operand_stack.pop().unwrap();
+                    let a = self.pop()?;
+                    let b = self.pop()?;
                     let sum = match (a, b) {
                         (Long(a_val), Long(b_val)) => Some(a_val + b_val),
                         _ => None,
                     };
                     self.operand_stack.push(Long(sum.unwrap()));
                 }
-                IfACmpNeq => {
-                    let branch_byte_1 = self.code[self.ip + 1] as usize;
-                    let branch_byte_2 = self.code[self.ip + 2] as usize;
-                    let target_address_offset = (branch_byte_1 << 8) | branch_byte_2;
-
-                    let a = self.operand_stack.pop().unwrap();
-                    let b = self.operand_stack.pop().unwrap();
+                IfACmpEq(offset) => {
+                    let a = self.pop()?;
+                    let b = self.pop()?;
+                    if a == b {
+                        jumped = true;
+                        self.branch(*offset as i32);
+                    }
+                }
+                IfACmpNe(offset) => {
+                    let a = self.pop()?;
+                    let b = self.pop()?;
                     if a != b {
                         jumped = true;
-                        self.ip += target_address_offset;
+                        self.branch(*offset as i32);
                     }
                 }
-                GOTO => {
-                    let branch_byte_1 = self.code[self.ip + 1] as usize;
-                    let branch_byte_2 = self.code[self.ip + 2] as usize;
-                    let target_address_offset = (branch_byte_1 << 8) | branch_byte_2;
-
+                IfNull(offset) | IfNonNull(offset) => {
+                    let is_null = self.pop()? == Null;
+                    if is_null == matches!(op, IfNull(_)) {
+                        jumped = true;
+                        self.branch(*offset as i32);
+                    }
+                }
+                Goto(offset) => {
+                    jumped = true;
+                    self.branch(*offset as i32);
+                }
+                GotoW(offset) => {
                     jumped = true;
-                    self.ip += target_address_offset;
+                    self.branch(*offset);
                 }
                 IReturn | LReturn | FReturn | DReturn | AReturn => {
                     // TODO implement synchronized
-                    return self.operand_stack.pop().unwrap();
+                    return self.pop();
                 }
                 Return => {
-                    return Void;
+                    return Ok(Void);
                 }
-                InvokeSpecial => {
-                    // TODO handle this complexity later
+                GetStatic(index) => {
+                    let reference = verify(self.class.get_field_ref_from_constant(*index))?;
+                    let value = self.runtime.get_static(&reference)?;
+                    self.operand_stack.push(value);
                 }
-                InvokeStatic => {
-                    let target_byte_1 = self.code[self.ip + 1] as usize;
-                    let target_byte_2 = self.code[self.ip + 2] as usize;
-                    let method_index = (target_byte_1 << 8) | target_byte_2;
-
-                    let mut new_frame =
-                        self.new_frame_for_static_method_from_constant(method_index);
-                    let result = new_frame.exec();
-                    self.operand_stack.push(result);
-                    println!("Continue executing method {}", self.method_name);
+                PutStatic(index) => {
+                    let reference = verify(self.class.get_field_ref_from_constant(*index))?;
+                    let value = self.pop()?;
+                    self.runtime.put_static(&reference, value)?;
+                }
+                GetField(index) => {
+                    let reference = verify(self.class.get_field_ref_from_constant(*index))?;
+                    let object = self.pop()?;
+                    let value = self.runtime.get_field(object, &reference)?;
+                    self.operand_stack.push(value);
+                }
+                PutField(index) => {
+                    let reference = verify(self.class.get_field_ref_from_constant(*index))?;
+                    let value = self.pop()?;
+                    let object = self.pop()?;
+                    self.runtime.put_field(object, &reference, value)?;
+                }
+                New(index) => {
+                    let class_name = verify(self.class.constant_pool.class_name(*index))?;
+                    let object = self.runtime.new_object(&class_name)?;
+                    self.operand_stack.push(object);
+                }
+                InvokeSpecial(method_index) | InvokeStatic(method_index) => {
+                    let mut new_frame = match op {
+                        InvokeSpecial(_) => {
+                            self.new_frame_for_special_method_from_constant(*method_index)?
+                        }
+                        _ => self.new_frame_for_static_method_from_constant(*method_index)?,
+                    };
+                    let result = new_frame.exec()?;
+                    if result != Void {
+                        self.operand_stack.push(result);
+                    }
+                    debug!(target: INVOKE, "Continue executing method {}", self.method().name);
+                }
+                // Skipping it would leave the operand stack wrong for everything after it
+                op => {
+                    return Err(Throwable::new(
+                        INTERNAL_ERROR,
+                        format!(
+                            "Instruction not implemented: {} at offset {} in {}",
+                            op.mnemonic(),
+                            self.ip,
+                            describe_method(&self.class, self.method())
+                        ),
+                    ))
                 }
             };
-            println!("\t↳ STACK {:?}", self.operand_stack);
-            println!("\t↳ LOCALS {:?}", self.local_variables);
+            trace!(target: INTERPRETER, "\t↳ STACK {:?}", self.operand_stack);
+            trace!(target: INTERPRETER, "\t↳ LOCALS [{}]", self.describe_locals());
             if !jumped {
                 // If we jumped, don't need to manually update ip
-                self.ip += op.get_width();
+                self.ip = code.next_offset(self.ip);
             }
         }
-        return Void;
+        Ok(Void)
     }
 
-    fn new_frame_for_static_method_from_constant(&mut self, index: usize) -> Frame {
-        // TODO implement a proper classpath
-        let classes = vec![self.class];
+    fn method(&self) -> &Method {
+        &self.class.methods[self.method]
+    }
 
-        let (class_name, name_and_type) = self.class.get_method_ref_from_constant(index).unwrap();
+    // Where execution is in the source, in the same form as Java stack traces, e.g. Foo.java:42
+    pub fn source_position(&self) -> String {
+        let file = self.class.source_file().unwrap_or("Unknown Source");
+        match self.method().line_number(self.ip) {
+            Some(line) => format!("{}:{}", file, line),
+            None => file.to_string(),
+        }
+    }
 
-        let method_class = classes
+    // Local variables labelled with their names where the class has a LocalVariableTable, and by
+    // slot otherwise
+    fn describe_locals(&self) -> String {
+        self.local_variables
             .iter()
-            .filter(|class| class.name == class_name)
-            .last()
-            .unwrap();
-        let method = method_class.get_method(name_and_type).unwrap();
-
-        let mut args = vec![];
-        for _ in 0..method.num_args() {
-            // TODO test that order is right
-            args.push(self.operand_stack.pop().unwrap());
+            .enumerate()
+            .map(
+                |(slot, value)| match self.method().local_variable(self.ip, slot as u16) {
+                    Some(variable) => format!("{}: {:?}", variable.name, value),
+                    None => format!("{}: {:?}", slot, value),
+                },
+            )
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // Branch offsets are relative to the offset of the branching instruction
+    fn branch(&mut self, offset: i32) {
+        self.ip = (self.ip as i64 + offset as i64) as usize;
+    }
+
+    fn new_frame_for_static_method_from_constant(&mut self, index: u16) -> JavaResult<Frame<'a>> {
+        let reference = verify(self.class.get_method_ref_from_constant(index))?;
+        let (method_class, method_index) = self.runtime.resolve_method(&reference)?;
+        let method = &method_class.class.methods[method_index];
+        if !method.access_flags.is_static {
+            return Err(Throwable::new(
+                INCOMPATIBLE_CLASS_CHANGE_ERROR,
+                format!(
+                    "Expected static method {}",
+                    describe_method(&method_class.class, method)
+                ),
+            ));
         }
+        self.runtime.initialize(&method_class)?;
 
-        load_frame(&method.name, method_class, args)
+        let args = self.pop_args(method.num_args())?;
+        load_frame(
+            self.runtime,
+            Rc::clone(&method_class.class),
+            method_index,
+            args,
+        )
     }
 
+    // Constructors, private methods and methods of superclasses, which are invoked on an object
+    // but without looking up an override in its class
+    fn new_frame_for_special_method_from_constant(&mut self, index: u16) -> JavaResult<Frame<'a>> {
+        let reference = verify(self.class.get_method_ref_from_constant(index))?;
+        let resolved = self.runtime.resolve_method(&reference)?;
+        let (resolved_class, resolved_index) = &resolved;
+        let method = &resolved_class.class.methods[*resolved_index];
+        // Constructors aren't inherited
+        if method.name == "<init>" && resolved_class.name() != reference.class_name {
+            return Err(Throwable::new(
+                NO_SUCH_METHOD_ERROR,
+                format!(
+                    "{}.<init>{}",
+                    reference.class_name.replace('/', "."),
+                    reference.descriptor
+                ),
+            ));
+        }
+        if method.access_flags.is_static {
+            return Err(Throwable::new(
+                INCOMPATIBLE_CLASS_CHANGE_ERROR,
+                format!(
+                    "Expected non-static method {}",
+                    describe_method(&resolved_class.class, method)
+                ),
+            ));
+        }
+
+        let args = self.pop_args(method.num_args() + 1)?;
+        args[0].dereference(|| {
+            format!(
+                "Cannot invoke \"{}\"",
+                describe_method(&resolved_class.class, method)
+            )
+        })?;
+
+        let current = self.runtime.link(&self.class.name)?;
+        let (method_class, method_index) = self.runtime.select_special(&current, resolved);
+        load_frame(
+            self.runtime,
+            Rc::clone(&method_class.class),
+            method_index,
+            args,
+        )
+    }
+
+    // Nothing verifies the code, so the operand stack can be empty when an instruction expects a
+    // value on it
+    fn pop(&mut self) -> JavaResult<DataType> {
+        self.operand_stack.pop().ok_or_else(|| {
+            Throwable::new(
+                VERIFY_ERROR,
+                "Expected a value on the operand stack, found none",
+            )
+        })
+    }
+
+    // The last argument is on top of the stack, above the object for instance methods
+    fn pop_args(&mut self, count: usize) -> JavaResult<Vec<DataType>> {
+        let start = self.operand_stack.len().checked_sub(count).ok_or_else(|| {
+            Throwable::new(
+                VERIFY_ERROR,
+                format!(
+                    "Expected {} arguments on the operand stack, found {}",
+                    count,
+                    self.operand_stack.len()
+                ),
+            )
+        })?;
+        Ok(self.operand_stack.split_off(start))
+    }
+
+    // Longs and doubles also take the next slot, like arguments do
     fn store_local(&mut self, index: usize, value: DataType) {
-        if index >= self.local_variables.len() {
-            self.local_variables.resize(index + 1, Placeholder);
+        let slots = match value {
+            Long(_) | Double(_) => 2,
+            _ => 1,
+        };
+        if index + slots > self.local_variables.len() {
+            self.local_variables.resize(index + slots, Placeholder);
         }
         self.local_variables[index] = value;
+        if slots == 2 {
+            self.local_variables[index + 1] = Placeholder;
+        }
     }
 }
 
-pub fn load_frame<'a>(method: &'a str, class: &'a Class, args: Vec<DataType>) -> Frame<'a> {
-    // Find first method in class of that name that contains code
-    // TODO do better than unwrap
-    println!("Executing method {}", method);
-    let code = class.get_code(method).unwrap();
+// A frame to run the method at the index in the class's methods. Abstract and native methods
+// have no code to run.
+pub fn load_frame<'a>(
+    runtime: &'a Runtime,
+    class: Rc<Class>,
+    index: usize,
+    args: Vec<DataType>,
+) -> JavaResult<Frame<'a>> {
+    let method = &class.methods[index];
+    debug!(target: INVOKE, "Executing method {}", describe_method(&class, method));
+    if method.get_code().is_none() {
+        let error = match method.access_flags.is_native {
+            true => UNSATISFIED_LINK_ERROR,
+            false => ABSTRACT_METHOD_ERROR,
+        };
+        return Err(Throwable::new(error, describe_method(&class, method)));
+    }
 
-    let locals = Vec::from(args);
-    // TODO first item should be current object for instance invocation
-    // Only for instance methods! - But why are we storing in position 1 then?
-    // locals.push_left();
+    // Arguments are passed in the first local variables, after the object for instance methods,
+    // with longs and doubles taking two
+    let mut args = args.into_iter();
+    let mut locals = Vec::with_capacity(method.arg_slots() + 1);
+    if !method.access_flags.is_static {
+        locals.extend(args.next());
+    }
+    for (arg, arg_type) in args.zip(&method.arg_types) {
+        locals.push(arg);
+        if arg_type.slots() == 2 {
+            locals.push(Placeholder);
+        }
+    }
 
-    Frame {
+    Ok(Frame {
         local_variables: locals,
         operand_stack: vec![],
+        runtime,
         class,
         ip: 0,
-        code,
-        method_name: method,
-    }
+        method: index,
+    })
+}
+
+// Constant pool entries are only checked once an instruction uses them, so one of the wrong kind
+// throws the VerifyError the verifier would have
+fn verify<T>(result: crate::error::Result<T>) -> JavaResult<T> {
+    result.map_err(|error| Throwable::new(VERIFY_ERROR, error.to_string()))
 }
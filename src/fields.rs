@@ -1,97 +1,181 @@
-use std::io::{Read, Result};
+use std::io::Write;
 
 use bitflags::*;
 
+use crate::access_flags::reserved_bits;
+use crate::annotations::Annotation;
 use crate::attributes::*;
 use crate::constants::*;
+use crate::descriptors::{parse_field_type, FieldType};
+use crate::error::{ErrorKind, Result};
 use crate::fields::Visibility::*;
 use crate::read::*;
+use crate::signatures::*;
+use crate::write::*;
 
-#[derive(Debug)]
-enum Visibility {
+#[derive(Debug, Default)]
+pub enum Visibility {
     Private,
     Protected,
+    #[default]
+    Package,
     Public,
 }
 
 bitflags! {
     struct AccessFlagsBits: u16 {
-        const Public = 0x0001;
-        const Private = 0x0002;
-        const Protected = 0x0004;
-        const Static = 0x0008;
-        const Final = 0x0010;
-        const Volatile = 0x0040;
-        const Transient = 0x0080;
-        const Synthetic = 0x1000;
-        const Enum = 0x4000;
+        const PUBLIC = 0x0001;
+        const PRIVATE = 0x0002;
+        const PROTECTED = 0x0004;
+        const STATIC = 0x0008;
+        const FINAL = 0x0010;
+        const VOLATILE = 0x0040;
+        const TRANSIENT = 0x0080;
+        const SYNTHETIC = 0x1000;
+        const ENUM = 0x4000;
     }
 }
 
-#[derive(Debug)]
-struct AccessFlags {
-    visibility: Visibility,
-    is_static: bool,
-    is_final: bool,
-    is_volatile: bool, // Probably doesn't matter since we aren't caching anyway
-    is_transient: bool,
-    is_synthetic: bool,
-    is_enum: bool,
+#[derive(Debug, Default)]
+pub struct AccessFlags {
+    pub visibility: Visibility,
+    pub is_static: bool,
+    pub is_final: bool,
+    pub is_volatile: bool, // Probably doesn't matter since we aren't caching anyway
+    pub is_transient: bool,
+    pub is_synthetic: bool,
+    pub is_enum: bool,
+    pub reserved: u16, // Unassigned bits, see access_flags::reserved_bits
+}
+
+impl AccessFlags {
+    pub fn bits(&self) -> u16 {
+        let mut flags = AccessFlagsBits::empty();
+        flags.set(AccessFlagsBits::PUBLIC, matches!(self.visibility, Public));
+        flags.set(AccessFlagsBits::PRIVATE, matches!(self.visibility, Private));
+        flags.set(
+            AccessFlagsBits::PROTECTED,
+            matches!(self.visibility, Protected),
+        );
+        flags.set(AccessFlagsBits::STATIC, self.is_static);
+        flags.set(AccessFlagsBits::FINAL, self.is_final);
+        flags.set(AccessFlagsBits::VOLATILE, self.is_volatile);
+        flags.set(AccessFlagsBits::TRANSIENT, self.is_transient);
+        flags.set(AccessFlagsBits::SYNTHETIC, self.is_synthetic);
+        flags.set(AccessFlagsBits::ENUM, self.is_enum);
+        flags.bits() | self.reserved
+    }
 }
 
 #[derive(Debug)]
 pub struct Field {
-    access_flags: AccessFlags,
-    name: String,
-    descriptor: String,
-    attributes: Vec<Attribute>,
+    pub access_flags: AccessFlags,
+    pub name: String,
+    pub descriptor: String,
+    // The Utf8 constants they were read from, which hold them exactly
+    pub name_index: u16,
+    pub descriptor_index: u16,
+    pub field_type: FieldType,
+    pub attributes: Vec<Attribute>,
+}
+
+impl Field {
+    // None if the field's type isn't generic
+    pub fn signature(&self) -> Option<Result<FieldSignature>> {
+        find_signature(&self.attributes).map(parse_field_signature)
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        is_deprecated(&self.attributes)
+    }
+
+    pub fn annotations(&self) -> Vec<&Annotation> {
+        find_annotations(&self.attributes)
+    }
 }
 
 fn parse_access_flags(mask: u16) -> Result<AccessFlags> {
-    let flags = AccessFlagsBits::from_bits(mask).unwrap();
+    let flags = AccessFlagsBits::from_bits_truncate(mask);
 
-    let visibility = if flags.contains(AccessFlagsBits::Private) {
+    let visibility_flags =
+        flags & (AccessFlagsBits::PUBLIC | AccessFlagsBits::PRIVATE | AccessFlagsBits::PROTECTED);
+    if visibility_flags.bits().count_ones() > 1
+        || flags.contains(AccessFlagsBits::FINAL | AccessFlagsBits::VOLATILE)
+    {
+        return Err(ErrorKind::InvalidAccessFlags(mask).into());
+    }
+
+    let visibility = if flags.contains(AccessFlagsBits::PRIVATE) {
         Private
-    } else if flags.contains(AccessFlagsBits::Protected) {
+    } else if flags.contains(AccessFlagsBits::PROTECTED) {
         Protected
-    } else if flags.contains(AccessFlagsBits::Public) {
+    } else if flags.contains(AccessFlagsBits::PUBLIC) {
         Public
     } else {
-        // Default to public
-        // TODO make this an error at some point
-        Public
+        Package
     };
 
     Ok(AccessFlags {
         visibility,
-        is_static: flags.contains(AccessFlagsBits::Static),
-        is_final: flags.contains(AccessFlagsBits::Final),
-        is_volatile: flags.contains(AccessFlagsBits::Volatile),
-        is_transient: flags.contains(AccessFlagsBits::Transient),
-        is_synthetic: flags.contains(AccessFlagsBits::Synthetic),
-        is_enum: flags.contains(AccessFlagsBits::Enum),
+        is_static: flags.contains(AccessFlagsBits::STATIC),
+        is_final: flags.contains(AccessFlagsBits::FINAL),
+        is_volatile: flags.contains(AccessFlagsBits::VOLATILE),
+        is_transient: flags.contains(AccessFlagsBits::TRANSIENT),
+        is_synthetic: flags.contains(AccessFlagsBits::SYNTHETIC),
+        is_enum: flags.contains(AccessFlagsBits::ENUM),
+        reserved: reserved_bits(mask, AccessFlagsBits::all().bits()),
     })
 }
 
-pub fn parse_fields(reader: &mut dyn Read, constant_pool: &Vec<Constant>) -> Result<Vec<Field>> {
+pub fn parse_fields(reader: &mut ClassReader, constant_pool: &ConstantPool) -> Result<Vec<Field>> {
     let mut fields = Vec::new();
     let fields_count = read_u2(reader)?;
 
-    // TODO come back to this when I'm testing a class file with fields
-    for _ in 0..fields_count {
-        let access_flags = parse_access_flags(read_u2(reader)?)?;
+    for i in 0..fields_count {
+        let field = reader.within(format!("field #{}", i), |reader| {
+            let access_flags = parse_access_flags(read_u2(reader)?)?;
 
-        let name = resolve_utf8(read_u2(reader)? as usize, constant_pool).unwrap();
+            let name_index = read_u2(reader)?;
+            let name = constant_pool.utf8(name_index)?;
 
-        let descriptor = resolve_utf8(read_u2(reader)? as usize, constant_pool).unwrap();
+            let descriptor_index = read_u2(reader)?;
+            let descriptor = constant_pool.utf8(descriptor_index)?;
 
-        fields.push(Field {
-            access_flags,
-            name,
-            descriptor,
-            attributes: parse_attributes(reader, constant_pool)?,
-        });
+            let field_type = parse_field_type(&descriptor)?;
+
+            Ok(Field {
+                access_flags,
+                name,
+                descriptor,
+                name_index,
+                descriptor_index,
+                field_type,
+                attributes: parse_attributes(reader, constant_pool)?,
+            })
+        })?;
+        fields.push(field);
     }
 
     Ok(fields)
 }
+
+pub fn write_fields(
+    fields: &[Field],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_count(fields.len(), writer)?;
+    for field in fields {
+        write_u2(field.access_flags.bits(), writer)?;
+        write_u2(
+            constant_pool.add_utf8_from(field.name_index, &field.name)?,
+            writer,
+        )?;
+        write_u2(
+            constant_pool.add_utf8_from(field.descriptor_index, &field.descriptor)?,
+            writer,
+        )?;
+        write_attributes(&field.attributes, writer, constant_pool)?;
+    }
+    Ok(())
+}
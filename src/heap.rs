@@ -0,0 +1,56 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::execution::DataType;
+use crate::runtime::RuntimeClass;
+
+// Where the objects a program creates live. Objects are never freed, as there's no garbage
+// collector yet.
+#[derive(Default)]
+pub struct Heap {
+    objects: RefCell<Vec<Object>>,
+}
+
+// Refers to an object on the heap. Null references are DataType::Null instead.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ObjectRef(usize);
+
+pub struct Object {
+    pub class: Rc<RuntimeClass>,
+    // A value for each of the class's instance fields, in the same order
+    fields: Vec<DataType>,
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap::default()
+    }
+
+    // A new object of the class, with every field set to its default value
+    pub fn allocate(&self, class: &Rc<RuntimeClass>) -> ObjectRef {
+        let fields = class
+            .instance_fields()
+            .iter()
+            .map(|field| field.default_value)
+            .collect();
+        let mut objects = self.objects.borrow_mut();
+        objects.push(Object {
+            class: Rc::clone(class),
+            fields,
+        });
+        ObjectRef(objects.len() - 1)
+    }
+
+    pub fn class_of(&self, object: ObjectRef) -> Rc<RuntimeClass> {
+        Rc::clone(&self.objects.borrow()[object.0].class)
+    }
+
+    // `slot` is an index into the object's class's instance fields
+    pub fn get_field(&self, object: ObjectRef, slot: usize) -> DataType {
+        self.objects.borrow()[object.0].fields[slot]
+    }
+
+    pub fn put_field(&self, object: ObjectRef, slot: usize, value: DataType) {
+        self.objects.borrow_mut()[object.0].fields[slot] = value;
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::Write;
+
+use bitflags::*;
+
+use crate::access_flags::reserved_bits;
+use crate::constants::*;
+use crate::error::Result;
+use crate::inner_classes::Visibility::*;
+use crate::read::*;
+use crate::write::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Visibility {
+    Private,
+    Protected,
+    Package,
+    Public,
+}
+
+bitflags! {
+    struct AccessFlagsBits: u16 {
+        const PUBLIC = 0x0001;
+        const PRIVATE = 0x0002;
+        const PROTECTED = 0x0004;
+        const STATIC = 0x0008;
+        const FINAL = 0x0010;
+        const INTERFACE = 0x0200;
+        const ABSTRACT = 0x0400;
+        const SYNTHETIC = 0x1000;
+        const ANNOTATION = 0x2000;
+        const ENUM = 0x4000;
+    }
+}
+
+// The flags the class was declared with in source, which the class's own access flags can't
+// express, e.g. private or static
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessFlags {
+    pub visibility: Visibility,
+    pub is_static: bool,
+    pub is_final: bool,
+    pub is_interface: bool,
+    pub is_abstract: bool,
+    pub is_synthetic: bool,
+    pub is_annotation: bool,
+    pub is_enum: bool,
+    pub reserved: u16, // Unassigned bits, see access_flags::reserved_bits
+}
+
+impl AccessFlags {
+    pub fn bits(&self) -> u16 {
+        let mut flags = AccessFlagsBits::empty();
+        flags.set(AccessFlagsBits::PUBLIC, self.visibility == Public);
+        flags.set(AccessFlagsBits::PRIVATE, self.visibility == Private);
+        flags.set(AccessFlagsBits::PROTECTED, self.visibility == Protected);
+        flags.set(AccessFlagsBits::STATIC, self.is_static);
+        flags.set(AccessFlagsBits::FINAL, self.is_final);
+        flags.set(AccessFlagsBits::INTERFACE, self.is_interface);
+        flags.set(AccessFlagsBits::ABSTRACT, self.is_abstract);
+        flags.set(AccessFlagsBits::SYNTHETIC, self.is_synthetic);
+        flags.set(AccessFlagsBits::ANNOTATION, self.is_annotation);
+        flags.set(AccessFlagsBits::ENUM, self.is_enum);
+        flags.bits() | self.reserved
+    }
+}
+
+// Not checked for consistency, since the JVM doesn't look at these flags when loading a class
+fn parse_access_flags(mask: u16) -> AccessFlags {
+    let flags = AccessFlagsBits::from_bits_truncate(mask);
+
+    let visibility = if flags.contains(AccessFlagsBits::PRIVATE) {
+        Private
+    } else if flags.contains(AccessFlagsBits::PROTECTED) {
+        Protected
+    } else if flags.contains(AccessFlagsBits::PUBLIC) {
+        Public
+    } else {
+        Package
+    };
+
+    AccessFlags {
+        visibility,
+        is_static: flags.contains(AccessFlagsBits::STATIC),
+        is_final: flags.contains(AccessFlagsBits::FINAL),
+        is_interface: flags.contains(AccessFlagsBits::INTERFACE),
+        is_abstract: flags.contains(AccessFlagsBits::ABSTRACT),
+        is_synthetic: flags.contains(AccessFlagsBits::SYNTHETIC),
+        is_annotation: flags.contains(AccessFlagsBits::ANNOTATION),
+        is_enum: flags.contains(AccessFlagsBits::ENUM),
+        reserved: reserved_bits(mask, AccessFlagsBits::all().bits()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InnerClass {
+    pub inner_class: String,
+    pub outer_class: Option<String>, // None for local and anonymous classes
+    pub inner_name: Option<String>,  // None for anonymous classes
+    pub access_flags: AccessFlags,
+}
+
+pub fn parse_inner_classes(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<Vec<InnerClass>> {
+    let number_of_classes = read_u2(reader)?;
+    let mut classes = Vec::with_capacity(number_of_classes as usize);
+    for i in 0..number_of_classes {
+        classes.push(reader.within(format!("inner class #{}", i), |reader| {
+            let inner_class = constant_pool.class_name(read_u2(reader)?)?;
+            let outer_class = match read_u2(reader)? {
+                0 => None,
+                index => Some(constant_pool.class_name(index)?),
+            };
+            let inner_name = match read_u2(reader)? {
+                0 => None,
+                index => Some(constant_pool.utf8(index)?),
+            };
+            Ok(InnerClass {
+                inner_class,
+                outer_class,
+                inner_name,
+                access_flags: parse_access_flags(read_u2(reader)?),
+            })
+        })?);
+    }
+    Ok(classes)
+}
+
+pub fn write_inner_classes(
+    classes: &[InnerClass],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_count(classes.len(), writer)?;
+    for class in classes {
+        write_u2(constant_pool.add_class(&class.inner_class)?, writer)?;
+        let outer_class = match &class.outer_class {
+            Some(outer_class) => constant_pool.add_class(outer_class)?,
+            None => 0,
+        };
+        write_u2(outer_class, writer)?;
+        let inner_name = match &class.inner_name {
+            Some(inner_name) => constant_pool.add_utf8(inner_name.as_str())?,
+            None => 0,
+        };
+        write_u2(inner_name, writer)?;
+        write_u2(class.access_flags.bits(), writer)?;
+    }
+    Ok(())
+}
@@ -2,78 +2,1384 @@ use std::convert::TryFrom;
 
 use num_enum::TryFromPrimitive;
 
+use crate::error::{ErrorKind, Result};
+
+// Element type operand of newarray
 #[derive(Debug, Eq, PartialEq, TryFromPrimitive, Clone, Copy)]
 #[repr(u8)]
+pub enum ArrayType {
+    Boolean = 4,
+    Char = 5,
+    Float = 6,
+    Double = 7,
+    Byte = 8,
+    Short = 9,
+    Int = 10,
+    Long = 11,
+}
+
+// Operands are stored as they appear in the class file: local variable and constant pool indices
+// are unsigned, branch offsets are signed and relative to the offset of the branching instruction.
+// Instructions modified by `wide` decode to the same variant as their narrow form.
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Instruction {
-    Nop = 0,
-    AConstNull = 1,
-    IConstM1 = 2,
-    IConst0 = 3,
-    IConst1 = 4,
-    IConst2 = 5,
-    IConst3 = 6,
-    IConst4 = 7,
-    IConst5 = 8,
-    LConst0 = 9,
-    LConst1 = 10,
-    FConst0 = 11,
-    FConst1 = 12,
-    FConst2 = 13,
-    LDC = 18,
-    ILoad0 = 26,
-    ILoad1 = 27,
-    ILoad2 = 28,
-    ILoad3 = 29,
-    LLoad1 = 31,
-    ALoad0 = 42,
-    ALoad1 = 43,
-    IStore1 = 60,
-    Dup = 89,
-    IAdd = 96,
-    LAdd = 97,
-    IfACmpNeq = 166,
-    GOTO = 167,
-    IReturn = 172,
-    LReturn = 173,
-    FReturn = 174,
-    DReturn = 175,
-    AReturn = 176,
-    Return = 177,
-    InvokeSpecial = 183,
-    InvokeStatic = 184,
+    Nop,
+    AConstNull,
+    IConstM1,
+    IConst0,
+    IConst1,
+    IConst2,
+    IConst3,
+    IConst4,
+    IConst5,
+    LConst0,
+    LConst1,
+    FConst0,
+    FConst1,
+    FConst2,
+    DConst0,
+    DConst1,
+    BIPush(i8),
+    SIPush(i16),
+    Ldc(u16),
+    LdcW(u16),
+    Ldc2W(u16),
+    ILoad(u16),
+    LLoad(u16),
+    FLoad(u16),
+    DLoad(u16),
+    ALoad(u16),
+    ILoad0,
+    ILoad1,
+    ILoad2,
+    ILoad3,
+    LLoad0,
+    LLoad1,
+    LLoad2,
+    LLoad3,
+    FLoad0,
+    FLoad1,
+    FLoad2,
+    FLoad3,
+    DLoad0,
+    DLoad1,
+    DLoad2,
+    DLoad3,
+    ALoad0,
+    ALoad1,
+    ALoad2,
+    ALoad3,
+    IALoad,
+    LALoad,
+    FALoad,
+    DALoad,
+    AALoad,
+    BALoad,
+    CALoad,
+    SALoad,
+    IStore(u16),
+    LStore(u16),
+    FStore(u16),
+    DStore(u16),
+    AStore(u16),
+    IStore0,
+    IStore1,
+    IStore2,
+    IStore3,
+    LStore0,
+    LStore1,
+    LStore2,
+    LStore3,
+    FStore0,
+    FStore1,
+    FStore2,
+    FStore3,
+    DStore0,
+    DStore1,
+    DStore2,
+    DStore3,
+    AStore0,
+    AStore1,
+    AStore2,
+    AStore3,
+    IAStore,
+    LAStore,
+    FAStore,
+    DAStore,
+    AAStore,
+    BAStore,
+    CAStore,
+    SAStore,
+    Pop,
+    Pop2,
+    Dup,
+    DupX1,
+    DupX2,
+    Dup2,
+    Dup2X1,
+    Dup2X2,
+    Swap,
+    IAdd,
+    LAdd,
+    FAdd,
+    DAdd,
+    ISub,
+    LSub,
+    FSub,
+    DSub,
+    IMul,
+    LMul,
+    FMul,
+    DMul,
+    IDiv,
+    LDiv,
+    FDiv,
+    DDiv,
+    IRem,
+    LRem,
+    FRem,
+    DRem,
+    INeg,
+    LNeg,
+    FNeg,
+    DNeg,
+    IShl,
+    LShl,
+    IShr,
+    LShr,
+    IUShr,
+    LUShr,
+    IAnd,
+    LAnd,
+    IOr,
+    LOr,
+    IXor,
+    LXor,
+    IInc {
+        index: u16,
+        value: i16,
+    },
+    I2L,
+    I2F,
+    I2D,
+    L2I,
+    L2F,
+    L2D,
+    F2I,
+    F2L,
+    F2D,
+    D2I,
+    D2L,
+    D2F,
+    I2B,
+    I2C,
+    I2S,
+    LCmp,
+    FCmpL,
+    FCmpG,
+    DCmpL,
+    DCmpG,
+    IfEq(i16),
+    IfNe(i16),
+    IfLt(i16),
+    IfGe(i16),
+    IfGt(i16),
+    IfLe(i16),
+    IfICmpEq(i16),
+    IfICmpNe(i16),
+    IfICmpLt(i16),
+    IfICmpGe(i16),
+    IfICmpGt(i16),
+    IfICmpLe(i16),
+    IfACmpEq(i16),
+    IfACmpNe(i16),
+    Goto(i16),
+    Jsr(i16),
+    Ret(u16),
+    TableSwitch {
+        default: i32,
+        low: i32,
+        offsets: Vec<i32>, // Jump offsets for low, low + 1, ..., high
+    },
+    LookupSwitch {
+        default: i32,
+        pairs: Vec<(i32, i32)>, // (match, offset), sorted by match
+    },
+    IReturn,
+    LReturn,
+    FReturn,
+    DReturn,
+    AReturn,
+    Return,
+    GetStatic(u16),
+    PutStatic(u16),
+    GetField(u16),
+    PutField(u16),
+    InvokeVirtual(u16),
+    InvokeSpecial(u16),
+    InvokeStatic(u16),
+    InvokeInterface {
+        index: u16,
+        count: u8,
+    },
+    InvokeDynamic(u16),
+    New(u16),
+    NewArray(ArrayType),
+    ANewArray(u16),
+    ArrayLength,
+    AThrow,
+    CheckCast(u16),
+    InstanceOf(u16),
+    MonitorEnter,
+    MonitorExit,
+    MultiANewArray {
+        index: u16,
+        dimensions: u8,
+    },
+    IfNull(i16),
+    IfNonNull(i16),
+    GotoW(i32),
+    JsrW(i32),
 }
 
 impl Instruction {
-    pub fn get_width(&self) -> usize {
+    // The name used for the opcode in the JVM spec and by javap, e.g. invokevirtual
+    pub fn mnemonic(&self) -> &'static str {
+        use Instruction::*;
+
         match self {
-            Instruction::LDC => 2,       // Unsigned byte arg
-            Instruction::IfACmpNeq => 3, // 2 reference args
-            Instruction::InvokeSpecial | Instruction::InvokeStatic => 3, // 2 byte args
-            _ => 1,
+            Nop => "nop",
+            AConstNull => "aconst_null",
+            IConstM1 => "iconst_m1",
+            IConst0 => "iconst_0",
+            IConst1 => "iconst_1",
+            IConst2 => "iconst_2",
+            IConst3 => "iconst_3",
+            IConst4 => "iconst_4",
+            IConst5 => "iconst_5",
+            LConst0 => "lconst_0",
+            LConst1 => "lconst_1",
+            FConst0 => "fconst_0",
+            FConst1 => "fconst_1",
+            FConst2 => "fconst_2",
+            DConst0 => "dconst_0",
+            DConst1 => "dconst_1",
+            BIPush(_) => "bipush",
+            SIPush(_) => "sipush",
+            Ldc(_) => "ldc",
+            LdcW(_) => "ldc_w",
+            Ldc2W(_) => "ldc2_w",
+            ILoad(_) => "iload",
+            LLoad(_) => "lload",
+            FLoad(_) => "fload",
+            DLoad(_) => "dload",
+            ALoad(_) => "aload",
+            ILoad0 => "iload_0",
+            ILoad1 => "iload_1",
+            ILoad2 => "iload_2",
+            ILoad3 => "iload_3",
+            LLoad0 => "lload_0",
+            LLoad1 => "lload_1",
+            LLoad2 => "lload_2",
+            LLoad3 => "lload_3",
+            FLoad0 => "fload_0",
+            FLoad1 => "fload_1",
+            FLoad2 => "fload_2",
+            FLoad3 => "fload_3",
+            DLoad0 => "dload_0",
+            DLoad1 => "dload_1",
+            DLoad2 => "dload_2",
+            DLoad3 => "dload_3",
+            ALoad0 => "aload_0",
+            ALoad1 => "aload_1",
+            ALoad2 => "aload_2",
+            ALoad3 => "aload_3",
+            IALoad => "iaload",
+            LALoad => "laload",
+            FALoad => "faload",
+            DALoad => "daload",
+            AALoad => "aaload",
+            BALoad => "baload",
+            CALoad => "caload",
+            SALoad => "saload",
+            IStore(_) => "istore",
+            LStore(_) => "lstore",
+            FStore(_) => "fstore",
+            DStore(_) => "dstore",
+            AStore(_) => "astore",
+            IStore0 => "istore_0",
+            IStore1 => "istore_1",
+            IStore2 => "istore_2",
+            IStore3 => "istore_3",
+            LStore0 => "lstore_0",
+            LStore1 => "lstore_1",
+            LStore2 => "lstore_2",
+            LStore3 => "lstore_3",
+            FStore0 => "fstore_0",
+            FStore1 => "fstore_1",
+            FStore2 => "fstore_2",
+            FStore3 => "fstore_3",
+            DStore0 => "dstore_0",
+            DStore1 => "dstore_1",
+            DStore2 => "dstore_2",
+            DStore3 => "dstore_3",
+            AStore0 => "astore_0",
+            AStore1 => "astore_1",
+            AStore2 => "astore_2",
+            AStore3 => "astore_3",
+            IAStore => "iastore",
+            LAStore => "lastore",
+            FAStore => "fastore",
+            DAStore => "dastore",
+            AAStore => "aastore",
+            BAStore => "bastore",
+            CAStore => "castore",
+            SAStore => "sastore",
+            Pop => "pop",
+            Pop2 => "pop2",
+            Dup => "dup",
+            DupX1 => "dup_x1",
+            DupX2 => "dup_x2",
+            Dup2 => "dup2",
+            Dup2X1 => "dup2_x1",
+            Dup2X2 => "dup2_x2",
+            Swap => "swap",
+            IAdd => "iadd",
+            LAdd => "ladd",
+            FAdd => "fadd",
+            DAdd => "dadd",
+            ISub => "isub",
+            LSub => "lsub",
+            FSub => "fsub",
+            DSub => "dsub",
+            IMul => "imul",
+            LMul => "lmul",
+            FMul => "fmul",
+            DMul => "dmul",
+            IDiv => "idiv",
+            LDiv => "ldiv",
+            FDiv => "fdiv",
+            DDiv => "ddiv",
+            IRem => "irem",
+            LRem => "lrem",
+            FRem => "frem",
+            DRem => "drem",
+            INeg => "ineg",
+            LNeg => "lneg",
+            FNeg => "fneg",
+            DNeg => "dneg",
+            IShl => "ishl",
+            LShl => "lshl",
+            IShr => "ishr",
+            LShr => "lshr",
+            IUShr => "iushr",
+            LUShr => "lushr",
+            IAnd => "iand",
+            LAnd => "land",
+            IOr => "ior",
+            LOr => "lor",
+            IXor => "ixor",
+            LXor => "lxor",
+            IInc { .. } => "iinc",
+            I2L => "i2l",
+            I2F => "i2f",
+            I2D => "i2d",
+            L2I => "l2i",
+            L2F => "l2f",
+            L2D => "l2d",
+            F2I => "f2i",
+            F2L => "f2l",
+            F2D => "f2d",
+            D2I => "d2i",
+            D2L => "d2l",
+            D2F => "d2f",
+            I2B => "i2b",
+            I2C => "i2c",
+            I2S => "i2s",
+            LCmp => "lcmp",
+            FCmpL => "fcmpl",
+            FCmpG => "fcmpg",
+            DCmpL => "dcmpl",
+            DCmpG => "dcmpg",
+            IfEq(_) => "ifeq",
+            IfNe(_) => "ifne",
+            IfLt(_) => "iflt",
+            IfGe(_) => "ifge",
+            IfGt(_) => "ifgt",
+            IfLe(_) => "ifle",
+            IfICmpEq(_) => "if_icmpeq",
+            IfICmpNe(_) => "if_icmpne",
+            IfICmpLt(_) => "if_icmplt",
+            IfICmpGe(_) => "if_icmpge",
+            IfICmpGt(_) => "if_icmpgt",
+            IfICmpLe(_) => "if_icmple",
+            IfACmpEq(_) => "if_acmpeq",
+            IfACmpNe(_) => "if_acmpne",
+            Goto(_) => "goto",
+            Jsr(_) => "jsr",
+            Ret(_) => "ret",
+            TableSwitch { .. } => "tableswitch",
+            LookupSwitch { .. } => "lookupswitch",
+            IReturn => "ireturn",
+            LReturn => "lreturn",
+            FReturn => "freturn",
+            DReturn => "dreturn",
+            AReturn => "areturn",
+            Return => "return",
+            GetStatic(_) => "getstatic",
+            PutStatic(_) => "putstatic",
+            GetField(_) => "getfield",
+            PutField(_) => "putfield",
+            InvokeVirtual(_) => "invokevirtual",
+            InvokeSpecial(_) => "invokespecial",
+            InvokeStatic(_) => "invokestatic",
+            InvokeInterface { .. } => "invokeinterface",
+            InvokeDynamic(_) => "invokedynamic",
+            New(_) => "new",
+            NewArray(_) => "newarray",
+            ANewArray(_) => "anewarray",
+            ArrayLength => "arraylength",
+            AThrow => "athrow",
+            CheckCast(_) => "checkcast",
+            InstanceOf(_) => "instanceof",
+            MonitorEnter => "monitorenter",
+            MonitorExit => "monitorexit",
+            MultiANewArray { .. } => "multianewarray",
+            IfNull(_) => "ifnull",
+            IfNonNull(_) => "ifnonnull",
+            GotoW(_) => "goto_w",
+            JsrW(_) => "jsr_w",
+        }
+    }
+}
+
+// A method's decoded code, with each instruction keyed by its offset in the code array so branch
+// targets and exception table ranges can be looked up directly
+#[derive(Debug, Default)]
+pub struct Bytecode {
+    instructions: Vec<(usize, Instruction)>,
+    length: usize,
+}
+
+impl Bytecode {
+    pub fn get(&self, offset: usize) -> Option<&Instruction> {
+        self.instructions
+            .binary_search_by_key(&offset, |(o, _)| *o)
+            .ok()
+            .map(|i| &self.instructions[i].1)
+    }
+
+    // Offset of the instruction following the one at `offset`, or the code length if it's last
+    pub fn next_offset(&self, offset: usize) -> usize {
+        let i = match self.instructions.binary_search_by_key(&offset, |(o, _)| *o) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        self.instructions
+            .get(i)
+            .map(|(o, _)| *o)
+            .unwrap_or(self.length)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Instruction)> {
+        self.instructions.iter().map(|(o, i)| (*o, i))
+    }
+
+    // Length of the code array in bytes
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    // Lays the instructions out one after another, using wide forms only where operands need them.
+    // Branch offsets are kept as they are.
+    pub fn new(instructions: Vec<Instruction>) -> Result<Bytecode> {
+        let mut laid_out = Vec::with_capacity(instructions.len());
+        let mut offset = 0;
+        let mut encoded = Vec::new();
+        for instruction in instructions {
+            encoded.clear();
+            write_instruction(&instruction, offset, None, &mut encoded)?;
+            laid_out.push((offset, instruction));
+            offset += encoded.len();
         }
+        Ok(Bytecode {
+            instructions: laid_out,
+            length: offset,
+        })
     }
 }
 
-// TODO use Result
-pub fn parse_code(block: Vec<u8>) -> Option<Vec<Instruction>> {
-    let mut any_not_implemented = false;
-    for &op in block.iter() {
-        match Instruction::try_from(op) {
-            Err(_) => {
-                println!("op {}", op);
-                any_not_implemented = true;
+// Cursor over the code array, tracking the offset of the instruction being decoded for errors
+struct CodeReader<'a> {
+    code: &'a [u8],
+    position: usize,
+    start: usize,
+}
+
+impl CodeReader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let bytes = self
+            .code
+            .get(self.position..self.position + N)
+            .ok_or(ErrorKind::TruncatedInstruction(self.start))?;
+        self.position += N;
+        Ok(<[u8; N]>::try_from(bytes).unwrap())
+    }
+
+    fn u1(&mut self) -> Result<u8> {
+        Ok(u8::from_be_bytes(self.bytes()?))
+    }
+
+    fn u2(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes()?))
+    }
+
+    fn i1(&mut self) -> Result<i8> {
+        Ok(i8::from_be_bytes(self.bytes()?))
+    }
+
+    fn i2(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.bytes()?))
+    }
+
+    fn i4(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.bytes()?))
+    }
+
+    fn switch_count(&self, count: i64) -> Result<usize> {
+        // Each entry takes at least 4 bytes, so anything larger than the code can't be valid
+        if count < 0 || count as usize > self.code.len() / 4 {
+            return Err(ErrorKind::InvalidInstruction(self.start).into());
+        }
+        Ok(count as usize)
+    }
+}
+
+pub fn parse_code(block: &[u8]) -> Result<Bytecode> {
+    use Instruction::*;
+
+    let mut instructions = Vec::new();
+    let reader = &mut CodeReader {
+        code: block,
+        position: 0,
+        start: 0,
+    };
+
+    while reader.position < block.len() {
+        reader.start = reader.position;
+        let opcode = reader.u1()?;
+        let instruction = match opcode {
+            0 => Nop,
+            1 => AConstNull,
+            2 => IConstM1,
+            3 => IConst0,
+            4 => IConst1,
+            5 => IConst2,
+            6 => IConst3,
+            7 => IConst4,
+            8 => IConst5,
+            9 => LConst0,
+            10 => LConst1,
+            11 => FConst0,
+            12 => FConst1,
+            13 => FConst2,
+            14 => DConst0,
+            15 => DConst1,
+            16 => BIPush(reader.i1()?),
+            17 => SIPush(reader.i2()?),
+            18 => Ldc(reader.u1()? as u16),
+            19 => LdcW(reader.u2()?),
+            20 => Ldc2W(reader.u2()?),
+            21 => ILoad(reader.u1()? as u16),
+            22 => LLoad(reader.u1()? as u16),
+            23 => FLoad(reader.u1()? as u16),
+            24 => DLoad(reader.u1()? as u16),
+            25 => ALoad(reader.u1()? as u16),
+            26 => ILoad0,
+            27 => ILoad1,
+            28 => ILoad2,
+            29 => ILoad3,
+            30 => LLoad0,
+            31 => LLoad1,
+            32 => LLoad2,
+            33 => LLoad3,
+            34 => FLoad0,
+            35 => FLoad1,
+            36 => FLoad2,
+            37 => FLoad3,
+            38 => DLoad0,
+            39 => DLoad1,
+            40 => DLoad2,
+            41 => DLoad3,
+            42 => ALoad0,
+            43 => ALoad1,
+            44 => ALoad2,
+            45 => ALoad3,
+            46 => IALoad,
+            47 => LALoad,
+            48 => FALoad,
+            49 => DALoad,
+            50 => AALoad,
+            51 => BALoad,
+            52 => CALoad,
+            53 => SALoad,
+            54 => IStore(reader.u1()? as u16),
+            55 => LStore(reader.u1()? as u16),
+            56 => FStore(reader.u1()? as u16),
+            57 => DStore(reader.u1()? as u16),
+            58 => AStore(reader.u1()? as u16),
+            59 => IStore0,
+            60 => IStore1,
+            61 => IStore2,
+            62 => IStore3,
+            63 => LStore0,
+            64 => LStore1,
+            65 => LStore2,
+            66 => LStore3,
+            67 => FStore0,
+            68 => FStore1,
+            69 => FStore2,
+            70 => FStore3,
+            71 => DStore0,
+            72 => DStore1,
+            73 => DStore2,
+            74 => DStore3,
+            75 => AStore0,
+            76 => AStore1,
+            77 => AStore2,
+            78 => AStore3,
+            79 => IAStore,
+            80 => LAStore,
+            81 => FAStore,
+            82 => DAStore,
+            83 => AAStore,
+            84 => BAStore,
+            85 => CAStore,
+            86 => SAStore,
+            87 => Pop,
+            88 => Pop2,
+            89 => Dup,
+            90 => DupX1,
+            91 => DupX2,
+            92 => Dup2,
+            93 => Dup2X1,
+            94 => Dup2X2,
+            95 => Swap,
+            96 => IAdd,
+            97 => LAdd,
+            98 => FAdd,
+            99 => DAdd,
+            100 => ISub,
+            101 => LSub,
+            102 => FSub,
+            103 => DSub,
+            104 => IMul,
+            105 => LMul,
+            106 => FMul,
+            107 => DMul,
+            108 => IDiv,
+            109 => LDiv,
+            110 => FDiv,
+            111 => DDiv,
+            112 => IRem,
+            113 => LRem,
+            114 => FRem,
+            115 => DRem,
+            116 => INeg,
+            117 => LNeg,
+            118 => FNeg,
+            119 => DNeg,
+            120 => IShl,
+            121 => LShl,
+            122 => IShr,
+            123 => LShr,
+            124 => IUShr,
+            125 => LUShr,
+            126 => IAnd,
+            127 => LAnd,
+            128 => IOr,
+            129 => LOr,
+            130 => IXor,
+            131 => LXor,
+            132 => IInc {
+                index: reader.u1()? as u16,
+                value: reader.i1()? as i16,
+            },
+            133 => I2L,
+            134 => I2F,
+            135 => I2D,
+            136 => L2I,
+            137 => L2F,
+            138 => L2D,
+            139 => F2I,
+            140 => F2L,
+            141 => F2D,
+            142 => D2I,
+            143 => D2L,
+            144 => D2F,
+            145 => I2B,
+            146 => I2C,
+            147 => I2S,
+            148 => LCmp,
+            149 => FCmpL,
+            150 => FCmpG,
+            151 => DCmpL,
+            152 => DCmpG,
+            153 => IfEq(reader.i2()?),
+            154 => IfNe(reader.i2()?),
+            155 => IfLt(reader.i2()?),
+            156 => IfGe(reader.i2()?),
+            157 => IfGt(reader.i2()?),
+            158 => IfLe(reader.i2()?),
+            159 => IfICmpEq(reader.i2()?),
+            160 => IfICmpNe(reader.i2()?),
+            161 => IfICmpLt(reader.i2()?),
+            162 => IfICmpGe(reader.i2()?),
+            163 => IfICmpGt(reader.i2()?),
+            164 => IfICmpLe(reader.i2()?),
+            165 => IfACmpEq(reader.i2()?),
+            166 => IfACmpNe(reader.i2()?),
+            167 => Goto(reader.i2()?),
+            168 => Jsr(reader.i2()?),
+            169 => Ret(reader.u1()? as u16),
+            170 | 171 => {
+                // Switch operands are aligned to a multiple of 4 bytes from the start of the code
+                while !reader.position.is_multiple_of(4) {
+                    reader.u1()?;
+                }
+                let default = reader.i4()?;
+                if opcode == 170 {
+                    let low = reader.i4()?;
+                    let high = reader.i4()?;
+                    let count = reader.switch_count(high as i64 - low as i64 + 1)?;
+                    let mut offsets = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        offsets.push(reader.i4()?);
+                    }
+                    TableSwitch {
+                        default,
+                        low,
+                        offsets,
+                    }
+                } else {
+                    let npairs = reader.i4()?;
+                    let count = reader.switch_count(npairs as i64)?;
+                    let mut pairs = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        pairs.push((reader.i4()?, reader.i4()?));
+                    }
+                    LookupSwitch { default, pairs }
+                }
+            }
+            172 => IReturn,
+            173 => LReturn,
+            174 => FReturn,
+            175 => DReturn,
+            176 => AReturn,
+            177 => Return,
+            178 => GetStatic(reader.u2()?),
+            179 => PutStatic(reader.u2()?),
+            180 => GetField(reader.u2()?),
+            181 => PutField(reader.u2()?),
+            182 => InvokeVirtual(reader.u2()?),
+            183 => InvokeSpecial(reader.u2()?),
+            184 => InvokeStatic(reader.u2()?),
+            185 => {
+                let index = reader.u2()?;
+                let count = reader.u1()?;
+                reader.u1()?; // Always zero
+                InvokeInterface { index, count }
+            }
+            186 => {
+                let index = reader.u2()?;
+                reader.u2()?; // Always zero
+                InvokeDynamic(index)
+            }
+            187 => New(reader.u2()?),
+            188 => {
+                let atype = reader.u1()?;
+                NewArray(
+                    ArrayType::try_from(atype)
+                        .map_err(|_| ErrorKind::InvalidInstruction(reader.start))?,
+                )
+            }
+            189 => ANewArray(reader.u2()?),
+            190 => ArrayLength,
+            191 => AThrow,
+            192 => CheckCast(reader.u2()?),
+            193 => InstanceOf(reader.u2()?),
+            194 => MonitorEnter,
+            195 => MonitorExit,
+            196 => {
+                // wide widens the local variable index (and iinc's constant) of the next opcode
+                match reader.u1()? {
+                    21 => ILoad(reader.u2()?),
+                    22 => LLoad(reader.u2()?),
+                    23 => FLoad(reader.u2()?),
+                    24 => DLoad(reader.u2()?),
+                    25 => ALoad(reader.u2()?),
+                    54 => IStore(reader.u2()?),
+                    55 => LStore(reader.u2()?),
+                    56 => FStore(reader.u2()?),
+                    57 => DStore(reader.u2()?),
+                    58 => AStore(reader.u2()?),
+                    169 => Ret(reader.u2()?),
+                    132 => IInc {
+                        index: reader.u2()?,
+                        value: reader.i2()?,
+                    },
+                    _ => return Err(ErrorKind::InvalidInstruction(reader.start).into()),
+                }
+            }
+            197 => MultiANewArray {
+                index: reader.u2()?,
+                dimensions: reader.u1()?,
+            },
+            198 => IfNull(reader.i2()?),
+            199 => IfNonNull(reader.i2()?),
+            200 => GotoW(reader.i4()?),
+            201 => JsrW(reader.i4()?),
+            _ => {
+                return Err(ErrorKind::BadOpcode {
+                    opcode,
+                    offset: reader.start,
+                }
+                .into())
+            }
+        };
+        instructions.push((reader.start, instruction));
+    }
+
+    Ok(Bytecode {
+        instructions,
+        length: block.len(),
+    })
+}
+
+// Instructions are encoded in the order they're keyed by, and each has to start at its own offset
+pub fn write_code(code: &Bytecode) -> Result<Vec<u8>> {
+    let mut block = Vec::with_capacity(code.len());
+    for (offset, instruction) in code.iter() {
+        if block.len() != offset {
+            return Err(ErrorKind::InvalidInstruction(offset).into());
+        }
+        let read_length = code.next_offset(offset) - offset;
+        write_instruction(instruction, offset, Some(read_length), &mut block)?;
+    }
+
+    if block.len() != code.len() {
+        return Err(ErrorKind::InvalidInstruction(block.len()).into());
+    }
+    Ok(block)
+}
+
+// Since wide forms decode to the same variant as narrow ones, an instruction is written as wide if
+// its operands need it or if that's the space it took up when it was read
+fn write_instruction(
+    instruction: &Instruction,
+    offset: usize,
+    read_length: Option<usize>,
+    block: &mut Vec<u8>,
+) -> Result<()> {
+    use Instruction::*;
+
+    let wide = |index: u16, wide_length: usize| index > 0xFF || read_length == Some(wide_length);
+
+    match instruction {
+        Nop => block.push(0),
+        AConstNull => block.push(1),
+        IConstM1 => block.push(2),
+        IConst0 => block.push(3),
+        IConst1 => block.push(4),
+        IConst2 => block.push(5),
+        IConst3 => block.push(6),
+        IConst4 => block.push(7),
+        IConst5 => block.push(8),
+        LConst0 => block.push(9),
+        LConst1 => block.push(10),
+        FConst0 => block.push(11),
+        FConst1 => block.push(12),
+        FConst2 => block.push(13),
+        DConst0 => block.push(14),
+        DConst1 => block.push(15),
+        ILoad0 => block.push(26),
+        ILoad1 => block.push(27),
+        ILoad2 => block.push(28),
+        ILoad3 => block.push(29),
+        LLoad0 => block.push(30),
+        LLoad1 => block.push(31),
+        LLoad2 => block.push(32),
+        LLoad3 => block.push(33),
+        FLoad0 => block.push(34),
+        FLoad1 => block.push(35),
+        FLoad2 => block.push(36),
+        FLoad3 => block.push(37),
+        DLoad0 => block.push(38),
+        DLoad1 => block.push(39),
+        DLoad2 => block.push(40),
+        DLoad3 => block.push(41),
+        ALoad0 => block.push(42),
+        ALoad1 => block.push(43),
+        ALoad2 => block.push(44),
+        ALoad3 => block.push(45),
+        IALoad => block.push(46),
+        LALoad => block.push(47),
+        FALoad => block.push(48),
+        DALoad => block.push(49),
+        AALoad => block.push(50),
+        BALoad => block.push(51),
+        CALoad => block.push(52),
+        SALoad => block.push(53),
+        IStore0 => block.push(59),
+        IStore1 => block.push(60),
+        IStore2 => block.push(61),
+        IStore3 => block.push(62),
+        LStore0 => block.push(63),
+        LStore1 => block.push(64),
+        LStore2 => block.push(65),
+        LStore3 => block.push(66),
+        FStore0 => block.push(67),
+        FStore1 => block.push(68),
+        FStore2 => block.push(69),
+        FStore3 => block.push(70),
+        DStore0 => block.push(71),
+        DStore1 => block.push(72),
+        DStore2 => block.push(73),
+        DStore3 => block.push(74),
+        AStore0 => block.push(75),
+        AStore1 => block.push(76),
+        AStore2 => block.push(77),
+        AStore3 => block.push(78),
+        IAStore => block.push(79),
+        LAStore => block.push(80),
+        FAStore => block.push(81),
+        DAStore => block.push(82),
+        AAStore => block.push(83),
+        BAStore => block.push(84),
+        CAStore => block.push(85),
+        SAStore => block.push(86),
+        Pop => block.push(87),
+        Pop2 => block.push(88),
+        Dup => block.push(89),
+        DupX1 => block.push(90),
+        DupX2 => block.push(91),
+        Dup2 => block.push(92),
+        Dup2X1 => block.push(93),
+        Dup2X2 => block.push(94),
+        Swap => block.push(95),
+        IAdd => block.push(96),
+        LAdd => block.push(97),
+        FAdd => block.push(98),
+        DAdd => block.push(99),
+        ISub => block.push(100),
+        LSub => block.push(101),
+        FSub => block.push(102),
+        DSub => block.push(103),
+        IMul => block.push(104),
+        LMul => block.push(105),
+        FMul => block.push(106),
+        DMul => block.push(107),
+        IDiv => block.push(108),
+        LDiv => block.push(109),
+        FDiv => block.push(110),
+        DDiv => block.push(111),
+        IRem => block.push(112),
+        LRem => block.push(113),
+        FRem => block.push(114),
+        DRem => block.push(115),
+        INeg => block.push(116),
+        LNeg => block.push(117),
+        FNeg => block.push(118),
+        DNeg => block.push(119),
+        IShl => block.push(120),
+        LShl => block.push(121),
+        IShr => block.push(122),
+        LShr => block.push(123),
+        IUShr => block.push(124),
+        LUShr => block.push(125),
+        IAnd => block.push(126),
+        LAnd => block.push(127),
+        IOr => block.push(128),
+        LOr => block.push(129),
+        IXor => block.push(130),
+        LXor => block.push(131),
+        I2L => block.push(133),
+        I2F => block.push(134),
+        I2D => block.push(135),
+        L2I => block.push(136),
+        L2F => block.push(137),
+        L2D => block.push(138),
+        F2I => block.push(139),
+        F2L => block.push(140),
+        F2D => block.push(141),
+        D2I => block.push(142),
+        D2L => block.push(143),
+        D2F => block.push(144),
+        I2B => block.push(145),
+        I2C => block.push(146),
+        I2S => block.push(147),
+        LCmp => block.push(148),
+        FCmpL => block.push(149),
+        FCmpG => block.push(150),
+        DCmpL => block.push(151),
+        DCmpG => block.push(152),
+        IReturn => block.push(172),
+        LReturn => block.push(173),
+        FReturn => block.push(174),
+        DReturn => block.push(175),
+        AReturn => block.push(176),
+        Return => block.push(177),
+        ArrayLength => block.push(190),
+        AThrow => block.push(191),
+        MonitorEnter => block.push(194),
+        MonitorExit => block.push(195),
+        BIPush(operand) => {
+            block.push(16);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        SIPush(operand) => {
+            block.push(17);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        Ldc(index) => {
+            let index = u8::try_from(*index).map_err(|_| ErrorKind::InvalidInstruction(offset))?;
+            block.extend_from_slice(&[18, index]);
+        }
+        LdcW(operand) => {
+            block.push(19);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        Ldc2W(operand) => {
+            block.push(20);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfEq(operand) => {
+            block.push(153);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfNe(operand) => {
+            block.push(154);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfLt(operand) => {
+            block.push(155);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfGe(operand) => {
+            block.push(156);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfGt(operand) => {
+            block.push(157);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfLe(operand) => {
+            block.push(158);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfICmpEq(operand) => {
+            block.push(159);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfICmpNe(operand) => {
+            block.push(160);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfICmpLt(operand) => {
+            block.push(161);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfICmpGe(operand) => {
+            block.push(162);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfICmpGt(operand) => {
+            block.push(163);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfICmpLe(operand) => {
+            block.push(164);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfACmpEq(operand) => {
+            block.push(165);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfACmpNe(operand) => {
+            block.push(166);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        Goto(operand) => {
+            block.push(167);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        Jsr(operand) => {
+            block.push(168);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        GetStatic(operand) => {
+            block.push(178);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        PutStatic(operand) => {
+            block.push(179);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        GetField(operand) => {
+            block.push(180);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        PutField(operand) => {
+            block.push(181);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        InvokeVirtual(operand) => {
+            block.push(182);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        InvokeSpecial(operand) => {
+            block.push(183);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        InvokeStatic(operand) => {
+            block.push(184);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        New(operand) => {
+            block.push(187);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        ANewArray(operand) => {
+            block.push(189);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        CheckCast(operand) => {
+            block.push(192);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        InstanceOf(operand) => {
+            block.push(193);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfNull(operand) => {
+            block.push(198);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        IfNonNull(operand) => {
+            block.push(199);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        GotoW(operand) => {
+            block.push(200);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        JsrW(operand) => {
+            block.push(201);
+            block.extend_from_slice(&operand.to_be_bytes());
+        }
+        ILoad(index) => write_local(21, *index, wide(*index, 4), block),
+        LLoad(index) => write_local(22, *index, wide(*index, 4), block),
+        FLoad(index) => write_local(23, *index, wide(*index, 4), block),
+        DLoad(index) => write_local(24, *index, wide(*index, 4), block),
+        ALoad(index) => write_local(25, *index, wide(*index, 4), block),
+        IStore(index) => write_local(54, *index, wide(*index, 4), block),
+        LStore(index) => write_local(55, *index, wide(*index, 4), block),
+        FStore(index) => write_local(56, *index, wide(*index, 4), block),
+        DStore(index) => write_local(57, *index, wide(*index, 4), block),
+        AStore(index) => write_local(58, *index, wide(*index, 4), block),
+        Ret(index) => write_local(169, *index, wide(*index, 4), block),
+        IInc { index, value } => {
+            if wide(*index, 6) || i8::try_from(*value).is_err() {
+                block.extend_from_slice(&[196, 132]);
+                block.extend_from_slice(&index.to_be_bytes());
+                block.extend_from_slice(&value.to_be_bytes());
+            } else {
+                block.extend_from_slice(&[132, *index as u8, *value as u8]);
+            }
+        }
+        TableSwitch {
+            default,
+            low,
+            offsets,
+        } => {
+            write_switch_start(170, *default, offset, block);
+            let high = *low as i64 + offsets.len() as i64 - 1;
+            let high = i32::try_from(high).map_err(|_| ErrorKind::InvalidInstruction(offset))?;
+            block.extend_from_slice(&low.to_be_bytes());
+            block.extend_from_slice(&high.to_be_bytes());
+            for jump in offsets {
+                block.extend_from_slice(&jump.to_be_bytes());
             }
-            Ok(_) => {}
         }
+        LookupSwitch { default, pairs } => {
+            write_switch_start(171, *default, offset, block);
+            block.extend_from_slice(&(pairs.len() as i32).to_be_bytes());
+            for (key, jump) in pairs {
+                block.extend_from_slice(&key.to_be_bytes());
+                block.extend_from_slice(&jump.to_be_bytes());
+            }
+        }
+        InvokeInterface { index, count } => {
+            block.push(185);
+            block.extend_from_slice(&index.to_be_bytes());
+            block.extend_from_slice(&[*count, 0]);
+        }
+        InvokeDynamic(index) => {
+            block.push(186);
+            block.extend_from_slice(&index.to_be_bytes());
+            block.extend_from_slice(&[0, 0]);
+        }
+        NewArray(atype) => block.extend_from_slice(&[188, *atype as u8]),
+        MultiANewArray { index, dimensions } => {
+            block.push(197);
+            block.extend_from_slice(&index.to_be_bytes());
+            block.push(*dimensions);
+        }
+    }
+    Ok(())
+}
+
+// Loads, stores and ret
+fn write_local(opcode: u8, index: u16, wide: bool, block: &mut Vec<u8>) {
+    if wide {
+        block.extend_from_slice(&[196, opcode]);
+        block.extend_from_slice(&index.to_be_bytes());
+    } else {
+        block.extend_from_slice(&[opcode, index as u8]);
     }
-    if any_not_implemented {
-        return None;
+}
+
+// Switch operands are aligned relative to the start of the code, not the block being written to
+fn write_switch_start(opcode: u8, default: i32, offset: usize, block: &mut Vec<u8>) {
+    block.push(opcode);
+    for _ in 0..(3 - offset % 4) {
+        block.push(0);
+    }
+    block.extend_from_slice(&default.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction::*;
+    use super::*;
+
+    // The instructions and their offsets, checking they're written back as they were read
+    fn decode(block: &[u8]) -> Vec<(usize, Instruction)> {
+        let code = parse_code(block).unwrap();
+        assert_eq!(write_code(&code).unwrap(), block);
+        code.iter().map(|(offset, i)| (offset, i.clone())).collect()
+    }
+
+    #[test]
+    fn switch_operands_are_aligned_from_the_start_of_the_code() {
+        #[rustfmt::skip]
+        let block = [
+            0x00,                   // nop
+            0xAA,                   // tableswitch
+            0x00, 0x00,             // Padding to offset 4
+            0x00, 0x00, 0x00, 0x20, // default
+            0x00, 0x00, 0x00, 0x01, // low
+            0x00, 0x00, 0x00, 0x02, // high
+            0x00, 0x00, 0x00, 0x21,
+            0x00, 0x00, 0x00, 0x22,
+            0xAB,                   // lookupswitch, at offset 24
+            0x00, 0x00, 0x00,       // Padding to offset 28
+            0xFF, 0xFF, 0xFF, 0xF0, // default
+            0x00, 0x00, 0x00, 0x01, // npairs
+            0x00, 0x00, 0x00, 0x07,
+            0x00, 0x00, 0x00, 0x10,
+            0xB1,                   // return
+        ];
+        assert_eq!(
+            decode(&block),
+            [
+                (0, Nop),
+                (
+                    1,
+                    TableSwitch {
+                        default: 0x20,
+                        low: 1,
+                        offsets: vec![0x21, 0x22],
+                    }
+                ),
+                (
+                    24,
+                    LookupSwitch {
+                        default: -0x10,
+                        pairs: vec![(7, 0x10)],
+                    }
+                ),
+                (44, Return),
+            ]
+        );
+    }
+
+    #[test]
+    fn switches_at_an_aligned_offset_have_no_padding() {
+        #[rustfmt::skip]
+        let block = [
+            0x00, 0x00, 0x00,       // nop, nop, nop
+            0xAB,                   // lookupswitch, at offset 3
+            0x00, 0x00, 0x00, 0x09, // default
+            0x00, 0x00, 0x00, 0x00, // npairs
+            0xB1,                   // return
+        ];
+        let code = decode(&block);
+        assert_eq!(
+            code[3],
+            (
+                3,
+                LookupSwitch {
+                    default: 9,
+                    pairs: vec![],
+                }
+            )
+        );
+        assert_eq!(code[4], (12, Return));
+    }
+
+    #[test]
+    fn wide_forms_decode_to_the_same_instructions() {
+        #[rustfmt::skip]
+        let block = [
+            0xC4, 0x15, 0x01, 0x00,             // wide iload 256
+            0xC4, 0x84, 0x01, 0x00, 0xFF, 0xFE, // wide iinc 256 -2
+            0xC4, 0x3A, 0x00, 0x01,             // wide astore 1, which didn't need to be wide
+            0x84, 0x01, 0xFF,                   // iinc 1 -1
+            0xB1,                               // return
+        ];
+        assert_eq!(
+            decode(&block),
+            [
+                (0, ILoad(256)),
+                (
+                    4,
+                    IInc {
+                        index: 256,
+                        value: -2,
+                    }
+                ),
+                (10, AStore(1)),
+                (
+                    14,
+                    IInc {
+                        index: 1,
+                        value: -1
+                    }
+                ),
+                (17, Return),
+            ]
+        );
+    }
+
+    #[test]
+    fn wide_only_widens_local_variable_instructions() {
+        // wide iconst_0
+        let error = parse_code(&[0xC4, 0x03]).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::InvalidInstruction(0)));
     }
-    Some(
-        block
-            .iter()
-            // TODO handle unwrapping better here
-            .map(|&bytecode| Instruction::try_from(bytecode).unwrap())
-            .collect(),
-    )
 }
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::DeflateDecoder;
+use flate2::Crc;
+
+use crate::class::VersionPolicy;
+
+// JAR files, or any ZIP archive of classes. Entries are found through the central directory at
+// the end of the archive, and may be stored or deflated. ZIP64, encrypted entries and other
+// compression methods aren't supported, and javac never produces them.
+pub struct Jar {
+    path: PathBuf,
+    bytes: Vec<u8>,
+    entries: HashMap<String, ZipEntry>,
+    manifest: Option<Manifest>,
+    // The Java release classes are looked up for in multi-release jars, i.e. the highest N to
+    // use META-INF/versions/N from
+    release: u16,
+}
+
+// A file in the archive, as its central directory header describes it
+#[derive(Debug, Clone)]
+struct ZipEntry {
+    flags: u16,
+    method: u16,
+    crc32: u32,
+    compressed_size: usize,
+    uncompressed_size: usize,
+    local_header_offset: usize,
+}
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const END_OF_CENTRAL_DIRECTORY_LENGTH: usize = 22;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+const ENCRYPTED: u16 = 0x1;
+
+pub const MANIFEST_PATH: &str = "META-INF/MANIFEST.MF";
+
+// The oldest release with multi-release jars
+const FIRST_VERSIONED_RELEASE: u16 = 9;
+
+impl Jar {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Jar> {
+        let path = path.into();
+        let bytes = fs::read(&path)?;
+        Jar::from_bytes(path, bytes)
+    }
+
+    // `path` is only used to describe the jar, and to find what its Class-Path refers to
+    pub fn from_bytes(path: impl Into<PathBuf>, bytes: Vec<u8>) -> io::Result<Jar> {
+        let entries = read_central_directory(&bytes).map_err(invalid)?;
+        let mut jar = Jar {
+            path: path.into(),
+            bytes,
+            entries,
+            manifest: None,
+            // Class files from the newest release parse_class accepts, e.g. 61 is Java 17
+            release: VersionPolicy::default().newest_major - 44,
+        };
+        jar.manifest = jar
+            .read(MANIFEST_PATH)?
+            .map(|bytes| Manifest::parse(&String::from_utf8_lossy(&bytes)));
+        Ok(jar)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
+    }
+
+    pub fn set_release(&mut self, release: u16) {
+        self.release = release;
+    }
+
+    pub fn is_multi_release(&self) -> bool {
+        self.manifest
+            .as_ref()
+            .and_then(|manifest| manifest.get("Multi-Release"))
+            .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+    }
+
+    // Names of every file in the archive, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|name| name.as_str())
+    }
+
+    // The class file for the binary name in internal form, from the newest version directory
+    // that has one in multi-release jars
+    pub fn find_class(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let file = format!("{}.class", name);
+        if self.is_multi_release() {
+            for release in (FIRST_VERSIONED_RELEASE..=self.release).rev() {
+                let versioned = format!("META-INF/versions/{}/{}", release, file);
+                if let Some(bytes) = self.read(&versioned)? {
+                    return Ok(Some(bytes));
+                }
+            }
+        }
+        self.read(&file)
+    }
+
+    // The uncompressed contents of the file, or None if the archive doesn't have it
+    pub fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let entry = match self.entries.get(name) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let error = |message: &str| invalid(format!("{} {}", message, name));
+        if entry.flags & ENCRYPTED != 0 {
+            return Err(error("cannot decrypt"));
+        }
+
+        // The local header repeats the name, but its extra field can differ in length from the
+        // central directory's
+        let header = entry.local_header_offset;
+        let fixed = self
+            .bytes
+            .get(header..header + 30)
+            .ok_or_else(|| error("missing local header for"))?;
+        if le_u32(fixed, 0) != LOCAL_HEADER_SIGNATURE {
+            return Err(error("bad local header for"));
+        }
+        let start = header + 30 + le_u16(fixed, 26) as usize + le_u16(fixed, 28) as usize;
+        let data = start
+            .checked_add(entry.compressed_size)
+            .and_then(|end| self.bytes.get(start..end))
+            .ok_or_else(|| error("truncated data for"))?;
+
+        let bytes = match entry.method {
+            STORED => data.to_vec(),
+            DEFLATED => {
+                // Reading a byte past the declared size is enough to tell it was wrong
+                let mut bytes = vec![];
+                DeflateDecoder::new(data)
+                    .take(entry.uncompressed_size as u64 + 1)
+                    .read_to_end(&mut bytes)?;
+                bytes
+            }
+            _ => return Err(error("unsupported compression method for")),
+        };
+        let mut crc = Crc::new();
+        crc.update(&bytes);
+        if bytes.len() != entry.uncompressed_size || crc.sum() != entry.crc32 {
+            return Err(error("corrupt data for"));
+        }
+        Ok(Some(bytes))
+    }
+}
+
+impl fmt::Debug for Jar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Jar")
+            .field("path", &self.path)
+            .field("entries", &self.entries.len())
+            .field("manifest", &self.manifest)
+            .field("release", &self.release)
+            .finish()
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn le_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn read_central_directory(bytes: &[u8]) -> Result<HashMap<String, ZipEntry>, &'static str> {
+    // The end of central directory record is last, followed only by a comment of up to 65535
+    // bytes, so search backwards for its signature. The comment can contain the signature too, so
+    // the record's comment length has to reach exactly to the end.
+    let last = bytes
+        .len()
+        .checked_sub(END_OF_CENTRAL_DIRECTORY_LENGTH)
+        .ok_or("not a zip file")?;
+    let end = (last.saturating_sub(0xFFFF)..=last)
+        .rev()
+        .find(|&offset| {
+            le_u32(bytes, offset) == END_OF_CENTRAL_DIRECTORY_SIGNATURE
+                && offset + END_OF_CENTRAL_DIRECTORY_LENGTH + le_u16(bytes, offset + 20) as usize
+                    == bytes.len()
+        })
+        .ok_or("not a zip file")?;
+    let count = le_u16(bytes, end + 10);
+    let offset = le_u32(bytes, end + 16);
+    if count == 0xFFFF || offset == 0xFFFFFFFF {
+        return Err("zip64 archives are not supported");
+    }
+
+    let mut entries = HashMap::with_capacity(count as usize);
+    let mut offset = offset as usize;
+    for _ in 0..count {
+        let header = bytes
+            .get(offset..offset + 46)
+            .ok_or("truncated central directory")?;
+        if le_u32(header, 0) != CENTRAL_HEADER_SIGNATURE {
+            return Err("bad central directory header");
+        }
+        let name_length = le_u16(header, 28) as usize;
+        let extra_length = le_u16(header, 30) as usize;
+        let comment_length = le_u16(header, 32) as usize;
+        let name = bytes
+            .get(offset + 46..offset + 46 + name_length)
+            .ok_or("truncated central directory")?;
+        // Names are UTF-8 when flag 11 is set and CP437 otherwise, which only differ outside
+        // ASCII, and class and package names in jars are nearly always ASCII
+        let name = String::from_utf8_lossy(name).into_owned();
+        entries.insert(
+            name,
+            ZipEntry {
+                flags: le_u16(header, 8),
+                method: le_u16(header, 10),
+                crc32: le_u32(header, 16),
+                compressed_size: le_u32(header, 20) as usize,
+                uncompressed_size: le_u32(header, 24) as usize,
+                local_header_offset: le_u32(header, 42) as usize,
+            },
+        );
+        offset += 46 + name_length + extra_length + comment_length;
+    }
+    Ok(entries)
+}
+
+// The main section of a jar's manifest, which is all the launcher uses
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    attributes: Vec<(String, String)>,
+}
+
+impl Manifest {
+    // Lines are "Name: value", and long values continue on lines starting with a space. The main
+    // section ends at the first blank line, where the per-entry sections start.
+    pub fn parse(text: &str) -> Manifest {
+        let mut attributes: Vec<(String, String)> = vec![];
+        for line in text.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+            if line.is_empty() {
+                if attributes.is_empty() {
+                    continue;
+                }
+                break;
+            }
+            match (line.strip_prefix(' '), attributes.last_mut()) {
+                (Some(continuation), Some((_, value))) => value.push_str(continuation),
+                _ => {
+                    if let Some((name, value)) = line.split_once(':') {
+                        let value = value.strip_prefix(' ').unwrap_or(value);
+                        attributes.push((name.to_string(), value.to_string()));
+                    }
+                }
+            }
+        }
+        Manifest { attributes }
+    }
+
+    // Attribute names are case insensitive
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // e.g. com.acme.Main
+    pub fn main_class(&self) -> Option<&str> {
+        self.get("Main-Class")
+    }
+
+    // Other jars and directories to search, relative to the jar. Directories end in a slash.
+    pub fn class_path(&self) -> Vec<&str> {
+        self.get("Class-Path")
+            .map_or(vec![], |paths| paths.split_whitespace().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    // A zip archive of the files, deflating those marked true. Each local header has an extra field
+    // the central directory doesn't, and the archive ends with a comment.
+    fn zip(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut archive = vec![];
+        let mut central = vec![];
+        for &(name, contents, deflate) in files {
+            let (method, data) = if deflate {
+                let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+                encoder.write_all(contents).unwrap();
+                (DEFLATED, encoder.finish().unwrap())
+            } else {
+                (STORED, contents.to_vec())
+            };
+            let mut crc = Crc::new();
+            crc.update(contents);
+            let extra = [0xFE, 0xCA, 0x00, 0x00];
+
+            // Everything from the version needed to the extra field length, which both headers share
+            let mut common = vec![];
+            common.extend(20u16.to_le_bytes());
+            common.extend(0u16.to_le_bytes()); // Flags
+            common.extend(method.to_le_bytes());
+            common.extend(0u32.to_le_bytes()); // Modification time and date
+            common.extend(crc.sum().to_le_bytes());
+            common.extend((data.len() as u32).to_le_bytes());
+            common.extend((contents.len() as u32).to_le_bytes());
+            common.extend((name.len() as u16).to_le_bytes());
+
+            central.extend(CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+            central.extend(20u16.to_le_bytes()); // Version made by
+            central.extend(&common);
+            central.extend(0u16.to_le_bytes()); // Extra field length
+            central.extend([0; 6]); // Comment length, disk, and internal attributes
+            central.extend(0u32.to_le_bytes()); // External attributes
+            central.extend((archive.len() as u32).to_le_bytes());
+            central.extend(name.as_bytes());
+
+            archive.extend(LOCAL_HEADER_SIGNATURE.to_le_bytes());
+            archive.extend(&common);
+            archive.extend((extra.len() as u16).to_le_bytes());
+            archive.extend(name.as_bytes());
+            archive.extend(extra);
+            archive.extend(data);
+        }
+
+        let comment = b"PK\x05\x06 is the signature it has to find";
+        let offset = archive.len() as u32;
+        archive.extend(&central);
+        archive.extend(END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        archive.extend([0; 4]); // Disk numbers
+        archive.extend((files.len() as u16).to_le_bytes());
+        archive.extend((files.len() as u16).to_le_bytes());
+        archive.extend((central.len() as u32).to_le_bytes());
+        archive.extend(offset.to_le_bytes());
+        archive.extend((comment.len() as u16).to_le_bytes());
+        archive.extend(comment);
+        archive
+    }
+
+    fn jar(files: &[(&str, &[u8], bool)]) -> Jar {
+        Jar::from_bytes("test.jar", zip(files)).unwrap()
+    }
+
+    #[test]
+    fn reads_stored_and_deflated_files() {
+        let text = b"deflated, deflated, deflated, deflated";
+        let jar = jar(&[("a/Stored.class", b"stored", false), ("b.txt", text, true)]);
+        let mut names: Vec<&str> = jar.names().collect();
+        names.sort_unstable();
+        assert_eq!(names, ["a/Stored.class", "b.txt"]);
+        assert_eq!(jar.read("a/Stored.class").unwrap().unwrap(), b"stored");
+        assert_eq!(jar.read("b.txt").unwrap().unwrap(), text);
+        assert_eq!(jar.read("c.txt").unwrap(), None);
+        assert_eq!(jar.find_class("a/Stored").unwrap().unwrap(), b"stored");
+    }
+
+    #[test]
+    fn rejects_corrupt_data() {
+        for deflate in [false, true] {
+            let mut bytes = zip(&[("a.txt", b"contents", deflate)]);
+            // The CRC in the central directory, which is what the data is checked against
+            let central = bytes
+                .windows(4)
+                .position(|window| window == CENTRAL_HEADER_SIGNATURE.to_le_bytes())
+                .unwrap();
+            bytes[central + 16] ^= 0xFF;
+            let jar = Jar::from_bytes("test.jar", bytes).unwrap();
+            let error = jar.read("a.txt").unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(error.to_string(), "corrupt data for a.txt");
+        }
+    }
+
+    #[test]
+    fn rejects_files_that_arent_zips() {
+        assert!(Jar::from_bytes("test.jar", b"not a zip".to_vec()).is_err());
+        assert!(Jar::from_bytes("test.jar", vec![0; 100]).is_err());
+    }
+
+    #[test]
+    fn parses_manifests_with_continuation_lines() {
+        let manifest = Manifest::parse(
+            "Manifest-Version: 1.0\r\n\
+             Main-Class: com.acme.Main\r\n\
+             Class-Path: lib/first.jar lib/sec\r\n ond.jar\r\n  classes/\r\n\
+             \r\n\
+             Name: com/acme/\r\n\
+             Class-Path: ignored.jar\r\n",
+        );
+        assert_eq!(manifest.main_class(), Some("com.acme.Main"));
+        assert_eq!(
+            manifest.class_path(),
+            ["lib/first.jar", "lib/second.jar", "classes/"]
+        );
+        assert_eq!(manifest.get("manifest-version"), Some("1.0"));
+
+        let unix = Manifest::parse("Main-Class: Main\nClass-Path: a.jar\n b.jar\n");
+        assert_eq!(unix.class_path(), ["a.jarb.jar"]);
+    }
+
+    #[test]
+    fn versioned_classes_override_the_base_ones() {
+        let files: &[(&str, &[u8], bool)] = &[
+            (MANIFEST_PATH, b"Multi-Release: true\r\n", true),
+            ("q/B.class", b"base", false),
+            ("META-INF/versions/9/q/B.class", b"9", true),
+            ("META-INF/versions/11/q/B.class", b"11", false),
+            ("META-INF/versions/9/q/C.class", b"only 9", false),
+        ];
+        let mut jar = jar(files);
+        assert!(jar.is_multi_release());
+        jar.set_release(10);
+        assert_eq!(jar.find_class("q/B").unwrap().unwrap(), b"9");
+        jar.set_release(17);
+        assert_eq!(jar.find_class("q/B").unwrap().unwrap(), b"11");
+        jar.set_release(8);
+        assert_eq!(jar.find_class("q/B").unwrap().unwrap(), b"base");
+        assert_eq!(jar.find_class("q/C").unwrap(), None);
+
+        // Without Multi-Release in the manifest, the version directories are just files
+        let mut jar = self::jar(&files[1..]);
+        jar.set_release(17);
+        assert!(!jar.is_multi_release());
+        assert_eq!(jar.find_class("q/B").unwrap().unwrap(), b"base");
+    }
+}
@@ -0,0 +1,1172 @@
+use std::fmt::Display;
+use std::io::Write;
+
+use crate::access_flags::{flag_names, FlagsOf};
+use crate::annotations::{
+    Annotation, ElementValue, TypeAnnotation, TypeAnnotationTarget, TypePathKind,
+};
+use crate::attributes::*;
+use crate::class::Class;
+use crate::constants::Constant::*;
+use crate::constants::*;
+use crate::descriptors::parse_field_type;
+use crate::error::Result;
+use crate::fields::Field;
+use crate::instructions::Instruction::*;
+use crate::instructions::*;
+use crate::methods::{Method, Visibility};
+use crate::modified_utf8::JavaString;
+use crate::signatures::*;
+use crate::stack_map::*;
+
+// Prints classes the way `javap -c -v -p` does: the class header, constant pool, every field and
+// method with its disassembled code, then the class's attributes. References the parser already
+// resolved, e.g. the class's own name or a catch type, are printed by name rather than as #n.
+//
+// Constants that fail to resolve are printed as the error rather than stopping the listing, since
+// operands in code aren't checked when the class is parsed.
+
+// The flags that are also Java modifiers, in the order javac writes them
+const MODIFIERS: &[(u16, &str)] = &[
+    (0x0001, "public"),
+    (0x0002, "private"),
+    (0x0004, "protected"),
+    (0x0008, "static"),
+    (0x0010, "final"),
+    (0x0400, "abstract"),
+];
+
+const FIELD_MODIFIERS: &[(u16, &str)] = &[(0x0040, "volatile"), (0x0080, "transient")];
+
+const METHOD_MODIFIERS: &[(u16, &str)] = &[
+    (0x0020, "synchronized"),
+    (0x0100, "native"),
+    (0x0800, "strictfp"),
+];
+
+pub fn disassemble(class: &Class, writer: &mut dyn Write) -> Result<()> {
+    Printer { class, out: writer }.class()
+}
+
+struct Printer<'a> {
+    class: &'a Class,
+    out: &'a mut dyn Write,
+}
+
+impl Printer<'_> {
+    fn class(&mut self) -> Result<()> {
+        let class = self.class;
+        if let Some(source_file) = class.source_file() {
+            writeln!(self.out, "  Compiled from \"{}\"", source_file)?;
+        }
+        writeln!(self.out, "{}", class_declaration(class))?;
+        writeln!(self.out, "  minor version: {}", class.minor_version)?;
+        writeln!(self.out, "  major version: {}", class.major_version)?;
+        writeln!(
+            self.out,
+            "  flags: {}",
+            flags(class.access_flags.bits(), FlagsOf::Class)
+        )?;
+        writeln!(self.out, "  this_class: {}", class.name)?;
+        if let Some(super_name) = &class.super_name {
+            writeln!(self.out, "  super_class: {}", super_name)?;
+        }
+        writeln!(
+            self.out,
+            "  interfaces: {}, fields: {}, methods: {}, attributes: {}",
+            class.interfaces.len(),
+            class.fields.len(),
+            class.methods.len(),
+            class.attributes.len()
+        )?;
+
+        self.constant_pool()?;
+
+        writeln!(self.out, "{{")?;
+        for (i, field) in class.fields.iter().enumerate() {
+            if i > 0 {
+                writeln!(self.out)?;
+            }
+            self.field(field)?;
+        }
+        for (i, method) in class.methods.iter().enumerate() {
+            if i > 0 || !class.fields.is_empty() {
+                writeln!(self.out)?;
+            }
+            self.method(method)?;
+        }
+        writeln!(self.out, "}}")?;
+
+        for attribute in &class.attributes {
+            self.attribute(attribute, "", None)?;
+        }
+        Ok(())
+    }
+
+    fn constant_pool(&mut self) -> Result<()> {
+        let pool = &self.class.constant_pool;
+        writeln!(self.out, "Constant pool:")?;
+        // Wide enough for the largest index, with the comments starting in the same column
+        let width = (pool.len() + 1).to_string().len() + 3;
+        for (index, constant) in pool.iter() {
+            let (kind, operands) = match constant {
+                Empty => continue, // The second slot of a Long or Double
+                ClassInfo { name_index, .. } => ("Class", format!("#{}", name_index)),
+                FieldRefInfo {
+                    class_index,
+                    name_and_type_index,
+                    ..
+                } => (
+                    "Fieldref",
+                    format!("#{}.#{}", class_index, name_and_type_index),
+                ),
+                MethodRefInfo {
+                    class_index,
+                    name_and_type_index,
+                    ..
+                } => (
+                    "Methodref",
+                    format!("#{}.#{}", class_index, name_and_type_index),
+                ),
+                InterfaceMethodRefInfo {
+                    class_index,
+                    name_and_type_index,
+                    ..
+                } => (
+                    "InterfaceMethodref",
+                    format!("#{}.#{}", class_index, name_and_type_index),
+                ),
+                StringInfo { index, .. } => ("String", format!("#{}", index)),
+                IntInfo { value, .. } => ("Integer", value.to_string()),
+                FloatInfo { value, .. } => ("Float", format!("{:?}f", value)),
+                LongInfo { value, .. } => ("Long", format!("{}l", value)),
+                DoubleInfo { value, .. } => ("Double", format!("{:?}d", value)),
+                NameAndTypeInfo {
+                    name_index,
+                    descriptor_index,
+                    ..
+                } => (
+                    "NameAndType",
+                    format!("#{}:#{}", name_index, descriptor_index),
+                ),
+                Utf8Info { value, .. } => ("Utf8", escape(value)),
+                MethodHandleInfo { kind, index, .. } => {
+                    ("MethodHandle", format!("{}:#{}", *kind as u8, index))
+                }
+                MethodTypeInfo {
+                    descriptor_index, ..
+                } => ("MethodType", format!("#{}", descriptor_index)),
+                DynamicInfo {
+                    tag,
+                    bootstrap_method_attr_index,
+                    name_and_type_index,
+                } => (
+                    if *tag == ConstantTag::Dynamic {
+                        "Dynamic"
+                    } else {
+                        "InvokeDynamic"
+                    },
+                    format!("#{}:#{}", bootstrap_method_attr_index, name_and_type_index),
+                ),
+                ModuleInfo { name_index, .. } => ("Module", format!("#{}", name_index)),
+                PackageInfo { name_index, .. } => ("Package", format!("#{}", name_index)),
+            };
+            let index_column = format!("{:>width$}", format!("#{}", index), width = width);
+            let entry = format!("{} = {:<18} {}", index_column, kind, operands);
+            match constant {
+                IntInfo { .. }
+                | FloatInfo { .. }
+                | LongInfo { .. }
+                | DoubleInfo { .. }
+                | Utf8Info { .. } => writeln!(self.out, "{}", entry)?,
+                _ => writeln!(self.out, "{:<42}// {}", entry, describe(pool, index, None))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn field(&mut self, field: &Field) -> Result<()> {
+        let bits = field.access_flags.bits();
+        let java_type = match field.signature() {
+            Some(Ok(signature)) => signature.to_string(),
+            _ => java_type(&field.descriptor),
+        };
+        writeln!(
+            self.out,
+            "  {}{}{} {};",
+            modifiers(bits, MODIFIERS),
+            modifiers(bits, FIELD_MODIFIERS),
+            java_type,
+            field.name
+        )?;
+        writeln!(self.out, "    descriptor: {}", field.descriptor)?;
+        writeln!(self.out, "    flags: {}", flags(bits, FlagsOf::Field))?;
+        for attribute in &field.attributes {
+            self.attribute(attribute, "    ", None)?;
+        }
+        Ok(())
+    }
+
+    fn method(&mut self, method: &Method) -> Result<()> {
+        writeln!(self.out, "  {};", method_declaration(self.class, method))?;
+        writeln!(self.out, "    descriptor: {}", method.descriptor)?;
+        writeln!(
+            self.out,
+            "    flags: {}",
+            flags(method.access_flags.bits(), FlagsOf::Method)
+        )?;
+        for attribute in &method.attributes {
+            self.attribute(attribute, "    ", Some(method))?;
+        }
+        Ok(())
+    }
+
+    // Attributes are printed the same way wherever they appear, just indented further when nested
+    fn attribute(
+        &mut self,
+        attribute: &Attribute,
+        indent: &str,
+        method: Option<&Method>,
+    ) -> Result<()> {
+        let class = self.class;
+        match attribute {
+            Attribute::Unknown { name, info } => {
+                writeln!(self.out, "{}{}: length = {:#x}", indent, name, info.len())?;
+                for line in info.chunks(16) {
+                    let bytes: Vec<_> = line.iter().map(|byte| format!("{:02X}", byte)).collect();
+                    writeln!(self.out, "{}   {}", indent, bytes.join(" "))?;
+                }
+            }
+            Attribute::ConstantValue { value, .. } => {
+                writeln!(self.out, "{}ConstantValue: {}", indent, loadable(value))?
+            }
+            Attribute::Code {
+                max_stack,
+                max_locals,
+                code,
+                exceptions,
+                attributes,
+                ..
+            } => {
+                writeln!(self.out, "{}Code:", indent)?;
+                let args_size = method.map_or(0, |method| {
+                    let receiver = if method.access_flags.is_static { 0 } else { 1 };
+                    receiver
+                        + method
+                            .arg_types
+                            .iter()
+                            .map(|arg| arg.slots())
+                            .sum::<usize>()
+                });
+                writeln!(
+                    self.out,
+                    "{}  stack={}, locals={}, args_size={}",
+                    indent, max_stack, max_locals, args_size
+                )?;
+                for (offset, instruction) in code.iter() {
+                    let length = code.next_offset(offset) - offset;
+                    self.instruction(offset, instruction, length)?;
+                }
+                if !exceptions.is_empty() {
+                    writeln!(self.out, "{}  Exception table:", indent)?;
+                    writeln!(self.out, "{}     from    to  target type", indent)?;
+                    for exception in exceptions {
+                        let catch_type = match &exception.catch_type {
+                            Some(catch_type) => format!("Class {}", catch_type),
+                            None => "any".to_string(),
+                        };
+                        writeln!(
+                            self.out,
+                            "{}    {:>6} {:>5} {:>5}   {}",
+                            indent,
+                            exception.start_pc,
+                            exception.end_pc,
+                            exception.handler_pc,
+                            catch_type
+                        )?;
+                    }
+                }
+                let nested = format!("{}  ", indent);
+                for attribute in attributes {
+                    self.attribute(attribute, &nested, method)?;
+                }
+            }
+            Attribute::StackMapTable { entries, .. } => {
+                writeln!(
+                    self.out,
+                    "{}StackMapTable: number_of_entries = {}",
+                    indent,
+                    entries.len()
+                )?;
+                for frame in entries {
+                    self.stack_map_frame(frame, indent)?;
+                }
+            }
+            Attribute::BootstrapMethods { methods, .. } => {
+                writeln!(self.out, "{}BootstrapMethods:", indent)?;
+                for (i, method) in methods.iter().enumerate() {
+                    writeln!(
+                        self.out,
+                        "{}  {}: {}",
+                        indent,
+                        i,
+                        method_handle(&method.method_reference)
+                    )?;
+                    writeln!(self.out, "{}    Method arguments:", indent)?;
+                    for argument in &method.arguments {
+                        writeln!(self.out, "{}      {}", indent, loadable(argument))?;
+                    }
+                }
+            }
+            Attribute::NestHost { host_class, .. } => {
+                writeln!(self.out, "{}NestHost: class {}", indent, host_class)?
+            }
+            Attribute::NestMembers { classes, .. } => self.list(indent, "NestMembers", classes)?,
+            Attribute::SourceFile { source_file, .. } => {
+                writeln!(self.out, "{}SourceFile: \"{}\"", indent, source_file)?
+            }
+            Attribute::LineNumberTable { entries, .. } => {
+                writeln!(self.out, "{}LineNumberTable:", indent)?;
+                for entry in entries {
+                    writeln!(
+                        self.out,
+                        "{}  line {}: {}",
+                        indent, entry.line_number, entry.start_pc
+                    )?;
+                }
+            }
+            Attribute::LocalVariableTable { entries, .. } => {
+                self.local_variables(indent, "LocalVariableTable", entries)?
+            }
+            Attribute::LocalVariableTypeTable { entries, .. } => {
+                self.local_variables(indent, "LocalVariableTypeTable", entries)?
+            }
+            Attribute::Signature { signature, .. } => {
+                writeln!(self.out, "{}Signature: {}", indent, signature)?
+            }
+            Attribute::Deprecated { .. } => writeln!(self.out, "{}Deprecated: true", indent)?,
+            Attribute::Synthetic { .. } => writeln!(self.out, "{}Synthetic: true", indent)?,
+            Attribute::RuntimeVisibleAnnotations { name, annotations }
+            | Attribute::RuntimeInvisibleAnnotations { name, annotations } => {
+                writeln!(self.out, "{}{}:", indent, name)?;
+                for (i, annotation) in annotations.iter().enumerate() {
+                    writeln!(
+                        self.out,
+                        "{}  {}: {}",
+                        indent,
+                        i,
+                        java_annotation(annotation)
+                    )?;
+                }
+            }
+            Attribute::RuntimeVisibleParameterAnnotations { name, parameters }
+            | Attribute::RuntimeInvisibleParameterAnnotations { name, parameters } => {
+                writeln!(self.out, "{}{}:", indent, name)?;
+                for (i, annotations) in parameters.iter().enumerate() {
+                    writeln!(self.out, "{}  parameter {}:", indent, i)?;
+                    for (j, annotation) in annotations.iter().enumerate() {
+                        writeln!(
+                            self.out,
+                            "{}    {}: {}",
+                            indent,
+                            j,
+                            java_annotation(annotation)
+                        )?;
+                    }
+                }
+            }
+            Attribute::RuntimeVisibleTypeAnnotations { name, annotations }
+            | Attribute::RuntimeInvisibleTypeAnnotations { name, annotations } => {
+                writeln!(self.out, "{}{}:", indent, name)?;
+                for (i, annotation) in annotations.iter().enumerate() {
+                    writeln!(
+                        self.out,
+                        "{}  {}: {}",
+                        indent,
+                        i,
+                        type_annotation(annotation)
+                    )?;
+                }
+            }
+            Attribute::AnnotationDefault { value, .. } => {
+                writeln!(self.out, "{}AnnotationDefault:", indent)?;
+                writeln!(
+                    self.out,
+                    "{}  default_value: {}",
+                    indent,
+                    element_value(value)
+                )?;
+            }
+            Attribute::Module { .. } => {
+                // Printed together with ModulePackages and ModuleMainClass, like a module-info.java
+                if let Some(module) = class.module_descriptor() {
+                    writeln!(self.out, "{}Module:", indent)?;
+                    let version = module
+                        .version
+                        .as_ref()
+                        .map_or(String::new(), |version| format!("@{}", version));
+                    let open = if module.flags.is_open { "open " } else { "" };
+                    writeln!(
+                        self.out,
+                        "{}  {}module {}{}",
+                        indent, open, module.name, version
+                    )?;
+                    for requires in &module.requires {
+                        let mut flags = String::new();
+                        if requires.flags.is_transitive {
+                            flags += "transitive ";
+                        }
+                        if requires.flags.is_static_phase {
+                            flags += "static ";
+                        }
+                        writeln!(
+                            self.out,
+                            "{}    requires {}{};",
+                            indent, flags, requires.module
+                        )?;
+                    }
+                    for (keyword, packages) in
+                        [("exports", &module.exports), ("opens", &module.opens)]
+                    {
+                        for exports in packages {
+                            let to = if exports.to.is_empty() {
+                                String::new()
+                            } else {
+                                format!(" to {}", exports.to.join(", "))
+                            };
+                            writeln!(
+                                self.out,
+                                "{}    {} {}{};",
+                                indent,
+                                keyword,
+                                java_name(&exports.package),
+                                to
+                            )?;
+                        }
+                    }
+                    for service in &module.uses {
+                        writeln!(self.out, "{}    uses {};", indent, java_name(service))?;
+                    }
+                    for provides in &module.provides {
+                        let with: Vec<_> = provides.with.iter().map(|c| java_name(c)).collect();
+                        writeln!(
+                            self.out,
+                            "{}    provides {} with {};",
+                            indent,
+                            java_name(&provides.service),
+                            with.join(", ")
+                        )?;
+                    }
+                }
+            }
+            Attribute::ModulePackages { packages, .. } => {
+                let packages: Vec<_> = packages.iter().map(|p| java_name(p)).collect();
+                self.list(indent, "ModulePackages", &packages)?
+            }
+            Attribute::ModuleMainClass { main_class, .. } => {
+                writeln!(self.out, "{}ModuleMainClass: class {}", indent, main_class)?
+            }
+            Attribute::Record { components, .. } => {
+                writeln!(self.out, "{}Record:", indent)?;
+                let nested = format!("{}    ", indent);
+                for component in components {
+                    let java_type =
+                        match find_signature(&component.attributes).map(parse_field_signature) {
+                            Some(Ok(signature)) => signature.to_string(),
+                            _ => java_type(&component.descriptor),
+                        };
+                    writeln!(self.out, "{}  {} {};", indent, java_type, component.name)?;
+                    writeln!(
+                        self.out,
+                        "{}    descriptor: {}",
+                        indent, component.descriptor
+                    )?;
+                    for attribute in &component.attributes {
+                        self.attribute(attribute, &nested, None)?;
+                    }
+                }
+            }
+            Attribute::PermittedSubclasses { classes, .. } => {
+                self.list(indent, "PermittedSubclasses", classes)?
+            }
+            Attribute::InnerClasses { classes, .. } => {
+                writeln!(self.out, "{}InnerClasses:", indent)?;
+                for inner in classes {
+                    let name = inner
+                        .inner_name
+                        .as_ref()
+                        .map_or(String::new(), |name| format!("{}=", name));
+                    let outer = inner
+                        .outer_class
+                        .as_ref()
+                        .map_or(String::new(), |outer| format!(" of class {}", outer));
+                    writeln!(
+                        self.out,
+                        "{}  {}{}class {}{};",
+                        indent,
+                        modifiers(inner.access_flags.bits(), MODIFIERS),
+                        name,
+                        inner.inner_class,
+                        outer
+                    )?;
+                }
+            }
+            Attribute::EnclosingMethod {
+                class_name,
+                method: enclosing,
+                ..
+            } => match enclosing {
+                Some((name, descriptor)) => writeln!(
+                    self.out,
+                    "{}EnclosingMethod: {}.{}:{}",
+                    indent,
+                    class_name,
+                    quote_name(name),
+                    descriptor
+                )?,
+                None => writeln!(self.out, "{}EnclosingMethod: {}", indent, class_name)?,
+            },
+            Attribute::Exceptions { exceptions, .. } => {
+                writeln!(self.out, "{}Exceptions:", indent)?;
+                let names: Vec<_> = exceptions.iter().map(|name| java_name(name)).collect();
+                writeln!(self.out, "{}  throws {}", indent, names.join(", "))?;
+            }
+            Attribute::MethodParameters { parameters, .. } => {
+                writeln!(self.out, "{}MethodParameters:", indent)?;
+                writeln!(self.out, "{}  {:<30} Flags", indent, "Name")?;
+                for parameter in parameters {
+                    let mut flags = vec![];
+                    if parameter.access_flags.is_final {
+                        flags.push("final");
+                    }
+                    if parameter.access_flags.is_synthetic {
+                        flags.push("synthetic");
+                    }
+                    if parameter.access_flags.is_mandated {
+                        flags.push("mandated");
+                    }
+                    let name = parameter.name.as_deref().unwrap_or("<no name>");
+                    let line = format!("{}  {:<30} {}", indent, name, flags.join(" "));
+                    writeln!(self.out, "{}", line.trim_end())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, indent: &str, title: &str, items: &[String]) -> Result<()> {
+        writeln!(self.out, "{}{}:", indent, title)?;
+        for item in items {
+            writeln!(self.out, "{}  {}", indent, item)?;
+        }
+        Ok(())
+    }
+
+    fn local_variables(
+        &mut self,
+        indent: &str,
+        title: &str,
+        entries: &[LocalVariable],
+    ) -> Result<()> {
+        writeln!(self.out, "{}{}:", indent, title)?;
+        writeln!(
+            self.out,
+            "{}  Start  Length  Slot  Name   Signature",
+            indent
+        )?;
+        for entry in entries {
+            writeln!(
+                self.out,
+                "{}  {:>5} {:>7} {:>5} {:>5}   {}",
+                indent, entry.start_pc, entry.length, entry.index, entry.name, entry.descriptor
+            )?;
+        }
+        Ok(())
+    }
+
+    fn stack_map_frame(&mut self, frame: &StackMapFrame, indent: &str) -> Result<()> {
+        let delta = frame.offset_delta;
+        let (frame_type, name) = match &frame.kind {
+            StackMapFrameKind::Same => (delta, "same"),
+            StackMapFrameKind::SameLocals1StackItem(_) => (64 + delta, "same_locals_1_stack_item"),
+            StackMapFrameKind::SameLocals1StackItemExtended(_) => {
+                (247, "same_locals_1_stack_item_frame_extended")
+            }
+            StackMapFrameKind::Chop(count) => (251 - *count as u16, "chop"),
+            StackMapFrameKind::SameExtended => (251, "same_frame_extended"),
+            StackMapFrameKind::Append(locals) => (251 + locals.len() as u16, "append"),
+            StackMapFrameKind::Full { .. } => (255, "full_frame"),
+        };
+        writeln!(
+            self.out,
+            "{}  frame_type = {} /* {} */",
+            indent, frame_type, name
+        )?;
+        // The compact forms encode the offset delta in the frame type
+        if !matches!(
+            frame.kind,
+            StackMapFrameKind::Same | StackMapFrameKind::SameLocals1StackItem(_)
+        ) {
+            writeln!(self.out, "{}    offset_delta = {}", indent, delta)?;
+        }
+        match &frame.kind {
+            StackMapFrameKind::SameLocals1StackItem(item)
+            | StackMapFrameKind::SameLocals1StackItemExtended(item) => writeln!(
+                self.out,
+                "{}    stack = {}",
+                indent,
+                verification_types(std::slice::from_ref(item))
+            )?,
+            StackMapFrameKind::Append(locals) => writeln!(
+                self.out,
+                "{}    locals = {}",
+                indent,
+                verification_types(locals)
+            )?,
+            StackMapFrameKind::Full { locals, stack } => {
+                writeln!(
+                    self.out,
+                    "{}    locals = {}",
+                    indent,
+                    verification_types(locals)
+                )?;
+                writeln!(
+                    self.out,
+                    "{}    stack = {}",
+                    indent,
+                    verification_types(stack)
+                )?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // `length` is the instruction's encoded length, which shows whether it was modified by wide
+    fn instruction(
+        &mut self,
+        offset: usize,
+        instruction: &Instruction,
+        length: usize,
+    ) -> Result<()> {
+        let wide = match instruction {
+            IInc { .. } => length == 6,
+            ILoad(_) | LLoad(_) | FLoad(_) | DLoad(_) | ALoad(_) | IStore(_) | LStore(_)
+            | FStore(_) | DStore(_) | AStore(_) | Ret(_) => length == 4,
+            _ => false,
+        };
+        let wide_mnemonic = format!("{}_w", instruction.mnemonic());
+        let mnemonic = if wide {
+            wide_mnemonic.as_str()
+        } else {
+            instruction.mnemonic()
+        };
+        let target = |relative: i64| offset as i64 + relative;
+        let line = match instruction {
+            BIPush(value) => operand(mnemonic, value),
+            SIPush(value) => operand(mnemonic, value),
+            ILoad(index) | LLoad(index) | FLoad(index) | DLoad(index) | ALoad(index)
+            | IStore(index) | LStore(index) | FStore(index) | DStore(index) | AStore(index)
+            | Ret(index) => operand(mnemonic, index),
+            IInc { index, value } => operand(mnemonic, format!("{}, {}", index, value)),
+            IfEq(relative) | IfNe(relative) | IfLt(relative) | IfGe(relative) | IfGt(relative)
+            | IfLe(relative) | IfICmpEq(relative) | IfICmpNe(relative) | IfICmpLt(relative)
+            | IfICmpGe(relative) | IfICmpGt(relative) | IfICmpLe(relative) | IfACmpEq(relative)
+            | IfACmpNe(relative) | Goto(relative) | Jsr(relative) | IfNull(relative)
+            | IfNonNull(relative) => operand(mnemonic, target(*relative as i64)),
+            GotoW(relative) | JsrW(relative) => operand(mnemonic, target(*relative as i64)),
+            NewArray(array_type) => operand(
+                mnemonic,
+                format!(" {}", format!("{:?}", array_type).to_lowercase()),
+            ),
+            Ldc(index) | LdcW(index) | Ldc2W(index) | GetStatic(index) | PutStatic(index)
+            | GetField(index) | PutField(index) | InvokeVirtual(index) | InvokeSpecial(index)
+            | InvokeStatic(index) | New(index) | ANewArray(index) | CheckCast(index)
+            | InstanceOf(index) => self.constant_operand(mnemonic, format!("#{}", index), *index),
+            InvokeInterface { index, count } => {
+                self.constant_operand(mnemonic, format!("#{},  {}", index, count), *index)
+            }
+            InvokeDynamic(index) => {
+                self.constant_operand(mnemonic, format!("#{},  0", index), *index)
+            }
+            MultiANewArray { index, dimensions } => {
+                self.constant_operand(mnemonic, format!("#{},  {}", index, dimensions), *index)
+            }
+            TableSwitch {
+                default,
+                low,
+                offsets,
+            } => {
+                let high = *low as i64 + offsets.len() as i64 - 1;
+                let mut line = format!("{:<13} {{ // {} to {}", mnemonic, low, high);
+                for (i, relative) in offsets.iter().enumerate() {
+                    let key = *low as i64 + i as i64;
+                    line += &format!("\n{:>24}: {}", key, target(*relative as i64));
+                }
+                line += &format!(
+                    "\n{:>24}: {}\n            }}",
+                    "default",
+                    target(*default as i64)
+                );
+                line
+            }
+            LookupSwitch { default, pairs } => {
+                let mut line = format!("{:<13} {{ // {}", mnemonic, pairs.len());
+                for (key, relative) in pairs {
+                    line += &format!("\n{:>24}: {}", key, target(*relative as i64));
+                }
+                line += &format!(
+                    "\n{:>24}: {}\n            }}",
+                    "default",
+                    target(*default as i64)
+                );
+                line
+            }
+            _ => mnemonic.to_string(),
+        };
+        writeln!(self.out, "{:>10}: {}", offset, line)?;
+        Ok(())
+    }
+
+    // e.g. `getfield      #1                  // Field this$0:LLambdas;`
+    fn constant_operand(&self, mnemonic: &str, operands: String, index: u16) -> String {
+        let pool = &self.class.constant_pool;
+        let kind = match pool.get(index).map(Constant::tag) {
+            Ok(ConstantTag::Class) => "class",
+            Ok(ConstantTag::FieldRef) => "Field",
+            Ok(ConstantTag::MethodRef) => "Method",
+            Ok(ConstantTag::InterfaceMethodRef) => "InterfaceMethod",
+            Ok(ConstantTag::ConstString) => "String",
+            Ok(ConstantTag::Integer) => "int",
+            Ok(ConstantTag::Float) => "float",
+            Ok(ConstantTag::Long) => "long",
+            Ok(ConstantTag::Double) => "double",
+            Ok(ConstantTag::MethodHandle) => "MethodHandle",
+            Ok(ConstantTag::MethodType) => "MethodType",
+            Ok(ConstantTag::Dynamic) => "Dynamic",
+            Ok(ConstantTag::InvokeDynamic) => "InvokeDynamic",
+            _ => "",
+        };
+        let mut comment = describe(pool, index, Some(&self.class.name));
+        if !kind.is_empty() {
+            comment = format!("{} {}", kind, comment);
+        }
+        format!("{:<33} // {}", operand(mnemonic, operands), comment)
+    }
+}
+
+fn operand(mnemonic: &str, operand: impl Display) -> String {
+    format!("{:<13} {}", mnemonic, operand)
+}
+
+// What the constant at `index` refers to, e.g. java/lang/Object."<init>":()V. Members of
+// `this_class` are printed without the class name, like javap does in code listings.
+fn describe(pool: &ConstantPool, index: u16, this_class: Option<&str>) -> String {
+    let description = || -> Result<String> {
+        Ok(match pool.get(index)? {
+            ClassInfo { .. } => quote_class(&pool.class_name(index)?),
+            FieldRefInfo { .. } => member(&pool.field_ref(index)?, this_class),
+            MethodRefInfo { .. } | InterfaceMethodRefInfo { .. } => {
+                member(&pool.any_method_ref(index)?, this_class)
+            }
+            StringInfo { .. } => escape(&pool.string(index)?),
+            IntInfo { value, .. } => value.to_string(),
+            FloatInfo { value, .. } => format!("{:?}f", value),
+            LongInfo { value, .. } => format!("{}l", value),
+            DoubleInfo { value, .. } => format!("{:?}d", value),
+            NameAndTypeInfo { .. } => {
+                let (name, descriptor) = pool.name_and_type(index)?;
+                format!("{}:{}", quote_name(&name), descriptor)
+            }
+            Utf8Info { value, .. } => escape(value),
+            MethodHandleInfo { .. } => method_handle(&pool.method_handle(index)?),
+            MethodTypeInfo {
+                descriptor_index, ..
+            } => pool.utf8(*descriptor_index)?,
+            DynamicInfo {
+                bootstrap_method_attr_index,
+                name_and_type_index,
+                ..
+            } => {
+                let (name, descriptor) = pool.name_and_type(*name_and_type_index)?;
+                format!(
+                    "#{}:{}:{}",
+                    bootstrap_method_attr_index,
+                    quote_name(&name),
+                    descriptor
+                )
+            }
+            ModuleInfo { .. } => pool.module_name(index)?,
+            PackageInfo { .. } => pool.package_name(index)?,
+            Empty => String::new(),
+        })
+    };
+    description().unwrap_or_else(|e| format!("<{}>", e))
+}
+
+fn member(reference: &MemberRef, this_class: Option<&str>) -> String {
+    let name = quote_name(&reference.name);
+    if this_class == Some(reference.class_name.as_str()) {
+        format!("{}:{}", name, reference.descriptor)
+    } else {
+        format!(
+            "{}.{}:{}",
+            quote_class(&reference.class_name),
+            name,
+            reference.descriptor
+        )
+    }
+}
+
+fn method_handle(handle: &MethodHandle) -> String {
+    let kind = match handle.kind {
+        ReferenceKind::GetField => "REF_getField",
+        ReferenceKind::GetStatic => "REF_getStatic",
+        ReferenceKind::PutField => "REF_putField",
+        ReferenceKind::PutStatic => "REF_putStatic",
+        ReferenceKind::InvokeVirtual => "REF_invokeVirtual",
+        ReferenceKind::InvokeStatic => "REF_invokeStatic",
+        ReferenceKind::InvokeSpecial => "REF_invokeSpecial",
+        ReferenceKind::NewInvokeSpecial => "REF_newInvokeSpecial",
+        ReferenceKind::InvokeInterface => "REF_invokeInterface",
+    };
+    format!("{} {}", kind, member(&handle.reference, None))
+}
+
+fn loadable(constant: &LoadableConstant) -> String {
+    match constant {
+        LoadableConstant::Integer(value) => format!("int {}", value),
+        LoadableConstant::Float(value) => format!("float {:?}f", value),
+        LoadableConstant::Long(value) => format!("long {}l", value),
+        LoadableConstant::Double(value) => format!("double {:?}d", value),
+        LoadableConstant::String(value) => format!("String {}", escape(value)),
+        LoadableConstant::Class(name) => format!("class {}", quote_class(name)),
+        LoadableConstant::MethodType(descriptor) => format!("MethodType {}", descriptor),
+        LoadableConstant::MethodHandle(handle) => method_handle(handle),
+        LoadableConstant::Dynamic {
+            bootstrap_method_attr_index,
+            name,
+            descriptor,
+        } => format!(
+            "Dynamic #{}:{}:{}",
+            bootstrap_method_attr_index,
+            quote_name(name),
+            descriptor
+        ),
+    }
+}
+
+// Special method names are quoted, e.g. "<init>"
+fn quote_name(name: &str) -> String {
+    if name.starts_with('<') {
+        format!("\"{}\"", name)
+    } else {
+        name.to_string()
+    }
+}
+
+// Array classes are quoted, e.g. "[Ljava/lang/String;"
+fn quote_class(name: &str) -> String {
+    if name.starts_with('[') {
+        format!("\"{}\"", name)
+    } else {
+        name.to_string()
+    }
+}
+
+// Control characters and unpaired surrogates are escaped, everything else is printed as is
+fn escape(string: &JavaString) -> String {
+    let mut escaped = String::new();
+    for c in char::decode_utf16(string.as_utf16().iter().copied()) {
+        match c {
+            Ok('\t') => escaped += "\\t",
+            Ok('\n') => escaped += "\\n",
+            Ok('\r') => escaped += "\\r",
+            Ok('\u{8}') => escaped += "\\b",
+            Ok('\u{c}') => escaped += "\\f",
+            Ok(c) if c.is_control() => escaped += &format!("\\u{:04x}", c as u32),
+            Ok(c) => escaped.push(c),
+            Err(e) => escaped += &format!("\\u{:04x}", e.unpaired_surrogate()),
+        }
+    }
+    escaped
+}
+
+// e.g. (0x0021) ACC_PUBLIC, ACC_SUPER
+fn flags(bits: u16, flags_of: FlagsOf) -> String {
+    let names = flag_names(bits, flags_of);
+    format!("({:#06x}) {}", bits, names.join(", "))
+        .trim_end()
+        .to_string()
+}
+
+// Each followed by a space, e.g. "public static "
+fn modifiers(bits: u16, names: &[(u16, &str)]) -> String {
+    names
+        .iter()
+        .filter(|(flag, _)| bits & flag != 0)
+        .map(|(_, name)| format!("{} ", name))
+        .collect()
+}
+
+fn java_name(internal_name: &str) -> String {
+    internal_name.replace('/', ".")
+}
+
+// e.g. java.lang.String[] for [Ljava/lang/String;
+fn java_type(descriptor: &str) -> String {
+    match parse_field_type(descriptor) {
+        Ok(field_type) => field_type.to_string(),
+        Err(_) => descriptor.to_string(),
+    }
+}
+
+fn join<T: Display>(items: &[T]) -> String {
+    let items: Vec<_> = items.iter().map(|item| item.to_string()).collect();
+    items.join(", ")
+}
+
+fn class_declaration(class: &Class) -> String {
+    let flags = &class.access_flags;
+    if let Some(module) = class.module_descriptor() {
+        let version = module
+            .version
+            .map_or(String::new(), |version| format!("@{}", version));
+        return format!("module {}{}", module.name, version);
+    }
+    let mut bits = flags.bits();
+    if flags.is_interface {
+        bits &= !0x0400; // Interfaces are always abstract
+    }
+    let kind = if flags.is_interface {
+        "interface"
+    } else {
+        "class"
+    };
+    let mut declaration = format!("{}{} ", modifiers(bits, MODIFIERS), kind);
+
+    match class.signature() {
+        Some(Ok(signature)) => declaration += &signature.java_declaration(&class.name),
+        _ => {
+            declaration += &java_name(&class.name);
+            let interfaces: Vec<_> = class.interfaces.iter().map(|i| java_name(i)).collect();
+            if flags.is_interface {
+                if !interfaces.is_empty() {
+                    declaration += &format!(" extends {}", interfaces.join(", "));
+                }
+            } else {
+                if let Some(super_name) = &class.super_name {
+                    if super_name != "java/lang/Object" {
+                        declaration += &format!(" extends {}", java_name(super_name));
+                    }
+                }
+                if !interfaces.is_empty() {
+                    declaration += &format!(" implements {}", interfaces.join(", "));
+                }
+            }
+        }
+    }
+    declaration
+}
+
+// e.g. public static void main(java.lang.String...) throws java.io.IOException
+fn method_declaration(class: &Class, method: &Method) -> String {
+    let flags = &method.access_flags;
+    let bits = flags.bits();
+    let mut declaration = modifiers(bits, MODIFIERS);
+    if class.access_flags.is_interface
+        && !flags.is_abstract
+        && !flags.is_static
+        && !matches!(flags.visibility, Visibility::Private)
+    {
+        declaration += "default ";
+    }
+    declaration += &modifiers(bits, METHOD_MODIFIERS);
+    if method.name == "<clinit>" {
+        return declaration + "{}";
+    }
+
+    let signature = match method.signature() {
+        Some(Ok(signature)) => signature,
+        _ => match parse_method_signature(&method.descriptor) {
+            Ok(signature) => signature,
+            Err(_) => return declaration + &method.name + &method.descriptor,
+        },
+    };
+    if !signature.type_parameters.is_empty() {
+        declaration += &format!("<{}> ", join(&signature.type_parameters));
+    }
+    if method.name == "<init>" {
+        declaration += &java_name(&class.name);
+    } else {
+        match &signature.result {
+            Some(result) => declaration += &result.to_string(),
+            None => declaration += "void",
+        }
+        declaration += " ";
+        declaration += &method.name;
+    }
+
+    let mut parameters = join(&signature.parameters);
+    if method.access_flags.is_varargs && parameters.ends_with("[]") {
+        parameters.truncate(parameters.len() - 2);
+        parameters += "...";
+    }
+    declaration += &format!("({})", parameters);
+
+    // The signature only has a throws clause if it mentions type variables
+    if !signature.throws.is_empty() {
+        declaration += &format!(" throws {}", join(&signature.throws));
+    } else if !method.exceptions().is_empty() {
+        let exceptions: Vec<_> = method.exceptions().iter().map(|e| java_name(e)).collect();
+        declaration += &format!(" throws {}", exceptions.join(", "));
+    }
+    declaration
+}
+
+fn verification_types(types: &[VerificationType]) -> String {
+    let types: Vec<_> = types
+        .iter()
+        .map(|verification_type| match verification_type {
+            VerificationType::Top => "top".to_string(),
+            VerificationType::Integer => "int".to_string(),
+            VerificationType::Float => "float".to_string(),
+            VerificationType::Double => "double".to_string(),
+            VerificationType::Long => "long".to_string(),
+            VerificationType::Null => "null".to_string(),
+            VerificationType::UninitializedThis => "uninitialized_this".to_string(),
+            VerificationType::Object(class) => format!("class {}", quote_class(class)),
+            VerificationType::Uninitialized(offset) => format!("uninitialized {}", offset),
+        })
+        .collect();
+    if types.is_empty() {
+        "[]".to_string()
+    } else {
+        format!("[ {} ]", types.join(", "))
+    }
+}
+
+// As it would be written in source, e.g. @java.lang.annotation.Retention(value=RUNTIME)
+fn java_annotation(annotation: &Annotation) -> String {
+    let elements: Vec<_> = annotation
+        .elements
+        .iter()
+        .map(|element| format!("{}={}", element.name, element_value(&element.value)))
+        .collect();
+    format!(
+        "@{}({})",
+        java_type(&annotation.type_name),
+        elements.join(",")
+    )
+}
+
+fn element_value(value: &ElementValue) -> String {
+    match value {
+        ElementValue::Byte(value) => format!("(byte){}", value),
+        ElementValue::Char(value) => match char::from_u32(*value as u32) {
+            Some(c) => format!("'{}'", escape(&JavaString::from(c.to_string().as_str()))),
+            None => format!("'\\u{:04x}'", value),
+        },
+        ElementValue::Short(value) => format!("(short){}", value),
+        ElementValue::Boolean(value) => value.to_string(),
+        ElementValue::Int(value) => value.to_string(),
+        ElementValue::Long(value) => format!("{}L", value),
+        ElementValue::Float(value) => format!("{:?}f", value),
+        ElementValue::Double(value) => format!("{:?}d", value),
+        ElementValue::String(value) => format!("\"{}\"", escape(value)),
+        ElementValue::Enum {
+            type_name,
+            const_name,
+        } => format!("{}.{}", java_type(type_name), const_name),
+        ElementValue::Class(descriptor) if descriptor == "V" => "void.class".to_string(),
+        ElementValue::Class(descriptor) => format!("{}.class", java_type(descriptor)),
+        ElementValue::Annotation(annotation) => java_annotation(annotation),
+        ElementValue::Array(values) => {
+            let values: Vec<_> = values.iter().map(element_value).collect();
+            format!("[{}]", values.join(","))
+        }
+    }
+}
+
+// e.g. @Nullable(): LOCAL_VARIABLE, {start_pc=8, length=11, index=3}, location=[ARRAY]
+fn type_annotation(annotation: &TypeAnnotation) -> String {
+    let target_type = match annotation.target_type {
+        0x00 => "CLASS_TYPE_PARAMETER",
+        0x01 => "METHOD_TYPE_PARAMETER",
+        0x10 => "CLASS_EXTENDS",
+        0x11 => "CLASS_TYPE_PARAMETER_BOUND",
+        0x12 => "METHOD_TYPE_PARAMETER_BOUND",
+        0x13 => "FIELD",
+        0x14 => "METHOD_RETURN",
+        0x15 => "METHOD_RECEIVER",
+        0x16 => "METHOD_FORMAL_PARAMETER",
+        0x17 => "THROWS",
+        0x40 => "LOCAL_VARIABLE",
+        0x41 => "RESOURCE_VARIABLE",
+        0x42 => "EXCEPTION_PARAMETER",
+        0x43 => "INSTANCEOF",
+        0x44 => "NEW",
+        0x45 => "CONSTRUCTOR_REFERENCE",
+        0x46 => "METHOD_REFERENCE",
+        0x47 => "CAST",
+        0x48 => "CONSTRUCTOR_INVOCATION_TYPE_ARGUMENT",
+        0x49 => "METHOD_INVOCATION_TYPE_ARGUMENT",
+        0x4A => "CONSTRUCTOR_REFERENCE_TYPE_ARGUMENT",
+        0x4B => "METHOD_REFERENCE_TYPE_ARGUMENT",
+        _ => "UNKNOWN",
+    };
+    let target = match &annotation.target {
+        TypeAnnotationTarget::TypeParameter(index) => format!(", param_index={}", index),
+        TypeAnnotationTarget::Supertype(index) => format!(", type_index={}", index),
+        TypeAnnotationTarget::TypeParameterBound {
+            type_parameter_index,
+            bound_index,
+        } => format!(
+            ", param_index={}, bound_index={}",
+            type_parameter_index, bound_index
+        ),
+        TypeAnnotationTarget::Empty => String::new(),
+        TypeAnnotationTarget::FormalParameter(index) => format!(", param_index={}", index),
+        TypeAnnotationTarget::Throws(index) => format!(", type_index={}", index),
+        TypeAnnotationTarget::LocalVariable(ranges) => {
+            let ranges: Vec<_> = ranges
+                .iter()
+                .map(|range| {
+                    format!(
+                        "{{start_pc={}, length={}, index={}}}",
+                        range.start_pc, range.length, range.index
+                    )
+                })
+                .collect();
+            format!(", {}", ranges.join(", "))
+        }
+        TypeAnnotationTarget::Catch(index) => format!(", exception_index={}", index),
+        TypeAnnotationTarget::Offset(offset) => format!(", offset={}", offset),
+        TypeAnnotationTarget::TypeArgument {
+            offset,
+            type_argument_index,
+        } => format!(", offset={}, type_index={}", offset, type_argument_index),
+    };
+    let mut description = format!(
+        "{}: {}{}",
+        java_annotation(&annotation.annotation),
+        target_type,
+        target
+    );
+    if !annotation.type_path.is_empty() {
+        let path: Vec<_> = annotation
+            .type_path
+            .iter()
+            .map(|entry| match entry.kind {
+                TypePathKind::Array => "ARRAY".to_string(),
+                TypePathKind::Nested => "INNER_TYPE".to_string(),
+                TypePathKind::WildcardBound => "WILDCARD".to_string(),
+                TypePathKind::TypeArgument => {
+                    format!("TYPE_ARGUMENT({})", entry.type_argument_index)
+                }
+            })
+            .collect();
+        description += &format!(", location=[{}]", path.join(", "));
+    }
+    description
+}
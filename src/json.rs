@@ -0,0 +1,886 @@
+use std::io::{self, Write};
+
+use serde_json::{json, Map, Value};
+
+use crate::access_flags::{flag_names, FlagsOf};
+use crate::annotations::{
+    Annotation, ElementValue, TypeAnnotation, TypeAnnotationTarget, TypePathKind,
+};
+use crate::attributes::{Attribute, LocalVariable};
+use crate::class::Class;
+use crate::constants::Constant::*;
+use crate::constants::*;
+use crate::error::Result;
+use crate::fields::Field;
+use crate::instructions::Instruction::*;
+use crate::instructions::*;
+use crate::methods::Method;
+use crate::modified_utf8::JavaString;
+use crate::modules::{self, Exports};
+use crate::stack_map::*;
+
+// Serializes a parsed class as JSON, for tools that want to query or diff class metadata.
+//
+// The schema is versioned by the top level "schema_version", which only changes when existing
+// keys are removed or change meaning; new keys may be added without bumping it. Every key listed
+// here is always present, with null standing in for optional values, so queries don't need to
+// guard against missing keys.
+//
+// Class:
+//   { "schema_version": 1, "minor_version", "major_version", "access_flags": flags,
+//     "name", "super_name": string|null, "interfaces": [string],
+//     "constant_pool": [constant], "fields": [field], "methods": [method],
+//     "attributes": [attribute] }
+//
+// Class, package and module names are in internal form, e.g. java/lang/Object. Strings are
+// converted from the class file's UTF-16 with unpaired surrogates replaced by U+FFFD.
+//
+// flags: { "bits": number, "names": ["ACC_PUBLIC", ...] }, the names in bit order
+//
+// constant: { "index", "tag", ... } with the tag named as in the JVMS without the CONSTANT_
+// prefix, and its references resolved:
+//   Utf8, String:                      "value": string
+//   Integer, Float, Long, Double:      "value": number
+//   Class, Module, Package:            "name"
+//   NameAndType:                       "name", "descriptor"
+//   Fieldref, Methodref, InterfaceMethodref: "class", "name", "descriptor"
+//   MethodHandle:                      "reference_kind" (e.g. "REF_invokeStatic"), "reference"
+//                                      holding the member as a Fieldref/Methodref constant
+//   MethodType:                        "descriptor"
+//   Dynamic, InvokeDynamic:            "bootstrap_method_attr_index", "name", "descriptor"
+// The unusable slot after a Long or Double isn't listed. Constants held by attributes, e.g.
+// ConstantValue or bootstrap method arguments, have the same form without the "index". Floats
+// and doubles that aren't finite are written as the strings "NaN", "Infinity" and "-Infinity".
+// Longs are written exactly, so tools that read every number as a double can lose precision past
+// 2^53.
+//
+// field:  { "access_flags": flags, "name", "descriptor", "attributes": [attribute] }
+// method: { "access_flags": flags, "name", "descriptor", "attributes": [attribute] }
+//
+// attribute: { "name", ... } in class file order, with the remaining keys depending on the name.
+// Attributes the parser doesn't understand have "info", their contents as lowercase hex.
+//   Code:  "max_stack", "max_locals", "code_length", "instructions": [instruction],
+//          "exception_table": [{ "start_pc", "end_pc", "handler_pc", "catch_type": string|null }],
+//          "attributes": [attribute]
+//   ConstantValue: "value": constant     StackMapTable: "entries": [frame]
+//   BootstrapMethods: "methods": [{ "method_handle": constant, "arguments": [constant] }]
+//   NestHost: "host_class"               NestMembers, PermittedSubclasses: "classes": [string]
+//   SourceFile: "source_file"            Signature: "signature"
+//   LineNumberTable: "entries": [{ "start_pc", "line_number" }]
+//   LocalVariableTable: "entries": [{ "start_pc", "length", "name", "descriptor", "index" }]
+//   LocalVariableTypeTable: the same, with "signature" in place of "descriptor"
+//   Deprecated, Synthetic: nothing else
+//   Runtime(In)VisibleAnnotations: "annotations": [annotation]
+//   Runtime(In)VisibleParameterAnnotations: "parameters": [[annotation]]
+//   Runtime(In)VisibleTypeAnnotations: "annotations": [type annotation]
+//   AnnotationDefault: "value": element value
+//   Module: "module": { "name", "access_flags": flags, "version": string|null,
+//            "requires": [{ "module", "access_flags": flags, "version": string|null }],
+//            "exports", "opens": [{ "package", "access_flags": flags, "to": [string] }],
+//            "uses": [string], "provides": [{ "service", "with": [string] }] }
+//   ModulePackages: "packages": [string] ModuleMainClass: "main_class"
+//   Record: "components": [{ "name", "descriptor", "attributes": [attribute] }]
+//   InnerClasses: "classes": [{ "inner_class", "outer_class": string|null,
+//                 "inner_name": string|null, "access_flags": flags }]
+//   EnclosingMethod: "class", "method": { "name", "descriptor" }|null
+//   Exceptions: "exceptions": [string]
+//   MethodParameters: "parameters": [{ "name": string|null, "access_flags": flags }]
+//
+// instruction: { "offset", "opcode", ... } with the JVMS mnemonic as the opcode. Instructions
+// modified by wide have the same opcode as their narrow form. Operands, where there are any:
+//   bipush, sipush:           "value"
+//   loads, stores, ret:       "local"
+//   iinc:                     "local", "increment"
+//   branches:                 "target", the absolute offset
+//   tableswitch:              "default", "low", "targets": [offset] for low, low + 1, ...
+//   lookupswitch:             "default", "pairs": [{ "key", "target" }]
+//   newarray:                 "element_type", e.g. "int"
+//   instructions that take a constant pool index: "constant", the resolved constant, or null if
+//   the index doesn't refer to a valid constant. invokeinterface also has "count", and
+//   multianewarray "dimensions".
+//
+// frame: { "offset", "offset_delta", "type", "locals": [verification type],
+//          "stack": [verification type], "chopped": number }
+// with "type" one of "same", "same_locals_1_stack_item", "chop", "append" and "full", and the
+// locals and stack as given by the frame: "append" frames list only the new locals, and
+// "chopped" is 0 except on "chop" frames.
+//
+// verification type: { "type" } with type one of "top", "int", "float", "long", "double",
+// "null", "uninitialized_this", "object" with "class", or "uninitialized" with "offset", the
+// offset of the new instruction that created it.
+//
+// annotation: { "type", "elements": [{ "name", "value": element value }] }, with the type as a
+// field descriptor
+// element value: { "tag", ... } with tag one of
+//   "byte", "char", "short", "boolean", "int", "long", "float", "double", "string": "value"
+//   "enum": "type", "const_name"         "class": "descriptor", a return descriptor
+//   "annotation": "annotation"           "array": "values": [element value]
+// chars are written as their UTF-16 code unit.
+//
+// type annotation: { "target_type", "target": {...}, "type_path": [{ "kind", "argument_index" }],
+// "annotation": annotation }, with target_type the number from the class file and the target's
+// keys depending on it: "type_parameter_index", "supertype_index", "bound_index",
+// "formal_parameter_index", "throws_type_index", "table": [{ "start_pc", "length", "index" }],
+// "exception_table_index", "offset" or "type_argument_index". Path kinds are "array", "nested",
+// "wildcard_bound" and "type_argument".
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+pub fn to_json(class: &Class) -> Result<Value> {
+    let pool = &class.constant_pool;
+    let mut constants = Vec::with_capacity(pool.len());
+    for (index, constant) in pool.iter() {
+        if let Empty = constant {
+            continue; // The second slot of a Long or Double
+        }
+        constants.push(self::constant(pool, index)?);
+    }
+
+    Ok(json!({
+        "schema_version": SCHEMA_VERSION,
+        "minor_version": class.minor_version,
+        "major_version": class.major_version,
+        "access_flags": flags(class.access_flags.bits(), FlagsOf::Class),
+        "name": class.name,
+        "super_name": class.super_name,
+        "interfaces": class.interfaces,
+        "constant_pool": constants,
+        "fields": class.fields.iter().map(|field| self::field(pool, field)).collect::<Vec<_>>(),
+        "methods": class.methods.iter().map(|method| self::method(pool, method)).collect::<Vec<_>>(),
+        "attributes": attributes(pool, &class.attributes),
+    }))
+}
+
+// Pretty printed, followed by a newline
+pub fn write_json(class: &Class, writer: &mut dyn Write) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *writer, &to_json(class)?).map_err(io::Error::from)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+fn field(pool: &ConstantPool, field: &Field) -> Value {
+    json!({
+        "access_flags": flags(field.access_flags.bits(), FlagsOf::Field),
+        "name": field.name,
+        "descriptor": field.descriptor,
+        "attributes": attributes(pool, &field.attributes),
+    })
+}
+
+fn method(pool: &ConstantPool, method: &Method) -> Value {
+    json!({
+        "access_flags": flags(method.access_flags.bits(), FlagsOf::Method),
+        "name": method.name,
+        "descriptor": method.descriptor,
+        "attributes": attributes(pool, &method.attributes),
+    })
+}
+
+fn constant(pool: &ConstantPool, index: u16) -> Result<Value> {
+    let mut object = Map::new();
+    object.insert("index".to_string(), json!(index));
+    let mut insert = |key: &str, value: Value| {
+        object.insert(key.to_string(), value);
+    };
+    match pool.get(index)? {
+        Empty => return Ok(Value::Null),
+        Utf8Info { value, .. } => {
+            insert("tag", json!("Utf8"));
+            insert("value", string(value));
+        }
+        IntInfo { value, .. } => {
+            insert("tag", json!("Integer"));
+            insert("value", json!(value));
+        }
+        FloatInfo { value, .. } => {
+            insert("tag", json!("Float"));
+            insert("value", float(*value));
+        }
+        LongInfo { value, .. } => {
+            insert("tag", json!("Long"));
+            insert("value", json!(value));
+        }
+        DoubleInfo { value, .. } => {
+            insert("tag", json!("Double"));
+            insert("value", double(*value));
+        }
+        ClassInfo { .. } => {
+            insert("tag", json!("Class"));
+            insert("name", json!(pool.class_name(index)?));
+        }
+        StringInfo { .. } => {
+            insert("tag", json!("String"));
+            insert("value", string(&pool.string(index)?));
+        }
+        FieldRefInfo { .. } | MethodRefInfo { .. } | InterfaceMethodRefInfo { .. } => {
+            let reference = match pool.get(index)? {
+                FieldRefInfo { .. } => pool.field_ref(index)?,
+                _ => pool.any_method_ref(index)?,
+            };
+            member(&mut object, &reference);
+        }
+        NameAndTypeInfo { .. } => {
+            let (name, descriptor) = pool.name_and_type(index)?;
+            insert("tag", json!("NameAndType"));
+            insert("name", json!(name));
+            insert("descriptor", json!(descriptor));
+        }
+        MethodHandleInfo {
+            index: reference_index,
+            ..
+        } => {
+            let handle = pool.method_handle(index)?;
+            insert("tag", json!("MethodHandle"));
+            insert("reference_kind", json!(reference_kind(handle.kind)));
+            let mut reference = Map::new();
+            reference.insert("index".to_string(), json!(reference_index));
+            member(&mut reference, &handle.reference);
+            insert("reference", Value::Object(reference));
+        }
+        MethodTypeInfo {
+            descriptor_index, ..
+        } => {
+            insert("tag", json!("MethodType"));
+            insert("descriptor", json!(pool.utf8(*descriptor_index)?));
+        }
+        DynamicInfo {
+            tag,
+            bootstrap_method_attr_index,
+            name_and_type_index,
+        } => {
+            let (name, descriptor) = pool.name_and_type(*name_and_type_index)?;
+            let tag = match tag {
+                ConstantTag::InvokeDynamic => "InvokeDynamic",
+                _ => "Dynamic",
+            };
+            insert("tag", json!(tag));
+            insert(
+                "bootstrap_method_attr_index",
+                json!(bootstrap_method_attr_index),
+            );
+            insert("name", json!(name));
+            insert("descriptor", json!(descriptor));
+        }
+        ModuleInfo { .. } => {
+            insert("tag", json!("Module"));
+            insert("name", json!(pool.module_name(index)?));
+        }
+        PackageInfo { .. } => {
+            insert("tag", json!("Package"));
+            insert("name", json!(pool.package_name(index)?));
+        }
+    }
+    Ok(Value::Object(object))
+}
+
+fn member(object: &mut Map<String, Value>, reference: &MemberRef) {
+    let tag = match reference.tag {
+        ConstantTag::FieldRef => "Fieldref",
+        ConstantTag::InterfaceMethodRef => "InterfaceMethodref",
+        _ => "Methodref",
+    };
+    object.insert("tag".to_string(), json!(tag));
+    object.insert("class".to_string(), json!(reference.class_name));
+    object.insert("name".to_string(), json!(reference.name));
+    object.insert("descriptor".to_string(), json!(reference.descriptor));
+}
+
+fn reference_kind(kind: ReferenceKind) -> &'static str {
+    match kind {
+        ReferenceKind::GetField => "REF_getField",
+        ReferenceKind::GetStatic => "REF_getStatic",
+        ReferenceKind::PutField => "REF_putField",
+        ReferenceKind::PutStatic => "REF_putStatic",
+        ReferenceKind::InvokeVirtual => "REF_invokeVirtual",
+        ReferenceKind::InvokeStatic => "REF_invokeStatic",
+        ReferenceKind::InvokeSpecial => "REF_invokeSpecial",
+        ReferenceKind::NewInvokeSpecial => "REF_newInvokeSpecial",
+        ReferenceKind::InvokeInterface => "REF_invokeInterface",
+    }
+}
+
+// Attributes only hold resolved constants, so unlike the constant pool these have no index
+fn loadable(constant: &LoadableConstant) -> Value {
+    match constant {
+        LoadableConstant::Integer(value) => json!({"tag": "Integer", "value": value}),
+        LoadableConstant::Float(value) => json!({"tag": "Float", "value": float(*value)}),
+        LoadableConstant::Long(value) => json!({"tag": "Long", "value": value}),
+        LoadableConstant::Double(value) => json!({"tag": "Double", "value": double(*value)}),
+        LoadableConstant::String(value) => json!({"tag": "String", "value": string(value)}),
+        LoadableConstant::Class(name) => json!({"tag": "Class", "name": name}),
+        LoadableConstant::MethodType(descriptor) => {
+            json!({"tag": "MethodType", "descriptor": descriptor})
+        }
+        LoadableConstant::MethodHandle(handle) => method_handle(handle),
+        LoadableConstant::Dynamic {
+            bootstrap_method_attr_index,
+            name,
+            descriptor,
+        } => json!({
+            "tag": "Dynamic",
+            "bootstrap_method_attr_index": bootstrap_method_attr_index,
+            "name": name,
+            "descriptor": descriptor,
+        }),
+    }
+}
+
+fn method_handle(handle: &MethodHandle) -> Value {
+    let mut reference = Map::new();
+    member(&mut reference, &handle.reference);
+    json!({
+        "tag": "MethodHandle",
+        "reference_kind": reference_kind(handle.kind),
+        "reference": reference,
+    })
+}
+
+fn attributes(pool: &ConstantPool, attributes: &[Attribute]) -> Vec<Value> {
+    attributes
+        .iter()
+        .map(|attribute| self::attribute(pool, attribute))
+        .collect()
+}
+
+fn attribute(pool: &ConstantPool, attribute: &Attribute) -> Value {
+    let mut object = Map::new();
+    object.insert("name".to_string(), json!(attribute.name()));
+    let mut insert = |key: &str, value: Value| {
+        object.insert(key.to_string(), value);
+    };
+    match attribute {
+        Attribute::Unknown { info, .. } => {
+            let hex: String = info.iter().map(|byte| format!("{:02x}", byte)).collect();
+            insert("info", json!(hex));
+        }
+        Attribute::ConstantValue { value, .. } => insert("value", loadable(value)),
+        Attribute::Code {
+            max_stack,
+            max_locals,
+            code_length,
+            code,
+            exceptions,
+            attributes,
+            ..
+        } => {
+            insert("max_stack", json!(max_stack));
+            insert("max_locals", json!(max_locals));
+            insert("code_length", json!(code_length));
+            let instructions = code
+                .iter()
+                .map(|(offset, instruction)| self::instruction(pool, offset, instruction))
+                .collect();
+            insert("instructions", Value::Array(instructions));
+            let exceptions = exceptions
+                .iter()
+                .map(|exception| {
+                    json!({
+                        "start_pc": exception.start_pc,
+                        "end_pc": exception.end_pc,
+                        "handler_pc": exception.handler_pc,
+                        "catch_type": exception.catch_type,
+                    })
+                })
+                .collect();
+            insert("exception_table", Value::Array(exceptions));
+            insert(
+                "attributes",
+                Value::Array(self::attributes(pool, attributes)),
+            );
+        }
+        Attribute::StackMapTable { entries, .. } => {
+            insert("entries", entries.iter().map(frame).collect());
+        }
+        Attribute::BootstrapMethods { methods, .. } => {
+            let methods = methods
+                .iter()
+                .map(|method| {
+                    json!({
+                        "method_handle": method_handle(&method.method_reference),
+                        "arguments": method.arguments.iter().map(loadable).collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            insert("methods", Value::Array(methods));
+        }
+        Attribute::NestHost { host_class, .. } => insert("host_class", json!(host_class)),
+        Attribute::NestMembers { classes, .. } | Attribute::PermittedSubclasses { classes, .. } => {
+            insert("classes", json!(classes))
+        }
+        Attribute::SourceFile { source_file, .. } => insert("source_file", json!(source_file)),
+        Attribute::LineNumberTable { entries, .. } => {
+            let entries = entries
+                .iter()
+                .map(|entry| json!({"start_pc": entry.start_pc, "line_number": entry.line_number}))
+                .collect();
+            insert("entries", Value::Array(entries));
+        }
+        Attribute::LocalVariableTable { entries, .. } => {
+            insert("entries", local_variables(entries, "descriptor"));
+        }
+        Attribute::LocalVariableTypeTable { entries, .. } => {
+            insert("entries", local_variables(entries, "signature"));
+        }
+        Attribute::Signature { signature, .. } => insert("signature", json!(signature)),
+        Attribute::Deprecated { .. } | Attribute::Synthetic { .. } => {}
+        Attribute::RuntimeVisibleAnnotations { annotations, .. }
+        | Attribute::RuntimeInvisibleAnnotations { annotations, .. } => {
+            insert("annotations", annotations.iter().map(annotation).collect());
+        }
+        Attribute::RuntimeVisibleParameterAnnotations { parameters, .. }
+        | Attribute::RuntimeInvisibleParameterAnnotations { parameters, .. } => {
+            let parameters = parameters
+                .iter()
+                .map(|annotations| annotations.iter().map(annotation).collect())
+                .collect();
+            insert("parameters", Value::Array(parameters));
+        }
+        Attribute::RuntimeVisibleTypeAnnotations { annotations, .. }
+        | Attribute::RuntimeInvisibleTypeAnnotations { annotations, .. } => {
+            insert(
+                "annotations",
+                annotations.iter().map(type_annotation).collect(),
+            );
+        }
+        Attribute::AnnotationDefault { value, .. } => insert("value", element_value(value)),
+        Attribute::Module { module, .. } => insert("module", self::module(module)),
+        Attribute::ModulePackages { packages, .. } => insert("packages", json!(packages)),
+        Attribute::ModuleMainClass { main_class, .. } => insert("main_class", json!(main_class)),
+        Attribute::Record { components, .. } => {
+            let components = components
+                .iter()
+                .map(|component| {
+                    json!({
+                        "name": component.name,
+                        "descriptor": component.descriptor,
+                        "attributes": attributes(pool, &component.attributes),
+                    })
+                })
+                .collect();
+            insert("components", Value::Array(components));
+        }
+        Attribute::InnerClasses { classes, .. } => {
+            let classes = classes
+                .iter()
+                .map(|inner| {
+                    json!({
+                        "inner_class": inner.inner_class,
+                        "outer_class": inner.outer_class,
+                        "inner_name": inner.inner_name,
+                        "access_flags": flags(inner.access_flags.bits(), FlagsOf::InnerClass),
+                    })
+                })
+                .collect();
+            insert("classes", Value::Array(classes));
+        }
+        Attribute::EnclosingMethod {
+            class_name, method, ..
+        } => {
+            insert("class", json!(class_name));
+            let method = match method {
+                Some((name, descriptor)) => json!({"name": name, "descriptor": descriptor}),
+                None => Value::Null,
+            };
+            insert("method", method);
+        }
+        Attribute::Exceptions { exceptions, .. } => insert("exceptions", json!(exceptions)),
+        Attribute::MethodParameters { parameters, .. } => {
+            let parameters = parameters
+                .iter()
+                .map(|parameter| {
+                    json!({
+                        "name": parameter.name,
+                        "access_flags": flags(
+                            parameter.access_flags.bits(),
+                            FlagsOf::Parameter,
+                        ),
+                    })
+                })
+                .collect();
+            insert("parameters", Value::Array(parameters));
+        }
+    }
+    Value::Object(object)
+}
+
+fn local_variables(entries: &[LocalVariable], descriptor_key: &str) -> Value {
+    entries
+        .iter()
+        .map(|entry| {
+            let mut object = Map::new();
+            object.insert("start_pc".to_string(), json!(entry.start_pc));
+            object.insert("length".to_string(), json!(entry.length));
+            object.insert("name".to_string(), json!(entry.name));
+            object.insert(descriptor_key.to_string(), json!(entry.descriptor));
+            object.insert("index".to_string(), json!(entry.index));
+            Value::Object(object)
+        })
+        .collect()
+}
+
+fn instruction(pool: &ConstantPool, offset: usize, instruction: &Instruction) -> Value {
+    let mut object = Map::new();
+    object.insert("offset".to_string(), json!(offset));
+    object.insert("opcode".to_string(), json!(instruction.mnemonic()));
+    let mut insert = |key: &str, value: Value| {
+        object.insert(key.to_string(), value);
+    };
+    let target = |relative: i64| json!(offset as i64 + relative);
+    let constant = |index: &u16| self::constant(pool, *index).unwrap_or(Value::Null);
+    match instruction {
+        BIPush(value) => insert("value", json!(value)),
+        SIPush(value) => insert("value", json!(value)),
+        ILoad(index) | LLoad(index) | FLoad(index) | DLoad(index) | ALoad(index)
+        | IStore(index) | LStore(index) | FStore(index) | DStore(index) | AStore(index)
+        | Ret(index) => insert("local", json!(index)),
+        IInc { index, value } => {
+            insert("local", json!(index));
+            insert("increment", json!(value));
+        }
+        IfEq(relative) | IfNe(relative) | IfLt(relative) | IfGe(relative) | IfGt(relative)
+        | IfLe(relative) | IfICmpEq(relative) | IfICmpNe(relative) | IfICmpLt(relative)
+        | IfICmpGe(relative) | IfICmpGt(relative) | IfICmpLe(relative) | IfACmpEq(relative)
+        | IfACmpNe(relative) | Goto(relative) | Jsr(relative) | IfNull(relative)
+        | IfNonNull(relative) => insert("target", target(*relative as i64)),
+        GotoW(relative) | JsrW(relative) => insert("target", target(*relative as i64)),
+        TableSwitch {
+            default,
+            low,
+            offsets,
+        } => {
+            insert("default", target(*default as i64));
+            insert("low", json!(low));
+            let targets = offsets
+                .iter()
+                .map(|relative| target(*relative as i64))
+                .collect();
+            insert("targets", Value::Array(targets));
+        }
+        LookupSwitch { default, pairs } => {
+            insert("default", target(*default as i64));
+            let pairs = pairs
+                .iter()
+                .map(|(key, relative)| json!({"key": key, "target": target(*relative as i64)}))
+                .collect();
+            insert("pairs", Value::Array(pairs));
+        }
+        NewArray(array_type) => insert(
+            "element_type",
+            json!(format!("{:?}", array_type).to_lowercase()),
+        ),
+        Ldc(index) | LdcW(index) | Ldc2W(index) | GetStatic(index) | PutStatic(index)
+        | GetField(index) | PutField(index) | InvokeVirtual(index) | InvokeSpecial(index)
+        | InvokeStatic(index) | InvokeDynamic(index) | New(index) | ANewArray(index)
+        | CheckCast(index) | InstanceOf(index) => insert("constant", constant(index)),
+        InvokeInterface { index, count } => {
+            insert("constant", constant(index));
+            insert("count", json!(count));
+        }
+        MultiANewArray { index, dimensions } => {
+            insert("constant", constant(index));
+            insert("dimensions", json!(dimensions));
+        }
+        _ => {}
+    }
+    Value::Object(object)
+}
+
+fn frame(frame: &StackMapFrame) -> Value {
+    let none: &[VerificationType] = &[];
+    let (kind, locals, stack, chopped) = match &frame.kind {
+        StackMapFrameKind::Same | StackMapFrameKind::SameExtended => ("same", none, none, 0),
+        StackMapFrameKind::SameLocals1StackItem(item)
+        | StackMapFrameKind::SameLocals1StackItemExtended(item) => (
+            "same_locals_1_stack_item",
+            none,
+            std::slice::from_ref(item),
+            0,
+        ),
+        StackMapFrameKind::Chop(count) => ("chop", none, none, *count),
+        StackMapFrameKind::Append(locals) => ("append", locals.as_slice(), none, 0),
+        StackMapFrameKind::Full { locals, stack } => {
+            ("full", locals.as_slice(), stack.as_slice(), 0)
+        }
+    };
+    json!({
+        "offset": frame.offset,
+        "offset_delta": frame.offset_delta,
+        "type": kind,
+        "locals": locals.iter().map(verification_type).collect::<Vec<_>>(),
+        "stack": stack.iter().map(verification_type).collect::<Vec<_>>(),
+        "chopped": chopped,
+    })
+}
+
+fn verification_type(verification_type: &VerificationType) -> Value {
+    match verification_type {
+        VerificationType::Top => json!({"type": "top"}),
+        VerificationType::Integer => json!({"type": "int"}),
+        VerificationType::Float => json!({"type": "float"}),
+        VerificationType::Long => json!({"type": "long"}),
+        VerificationType::Double => json!({"type": "double"}),
+        VerificationType::Null => json!({"type": "null"}),
+        VerificationType::UninitializedThis => json!({"type": "uninitialized_this"}),
+        VerificationType::Object(class) => json!({"type": "object", "class": class}),
+        VerificationType::Uninitialized(offset) => {
+            json!({"type": "uninitialized", "offset": offset})
+        }
+    }
+}
+
+fn annotation(annotation: &Annotation) -> Value {
+    let elements: Vec<_> = annotation
+        .elements
+        .iter()
+        .map(|element| json!({"name": element.name, "value": element_value(&element.value)}))
+        .collect();
+    json!({"type": annotation.type_name, "elements": elements})
+}
+
+fn element_value(value: &ElementValue) -> Value {
+    match value {
+        ElementValue::Byte(value) => json!({"tag": "byte", "value": value}),
+        ElementValue::Char(value) => json!({"tag": "char", "value": value}),
+        ElementValue::Short(value) => json!({"tag": "short", "value": value}),
+        ElementValue::Boolean(value) => json!({"tag": "boolean", "value": value}),
+        ElementValue::Int(value) => json!({"tag": "int", "value": value}),
+        ElementValue::Long(value) => json!({"tag": "long", "value": value}),
+        ElementValue::Float(value) => json!({"tag": "float", "value": float(*value)}),
+        ElementValue::Double(value) => json!({"tag": "double", "value": double(*value)}),
+        ElementValue::String(value) => json!({"tag": "string", "value": string(value)}),
+        ElementValue::Enum {
+            type_name,
+            const_name,
+        } => json!({"tag": "enum", "type": type_name, "const_name": const_name}),
+        ElementValue::Class(descriptor) => json!({"tag": "class", "descriptor": descriptor}),
+        ElementValue::Annotation(nested) => {
+            json!({"tag": "annotation", "annotation": annotation(nested)})
+        }
+        ElementValue::Array(values) => json!({
+            "tag": "array",
+            "values": values.iter().map(element_value).collect::<Vec<_>>(),
+        }),
+    }
+}
+
+fn type_annotation(type_annotation: &TypeAnnotation) -> Value {
+    let target = match &type_annotation.target {
+        TypeAnnotationTarget::TypeParameter(index) => json!({ "type_parameter_index": index }),
+        TypeAnnotationTarget::Supertype(index) => json!({ "supertype_index": index }),
+        TypeAnnotationTarget::TypeParameterBound {
+            type_parameter_index,
+            bound_index,
+        } => json!({
+            "type_parameter_index": type_parameter_index,
+            "bound_index": bound_index,
+        }),
+        TypeAnnotationTarget::Empty => json!({}),
+        TypeAnnotationTarget::FormalParameter(index) => json!({ "formal_parameter_index": index }),
+        TypeAnnotationTarget::Throws(index) => json!({ "throws_type_index": index }),
+        TypeAnnotationTarget::LocalVariable(ranges) => {
+            let table: Vec<_> = ranges
+                .iter()
+                .map(|range| {
+                    json!({
+                        "start_pc": range.start_pc,
+                        "length": range.length,
+                        "index": range.index,
+                    })
+                })
+                .collect();
+            json!({ "table": table })
+        }
+        TypeAnnotationTarget::Catch(index) => json!({ "exception_table_index": index }),
+        TypeAnnotationTarget::Offset(offset) => json!({ "offset": offset }),
+        TypeAnnotationTarget::TypeArgument {
+            offset,
+            type_argument_index,
+        } => json!({ "offset": offset, "type_argument_index": type_argument_index }),
+    };
+    let type_path: Vec<_> = type_annotation
+        .type_path
+        .iter()
+        .map(|entry| {
+            let kind = match entry.kind {
+                TypePathKind::Array => "array",
+                TypePathKind::Nested => "nested",
+                TypePathKind::WildcardBound => "wildcard_bound",
+                TypePathKind::TypeArgument => "type_argument",
+            };
+            json!({"kind": kind, "argument_index": entry.type_argument_index})
+        })
+        .collect();
+    json!({
+        "target_type": type_annotation.target_type,
+        "target": target,
+        "type_path": type_path,
+        "annotation": annotation(&type_annotation.annotation),
+    })
+}
+
+fn module(module: &modules::Module) -> Value {
+    let exports = |exports: &[Exports]| -> Vec<Value> {
+        exports
+            .iter()
+            .map(|export| {
+                json!({
+                    "package": export.package,
+                    "access_flags": flags(export.flags.bits(), FlagsOf::Exports),
+                    "to": export.to,
+                })
+            })
+            .collect()
+    };
+    let requires: Vec<_> = module
+        .requires
+        .iter()
+        .map(|requires| {
+            json!({
+                "module": requires.module,
+                "access_flags": flags(requires.flags.bits(), FlagsOf::Requires),
+                "version": requires.version,
+            })
+        })
+        .collect();
+    let provides: Vec<_> = module
+        .provides
+        .iter()
+        .map(|provides| json!({"service": provides.service, "with": provides.with}))
+        .collect();
+    json!({
+        "name": module.name,
+        "access_flags": flags(module.flags.bits(), FlagsOf::Module),
+        "version": module.version,
+        "requires": requires,
+        "exports": exports(&module.exports),
+        "opens": exports(&module.opens),
+        "uses": module.uses,
+        "provides": provides,
+    })
+}
+
+fn flags(bits: u16, flags_of: FlagsOf) -> Value {
+    json!({"bits": bits, "names": flag_names(bits, flags_of)})
+}
+
+fn string(value: &JavaString) -> Value {
+    json!(value.to_string_lossy())
+}
+
+// Written via the shortest decimal form of the f32, so e.g. 0.1f is 0.1 rather than the nearest
+// double to it, which still parses back to the same f32
+fn float(value: f32) -> Value {
+    if value.is_finite() {
+        json!(value.to_string().parse::<f64>().unwrap())
+    } else {
+        double(value as f64)
+    }
+}
+
+fn double(value: f64) -> Value {
+    if value.is_nan() {
+        json!("NaN")
+    } else if value.is_infinite() {
+        json!(if value > 0.0 { "Infinity" } else { "-Infinity" })
+    } else {
+        json!(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{ClassBuilder, Jump};
+    use crate::class::{parse_class, write_class};
+    use crate::methods;
+
+    // static void run(int, String), which pushes and pops 7 unless the int is 0, with a frame
+    // where the two paths join
+    fn class() -> Class {
+        let mut builder = ClassBuilder::new("Json");
+        let access_flags = methods::AccessFlags {
+            is_static: true,
+            ..Default::default()
+        };
+        builder
+            .method(access_flags, "run", "(ILjava/lang/String;)V", |code| {
+                let end = code.new_label();
+                code.emit(ILoad0)
+                    .jump(Jump::IfEq, end)
+                    .emit(BIPush(7))
+                    .emit(Pop)
+                    .place(end)
+                    .emit(Return);
+                Ok(())
+            })
+            .unwrap();
+        let mut class = builder.build();
+        if let Attribute::Code { attributes, .. } = &mut class.methods[0].attributes[0] {
+            attributes.push(Attribute::StackMapTable {
+                name: "StackMapTable".to_string(),
+                entries: vec![StackMapFrame {
+                    offset: 7,
+                    offset_delta: 7,
+                    kind: StackMapFrameKind::Full {
+                        locals: vec![
+                            VerificationType::Integer,
+                            VerificationType::Object("java/lang/String".to_string()),
+                        ],
+                        stack: vec![],
+                    },
+                }],
+            });
+        }
+
+        // Parsed back, so the JSON is of what was read from a class file
+        let mut bytes = vec![];
+        write_class(&class, &mut bytes).unwrap();
+        parse_class(&mut &bytes[..]).unwrap()
+    }
+
+    #[test]
+    fn pins_the_schema() {
+        let json = to_json(&class()).unwrap();
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["name"], "Json");
+        assert_eq!(json["super_name"], "java/lang/Object");
+
+        let code = &json["methods"][0]["attributes"][0];
+        assert_eq!(code["name"], "Code");
+        assert_eq!(
+            code["instructions"],
+            json!([
+                {"offset": 0, "opcode": "iload_0"},
+                {"offset": 1, "opcode": "ifeq", "target": 7},
+                {"offset": 4, "opcode": "bipush", "value": 7},
+                {"offset": 6, "opcode": "pop"},
+                {"offset": 7, "opcode": "return"},
+            ])
+        );
+        assert_eq!(
+            code["attributes"][0],
+            json!({
+                "name": "StackMapTable",
+                "entries": [{
+                    "offset": 7,
+                    "offset_delta": 7,
+                    "type": "full",
+                    "locals": [{"type": "int"}, {"type": "object", "class": "java/lang/String"}],
+                    "stack": [],
+                    "chopped": 0,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn writes_pretty_printed_json() {
+        let mut written = vec![];
+        write_json(&class(), &mut written).unwrap();
+        let text = String::from_utf8(written).unwrap();
+        assert!(text.contains("\n  \"schema_version\": 1,\n"));
+        assert!(text.ends_with("}\n"));
+        let parsed: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed, to_json(&class()).unwrap());
+    }
+}
@@ -0,0 +1,34 @@
+#[macro_use]
+extern crate bitflags;
+extern crate pest;
+#[macro_use]
+extern crate pest_derive;
+
+pub mod access_flags;
+pub mod annotations;
+pub mod attributes;
+pub mod builder;
+pub mod class;
+pub mod class_loader;
+pub mod class_path;
+pub mod constants;
+pub mod descriptors;
+pub mod error;
+pub mod execution;
+pub mod fields;
+pub mod heap;
+pub mod inner_classes;
+pub mod instructions;
+pub mod jar;
+pub mod javap;
+pub mod json;
+pub mod logging;
+pub mod methods;
+pub mod modified_utf8;
+pub mod modules;
+pub mod raw;
+pub mod read;
+pub mod runtime;
+pub mod signatures;
+pub mod stack_map;
+pub mod write;
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// Diagnostics are logged through the log crate with one of these categories as the target, e.g.
+// log::trace!(target: INTERPRETER, ...), so each can be turned up separately. Nothing is printed
+// unless a binary installs a Logger.
+pub const CLASS_LOAD: &str = "class-load"; // Classes being found and loaded
+pub const PARSE: &str = "parse"; // The contents of parsed classes
+pub const INTERPRETER: &str = "interpreter"; // Each instruction executed, with the stack and locals
+pub const INVOKE: &str = "invoke"; // Methods being invoked and returned from
+
+pub const CATEGORIES: &[&str] = &[CLASS_LOAD, PARSE, INTERPRETER, INVOKE];
+
+// The category with the given name, also accepting the short names used by -verbose and -Xtrace
+pub fn category(name: &str) -> Option<&'static str> {
+    match name {
+        "class" => Some(CLASS_LOAD),
+        "interp" => Some(INTERPRETER),
+        _ => CATEGORIES
+            .iter()
+            .copied()
+            .find(|&category| category == name),
+    }
+}
+
+// Writes each record as a line like "[info][class-load] Loaded Foo from Foo.class"
+pub struct Logger {
+    levels: HashMap<&'static str, LevelFilter>,
+    default_level: LevelFilter, // For categories without a level of their own
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Logger {
+    // Only warnings and errors are logged until levels are set
+    pub fn new(out: Box<dyn Write + Send>) -> Logger {
+        Logger {
+            levels: HashMap::new(),
+            default_level: LevelFilter::Warn,
+            out: Mutex::new(out),
+        }
+    }
+
+    pub fn set_level(&mut self, category: &'static str, level: LevelFilter) {
+        self.levels.insert(category, level);
+    }
+
+    pub fn set_default_level(&mut self, level: LevelFilter) {
+        self.default_level = level;
+    }
+
+    fn level(&self, category: &str) -> LevelFilter {
+        self.levels
+            .get(category)
+            .copied()
+            .unwrap_or(self.default_level)
+    }
+
+    // Makes this the logger for the log macros. Fails if one is already installed.
+    pub fn install(self) -> Result<(), log::SetLoggerError> {
+        let max_level = self
+            .levels
+            .values()
+            .copied()
+            .chain(Some(self.default_level))
+            .max()
+            .unwrap_or(LevelFilter::Off);
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        // Logging has nowhere to report its own failures
+        if let Ok(mut out) = self.out.lock() {
+            let _ = writeln!(out, "[{}][{}] {}", level, record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut out) = self.out.lock() {
+            let _ = out.flush();
+        }
+    }
+}
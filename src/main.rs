@@ -1,24 +1,21 @@
-#[macro_use]
-extern crate bitflags;
-extern crate pest;
-#[macro_use]
-extern crate pest_derive;
-
 use std::env;
-use std::fs::File;
-
-use class::*;
+use std::fs::{self, File};
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
 
-use crate::execution::*;
+use log::{debug, LevelFilter};
 
-mod attributes;
-mod class;
-mod constants;
-mod execution;
-mod fields;
-mod instructions;
-mod methods;
-mod read;
+use jvm::class::*;
+use jvm::class_loader::ClassLoader;
+use jvm::class_path::{ClassPath, ClassPathEntry};
+use jvm::execution::*;
+use jvm::jar::Manifest;
+use jvm::json::write_json;
+use jvm::logging::*;
+use jvm::raw::parse_raw_class;
+use jvm::runtime::Runtime;
 
 // TODO encode jvm primitives as types
 // TODO work out how references should work - conflict with rust type system?
@@ -30,17 +27,241 @@ mod read;
 //      - Completeness (i.e. will not cover entire spec)
 //      - Verification (i.e. will accept functional programs forbidden by spec)
 fn main() {
-    let args: Vec<_> = env::args().collect();
-    let path = args.get(1).unwrap();
-    println!("Path {}", path);
-    let reader = &mut File::open(path).unwrap();
-    let class = parse_class(reader).unwrap();
-    if class.has_method("<clinit>".to_string()) {
-        load_frame("<clinit>", &class, vec![]).exec();
-    }
-    if !class.has_method("main".to_string()) {
-        println!("No main method");
-    }
-    let result = load_frame("main", &class, vec![]).exec();
-    println!("Result: {:?}", result);
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    let out: Box<dyn Write + Send> = match &options.log_file {
+        // Line buffered so the trace leading up to a panic isn't lost
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(LineWriter::new(file)),
+            Err(e) => {
+                eprintln!("Could not create {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => Box::new(io::stderr()),
+    };
+    let mut logger = Logger::new(out);
+    for &(category, level) in &options.levels {
+        logger.set_level(category, level);
+    }
+    logger.install().expect("the logger is only installed once");
+
+    let main = &options.main;
+    let (class_path, name) = match class_path(&options) {
+        Ok(class_path) => class_path,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", main, e);
+            process::exit(1);
+        }
+    };
+    let runtime = Runtime::new(ClassLoader::new(class_path));
+    if options.json {
+        let class = match runtime.loader().load(&name) {
+            Ok(class) => class,
+            Err(e) => {
+                eprintln!("Could not load {}: {}", main, e);
+                process::exit(1);
+            }
+        };
+        if let Err(e) = write_json(&class, &mut io::stdout().lock()) {
+            eprintln!("Could not print {}: {}", main, e);
+            process::exit(1);
+        }
+        return;
+    }
+    let class = match runtime.link(&name) {
+        Ok(class) => class,
+        Err(thrown) => {
+            eprintln!("Could not load {}: {}", main, thrown);
+            process::exit(1);
+        }
+    };
+    dump_class(&class.class);
+    // There are no arrays to pass arguments in, so main takes none and can return anything
+    let main_method = class.class.methods.iter().position(|method| {
+        method.name == "main" && method.access_flags.is_static && method.arg_types.is_empty()
+    });
+    let main_method = match main_method {
+        Some(index) => index,
+        None => {
+            eprintln!("No static main() method in {}", class.name());
+            process::exit(1);
+        }
+    };
+    // The main class is initialized before main runs (JVMS 5.2)
+    let result = runtime
+        .initialize(&class)
+        .and_then(|()| load_frame(&runtime, Rc::clone(&class.class), main_method, vec![])?.exec());
+    match result {
+        Ok(result) => println!("Result: {:?}", result),
+        Err(thrown) => {
+            eprintln!("Exception in thread \"main\" {}", thrown);
+            process::exit(1);
+        }
+    }
+}
+
+// The class path to run with and the name of the class to run. A class file is run as the first
+// entry, and without -cp its package root is searched for the classes it uses. A jar is run with
+// only itself and its manifest's Class-Path as the class path, like java -jar.
+fn class_path(options: &Options) -> Result<(ClassPath, String), String> {
+    let mut class_path = ClassPath::new();
+    let main = Path::new(&options.main);
+    if options.jar {
+        class_path.add_jar(main).map_err(|e| e.to_string())?;
+        let name = match class_path.entries().first() {
+            Some(ClassPathEntry::Jar(jar)) => jar.manifest().and_then(Manifest::main_class),
+            _ => None,
+        };
+        let name = name.ok_or("no Main-Class in its manifest")?.to_string();
+        return Ok((class_path, name));
+    }
+
+    let (name, root) = if main.is_file() {
+        let bytes = fs::read(main).map_err(|e| e.to_string())?;
+        let name = parse_raw_class(&bytes)
+            .and_then(|class| Ok(class.name()?.into_owned()))
+            .map_err(|e| e.to_string())?;
+        class_path.add(ClassPathEntry::File {
+            name: name.clone(),
+            path: main.to_path_buf(),
+        });
+        let directory = main.parent().unwrap_or_else(|| Path::new(""));
+        let root = match name.rsplit_once('/') {
+            Some((package, _)) if directory.ends_with(package) => package
+                .split('/')
+                .try_fold(directory, |root, _| root.parent()),
+            Some(_) => None,
+            None => Some(directory),
+        };
+        let root = root.map(|root| match root.as_os_str().is_empty() {
+            true => ".".to_string(),
+            false => root.display().to_string(),
+        });
+        (name, root)
+    } else {
+        (options.main.clone(), None)
+    };
+
+    let paths = match (&options.class_path, root) {
+        (Some(paths), _) => paths.clone(),
+        (None, Some(root)) => root,
+        (None, None) => env::var("CLASSPATH").unwrap_or_else(|_| ".".to_string()),
+    };
+    class_path.add_paths(&paths).map_err(|e| e.to_string())?;
+    Ok((class_path, name))
+}
+
+const USAGE: &str = "Usage: jvm [options] <class file | class name>
+       jvm [options] -jar <jar file>
+
+Options:
+  -cp, -classpath <paths>         Where to find classes, separated by ':' (';' on Windows).
+                                  Defaults to $CLASSPATH or the current directory, or when
+                                  running a class file, to the root of its package.
+  --json                          Print the class as JSON instead of running it
+  -verbose:class                  Log each class as it's loaded
+  -Xtrace[:<category>,...]        Log everything in the given categories, or in all of them
+  -Xlog:<category>=<level>,...    Set the level each category is logged at: off, error, warn,
+                                  info, debug or trace. Only warnings and errors are logged
+                                  by default.
+  -Xlog-file:<path>               Log to a file instead of stderr
+
+Categories: class-load (or class), parse, interpreter (or interp), invoke";
+
+struct Options {
+    json: bool, // --json prints the class as JSON (see jvm::json for the schema)
+    levels: Vec<(&'static str, LevelFilter)>, // Later levels override earlier ones
+    log_file: Option<String>,
+    class_path: Option<String>,
+    jar: bool,    // -jar runs the manifest's Main-Class
+    main: String, // A class file, the binary name of a class on the class path, or a jar
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut json = false;
+    let mut levels = vec![];
+    let mut log_file = None;
+    let mut class_path = None;
+    let mut jar = false;
+    let mut mains = vec![];
+    while let Some(arg) = args.next() {
+        if arg == "--json" {
+            json = true;
+        } else if arg == "-verbose:class" {
+            levels.push((CLASS_LOAD, LevelFilter::Info));
+        } else if arg == "-Xtrace" {
+            levels.extend(
+                CATEGORIES
+                    .iter()
+                    .map(|&category| (category, LevelFilter::Trace)),
+            );
+        } else if let Some(names) = arg.strip_prefix("-Xtrace:") {
+            for name in names.split(',') {
+                levels.push((parse_category(name)?, LevelFilter::Trace));
+            }
+        } else if let Some(settings) = arg.strip_prefix("-Xlog:") {
+            for setting in settings.split(',') {
+                let (name, level) = setting
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected <category>=<level>, found {}", setting))?;
+                let level = level
+                    .parse()
+                    .map_err(|_| format!("Unknown log level {}", level))?;
+                levels.push((parse_category(name)?, level));
+            }
+        } else if let Some(path) = arg.strip_prefix("-Xlog-file:") {
+            log_file = Some(path.to_string());
+        } else if arg == "-jar" {
+            jar = true;
+        } else if arg == "-cp" || arg == "-classpath" {
+            let paths = args
+                .next()
+                .ok_or_else(|| format!("Expected a class path after {}", arg))?;
+            class_path = Some(paths);
+        } else if arg.starts_with('-') {
+            return Err(format!("Unknown option {}", arg));
+        } else {
+            mains.push(arg);
+        }
+    }
+    let main = match mains.as_slice() {
+        [main] => main.clone(),
+        [] => return Err("Expected a class to run".to_string()),
+        _ => return Err("Expected only one class to run".to_string()),
+    };
+    Ok(Options {
+        json,
+        levels,
+        log_file,
+        class_path,
+        jar,
+        main,
+    })
+}
+
+fn parse_category(name: &str) -> Result<&'static str, String> {
+    category(name).ok_or_else(|| format!("Unknown log category {}", name))
+}
+
+fn dump_class(class: &Class) {
+    debug!(target: PARSE, "Constant pool count {}", class.constant_pool.len() + 1);
+    for (i, constant) in class.constant_pool.iter() {
+        debug!(target: PARSE, "Constant {}: {:?}", i, constant);
+    }
+    debug!(target: PARSE, "This: {:?}", class.name);
+    debug!(target: PARSE, "Super: {:?}", class.super_name);
+    debug!(target: PARSE, "Interfaces {:?}", class.interfaces);
+    debug!(target: PARSE, "Fields {:?}", class.fields);
+    for (i, method) in class.methods.iter().enumerate() {
+        debug!(target: PARSE, "Method {}: {:?}", i, method);
+    }
+    for (i, attribute) in class.attributes.iter().enumerate() {
+        debug!(target: PARSE, "Attribute {}: {:?}", i, attribute);
+    }
 }
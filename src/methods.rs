@@ -1,19 +1,23 @@
-use std::io::{Read, Result};
-
-use bitflags;
-use pest::iterators::Pair;
-use pest::Parser;
-use regex::Regex;
+use std::io::Write;
 
+use crate::access_flags::reserved_bits;
+use crate::annotations::{Annotation, ElementValue};
 use crate::attributes::*;
 use crate::constants::*;
+use crate::descriptors::{parse_method_descriptor, FieldType, ReturnType};
+use crate::error::{ErrorKind, Result};
+use crate::instructions::Bytecode;
 use crate::methods::Visibility::*;
 use crate::read::*;
+use crate::signatures::{parse_method_signature, MethodSignature};
+use crate::write::*;
 
-#[derive(Debug)]
-enum Visibility {
+#[derive(Debug, Default)]
+pub enum Visibility {
     Private,
     Protected,
+    #[default]
+    Package,
     Public,
 }
 
@@ -34,22 +38,36 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
-struct AccessFlags {
-    visibility: Visibility,
-    is_static: bool,
-    is_final: bool,
-    is_synchronized: bool, // This probably won't matter unless I do multi-threading
-    is_bridge: bool,
-    is_varargs: bool,
-    is_native: bool,
-    is_abstract: bool,
-    is_strict: bool,
-    is_synthetic: bool,
+#[derive(Debug, Default)]
+pub struct AccessFlags {
+    pub visibility: Visibility,
+    pub is_static: bool,
+    pub is_final: bool,
+    pub is_synchronized: bool, // This probably won't matter unless I do multi-threading
+    pub is_bridge: bool,
+    pub is_varargs: bool,
+    pub is_native: bool,
+    pub is_abstract: bool,
+    pub is_strict: bool,
+    pub is_synthetic: bool,
+    pub reserved: u16, // Unassigned bits, see access_flags::reserved_bits
 }
 
 fn parse_access_flags(mask: u16) -> Result<AccessFlags> {
-    let flags = AccessFlagsBits::from_bits(mask).unwrap();
+    let flags = AccessFlagsBits::from_bits_truncate(mask);
+
+    let visibility_flags =
+        flags & (AccessFlagsBits::PUBLIC | AccessFlagsBits::PRIVATE | AccessFlagsBits::PROTECTED);
+    let not_abstract = AccessFlagsBits::PRIVATE
+        | AccessFlagsBits::STATIC
+        | AccessFlagsBits::FINAL
+        | AccessFlagsBits::SYNCHRONIZED
+        | AccessFlagsBits::NATIVE;
+    if visibility_flags.bits().count_ones() > 1
+        || (flags.contains(AccessFlagsBits::ABSTRACT) && flags.intersects(not_abstract))
+    {
+        return Err(ErrorKind::InvalidAccessFlags(mask).into());
+    }
 
     let visibility = if flags.contains(AccessFlagsBits::PRIVATE) {
         Private
@@ -58,9 +76,7 @@ fn parse_access_flags(mask: u16) -> Result<AccessFlags> {
     } else if flags.contains(AccessFlagsBits::PUBLIC) {
         Public
     } else {
-        // Default to public
-        // TODO make this an error at some point
-        Public
+        Package
     };
 
     Ok(AccessFlags {
@@ -74,15 +90,42 @@ fn parse_access_flags(mask: u16) -> Result<AccessFlags> {
         is_abstract: flags.contains(AccessFlagsBits::ABSTRACT),
         is_strict: flags.contains(AccessFlagsBits::STRICT),
         is_synthetic: flags.contains(AccessFlagsBits::SYNTHETIC),
+        reserved: reserved_bits(mask, AccessFlagsBits::all().bits()),
     })
 }
 
+impl AccessFlags {
+    pub fn bits(&self) -> u16 {
+        let mut flags = AccessFlagsBits::empty();
+        flags.set(AccessFlagsBits::PUBLIC, matches!(self.visibility, Public));
+        flags.set(AccessFlagsBits::PRIVATE, matches!(self.visibility, Private));
+        flags.set(
+            AccessFlagsBits::PROTECTED,
+            matches!(self.visibility, Protected),
+        );
+        flags.set(AccessFlagsBits::STATIC, self.is_static);
+        flags.set(AccessFlagsBits::FINAL, self.is_final);
+        flags.set(AccessFlagsBits::SYNCHRONIZED, self.is_synchronized);
+        flags.set(AccessFlagsBits::BRIDGE, self.is_bridge);
+        flags.set(AccessFlagsBits::VARARGS, self.is_varargs);
+        flags.set(AccessFlagsBits::NATIVE, self.is_native);
+        flags.set(AccessFlagsBits::ABSTRACT, self.is_abstract);
+        flags.set(AccessFlagsBits::STRICT, self.is_strict);
+        flags.set(AccessFlagsBits::SYNTHETIC, self.is_synthetic);
+        flags.bits() | self.reserved
+    }
+}
+
 #[derive(Debug)]
 pub struct Method {
-    access_flags: AccessFlags,
+    pub access_flags: AccessFlags,
     pub name: String,
-    return_type: ReturnDescriptor, // TODO do I want this to be an enum?
-    arg_types: Vec<FieldDescriptor>, // TODO do I want this to be an enum?
+    pub descriptor: String,
+    // The Utf8 constants they were read from, which hold them exactly
+    pub name_index: u16,
+    pub descriptor_index: u16,
+    pub return_type: ReturnType,
+    pub arg_types: Vec<FieldType>,
     pub attributes: Vec<Attribute>,
 }
 
@@ -90,128 +133,174 @@ impl Method {
     pub fn num_args(&self) -> usize {
         self.arg_types.len()
     }
-}
 
-#[derive(Debug)]
-enum FieldDescriptor {
-    Byte,
-    Char,
-    Double,
-    Float,
-    Int,
-    Long,
-    Ref(String),
-    Short,
-    Bool,
-    //Array(FieldDescriptor), TODO work out how to store type
-    Array,
-    Placeholder,
-}
+    // Local variable slots the arguments are passed in, not counting the receiver
+    pub fn arg_slots(&self) -> usize {
+        self.arg_types.iter().map(FieldType::slots).sum()
+    }
 
-#[derive(Parser)]
-#[grammar = "method_descriptor.pest"]
-pub struct MethodDescriptor;
-
-fn parse_field(field: Pair<Rule>) -> FieldDescriptor {
-    let inner = field.into_inner().peek().unwrap();
-    match inner.as_rule() {
-        Rule::base => match inner.as_span().as_str() {
-            "B" => FieldDescriptor::Byte,
-            "C" => FieldDescriptor::Char,
-            "D" => FieldDescriptor::Double,
-            "F" => FieldDescriptor::Float,
-            "I" => FieldDescriptor::Int,
-            "J" => FieldDescriptor::Long,
-            "S" => FieldDescriptor::Short,
-            "Z" => FieldDescriptor::Bool,
-            _ => FieldDescriptor::Placeholder,
-        },
-        Rule::reference => FieldDescriptor::Ref(
-            inner
-                .into_inner()
-                .peek()
-                .unwrap()
-                .as_span()
-                .as_str()
-                .parse()
-                .unwrap(),
-        ),
-        Rule::array => FieldDescriptor::Array,
-        x => {
-            println!("Unknown field descriptor ({:?})", x);
-            FieldDescriptor::Placeholder
-        }
+    // Declared checked exceptions, from the throws clause
+    pub fn exceptions(&self) -> &[String] {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Exceptions { exceptions, .. } => Some(exceptions.as_slice()),
+                _ => None,
+            })
+            .unwrap_or(&[])
     }
-}
 
-#[derive(Debug)]
-enum ReturnDescriptor {
-    Return(FieldDescriptor),
-    Void,
-}
+    // Only present if the class was compiled with -parameters. There may be fewer entries than
+    // arg_types, since compilers can leave out synthetic parameters.
+    pub fn parameters(&self) -> &[MethodParameter] {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::MethodParameters { parameters, .. } => Some(parameters.as_slice()),
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
 
-fn parse_return(pair: Pair<Rule>) -> ReturnDescriptor {
-    match pair.as_str() {
-        "V" => ReturnDescriptor::Void,
-        _ => ReturnDescriptor::Return(parse_field(pair.into_inner().peek().unwrap())),
+    // None if the method's type isn't generic
+    pub fn signature(&self) -> Option<Result<MethodSignature>> {
+        find_signature(&self.attributes).map(parse_method_signature)
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        is_deprecated(&self.attributes)
+    }
+
+    pub fn annotations(&self) -> Vec<&Annotation> {
+        find_annotations(&self.attributes)
+    }
+
+    // The default value of an annotation interface element
+    pub fn annotation_default(&self) -> Option<&ElementValue> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::AnnotationDefault { value, .. } => Some(value),
+                _ => None,
+            })
+    }
+
+    pub fn get_code(&self) -> Option<&Bytecode> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Code { code, .. } => Some(code),
+                _ => None,
+            })
     }
-}
 
-// TODO turn this into proper error handing
-fn parse_descriptor(descriptor: &str) -> Option<(Vec<FieldDescriptor>, ReturnDescriptor)> {
-    let mut arg_types = vec![];
-    let mut return_type = None;
+    // Attributes nested in the Code attribute, such as the debugging tables
+    fn code_attributes(&self) -> &[Attribute] {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Code { attributes, .. } => Some(attributes.as_slice()),
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
 
-    let parse = MethodDescriptor::parse(Rule::method, &descriptor).unwrap();
-    let method = parse.peek().unwrap();
+    // Source line of the instruction at `offset`, if the method was compiled with line numbers
+    pub fn line_number(&self, offset: usize) -> Option<u16> {
+        // There may be several tables, and their entries can be in any order
+        self.code_attributes()
+            .iter()
+            .filter_map(|attribute| match attribute {
+                Attribute::LineNumberTable { entries, .. } => Some(entries),
+                _ => None,
+            })
+            .flatten()
+            .filter(|entry| entry.start_pc as usize <= offset)
+            .max_by_key(|entry| entry.start_pc)
+            .map(|entry| entry.line_number)
+    }
 
-    let descriptors = method.into_inner();
+    // The local variable in `slot` at `offset`, if the method was compiled with variable names
+    pub fn local_variable(&self, offset: usize, slot: u16) -> Option<&LocalVariable> {
+        self.find_local_variable(offset, slot, false)
+    }
 
-    for pair in descriptors {
-        match pair.as_rule() {
-            Rule::field => {
-                arg_types.push(parse_field(pair));
-            }
-            Rule::result => {
-                return_type = Some(parse_return(pair));
-            }
-            _ => {}
-        };
+    // Like local_variable, but with the generic signature in place of the descriptor. Only
+    // present for variables whose type uses type variables or parameterized types.
+    pub fn local_variable_type(&self, offset: usize, slot: u16) -> Option<&LocalVariable> {
+        self.find_local_variable(offset, slot, true)
     }
-    match return_type {
-        Some(x) => Some((arg_types, x)),
-        None => None,
+
+    fn find_local_variable(
+        &self,
+        offset: usize,
+        slot: u16,
+        generic: bool,
+    ) -> Option<&LocalVariable> {
+        self.code_attributes()
+            .iter()
+            .filter_map(|attribute| match attribute {
+                Attribute::LocalVariableTable { entries, .. } if !generic => Some(entries),
+                Attribute::LocalVariableTypeTable { entries, .. } if generic => Some(entries),
+                _ => None,
+            })
+            .flatten()
+            .find(|variable| variable.index == slot && variable.in_scope(offset))
     }
 }
 
-pub fn parse_methods<'a>(
-    reader: &mut dyn Read,
-    constant_pool: &'a Vec<Constant>,
+pub fn parse_methods(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
 ) -> Result<Vec<Method>> {
     let mut methods = Vec::new();
     let methods_count = read_u2(reader)?;
 
-    for _ in 0..methods_count {
-        let access_flags = parse_access_flags(read_u2(reader)?)?;
+    for i in 0..methods_count {
+        let method = reader.within(format!("method #{}", i), |reader| {
+            let access_flags = parse_access_flags(read_u2(reader)?)?;
 
-        let name = resolve_utf8(read_u2(reader)? as usize, constant_pool).unwrap();
-        // println!("Method: {}", name);
-        let descriptor = resolve_utf8(read_u2(reader)? as usize, constant_pool).unwrap();
+            let name_index = read_u2(reader)?;
+            let name = constant_pool.utf8(name_index)?;
+            let descriptor_index = read_u2(reader)?;
+            let descriptor = constant_pool.utf8(descriptor_index)?;
 
-        let caps = Regex::new(r"\((.*)\)(.*)")
-            .unwrap()
-            .captures(descriptor.as_ref())
-            .unwrap();
-        let (arg_types, return_type) = parse_descriptor(caps.get(0).unwrap().as_str()).unwrap();
+            let (arg_types, return_type) = parse_method_descriptor(&descriptor)?;
 
-        methods.push(Method {
-            access_flags,
-            name,
-            return_type,
-            arg_types,
-            attributes: parse_attributes(reader, constant_pool)?,
-        });
+            Ok(Method {
+                access_flags,
+                name,
+                descriptor,
+                name_index,
+                descriptor_index,
+                return_type,
+                arg_types,
+                attributes: parse_attributes(reader, constant_pool)?,
+            })
+        })?;
+        methods.push(method);
     }
 
     Ok(methods)
 }
+
+pub fn write_methods(
+    methods: &[Method],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_count(methods.len(), writer)?;
+    for method in methods {
+        write_u2(method.access_flags.bits(), writer)?;
+        write_u2(
+            constant_pool.add_utf8_from(method.name_index, &method.name)?,
+            writer,
+        )?;
+        write_u2(
+            constant_pool.add_utf8_from(method.descriptor_index, &method.descriptor)?,
+            writer,
+        )?;
+        write_attributes(&method.attributes, writer, constant_pool)?;
+    }
+    Ok(())
+}
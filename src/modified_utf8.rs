@@ -0,0 +1,182 @@
+use std::char;
+use std::fmt;
+
+use crate::error::{ErrorKind, Result};
+
+// A string as Java sees it: a sequence of UTF-16 code units. Unlike a Rust String this can hold
+// unpaired surrogates, so any CONSTANT_Utf8 entry can be decoded and encoded again without loss.
+#[derive(Clone, Default, Eq, PartialEq, Hash)]
+pub struct JavaString(Vec<u16>);
+
+impl JavaString {
+    pub fn from_utf16(units: Vec<u16>) -> JavaString {
+        JavaString(units)
+    }
+
+    pub fn as_utf16(&self) -> &[u16] {
+        &self.0
+    }
+
+    // Decode the JVM's modified UTF-8, where NUL is encoded as C0 80 and supplementary characters
+    // are encoded as a surrogate pair of 3 byte sequences (CESU-8), per JVMS 4.4.7
+    pub fn from_modified_utf8(bytes: &[u8]) -> Result<JavaString> {
+        let mut units = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+
+        // Continuation bytes have the form 10xxxxxx
+        let continuation = |i: usize| match bytes.get(i) {
+            Some(byte) if byte & 0xC0 == 0x80 => Ok((byte & 0x3F) as u16),
+            _ => Err(ErrorKind::InvalidUtf8),
+        };
+
+        while i < bytes.len() {
+            let first = bytes[i] as u16;
+            let unit = if first & 0x80 == 0 {
+                // No byte may be zero, NUL is always encoded as C0 80
+                if first == 0 {
+                    return Err(ErrorKind::InvalidUtf8.into());
+                }
+                i += 1;
+                first
+            } else if first & 0xE0 == 0xC0 {
+                let unit = ((first & 0x1F) << 6) | continuation(i + 1)?;
+                // Only NUL may use a longer form than necessary
+                if unit != 0 && unit < 0x80 {
+                    return Err(ErrorKind::InvalidUtf8.into());
+                }
+                i += 2;
+                unit
+            } else if first & 0xF0 == 0xE0 {
+                let unit =
+                    ((first & 0x0F) << 12) | (continuation(i + 1)? << 6) | continuation(i + 2)?;
+                if unit < 0x800 {
+                    return Err(ErrorKind::InvalidUtf8.into());
+                }
+                i += 3;
+                unit
+            } else {
+                // Standard 4 byte forms never appear in modified UTF-8
+                return Err(ErrorKind::InvalidUtf8.into());
+            };
+            units.push(unit);
+        }
+
+        Ok(JavaString(units))
+    }
+
+    pub fn to_modified_utf8(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.0.len());
+        for &unit in &self.0 {
+            if unit != 0 && unit < 0x80 {
+                bytes.push(unit as u8);
+            } else if unit < 0x800 {
+                bytes.push(0xC0 | (unit >> 6) as u8);
+                bytes.push(0x80 | (unit & 0x3F) as u8);
+            } else {
+                bytes.push(0xE0 | (unit >> 12) as u8);
+                bytes.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                bytes.push(0x80 | (unit & 0x3F) as u8);
+            }
+        }
+        bytes
+    }
+
+    // None if the string contains unpaired surrogates
+    pub fn try_to_string(&self) -> Option<String> {
+        String::from_utf16(&self.0).ok()
+    }
+
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf16_lossy(&self.0)
+    }
+}
+
+impl From<&str> for JavaString {
+    fn from(s: &str) -> JavaString {
+        JavaString(s.encode_utf16().collect())
+    }
+}
+
+impl fmt::Display for JavaString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_lossy())
+    }
+}
+
+// Formats like a quoted Rust string, with unpaired surrogates escaped rather than replaced
+impl fmt::Debug for JavaString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"")?;
+        for c in char::decode_utf16(self.0.iter().copied()) {
+            match c {
+                Ok(c) => write!(f, "{}", c.escape_debug())?,
+                Err(e) => write!(f, "\\u{{{:x}}}", e.unpaired_surrogate())?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> JavaString {
+        JavaString::from_modified_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn nul_is_encoded_as_two_bytes() {
+        let string = JavaString::from("a\0b");
+        assert_eq!(string.to_modified_utf8(), [b'a', 0xC0, 0x80, b'b']);
+        assert_eq!(decode(&[b'a', 0xC0, 0x80, b'b']), string);
+        assert!(JavaString::from_modified_utf8(&[b'a', 0, b'b']).is_err());
+    }
+
+    #[test]
+    fn supplementary_characters_are_encoded_as_surrogate_pairs() {
+        // U+1F600 is the surrogate pair D83D DE00, each of which is encoded on its own
+        let bytes = [0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80];
+        let string = JavaString::from("\u{1F600}");
+        assert_eq!(string.as_utf16(), [0xD83D, 0xDE00]);
+        assert_eq!(string.to_modified_utf8(), bytes);
+        assert_eq!(decode(&bytes), string);
+        assert_eq!(decode(&bytes).try_to_string().unwrap(), "\u{1F600}");
+    }
+
+    #[test]
+    fn unpaired_surrogates_are_kept() {
+        let bytes = [b'a', 0xED, 0xB8, 0x80];
+        let string = decode(&bytes);
+        assert_eq!(string.as_utf16(), [0x61, 0xDE00]);
+        assert_eq!(string.to_modified_utf8(), bytes);
+        assert_eq!(string.try_to_string(), None);
+        assert_eq!(format!("{:?}", string), "\"a\\u{de00}\"");
+    }
+
+    #[test]
+    fn every_length_of_encoding_round_trips() {
+        let string = JavaString::from("A\u{e9}\u{20ac}");
+        let bytes = [0x41, 0xC3, 0xA9, 0xE2, 0x82, 0xAC];
+        assert_eq!(string.to_modified_utf8(), bytes);
+        assert_eq!(decode(&bytes), string);
+    }
+
+    #[test]
+    fn overlong_forms_are_rejected() {
+        // 'A' in 2 bytes, and U+00E9 in 3
+        assert!(JavaString::from_modified_utf8(&[0xC1, 0x81]).is_err());
+        assert!(JavaString::from_modified_utf8(&[0xE0, 0x83, 0xA9]).is_err());
+    }
+
+    #[test]
+    fn four_byte_forms_are_rejected() {
+        assert!(JavaString::from_modified_utf8(&[0xF0, 0x9F, 0x98, 0x80]).is_err());
+    }
+
+    #[test]
+    fn truncated_sequences_are_rejected() {
+        assert!(JavaString::from_modified_utf8(&[0xE2, 0x82]).is_err());
+        assert!(JavaString::from_modified_utf8(&[0xC3, 0x41]).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use std::io::Write;
+
+use crate::access_flags::reserved_bits;
+use crate::constants::*;
+use crate::error::Result;
+use crate::read::*;
+use crate::write::*;
+
+// Module descriptors, as found in module-info.class (JVMS 4.7.25)
+
+bitflags! {
+    struct ModuleFlagsBits: u16 {
+        const OPEN = 0x0020; // Only on the module itself
+        const TRANSITIVE = 0x0020; // Only on requires
+        const STATIC_PHASE = 0x0040; // Only on requires
+        const SYNTHETIC = 0x1000;
+        const MANDATED = 0x8000;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleFlags {
+    pub is_open: bool,
+    pub is_synthetic: bool,
+    pub is_mandated: bool,
+    pub reserved: u16, // Unassigned bits, see access_flags::reserved_bits
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequiresFlags {
+    pub is_transitive: bool,
+    pub is_static_phase: bool,
+    pub is_synthetic: bool,
+    pub is_mandated: bool,
+    pub reserved: u16, // Unassigned bits, see access_flags::reserved_bits
+}
+
+// Shared by exports and opens
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportsFlags {
+    pub is_synthetic: bool,
+    pub is_mandated: bool,
+    pub reserved: u16, // Unassigned bits, see access_flags::reserved_bits
+}
+
+impl ModuleFlags {
+    pub fn bits(&self) -> u16 {
+        let mut flags = ModuleFlagsBits::empty();
+        flags.set(ModuleFlagsBits::OPEN, self.is_open);
+        flags.set(ModuleFlagsBits::SYNTHETIC, self.is_synthetic);
+        flags.set(ModuleFlagsBits::MANDATED, self.is_mandated);
+        flags.bits() | self.reserved
+    }
+}
+
+impl RequiresFlags {
+    pub fn bits(&self) -> u16 {
+        let mut flags = ModuleFlagsBits::empty();
+        flags.set(ModuleFlagsBits::TRANSITIVE, self.is_transitive);
+        flags.set(ModuleFlagsBits::STATIC_PHASE, self.is_static_phase);
+        flags.set(ModuleFlagsBits::SYNTHETIC, self.is_synthetic);
+        flags.set(ModuleFlagsBits::MANDATED, self.is_mandated);
+        flags.bits() | self.reserved
+    }
+}
+
+impl ExportsFlags {
+    pub fn bits(&self) -> u16 {
+        let mut flags = ModuleFlagsBits::empty();
+        flags.set(ModuleFlagsBits::SYNTHETIC, self.is_synthetic);
+        flags.set(ModuleFlagsBits::MANDATED, self.is_mandated);
+        flags.bits() | self.reserved
+    }
+}
+
+fn parse_module_flags(mask: u16) -> ModuleFlags {
+    let flags = ModuleFlagsBits::from_bits_truncate(mask);
+    ModuleFlags {
+        is_open: flags.contains(ModuleFlagsBits::OPEN),
+        is_synthetic: flags.contains(ModuleFlagsBits::SYNTHETIC),
+        is_mandated: flags.contains(ModuleFlagsBits::MANDATED),
+        reserved: reserved_bits(
+            mask,
+            (ModuleFlagsBits::OPEN | ModuleFlagsBits::SYNTHETIC | ModuleFlagsBits::MANDATED).bits(),
+        ),
+    }
+}
+
+fn parse_requires_flags(mask: u16) -> RequiresFlags {
+    let flags = ModuleFlagsBits::from_bits_truncate(mask);
+    RequiresFlags {
+        is_transitive: flags.contains(ModuleFlagsBits::TRANSITIVE),
+        is_static_phase: flags.contains(ModuleFlagsBits::STATIC_PHASE),
+        is_synthetic: flags.contains(ModuleFlagsBits::SYNTHETIC),
+        is_mandated: flags.contains(ModuleFlagsBits::MANDATED),
+        reserved: reserved_bits(mask, ModuleFlagsBits::all().bits()),
+    }
+}
+
+fn parse_exports_flags(mask: u16) -> ExportsFlags {
+    let flags = ModuleFlagsBits::from_bits_truncate(mask);
+    ExportsFlags {
+        is_synthetic: flags.contains(ModuleFlagsBits::SYNTHETIC),
+        is_mandated: flags.contains(ModuleFlagsBits::MANDATED),
+        reserved: reserved_bits(
+            mask,
+            (ModuleFlagsBits::SYNTHETIC | ModuleFlagsBits::MANDATED).bits(),
+        ),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requires {
+    pub module: String,
+    pub flags: RequiresFlags,
+    pub version: Option<String>, // The version compiled against, if recorded
+}
+
+// Package names are in internal form, e.g. java/lang
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exports {
+    pub package: String,
+    pub flags: ExportsFlags,
+    pub to: Vec<String>, // Empty if the package is exported to every module
+}
+
+pub type Opens = Exports;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provides {
+    pub service: String,
+    pub with: Vec<String>,
+}
+
+// Everything the Module, ModulePackages and ModuleMainClass attributes say about a module
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleDescriptor {
+    pub name: String,
+    pub flags: ModuleFlags,
+    pub version: Option<String>,
+    pub requires: Vec<Requires>,
+    pub exports: Vec<Exports>,
+    pub opens: Vec<Opens>,
+    pub uses: Vec<String>,
+    pub provides: Vec<Provides>,
+    pub packages: Vec<String>, // Empty if there's no ModulePackages attribute
+    pub main_class: Option<String>,
+}
+
+// The contents of a Module attribute, which don't include the packages or main class
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub flags: ModuleFlags,
+    pub version: Option<String>,
+    pub requires: Vec<Requires>,
+    pub exports: Vec<Exports>,
+    pub opens: Vec<Opens>,
+    pub uses: Vec<String>,
+    pub provides: Vec<Provides>,
+}
+
+impl ModuleDescriptor {
+    pub fn new(module: &Module, packages: &[String], main_class: Option<&str>) -> ModuleDescriptor {
+        ModuleDescriptor {
+            name: module.name.clone(),
+            flags: module.flags.clone(),
+            version: module.version.clone(),
+            requires: module.requires.clone(),
+            exports: module.exports.clone(),
+            opens: module.opens.clone(),
+            uses: module.uses.clone(),
+            provides: module.provides.clone(),
+            packages: packages.to_vec(),
+            main_class: main_class.map(str::to_string),
+        }
+    }
+}
+
+fn resolve_version(index: u16, constant_pool: &ConstantPool) -> Result<Option<String>> {
+    Ok(match index {
+        0 => None,
+        _ => Some(constant_pool.utf8(index)?),
+    })
+}
+
+fn parse_count<T>(
+    reader: &mut ClassReader,
+    mut parse: impl FnMut(&mut ClassReader) -> Result<T>,
+) -> Result<Vec<T>> {
+    let count = read_u2(reader)?;
+    let mut items = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        items.push(parse(reader)?);
+    }
+    Ok(items)
+}
+
+fn parse_exports(reader: &mut ClassReader, constant_pool: &ConstantPool) -> Result<Exports> {
+    Ok(Exports {
+        package: constant_pool.package_name(read_u2(reader)?)?,
+        flags: parse_exports_flags(read_u2(reader)?),
+        to: parse_count(reader, |reader| constant_pool.module_name(read_u2(reader)?))?,
+    })
+}
+
+pub fn parse_module(reader: &mut ClassReader, constant_pool: &ConstantPool) -> Result<Module> {
+    let name = constant_pool.module_name(read_u2(reader)?)?;
+    let flags = parse_module_flags(read_u2(reader)?);
+    let version = resolve_version(read_u2(reader)?, constant_pool)?;
+
+    let requires = reader.within("requires", |reader| {
+        parse_count(reader, |reader| {
+            Ok(Requires {
+                module: constant_pool.module_name(read_u2(reader)?)?,
+                flags: parse_requires_flags(read_u2(reader)?),
+                version: resolve_version(read_u2(reader)?, constant_pool)?,
+            })
+        })
+    })?;
+    let exports = reader.within("exports", |reader| {
+        parse_count(reader, |reader| parse_exports(reader, constant_pool))
+    })?;
+    let opens = reader.within("opens", |reader| {
+        parse_count(reader, |reader| parse_exports(reader, constant_pool))
+    })?;
+    let uses = reader.within("uses", |reader| {
+        parse_count(reader, |reader| constant_pool.class_name(read_u2(reader)?))
+    })?;
+    let provides = reader.within("provides", |reader| {
+        parse_count(reader, |reader| {
+            Ok(Provides {
+                service: constant_pool.class_name(read_u2(reader)?)?,
+                with: parse_count(reader, |reader| constant_pool.class_name(read_u2(reader)?))?,
+            })
+        })
+    })?;
+
+    Ok(Module {
+        name,
+        flags,
+        version,
+        requires,
+        exports,
+        opens,
+        uses,
+        provides,
+    })
+}
+
+pub fn parse_module_packages(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<Vec<String>> {
+    parse_count(reader, |reader| {
+        constant_pool.package_name(read_u2(reader)?)
+    })
+}
+
+fn write_version(
+    version: &Option<String>,
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    let index = match version {
+        Some(version) => constant_pool.add_utf8(version.as_str())?,
+        None => 0,
+    };
+    Ok(write_u2(index, writer)?)
+}
+
+fn write_counted<T>(
+    items: &[T],
+    writer: &mut dyn Write,
+    mut write: impl FnMut(&T, &mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    write_count(items.len(), writer)?;
+    for item in items {
+        write(item, writer)?;
+    }
+    Ok(())
+}
+
+fn write_exports(
+    exports: &Exports,
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_u2(constant_pool.add_package(&exports.package)?, writer)?;
+    write_u2(exports.flags.bits(), writer)?;
+    write_counted(&exports.to, writer, |module, writer| {
+        Ok(write_u2(constant_pool.add_module(module)?, writer)?)
+    })
+}
+
+pub fn write_module(
+    module: &Module,
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_u2(constant_pool.add_module(&module.name)?, writer)?;
+    write_u2(module.flags.bits(), writer)?;
+    write_version(&module.version, writer, constant_pool)?;
+
+    write_counted(&module.requires, writer, |requires, writer| {
+        write_u2(constant_pool.add_module(&requires.module)?, writer)?;
+        write_u2(requires.flags.bits(), writer)?;
+        write_version(&requires.version, writer, constant_pool)
+    })?;
+    write_counted(&module.exports, writer, |exports, writer| {
+        write_exports(exports, writer, constant_pool)
+    })?;
+    write_counted(&module.opens, writer, |opens, writer| {
+        write_exports(opens, writer, constant_pool)
+    })?;
+    write_counted(&module.uses, writer, |service, writer| {
+        Ok(write_u2(constant_pool.add_class(service)?, writer)?)
+    })?;
+    write_counted(&module.provides, writer, |provides, writer| {
+        write_u2(constant_pool.add_class(&provides.service)?, writer)?;
+        write_counted(&provides.with, writer, |class, writer| {
+            Ok(write_u2(constant_pool.add_class(class)?, writer)?)
+        })
+    })
+}
+
+pub fn write_module_packages(
+    packages: &[String],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_counted(packages, writer, |package, writer| {
+        Ok(write_u2(constant_pool.add_package(package)?, writer)?)
+    })
+}
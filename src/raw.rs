@@ -0,0 +1,487 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::ops::Range;
+use std::str;
+
+use crate::constants::ConstantTag;
+use crate::error::{ErrorKind, Result};
+use crate::modified_utf8::JavaString;
+use crate::read::SliceReader;
+
+// A class file parsed only as far as its structure: the constant pool, fields, methods and
+// attributes are located and borrowed from the bytes, but not decoded or resolved. That makes it
+// much cheaper than parse_class when only a few things are needed from each class, e.g. the names
+// of every class in a jar, and every structure keeps the span of bytes it was read from.
+//
+// Nothing is checked beyond what's needed to find each structure, so unlike parse_class this
+// accepts any class file version, and constant pool references are only checked when resolved.
+
+// A range of bytes in the class file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl Span {
+    fn between(start: usize, end: usize) -> Span {
+        Span {
+            offset: start,
+            length: end - start,
+        }
+    }
+
+    pub fn end(&self) -> usize {
+        self.offset + self.length
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.end()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RawConstant<'a> {
+    pub tag: ConstantTag, // Placeholder for the unusable slot after a Long or Double
+    pub span: Span,       // Including the tag
+    pub info: &'a [u8],   // The bytes after the tag, or for Utf8 constants after the length
+}
+
+#[derive(Debug, Clone)]
+pub struct RawAttribute<'a> {
+    pub name_index: u16,
+    pub span: Span, // Including the name index and length
+    pub info: &'a [u8],
+}
+
+// A field or method
+#[derive(Debug, Clone)]
+pub struct RawMember<'a> {
+    pub access_flags: u16,
+    pub name_index: u16,
+    pub descriptor_index: u16,
+    pub span: Span, // Including the attributes
+    pub attributes: Vec<RawAttribute<'a>>,
+}
+
+// The contents of a Code attribute
+#[derive(Debug, Clone)]
+pub struct RawCode<'a> {
+    pub max_stack: u16,
+    pub max_locals: u16,
+    pub code: &'a [u8],
+    pub code_span: Span,
+    pub exception_table_span: Span, // Including its length
+    pub attributes: Vec<RawAttribute<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RawClass<'a> {
+    pub bytes: &'a [u8],
+    pub minor_version: u16,
+    pub major_version: u16,
+    pub constant_pool: Vec<RawConstant<'a>>, // Constant #1 first
+    pub access_flags: u16,
+    pub this_class: u16,
+    pub super_class: u16, // 0 for java/lang/Object and module-info
+    pub interfaces: Vec<u16>,
+    pub fields: Vec<RawMember<'a>>,
+    pub methods: Vec<RawMember<'a>>,
+    pub attributes: Vec<RawAttribute<'a>>,
+    // Each table, including its count
+    pub constant_pool_span: Span,
+    pub interfaces_span: Span,
+    pub fields_span: Span,
+    pub methods_span: Span,
+    pub attributes_span: Span,
+}
+
+pub fn parse_raw_class(bytes: &[u8]) -> Result<RawClass<'_>> {
+    let reader = &mut SliceReader::new(bytes);
+
+    let (minor_version, major_version) = reader.within(
+        || "header".to_string(),
+        |reader| {
+            let magic = reader.u4()?;
+            if magic != 0xCAFEBABE {
+                return Err(ErrorKind::BadMagic(magic).into());
+            }
+            let minor = reader.u2()?;
+            let major = reader.u2()?;
+            Ok((minor, major))
+        },
+    )?;
+
+    let start = reader.offset();
+    let constant_pool_count = reader.u2()?;
+    let mut constant_pool = Vec::with_capacity(constant_pool_count as usize);
+    let mut index = 1;
+    while index < constant_pool_count {
+        let constant = reader.within(|| format!("constant #{}", index), parse_constant)?;
+        let wide = matches!(constant.tag, ConstantTag::Long | ConstantTag::Double);
+        constant_pool.push(constant);
+        if wide {
+            constant_pool.push(RawConstant {
+                tag: ConstantTag::Placeholder,
+                span: Span::between(reader.offset(), reader.offset()),
+                info: &[],
+            });
+            index += 1;
+        }
+        index += 1;
+    }
+    // A Long or Double in the last slot leaves a placeholder past the end of the pool
+    constant_pool.truncate(constant_pool_count.saturating_sub(1) as usize);
+    let constant_pool_span = Span::between(start, reader.offset());
+
+    let (access_flags, this_class, super_class) = reader.within(
+        || "class".to_string(),
+        |reader| Ok((reader.u2()?, reader.u2()?, reader.u2()?)),
+    )?;
+
+    let start = reader.offset();
+    let interfaces = reader.within(
+        || "interfaces".to_string(),
+        |reader| {
+            let count = reader.u2()?;
+            (0..count).map(|_| Ok(reader.u2()?)).collect()
+        },
+    )?;
+    let interfaces_span = Span::between(start, reader.offset());
+
+    let start = reader.offset();
+    let fields = parse_members(reader, "field")?;
+    let fields_span = Span::between(start, reader.offset());
+
+    let start = reader.offset();
+    let methods = parse_members(reader, "method")?;
+    let methods_span = Span::between(start, reader.offset());
+
+    let start = reader.offset();
+    let attributes = parse_attributes(reader)?;
+    let attributes_span = Span::between(start, reader.offset());
+
+    Ok(RawClass {
+        bytes,
+        minor_version,
+        major_version,
+        constant_pool,
+        access_flags,
+        this_class,
+        super_class,
+        interfaces,
+        fields,
+        methods,
+        attributes,
+        constant_pool_span,
+        interfaces_span,
+        fields_span,
+        methods_span,
+        attributes_span,
+    })
+}
+
+fn parse_constant<'a>(reader: &mut SliceReader<'a>) -> Result<RawConstant<'a>> {
+    let start = reader.offset();
+    let tag_num = reader.u1()?;
+    let tag = match ConstantTag::try_from(tag_num) {
+        Ok(ConstantTag::Placeholder) | Err(_) => {
+            return Err(ErrorKind::BadConstantTag(tag_num).into())
+        }
+        Ok(tag) => tag,
+    };
+    let length = match tag {
+        ConstantTag::Utf8 => reader.u2()? as usize,
+        ConstantTag::Class
+        | ConstantTag::ConstString
+        | ConstantTag::MethodType
+        | ConstantTag::Module
+        | ConstantTag::Package => 2,
+        ConstantTag::MethodHandle => 3,
+        ConstantTag::Long | ConstantTag::Double => 8,
+        _ => 4,
+    };
+    let info = reader.bytes(length)?;
+    Ok(RawConstant {
+        tag,
+        span: Span::between(start, reader.offset()),
+        info,
+    })
+}
+
+fn parse_members<'a>(
+    reader: &mut SliceReader<'a>,
+    kind: &'static str,
+) -> Result<Vec<RawMember<'a>>> {
+    let count = reader.u2()?;
+    let mut members = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        members.push(reader.within(
+            || format!("{} #{}", kind, i),
+            |reader| {
+                let start = reader.offset();
+                let access_flags = reader.u2()?;
+                let name_index = reader.u2()?;
+                let descriptor_index = reader.u2()?;
+                let attributes = parse_attributes(reader)?;
+                Ok(RawMember {
+                    access_flags,
+                    name_index,
+                    descriptor_index,
+                    span: Span::between(start, reader.offset()),
+                    attributes,
+                })
+            },
+        )?);
+    }
+    Ok(members)
+}
+
+fn parse_attributes<'a>(reader: &mut SliceReader<'a>) -> Result<Vec<RawAttribute<'a>>> {
+    let count = reader.u2()?;
+    let mut attributes = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        attributes.push(reader.within(
+            || format!("attribute #{}", i),
+            |reader| {
+                let start = reader.offset();
+                let name_index = reader.u2()?;
+                let length = reader.u4()? as usize;
+                let info = reader.bytes(length)?;
+                Ok(RawAttribute {
+                    name_index,
+                    span: Span::between(start, reader.offset()),
+                    info,
+                })
+            },
+        )?);
+    }
+    Ok(attributes)
+}
+
+impl<'a> RawClass<'a> {
+    pub fn constant(&self, index: u16) -> Result<&RawConstant<'a>> {
+        index
+            .checked_sub(1)
+            .and_then(|i| self.constant_pool.get(i as usize))
+            .filter(|constant| constant.tag != ConstantTag::Placeholder)
+            .ok_or_else(|| ErrorKind::InvalidConstantIndex(index).into())
+    }
+
+    fn expect(&self, index: u16, tag: ConstantTag, expected: &'static str) -> Result<&'a [u8]> {
+        let constant = self.constant(index)?;
+        if constant.tag != tag {
+            return Err(ErrorKind::WrongConstantKind {
+                index,
+                expected,
+                found: constant.tag,
+            }
+            .into());
+        }
+        Ok(constant.info)
+    }
+
+    // The value of a Utf8 constant. Strings that are also valid UTF-8 are borrowed, which is the
+    // case for nearly every name and descriptor. Unpaired surrogates are replaced with U+FFFD.
+    pub fn utf8(&self, index: u16) -> Result<Cow<'a, str>> {
+        let bytes = self.expect(index, ConstantTag::Utf8, "Utf8")?;
+        // Modified UTF-8 only differs from UTF-8 in how it encodes NUL and supplementary
+        // characters, and neither encoding is valid UTF-8
+        if !bytes.iter().any(|&byte| byte == 0 || byte >= 0xF0) {
+            if let Ok(string) = str::from_utf8(bytes) {
+                return Ok(Cow::Borrowed(string));
+            }
+        }
+        Ok(Cow::Owned(
+            JavaString::from_modified_utf8(bytes)?.to_string_lossy(),
+        ))
+    }
+
+    // The name of a Class constant, in internal form
+    pub fn class_name(&self, index: u16) -> Result<Cow<'a, str>> {
+        let info = self.expect(index, ConstantTag::Class, "Class")?;
+        self.utf8(u16::from_be_bytes([info[0], info[1]]))
+    }
+
+    pub fn name(&self) -> Result<Cow<'a, str>> {
+        self.class_name(self.this_class)
+    }
+
+    pub fn super_name(&self) -> Result<Option<Cow<'a, str>>> {
+        match self.super_class {
+            0 => Ok(None),
+            index => Ok(Some(self.class_name(index)?)),
+        }
+    }
+
+    pub fn interface_names(&self) -> Result<Vec<Cow<'a, str>>> {
+        self.interfaces
+            .iter()
+            .map(|&index| self.class_name(index))
+            .collect()
+    }
+
+    pub fn member_name(&self, member: &RawMember) -> Result<Cow<'a, str>> {
+        self.utf8(member.name_index)
+    }
+
+    pub fn member_descriptor(&self, member: &RawMember) -> Result<Cow<'a, str>> {
+        self.utf8(member.descriptor_index)
+    }
+
+    pub fn attribute_name(&self, attribute: &RawAttribute) -> Result<Cow<'a, str>> {
+        self.utf8(attribute.name_index)
+    }
+
+    // Locates the code and nested attributes of a Code attribute, which is left undecoded
+    // like every other attribute
+    pub fn code(&self, attribute: &RawAttribute) -> Result<RawCode<'a>> {
+        // Skip the attribute's name index and length
+        let reader = &mut SliceReader::at(self.bytes, attribute.span.offset + 6);
+        reader.within(
+            || "attribute Code".to_string(),
+            |reader| {
+                let max_stack = reader.u2()?;
+                let max_locals = reader.u2()?;
+                let code_length = reader.u4()? as usize;
+                let start = reader.offset();
+                let code = reader.bytes(code_length)?;
+                let code_span = Span::between(start, reader.offset());
+
+                let start = reader.offset();
+                let exception_table_length = reader.u2()? as usize;
+                reader.bytes(exception_table_length * 8)?;
+                let exception_table_span = Span::between(start, reader.offset());
+
+                let attributes = parse_attributes(reader)?;
+                if reader.offset() != attribute.span.end() {
+                    return Err(ErrorKind::TruncatedAttribute {
+                        name: "Code".to_string(),
+                        length: attribute.info.len() as u32,
+                        read: (reader.offset() - attribute.span.offset - 6) as u64,
+                    }
+                    .into());
+                }
+                Ok(RawCode {
+                    max_stack,
+                    max_locals,
+                    code,
+                    code_span,
+                    exception_table_span,
+                    attributes,
+                })
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const CLASS: [u8; 84] = [
+        0xCA, 0xFE, 0xBA, 0xBE, 0x00, 0x00, 0x00, 0x34,
+        0x00, 0x08,                         // Constant pool count, at 8
+        0x01, 0x00, 0x01, b'A',             // #1 Utf8, at 10
+        0x07, 0x00, 0x01,                   // #2 Class, at 14
+        0x01, 0x00, 0x01, b'm',             // #3 Utf8, at 17
+        0x01, 0x00, 0x03, b'(', b')', b'V', // #4 Utf8, at 21
+        0x01, 0x00, 0x04, b'C', b'o', b'd', b'e', // #5 Utf8, at 27
+        0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2A, // #6 Long, at 34
+        0x00, 0x21, 0x00, 0x02, 0x00, 0x00, // Access flags, this and super, at 43
+        0x00, 0x00,                         // Interfaces, at 49
+        0x00, 0x00,                         // Fields, at 51
+        0x00, 0x01,                         // Methods, at 53
+        0x00, 0x09, 0x00, 0x03, 0x00, 0x04, 0x00, 0x01, // Method, at 55
+        0x00, 0x05, 0x00, 0x00, 0x00, 0x0D, // Code attribute, at 63
+        0x00, 0x00, 0x00, 0x01,             // max_stack and max_locals
+        0x00, 0x00, 0x00, 0x01, 0xB1,       // Code, with return at 77
+        0x00, 0x00,                         // Exception table, at 78
+        0x00, 0x00,                         // Code's attributes
+        0x00, 0x00,                         // Class attributes, at 82
+    ];
+
+    fn span(offset: usize, length: usize) -> Span {
+        Span { offset, length }
+    }
+
+    #[test]
+    fn spans_are_where_the_structures_were_read_from() {
+        let class = parse_raw_class(&CLASS).unwrap();
+        assert_eq!(class.name().unwrap(), "A");
+        assert_eq!(class.super_name().unwrap(), None);
+
+        let spans: Vec<Span> = class.constant_pool.iter().map(|c| c.span).collect();
+        assert_eq!(
+            spans,
+            [
+                span(10, 4),
+                span(14, 3),
+                span(17, 4),
+                span(21, 6),
+                span(27, 7),
+                span(34, 9),
+                span(43, 0),
+            ]
+        );
+        assert_eq!(class.constant(4).unwrap().info, b"()V");
+        assert_eq!(class.constant(6).unwrap().info, 42u64.to_be_bytes());
+        assert_eq!(class.constant_pool_span, span(8, 35));
+        assert_eq!(class.interfaces_span, span(49, 2));
+        assert_eq!(class.fields_span, span(51, 2));
+        assert_eq!(class.methods_span, span(53, 29));
+        assert_eq!(class.attributes_span, span(82, 2));
+
+        let method = &class.methods[0];
+        assert_eq!(method.span, span(55, 27));
+        assert_eq!(class.member_name(method).unwrap(), "m");
+        assert_eq!(class.member_descriptor(method).unwrap(), "()V");
+        let attribute = &method.attributes[0];
+        assert_eq!(attribute.span, span(63, 19));
+        assert_eq!(class.attribute_name(attribute).unwrap(), "Code");
+
+        let code = class.code(attribute).unwrap();
+        assert_eq!((code.max_stack, code.max_locals), (0, 1));
+        assert_eq!(code.code, [0xB1]);
+        assert_eq!(code.code_span, span(77, 1));
+        assert_eq!(code.exception_table_span, span(78, 2));
+        assert_eq!(&CLASS[code.code_span.range()], code.code);
+    }
+
+    #[test]
+    fn the_slot_after_a_long_is_unusable() {
+        let class = parse_raw_class(&CLASS).unwrap();
+        assert_eq!(class.constant_pool.len(), 7);
+        assert_eq!(class.constant_pool[6].tag, ConstantTag::Placeholder);
+        let error = class.constant(7).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::InvalidConstantIndex(7)));
+
+        // With the Long in the last slot, its placeholder would be past the end of the pool
+        let mut bytes = CLASS;
+        bytes[9] = 0x07;
+        let class = parse_raw_class(&bytes).unwrap();
+        assert_eq!(class.constant_pool.len(), 6);
+        assert_eq!(class.constant_pool_span, span(8, 35));
+        assert_eq!(class.methods[0].span, span(55, 27));
+        assert!(matches!(
+            class.constant(7).unwrap_err().kind,
+            ErrorKind::InvalidConstantIndex(7)
+        ));
+    }
+
+    #[test]
+    fn constants_are_only_checked_when_resolved() {
+        let mut bytes = CLASS;
+        bytes[46] = 0x01; // this_class is now the Utf8 constant #1
+        let class = parse_raw_class(&bytes).unwrap();
+        assert!(matches!(
+            class.name().unwrap_err().kind,
+            ErrorKind::WrongConstantKind {
+                index: 1,
+                expected: "Class",
+                found: ConstantTag::Utf8,
+            }
+        ));
+    }
+}
@@ -1,30 +1,151 @@
 use std::io::{Error, ErrorKind, Read, Result};
 
+use crate::error::ClassFormatError;
+
+// Wraps the class file being read, keeping track of the current byte offset and which structure
+// is being parsed so errors can say where they happened
+pub struct ClassReader<'a> {
+    inner: &'a mut dyn Read,
+    offset: u64,
+    structures: Vec<String>,
+}
+
+impl<'a> ClassReader<'a> {
+    pub fn new(inner: &'a mut dyn Read) -> ClassReader<'a> {
+        ClassReader {
+            inner,
+            offset: 0,
+            structures: vec![],
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    // Run `parse` as the named structure, locating any error it returns that doesn't already
+    // have a location
+    pub fn within<T, F>(
+        &mut self,
+        structure: impl Into<String>,
+        parse: F,
+    ) -> crate::error::Result<T>
+    where
+        F: FnOnce(&mut Self) -> crate::error::Result<T>,
+    {
+        self.structures.push(structure.into());
+        let result = parse(self).map_err(|e| self.locate(e));
+        self.structures.pop();
+        result
+    }
+
+    fn locate(&self, mut error: ClassFormatError) -> ClassFormatError {
+        if error.offset.is_none() {
+            error.offset = Some(self.offset);
+            error.structure = self.structures.join(" > ");
+        }
+        error
+    }
+}
+
+// Like ClassReader, but over a class file that's already in memory, so the bytes of a structure
+// can be borrowed rather than copied. Structures are only named when an error needs locating, so
+// scanning doesn't allocate for them.
+pub struct SliceReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> SliceReader<'a> {
+        SliceReader { bytes, offset: 0 }
+    }
+
+    // Starts reading part way through, so offsets are still from the start of `bytes`
+    pub fn at(bytes: &'a [u8], offset: usize) -> SliceReader<'a> {
+        SliceReader { bytes, offset }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    // Run `parse` as the structure `structure()` names. Errors that don't have a location yet are
+    // located here, and ones from nested structures get this structure prepended.
+    pub fn within<T, F>(
+        &mut self,
+        structure: impl FnOnce() -> String,
+        parse: F,
+    ) -> crate::error::Result<T>
+    where
+        F: FnOnce(&mut Self) -> crate::error::Result<T>,
+    {
+        parse(self).map_err(|mut error| {
+            if error.offset.is_none() {
+                error.offset = Some(self.offset as u64);
+                error.structure = structure();
+            } else {
+                error.structure = format!("{} > {}", structure(), error.structure);
+            }
+            error
+        })
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        let end = self
+            .offset
+            .checked_add(count)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| Error::from(ErrorKind::UnexpectedEof))?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    pub fn u1(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u2(&mut self) -> Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u4(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+impl Read for ClassReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.offset += count as u64;
+        Ok(count)
+    }
+}
+
 pub fn read_u1(reader: &mut dyn Read) -> Result<u8> {
     let mut buffer = [0; 1];
     reader.read_exact(&mut buffer)?;
-    // println!("Read u1 {:x}", u8::from_be_bytes(buffer));
     Ok(u8::from_be_bytes(buffer))
 }
 
 pub fn read_u2(reader: &mut dyn Read) -> Result<u16> {
     let mut buffer = [0; 2];
     reader.read_exact(&mut buffer)?;
-    // println!("Read u2 {:x}", u16::from_be_bytes(buffer));
     Ok(u16::from_be_bytes(buffer))
 }
 
 pub fn read_u4(reader: &mut dyn Read) -> Result<u32> {
     let mut buffer = [0; 4];
     reader.read_exact(&mut buffer)?;
-    // println!("Read u4 {:x}", u32::from_be_bytes(buffer));
     Ok(u32::from_be_bytes(buffer))
 }
 
 pub fn read_u8(reader: &mut dyn Read) -> Result<u64> {
     let mut buffer = [0; 8];
     reader.read_exact(&mut buffer)?;
-    // println!("Read u8 {:x}", u64::from_be_bytes(buffer));
     Ok(u64::from_be_bytes(buffer))
 }
 
@@ -32,8 +153,7 @@ pub fn read_bytes(count: u64, reader: &mut dyn Read) -> Result<Vec<u8>> {
     let mut buffer = Vec::new();
     let read_count = reader.take(count).read_to_end(&mut buffer)?;
     if read_count != count as usize {
-        return Err(Error::from(ErrorKind::NotFound));
+        return Err(Error::from(ErrorKind::UnexpectedEof));
     }
-    // println!("Read {} {:x?}", count, buffer);
     Ok(buffer)
 }
@@ -0,0 +1,1242 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use log::debug;
+
+use crate::attributes::Attribute;
+use crate::builder::ClassBuilder;
+use crate::class::Class;
+use crate::class_loader::ClassLoader;
+use crate::class_path::internal_name;
+use crate::constants::{ConstantTag, LoadableConstant, MemberRef};
+use crate::error::ErrorKind;
+use crate::execution::{load_frame, DataType};
+use crate::fields::Field;
+use crate::heap::{Heap, ObjectRef};
+use crate::instructions::Instruction;
+use crate::logging::CLASS_LOAD;
+use crate::methods::{self, Method};
+
+// The classes a running program uses, which are loaded, linked and initialized on demand as JVMS
+// chapter 5 describes. Only one thread ever runs, so a class being initialized can only be
+// waiting on its own initialization.
+
+pub const OBJECT: &str = "java/lang/Object";
+
+// Errors and exceptions the JVM itself throws
+pub const ABSTRACT_METHOD_ERROR: &str = "java/lang/AbstractMethodError";
+pub const CLASS_CIRCULARITY_ERROR: &str = "java/lang/ClassCircularityError";
+pub const CLASS_FORMAT_ERROR: &str = "java/lang/ClassFormatError";
+pub const EXCEPTION_IN_INITIALIZER_ERROR: &str = "java/lang/ExceptionInInitializerError";
+pub const INCOMPATIBLE_CLASS_CHANGE_ERROR: &str = "java/lang/IncompatibleClassChangeError";
+pub const INSTANTIATION_ERROR: &str = "java/lang/InstantiationError";
+pub const INTERNAL_ERROR: &str = "java/lang/InternalError";
+pub const NO_CLASS_DEF_FOUND_ERROR: &str = "java/lang/NoClassDefFoundError";
+pub const NO_SUCH_FIELD_ERROR: &str = "java/lang/NoSuchFieldError";
+pub const NO_SUCH_METHOD_ERROR: &str = "java/lang/NoSuchMethodError";
+pub const NULL_POINTER_EXCEPTION: &str = "java/lang/NullPointerException";
+pub const UNSATISFIED_LINK_ERROR: &str = "java/lang/UnsatisfiedLinkError";
+pub const UNSUPPORTED_CLASS_VERSION_ERROR: &str = "java/lang/UnsupportedClassVersionError";
+pub const VERIFY_ERROR: &str = "java/lang/VerifyError";
+
+const ERROR: &str = "java/lang/Error";
+
+// Those of the above that are subclasses of java/lang/Error
+const VM_ERRORS: &[&str] = &[
+    ABSTRACT_METHOD_ERROR,
+    CLASS_CIRCULARITY_ERROR,
+    CLASS_FORMAT_ERROR,
+    EXCEPTION_IN_INITIALIZER_ERROR,
+    INCOMPATIBLE_CLASS_CHANGE_ERROR,
+    INSTANTIATION_ERROR,
+    INTERNAL_ERROR,
+    NO_CLASS_DEF_FOUND_ERROR,
+    NO_SUCH_FIELD_ERROR,
+    NO_SUCH_METHOD_ERROR,
+    UNSATISFIED_LINK_ERROR,
+    UNSUPPORTED_CLASS_VERSION_ERROR,
+    VERIFY_ERROR,
+];
+
+// An exception or error thrown in the running program. Throwables aren't objects on the heap yet,
+// so nothing can catch these, and they unwind every frame back to the launcher.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throwable {
+    pub class_name: String, // In internal form, e.g. java/lang/NoClassDefFoundError
+    pub message: Option<String>,
+    pub cause: Option<Box<Throwable>>,
+}
+
+pub type JavaResult<T> = Result<T, Throwable>;
+
+impl Throwable {
+    pub fn new(class_name: &str, message: impl Into<String>) -> Throwable {
+        Throwable {
+            class_name: class_name.to_string(),
+            message: Some(message.into()),
+            cause: None,
+        }
+    }
+}
+
+// Like the start of a Java stack trace, e.g.
+//   java.lang.ExceptionInInitializerError
+//   Caused by: java.lang.ArithmeticException: / by zero
+impl fmt::Display for Throwable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.class_name.replace('/', "."))?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        if let Some(cause) = &self.cause {
+            write!(f, "\nCaused by: {}", cause)?;
+        }
+        Ok(())
+    }
+}
+
+// Where a class is in its lifecycle once it's been linked (JVMS 5.5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitState {
+    Linked,
+    Initializing,
+    Initialized,
+    Erroneous, // Initialization failed, so the class can't be used
+}
+
+// A linked class, with its superclass and superinterfaces linked before it
+pub struct RuntimeClass {
+    pub class: Rc<Class>,
+    pub super_class: Option<Rc<RuntimeClass>>, // None only for java/lang/Object
+    pub interfaces: Vec<Rc<RuntimeClass>>,
+    // Keyed by name and descriptor, since fields of different types can share a name
+    static_fields: RefCell<HashMap<(String, String), DataType>>,
+    // Inherited fields first, so a subclass's objects start with the same fields as its
+    // superclass's
+    instance_fields: Vec<InstanceField>,
+    state: Cell<InitState>,
+}
+
+// A field every object of a class has, whether the class declares it or inherits it
+#[derive(Debug, Clone)]
+pub struct InstanceField {
+    pub class_name: String, // The class that declares it, as subclasses can hide it
+    pub name: String,
+    pub descriptor: String,
+    pub default_value: DataType,
+}
+
+impl RuntimeClass {
+    pub fn name(&self) -> &str {
+        &self.class.name
+    }
+
+    pub fn state(&self) -> InitState {
+        self.state.get()
+    }
+
+    // None unless this class declares the static field
+    pub fn get_static(&self, name: &str, descriptor: &str) -> Option<DataType> {
+        self.static_fields
+            .borrow()
+            .get(&(name.to_string(), descriptor.to_string()))
+            .copied()
+    }
+
+    pub fn put_static(&self, name: &str, descriptor: &str, value: DataType) -> Option<DataType> {
+        self.static_fields
+            .borrow_mut()
+            .get_mut(&(name.to_string(), descriptor.to_string()))
+            .map(|field| std::mem::replace(field, value))
+    }
+
+    pub fn instance_fields(&self) -> &[InstanceField] {
+        &self.instance_fields
+    }
+
+    // Where the field the class declares is stored in objects of this class, if they have it
+    pub fn field_slot(&self, class_name: &str, name: &str, descriptor: &str) -> Option<usize> {
+        self.instance_fields.iter().position(|field| {
+            field.class_name == class_name && field.name == name && field.descriptor == descriptor
+        })
+    }
+
+    // Whether this is the class or one of its subclasses
+    pub fn is_subclass_of(&self, class: &RuntimeClass) -> bool {
+        std::iter::successors(Some(self), |current| current.super_class.as_deref())
+            .any(|current| std::ptr::eq(current, class))
+    }
+
+    // The field and the class that declares it, looking in this class, then its superinterfaces,
+    // then its superclass (JVMS 5.4.3.2). Fields are identified by name and descriptor together.
+    pub fn find_field(
+        self: &Rc<Self>,
+        name: &str,
+        descriptor: &str,
+    ) -> Option<(Rc<RuntimeClass>, &Field)> {
+        let declared = self
+            .class
+            .fields
+            .iter()
+            .find(|field| field.name == name && field.descriptor == descriptor);
+        if let Some(field) = declared {
+            return Some((Rc::clone(self), field));
+        }
+        self.interfaces
+            .iter()
+            .find_map(|interface| interface.find_field(name, descriptor))
+            .or_else(|| self.super_class.as_ref()?.find_field(name, descriptor))
+    }
+
+    // The method and the class that declares it, looking in this class then its superclasses
+    pub fn find_method(self: &Rc<Self>, name: &str, descriptor: &str) -> Option<ResolvedMethod> {
+        match self.class.method_index(name, descriptor) {
+            Some(index) => Some((Rc::clone(self), index)),
+            None => self.super_class.as_ref()?.find_method(name, descriptor),
+        }
+    }
+
+    // Interfaces are only initialized along with their implementations if they have default
+    // methods, which could depend on their static fields
+    fn declares_default_methods(&self) -> bool {
+        self.class
+            .methods
+            .iter()
+            .any(|method| !method.access_flags.is_abstract && !method.access_flags.is_static)
+    }
+}
+
+// A method and the class that declares it, as an index into its methods
+pub type ResolvedMethod = (Rc<RuntimeClass>, usize);
+
+// A class's superclass and direct superinterfaces
+type Supertypes = (Option<Rc<RuntimeClass>>, Vec<Rc<RuntimeClass>>);
+
+pub struct Runtime {
+    loader: ClassLoader,
+    heap: Heap,
+    classes: RefCell<HashMap<String, Rc<RuntimeClass>>>,
+    // Classes whose supertypes are being linked, so a class that's its own supertype is caught
+    linking: RefCell<Vec<String>>,
+}
+
+impl Runtime {
+    pub fn new(loader: ClassLoader) -> Runtime {
+        Runtime {
+            loader,
+            heap: Heap::new(),
+            classes: RefCell::new(HashMap::new()),
+            linking: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn loader(&self) -> &ClassLoader {
+        &self.loader
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    // The class if it's been linked, without loading it
+    pub fn linked(&self, name: &str) -> Option<Rc<RuntimeClass>> {
+        self.classes
+            .borrow()
+            .get(internal_name(name).as_ref())
+            .cloned()
+    }
+
+    // Whether the throwable is an Error rather than an Exception. Its class is checked if it's
+    // been linked, and otherwise it's one the JVM threw itself without loading its class.
+    pub fn is_error(&self, thrown: &Throwable) -> bool {
+        match self.linked(&thrown.class_name) {
+            Some(class) => std::iter::successors(Some(class), |class| class.super_class.clone())
+                .any(|class| class.name() == ERROR),
+            None => thrown.class_name == ERROR || VM_ERRORS.contains(&thrown.class_name.as_str()),
+        }
+    }
+
+    // Loads and links the class if that hasn't been done already (JVMS 5.3, 5.4). Its superclass
+    // and superinterfaces are linked first, and its static fields are given default values.
+    pub fn link(&self, name: &str) -> JavaResult<Rc<RuntimeClass>> {
+        let name = internal_name(name);
+        if let Some(class) = self.linked(&name) {
+            return Ok(class);
+        }
+        if self.linking.borrow().iter().any(|linking| *linking == name) {
+            return Err(Throwable::new(CLASS_CIRCULARITY_ERROR, name));
+        }
+
+        let class = self.load(&name)?;
+        self.linking.borrow_mut().push(name.to_string());
+        let supertypes = self.link_supertypes(&class);
+        self.linking.borrow_mut().pop();
+        let (super_class, interfaces) = supertypes?;
+
+        let static_fields = class
+            .fields
+            .iter()
+            .filter(|field| field.access_flags.is_static)
+            .map(|field| {
+                let key = (field.name.clone(), field.descriptor.clone());
+                (key, DataType::default_for(&field.field_type))
+            })
+            .collect();
+        let mut instance_fields = super_class
+            .as_ref()
+            .map_or(vec![], |super_class| super_class.instance_fields.clone());
+        instance_fields.extend(
+            class
+                .fields
+                .iter()
+                .filter(|field| !field.access_flags.is_static)
+                .map(|field| InstanceField {
+                    class_name: class.name.clone(),
+                    name: field.name.clone(),
+                    descriptor: field.descriptor.clone(),
+                    default_value: DataType::default_for(&field.field_type),
+                }),
+        );
+        debug!(target: CLASS_LOAD, "Linked {}", name);
+        let class = Rc::new(RuntimeClass {
+            class,
+            super_class,
+            interfaces,
+            static_fields: RefCell::new(static_fields),
+            instance_fields,
+            state: Cell::new(InitState::Linked),
+        });
+        self.classes
+            .borrow_mut()
+            .insert(name.into_owned(), Rc::clone(&class));
+        Ok(class)
+    }
+
+    fn load(&self, name: &str) -> JavaResult<Rc<Class>> {
+        let error = match self.loader.load(name) {
+            Ok(class) => return Ok(class),
+            Err(error) => error,
+        };
+        Err(match error.kind {
+            // There's no class library, so programs get the bare minimum Object unless the class
+            // path has one
+            ErrorKind::ClassNotFound(_) if name == OBJECT => {
+                return Ok(self.loader.define(object_class()))
+            }
+            ErrorKind::ClassNotFound(_) => Throwable::new(NO_CLASS_DEF_FOUND_ERROR, name),
+            ErrorKind::WrongClassName { found, .. } => Throwable::new(
+                NO_CLASS_DEF_FOUND_ERROR,
+                format!("{} (wrong name: {})", name, found),
+            ),
+            ErrorKind::UnsupportedVersion { .. } => Throwable::new(
+                UNSUPPORTED_CLASS_VERSION_ERROR,
+                format!("{}: {}", name, error),
+            ),
+            ErrorKind::Io(_) => {
+                Throwable::new(NO_CLASS_DEF_FOUND_ERROR, format!("{}: {}", name, error))
+            }
+            _ => Throwable::new(CLASS_FORMAT_ERROR, format!("{}: {}", name, error)),
+        })
+    }
+
+    fn link_supertypes(&self, class: &Class) -> JavaResult<Supertypes> {
+        let super_class = match &class.super_name {
+            Some(super_name) => {
+                let super_class = self.link(super_name)?;
+                if super_class.class.access_flags.is_interface {
+                    return Err(Throwable::new(
+                        INCOMPATIBLE_CLASS_CHANGE_ERROR,
+                        format!(
+                            "class {} has interface {} as super class",
+                            class.name, super_name
+                        ),
+                    ));
+                }
+                Some(super_class)
+            }
+            None => None,
+        };
+        let mut interfaces = vec![];
+        for interface_name in &class.interfaces {
+            let interface = self.link(interface_name)?;
+            if !interface.class.access_flags.is_interface {
+                return Err(Throwable::new(
+                    INCOMPATIBLE_CLASS_CHANGE_ERROR,
+                    format!(
+                        "class {} can not implement {}, because it is not an interface",
+                        class.name, interface_name
+                    ),
+                ));
+            }
+            interfaces.push(interface);
+        }
+        Ok((super_class, interfaces))
+    }
+
+    // Initializes the class unless it has been already (JVMS 5.5), which has to happen before
+    // its first active use: new, getstatic, putstatic or invokestatic on it, or initializing a
+    // subclass. Once initialization fails the class can't be used.
+    pub fn initialize(&self, class: &Rc<RuntimeClass>) -> JavaResult<()> {
+        match class.state() {
+            // A class being initialized can use itself, e.g. <clinit> setting its own statics
+            InitState::Initialized | InitState::Initializing => return Ok(()),
+            InitState::Erroneous => {
+                return Err(Throwable::new(
+                    NO_CLASS_DEF_FOUND_ERROR,
+                    format!("Could not initialize class {}", class.name()),
+                ))
+            }
+            InitState::Linked => {}
+        }
+        class.state.set(InitState::Initializing);
+        let result = self.run_initializers(class);
+        class.state.set(match result {
+            Ok(()) => InitState::Initialized,
+            Err(_) => InitState::Erroneous,
+        });
+        result
+    }
+
+    fn run_initializers(&self, class: &Rc<RuntimeClass>) -> JavaResult<()> {
+        // Static fields with a ConstantValue are set before anything else runs, whether or not
+        // they're final (JVMS 4.7.2)
+        for field in class.class.fields.iter() {
+            if !field.access_flags.is_static {
+                continue;
+            }
+            let value = field
+                .attributes
+                .iter()
+                .find_map(|attribute| match attribute {
+                    Attribute::ConstantValue { value, .. } => constant_value(value),
+                    _ => None,
+                });
+            if let Some(value) = value {
+                class.put_static(&field.name, &field.descriptor, value);
+            }
+        }
+
+        if !class.class.access_flags.is_interface {
+            if let Some(super_class) = &class.super_class {
+                self.initialize(super_class)?;
+            }
+            for interface in superinterfaces(class) {
+                if interface.declares_default_methods() {
+                    self.initialize(&interface)?;
+                }
+            }
+        }
+
+        let initializer = match class.class.method_index("<clinit>", "()V") {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        debug!(target: CLASS_LOAD, "Initializing {}", class.name());
+        load_frame(self, Rc::clone(&class.class), initializer, vec![])
+            .and_then(|mut frame| frame.exec())
+            .map(|_| ())
+            .map_err(|thrown| match self.is_error(&thrown) {
+                true => thrown,
+                false => Throwable {
+                    class_name: EXCEPTION_IN_INITIALIZER_ERROR.to_string(),
+                    message: None,
+                    cause: Some(Box::new(thrown)),
+                },
+            })
+    }
+
+    // Resolves a method ref (JVMS 5.4.3.3) or interface method ref (JVMS 5.4.3.4) to the method
+    // it names, which may be inherited. The class that declares it isn't initialized.
+    pub fn resolve_method(&self, reference: &MemberRef) -> JavaResult<ResolvedMethod> {
+        let class = self.link(&reference.class_name)?;
+        let is_interface = reference.tag == ConstantTag::InterfaceMethodRef;
+        if class.class.access_flags.is_interface != is_interface {
+            let (found, expected) = match is_interface {
+                true => ("class", "interface"),
+                false => ("interface", "class"),
+            };
+            return Err(Throwable::new(
+                INCOMPATIBLE_CLASS_CHANGE_ERROR,
+                format!(
+                    "Found {} {}, but {} was expected",
+                    found,
+                    class.name().replace('/', "."),
+                    expected
+                ),
+            ));
+        }
+
+        let (name, descriptor) = (&reference.name, &reference.descriptor);
+        let found = if is_interface {
+            // Interfaces inherit the public instance methods of Object, but nothing else from it
+            let object = self.link(OBJECT)?;
+            let from_object = object.find_method(name, descriptor).filter(|(_, index)| {
+                let flags = &object.class.methods[*index].access_flags;
+                matches!(flags.visibility, methods::Visibility::Public) && !flags.is_static
+            });
+            class
+                .class
+                .method_index(name, descriptor)
+                .map(|index| (Rc::clone(&class), index))
+                .or(from_object)
+        } else {
+            class.find_method(name, descriptor)
+        };
+        found
+            .or_else(|| superinterface_method(&class, name, descriptor))
+            .ok_or_else(|| {
+                Throwable::new(
+                    NO_SUCH_METHOD_ERROR,
+                    format!("{}.{}{}", class.name().replace('/', "."), name, descriptor),
+                )
+            })
+    }
+
+    // Resolves a field ref to the class that declares the field (JVMS 5.4.3.2)
+    fn resolve_field(
+        &self,
+        reference: &MemberRef,
+        is_static: bool,
+    ) -> JavaResult<Rc<RuntimeClass>> {
+        let class = self.link(&reference.class_name)?;
+        let (declaring_class, field) = class
+            .find_field(&reference.name, &reference.descriptor)
+            .ok_or_else(|| Throwable::new(NO_SUCH_FIELD_ERROR, reference.name.as_str()))?;
+        if field.access_flags.is_static != is_static {
+            let expected = match is_static {
+                true => "static",
+                false => "non-static",
+            };
+            return Err(Throwable::new(
+                INCOMPATIBLE_CLASS_CHANGE_ERROR,
+                format!(
+                    "Expected {} field {}.{}",
+                    expected,
+                    reference.class_name.replace('/', "."),
+                    reference.name
+                ),
+            ));
+        }
+        Ok(declaring_class)
+    }
+
+    // Resolves a static field ref, initializing the class that declares the field
+    fn resolve_static(&self, reference: &MemberRef) -> JavaResult<Rc<RuntimeClass>> {
+        let declaring_class = self.resolve_field(reference, true)?;
+        self.initialize(&declaring_class)?;
+        Ok(declaring_class)
+    }
+
+    pub fn get_static(&self, reference: &MemberRef) -> JavaResult<DataType> {
+        let class = self.resolve_static(reference)?;
+        Ok(class
+            .get_static(&reference.name, &reference.descriptor)
+            .expect("the field was resolved"))
+    }
+
+    pub fn put_static(&self, reference: &MemberRef, value: DataType) -> JavaResult<()> {
+        let class = self.resolve_static(reference)?;
+        class.put_static(&reference.name, &reference.descriptor, value);
+        Ok(())
+    }
+
+    // Creates an object for the new instruction, initializing its class first. Its fields have
+    // their default values until a constructor runs.
+    pub fn new_object(&self, class_name: &str) -> JavaResult<DataType> {
+        let class = self.link(class_name)?;
+        if class.class.access_flags.is_interface || class.class.access_flags.is_abstract {
+            return Err(Throwable::new(
+                INSTANTIATION_ERROR,
+                class.name().replace('/', "."),
+            ));
+        }
+        self.initialize(&class)?;
+        Ok(DataType::Reference(self.heap.allocate(&class)))
+    }
+
+    pub fn get_field(&self, object: DataType, reference: &MemberRef) -> JavaResult<DataType> {
+        let declaring_class = self.resolve_field(reference, false)?;
+        let object = object.dereference(|| format!("Cannot read field \"{}\"", reference.name))?;
+        let slot = self.field_slot(object, &declaring_class, reference)?;
+        Ok(self.heap.get_field(object, slot))
+    }
+
+    pub fn put_field(
+        &self,
+        object: DataType,
+        reference: &MemberRef,
+        value: DataType,
+    ) -> JavaResult<()> {
+        let declaring_class = self.resolve_field(reference, false)?;
+        let object =
+            object.dereference(|| format!("Cannot assign field \"{}\"", reference.name))?;
+        let slot = self.field_slot(object, &declaring_class, reference)?;
+        self.heap.put_field(object, slot, value);
+        Ok(())
+    }
+
+    // Where the object stores the field the class declares. Nothing verifies the object is of
+    // that class or a subclass of it, so it may not have the field at all.
+    fn field_slot(
+        &self,
+        object: ObjectRef,
+        declaring_class: &RuntimeClass,
+        reference: &MemberRef,
+    ) -> JavaResult<usize> {
+        let class = self.heap.class_of(object);
+        class
+            .field_slot(
+                declaring_class.name(),
+                &reference.name,
+                &reference.descriptor,
+            )
+            .ok_or_else(|| {
+                Throwable::new(
+                    INCOMPATIBLE_CLASS_CHANGE_ERROR,
+                    format!(
+                        "Class {} does not have field {}.{}",
+                        class.name().replace('/', "."),
+                        declaring_class.name().replace('/', "."),
+                        reference.name
+                    ),
+                )
+            })
+    }
+
+    // The method invokespecial runs (JVMS 6.5), which is the resolved method unless that's in a
+    // superclass of the current class. Then it's looked up again from the current class's
+    // direct superclass, so super.m() runs the override nearest the current class.
+    pub fn select_special(
+        &self,
+        current: &RuntimeClass,
+        resolved: ResolvedMethod,
+    ) -> ResolvedMethod {
+        let (class, index) = &resolved;
+        let method = &class.class.methods[*index];
+        let super_class = match &current.super_class {
+            Some(super_class) if super_class.is_subclass_of(class) => super_class,
+            _ => return resolved,
+        };
+        if method.name == "<init>" || class.class.access_flags.is_interface {
+            return resolved;
+        }
+        super_class
+            .find_method(&method.name, &method.descriptor)
+            .or_else(|| superinterface_method(super_class, &method.name, &method.descriptor))
+            .unwrap_or(resolved)
+    }
+}
+
+// Every interface the class implements, directly or through other interfaces, each once
+fn superinterfaces(class: &RuntimeClass) -> Vec<Rc<RuntimeClass>> {
+    let mut interfaces: Vec<Rc<RuntimeClass>> = vec![];
+    let mut pending: Vec<_> = class.interfaces.iter().rev().cloned().collect();
+    while let Some(interface) = pending.pop() {
+        if interfaces.iter().any(|seen| Rc::ptr_eq(seen, &interface)) {
+            continue;
+        }
+        pending.extend(interface.interfaces.iter().rev().cloned());
+        interfaces.push(interface);
+    }
+    interfaces
+}
+
+// Every interface the class implements, including through its superclasses, each once
+fn all_superinterfaces(class: &RuntimeClass) -> Vec<Rc<RuntimeClass>> {
+    let mut interfaces: Vec<Rc<RuntimeClass>> = vec![];
+    let mut current = Some(class);
+    while let Some(class) = current {
+        for interface in superinterfaces(class) {
+            if !interfaces.iter().any(|seen| Rc::ptr_eq(seen, &interface)) {
+                interfaces.push(interface);
+            }
+        }
+        current = class.super_class.as_deref();
+    }
+    interfaces
+}
+
+// The method from a superinterface when neither the class nor its superclasses declare it. A
+// maximally-specific one, i.e. one no subinterface overrides, is chosen, preferring the only one
+// that isn't abstract, e.g. the default method that a class would inherit. Private and static
+// interface methods are never inherited.
+fn superinterface_method(
+    class: &RuntimeClass,
+    name: &str,
+    descriptor: &str,
+) -> Option<ResolvedMethod> {
+    let candidates: Vec<ResolvedMethod> = all_superinterfaces(class)
+        .into_iter()
+        .filter_map(|interface| {
+            let index = interface.class.method_index(name, descriptor)?;
+            let flags = &interface.class.methods[index].access_flags;
+            match matches!(flags.visibility, methods::Visibility::Private) || flags.is_static {
+                true => None,
+                false => Some((interface, index)),
+            }
+        })
+        .collect();
+    let maximally_specific: Vec<&ResolvedMethod> = candidates
+        .iter()
+        .filter(|(interface, _)| {
+            !candidates.iter().any(|(other, _)| {
+                superinterfaces(other)
+                    .iter()
+                    .any(|inherited| Rc::ptr_eq(inherited, interface))
+            })
+        })
+        .collect();
+    let mut non_abstract = maximally_specific
+        .iter()
+        .filter(|(interface, index)| !interface.class.methods[*index].access_flags.is_abstract);
+    match (non_abstract.next(), non_abstract.next()) {
+        (Some(&method), None) => Some(method.clone()),
+        // Any of them will do, and invoking one that's abstract throws AbstractMethodError
+        _ => maximally_specific.first().map(|&method| method.clone()),
+    }
+}
+
+// How a method is named in errors, e.g. com.acme.Util.add(II)I
+pub fn describe_method(class: &Class, method: &Method) -> String {
+    format!(
+        "{}.{}{}",
+        class.name.replace('/', "."),
+        method.name,
+        method.descriptor
+    )
+}
+
+// TODO strings, once there are objects
+fn constant_value(value: &LoadableConstant) -> Option<DataType> {
+    match value {
+        LoadableConstant::Integer(value) => Some(DataType::Integer(*value)),
+        LoadableConstant::Float(value) => Some(DataType::Float(*value)),
+        LoadableConstant::Long(value) => Some(DataType::Long(*value)),
+        LoadableConstant::Double(value) => Some(DataType::Double(*value)),
+        _ => None,
+    }
+}
+
+// java/lang/Object with only a constructor, which is enough to construct and extend it
+fn object_class() -> Class {
+    let mut builder = ClassBuilder::new(OBJECT);
+    let access_flags = methods::AccessFlags {
+        visibility: methods::Visibility::Public,
+        ..Default::default()
+    };
+    builder
+        .super_class(None)
+        .method(access_flags, "<init>", "()V", |code| {
+            code.emit(Instruction::Return);
+            Ok(())
+        })
+        .expect("the constructor is valid");
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::{self, write_class};
+    use crate::class_path::ClassPath;
+    use crate::fields;
+    use crate::instructions::Bytecode;
+
+    fn static_method() -> methods::AccessFlags {
+        methods::AccessFlags {
+            visibility: methods::Visibility::Public,
+            is_static: true,
+            ..Default::default()
+        }
+    }
+
+    fn static_field() -> fields::AccessFlags {
+        fields::AccessFlags {
+            is_static: true,
+            ..Default::default()
+        }
+    }
+
+    // Changes a method's code after it's built, for code the builder wouldn't emit. `change`
+    // returns the instruction it removes or replaces.
+    fn patch(method: &mut Method, change: impl FnOnce(&mut Vec<Instruction>) -> Instruction) {
+        let code = method
+            .attributes
+            .iter_mut()
+            .find_map(|attribute| match attribute {
+                Attribute::Code { code, .. } => Some(code),
+                _ => None,
+            });
+        let code = code.unwrap();
+        let mut instructions = code.iter().map(|(_, i)| i.clone()).collect();
+        change(&mut instructions);
+        *code = Bytecode::new(instructions).unwrap();
+    }
+
+    // A runtime with the classes given as the class path
+    fn runtime(classes: Vec<Class>) -> Runtime {
+        let classes = classes
+            .into_iter()
+            .map(|class| {
+                let mut bytes = vec![];
+                write_class(&class, &mut bytes).unwrap();
+                (class.name, bytes)
+            })
+            .collect();
+        let mut class_path = ClassPath::new();
+        class_path.add_bytes(classes);
+        Runtime::new(ClassLoader::new(class_path))
+    }
+
+    // Runs a static method with no arguments
+    fn run(
+        classes: Vec<Class>,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> JavaResult<DataType> {
+        let runtime = runtime(classes);
+        let class = runtime.link(class_name)?;
+        runtime.initialize(&class)?;
+        let index = class.class.method_index(name, descriptor).unwrap();
+        load_frame(&runtime, Rc::clone(&class.class), index, vec![])?.exec()
+    }
+
+    #[test]
+    fn fields_with_the_same_name_but_different_types_are_different_fields() {
+        let mut class = ClassBuilder::new("Fields");
+        class
+            .field(static_field(), "x", "I")
+            .unwrap()
+            .field(static_field(), "x", "J")
+            .unwrap()
+            .method(static_method(), "run", "()I", |code| {
+                code.emit(Instruction::IConst2)
+                    .put_static("Fields", "x", "I")?
+                    .emit(Instruction::LConst1)
+                    .put_static("Fields", "x", "J")?
+                    .get_static("Fields", "x", "I")?
+                    .emit(Instruction::IReturn);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            run(vec![class.build()], "Fields", "run", "()I"),
+            Ok(DataType::Integer(2))
+        );
+    }
+
+    #[test]
+    fn field_refs_must_match_the_descriptor() {
+        let mut class = ClassBuilder::new("Fields");
+        class
+            .field(static_field(), "x", "I")
+            .unwrap()
+            .method(static_method(), "run", "()J", |code| {
+                code.get_static("Fields", "x", "J")?
+                    .emit(Instruction::LReturn);
+                Ok(())
+            })
+            .unwrap();
+        let thrown = run(vec![class.build()], "Fields", "run", "()J").unwrap_err();
+        assert_eq!(thrown.class_name, NO_SUCH_FIELD_ERROR);
+    }
+
+    #[test]
+    fn objects_without_the_field_throw_instead_of_panicking() {
+        let mut class = ClassBuilder::new("Fields");
+        class
+            .field(fields::AccessFlags::default(), "x", "I")
+            .unwrap()
+            .method(static_method(), "run", "()I", |code| {
+                code.new_object("java/lang/Object")?
+                    .get_field("Fields", "x", "I")?
+                    .emit(Instruction::IReturn);
+                Ok(())
+            })
+            .unwrap();
+        let thrown = run(vec![class.build()], "Fields", "run", "()I").unwrap_err();
+        assert_eq!(thrown.class_name, INCOMPATIBLE_CLASS_CHANGE_ERROR);
+    }
+
+    #[test]
+    fn values_that_arent_references_throw_instead_of_panicking() {
+        let mut class = ClassBuilder::new("Fields");
+        class
+            .field(fields::AccessFlags::default(), "x", "I")
+            .unwrap()
+            .method(static_method(), "run", "()I", |code| {
+                code.emit(Instruction::IConst1)
+                    .get_field("Fields", "x", "I")?
+                    .emit(Instruction::IReturn);
+                Ok(())
+            })
+            .unwrap();
+        let thrown = run(vec![class.build()], "Fields", "run", "()I").unwrap_err();
+        assert_eq!(thrown.class_name, VERIFY_ERROR);
+    }
+
+    #[test]
+    fn missing_arguments_throw_instead_of_panicking() {
+        let mut class = ClassBuilder::new("Calls");
+        class
+            .method(static_method(), "id", "(I)I", |code| {
+                code.emit(Instruction::ILoad0).emit(Instruction::IReturn);
+                Ok(())
+            })
+            .unwrap()
+            .method(static_method(), "run", "()I", |code| {
+                code.emit(Instruction::IConst0)
+                    .invoke_static("Calls", "id", "(I)I")?
+                    .emit(Instruction::IReturn);
+                Ok(())
+            })
+            .unwrap();
+        // The builder won't emit code that underflows the stack, so drop the argument afterwards
+        let mut class = class.build();
+        patch(&mut class.methods[1], |instructions| instructions.remove(0));
+        let thrown = run(vec![class], "Calls", "run", "()I").unwrap_err();
+        assert_eq!(thrown.class_name, VERIFY_ERROR);
+    }
+
+    #[test]
+    fn constant_values_are_set_on_static_fields_that_arent_final() {
+        let mut class = ClassBuilder::new("Constants");
+        class
+            .field(static_field(), "x", "I")
+            .unwrap()
+            .method(static_method(), "run", "()I", |code| {
+                code.get_static("Constants", "x", "I")?
+                    .emit(Instruction::IReturn);
+                Ok(())
+            })
+            .unwrap();
+        let mut class = class.build();
+        class.fields[0].attributes.push(Attribute::ConstantValue {
+            name: "ConstantValue".to_string(),
+            value: LoadableConstant::Integer(42),
+        });
+        assert_eq!(
+            run(vec![class], "Constants", "run", "()I"),
+            Ok(DataType::Integer(42))
+        );
+    }
+
+    #[test]
+    fn errors_are_subclasses_of_error_whatever_they_are_named() {
+        let mut error = ClassBuilder::new(ERROR);
+        error.super_class(Some("java/lang/Throwable"));
+        let mut throwable = ClassBuilder::new("java/lang/Throwable");
+        throwable.super_class(Some(OBJECT));
+        let mut fatal = ClassBuilder::new("Fatal");
+        fatal.super_class(Some(ERROR));
+        let runtime = runtime(vec![
+            throwable.build(),
+            error.build(),
+            fatal.build(),
+            ClassBuilder::new("NotAnError").build(),
+        ]);
+        runtime.link("Fatal").unwrap();
+        runtime.link("NotAnError").unwrap();
+
+        assert!(runtime.is_error(&Throwable::new("Fatal", "")));
+        assert!(!runtime.is_error(&Throwable::new("NotAnError", "")));
+        // Neither is linked, so these go by the errors the JVM throws itself
+        assert!(runtime.is_error(&Throwable::new(NO_CLASS_DEF_FOUND_ERROR, "")));
+        assert!(!runtime.is_error(&Throwable::new("UnlinkedError", "")));
+        assert!(!runtime.is_error(&Throwable::new(NULL_POINTER_EXCEPTION, "")));
+    }
+
+    fn interface(name: &str, superinterfaces: &[&str]) -> ClassBuilder {
+        let mut builder = ClassBuilder::new(name);
+        builder.version(52, 0).access_flags(class::AccessFlags {
+            visibility: class::Visibility::Public,
+            is_interface: true,
+            is_abstract: true,
+            ..Default::default()
+        });
+        for superinterface in superinterfaces {
+            builder.interface(superinterface);
+        }
+        builder
+    }
+
+    // A class implementing the interfaces, with a constructor that calls Object's
+    fn implementation(name: &str, interfaces: &[&str]) -> ClassBuilder {
+        let mut builder = ClassBuilder::new(name);
+        for interface in interfaces {
+            builder.interface(interface);
+        }
+        builder
+            .method(instance_method(), "<init>", "()V", |code| {
+                code.emit(Instruction::ALoad0)
+                    .invoke_special(OBJECT, "<init>", "()V")?
+                    .emit(Instruction::Return);
+                Ok(())
+            })
+            .unwrap();
+        builder
+    }
+
+    fn instance_method() -> methods::AccessFlags {
+        methods::AccessFlags {
+            visibility: methods::Visibility::Public,
+            ..Default::default()
+        }
+    }
+
+    // Adds an instance method m()I returning the value
+    fn returns(builder: &mut ClassBuilder, value: i32) {
+        builder
+            .method(instance_method(), "m", "()I", |code| {
+                code.push_int(value)?.emit(Instruction::IReturn);
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    fn method_ref(class_name: &str, name: &str, descriptor: &str) -> MemberRef {
+        MemberRef {
+            tag: ConstantTag::MethodRef,
+            class_name: class_name.to_string(),
+            name: name.to_string(),
+            descriptor: descriptor.to_string(),
+        }
+    }
+
+    // The class that declares the method the ref resolves to, and the method's descriptor
+    fn resolve(runtime: &Runtime, reference: &MemberRef) -> (String, String) {
+        let (class, index) = runtime.resolve_method(reference).unwrap();
+        let method = &class.class.methods[index];
+        (class.name().to_string(), method.descriptor.clone())
+    }
+
+    // add(II)I and add(JJ)J, and a method calling each of them
+    fn calc() -> Class {
+        let mut calc = ClassBuilder::new("Calc");
+        calc.method(static_method(), "add", "(II)I", |code| {
+            code.emit(Instruction::ILoad0)
+                .emit(Instruction::ILoad1)
+                .emit(Instruction::IAdd)
+                .emit(Instruction::IReturn);
+            Ok(())
+        })
+        .unwrap()
+        .method(static_method(), "add", "(JJ)J", |code| {
+            code.emit(Instruction::LLoad0)
+                .emit(Instruction::LLoad2)
+                .emit(Instruction::LAdd)
+                .emit(Instruction::LReturn);
+            Ok(())
+        })
+        .unwrap()
+        .method(static_method(), "ints", "()I", |code| {
+            code.emit(Instruction::IConst2)
+                .emit(Instruction::IConst3)
+                .invoke_static("Calc", "add", "(II)I")?
+                .emit(Instruction::IReturn);
+            Ok(())
+        })
+        .unwrap()
+        .method(static_method(), "longs", "()J", |code| {
+            code.emit(Instruction::LConst1)
+                .emit(Instruction::LConst1)
+                .invoke_static("Calc", "add", "(JJ)J")?
+                .emit(Instruction::LReturn);
+            Ok(())
+        })
+        .unwrap();
+        calc.build()
+    }
+
+    #[test]
+    fn overloads_are_resolved_by_descriptor() {
+        let runtime = runtime(vec![calc()]);
+        for descriptor in ["(II)I", "(JJ)J"] {
+            let reference = method_ref("Calc", "add", descriptor);
+            let resolved = resolve(&runtime, &reference);
+            assert_eq!(resolved, ("Calc".to_string(), descriptor.to_string()));
+        }
+        let missing = runtime.resolve_method(&method_ref("Calc", "add", "(FF)F"));
+        assert!(matches!(missing, Err(thrown) if thrown.class_name == NO_SUCH_METHOD_ERROR));
+
+        assert_eq!(
+            run(vec![calc()], "Calc", "ints", "()I"),
+            Ok(DataType::Integer(5))
+        );
+        assert_eq!(
+            run(vec![calc()], "Calc", "longs", "()J"),
+            Ok(DataType::Long(2))
+        );
+    }
+
+    #[test]
+    fn the_most_specific_default_method_is_selected() {
+        // Both are inherited, but B's overrides A's since B extends A
+        let mut a = interface("A", &[]);
+        returns(&mut a, 1);
+        let mut b = interface("B", &["A"]);
+        returns(&mut b, 2);
+        let mut main = implementation("Main", &["A", "B"]);
+        main.method(static_method(), "run", "()I", |code| {
+            code.new_object("Main")?
+                .emit(Instruction::Dup)
+                .invoke_special("Main", "<init>", "()V")?
+                .invoke_special("Main", "m", "()I")?
+                .emit(Instruction::IReturn);
+            Ok(())
+        })
+        .unwrap();
+        let classes = vec![a.build(), b.build(), main.build()];
+        assert_eq!(run(classes, "Main", "run", "()I"), Ok(DataType::Integer(2)));
+    }
+
+    #[test]
+    fn default_methods_are_selected_over_abstract_ones() {
+        // A's default and C's abstract method are both maximally specific, but only one has code
+        let mut a = interface("A", &[]);
+        returns(&mut a, 1);
+        let mut c = interface("C", &[]);
+        c.abstract_method(
+            methods::AccessFlags {
+                visibility: methods::Visibility::Public,
+                is_abstract: true,
+                ..Default::default()
+            },
+            "m",
+            "()I",
+        )
+        .unwrap();
+        let runtime = runtime(vec![
+            a.build(),
+            c.build(),
+            implementation("Main", &["C", "A"]).build(),
+        ]);
+        let resolved = resolve(&runtime, &method_ref("Main", "m", "()I"));
+        assert_eq!(resolved, ("A".to_string(), "()I".to_string()));
+    }
+
+    #[test]
+    fn bipush_and_sipush_push_ints() {
+        let mut class = ClassBuilder::new("Constants");
+        class
+            .method(static_method(), "run", "()I", |code| {
+                code.push_int(-100)?
+                    .push_int(1000)?
+                    .emit(Instruction::IAdd)
+                    .emit(Instruction::IReturn);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            run(vec![class.build()], "Constants", "run", "()I"),
+            Ok(DataType::Integer(900))
+        );
+    }
+
+    #[test]
+    fn unimplemented_instructions_throw_instead_of_being_skipped() {
+        let mut class = ClassBuilder::new("Unimplemented");
+        class
+            .method(static_method(), "run", "()I", |code| {
+                code.emit(Instruction::IConst1)
+                    .emit(Instruction::INeg)
+                    .emit(Instruction::IReturn);
+                Ok(())
+            })
+            .unwrap();
+        let thrown = run(vec![class.build()], "Unimplemented", "run", "()I").unwrap_err();
+        assert_eq!(thrown.class_name, INTERNAL_ERROR);
+        assert_eq!(
+            thrown.message.unwrap(),
+            "Instruction not implemented: ineg at offset 1 in Unimplemented.run()I"
+        );
+    }
+
+    #[test]
+    fn field_instructions_with_an_empty_stack_throw_instead_of_panicking() {
+        let mut class = ClassBuilder::new("Fields");
+        class
+            .field(fields::AccessFlags::default(), "x", "I")
+            .unwrap()
+            .method(static_method(), "run", "()V", |code| {
+                code.emit(Instruction::AConstNull)
+                    .emit(Instruction::IConst1)
+                    .put_field("Fields", "x", "I")?
+                    .emit(Instruction::Return);
+                Ok(())
+            })
+            .unwrap();
+        let mut class = class.build();
+        patch(&mut class.methods[0], |instructions| instructions.remove(0));
+        let thrown = run(vec![class], "Fields", "run", "()V").unwrap_err();
+        assert_eq!(thrown.class_name, VERIFY_ERROR);
+    }
+
+    #[test]
+    fn field_instructions_with_the_wrong_constant_throw_instead_of_panicking() {
+        let mut class = ClassBuilder::new("Fields");
+        class
+            .field(fields::AccessFlags::default(), "x", "I")
+            .unwrap()
+            .method(static_method(), "run", "()I", |code| {
+                code.emit(Instruction::AConstNull)
+                    .get_field("Fields", "x", "I")?
+                    .emit(Instruction::IReturn);
+                Ok(())
+            })
+            .unwrap();
+        let mut class = class.build();
+        // The class's own name, which is a Class constant rather than a FieldRef
+        let this_class = class.constant_pool.add_class("Fields").unwrap();
+        patch(&mut class.methods[0], |instructions| {
+            std::mem::replace(&mut instructions[1], Instruction::GetField(this_class))
+        });
+        let thrown = run(vec![class], "Fields", "run", "()I").unwrap_err();
+        assert_eq!(thrown.class_name, VERIFY_ERROR);
+    }
+
+    #[test]
+    fn static_field_instructions_with_an_empty_stack_throw_instead_of_panicking() {
+        let mut class = ClassBuilder::new("Fields");
+        class
+            .field(static_field(), "x", "I")
+            .unwrap()
+            .method(static_method(), "<clinit>", "()V", |code| {
+                code.push_int(100)?
+                    .put_static("Fields", "x", "I")?
+                    .emit(Instruction::Return);
+                Ok(())
+            })
+            .unwrap();
+        let mut class = class.build();
+        patch(&mut class.methods[0], |instructions| instructions.remove(0));
+        let runtime = runtime(vec![class]);
+        let class = runtime.link("Fields").unwrap();
+        assert_eq!(
+            runtime.initialize(&class).unwrap_err().class_name,
+            VERIFY_ERROR
+        );
+    }
+
+    #[test]
+    fn new_with_the_wrong_constant_throws_instead_of_panicking() {
+        let mut class = ClassBuilder::new("New");
+        class
+            .method(static_method(), "run", "()V", |code| {
+                code.new_object("New")?.emit(Instruction::Return);
+                Ok(())
+            })
+            .unwrap();
+        let mut class = class.build();
+        // The Utf8 constant holding the name, rather than the Class constant referring to it
+        let name = class.constant_pool.add_utf8("New").unwrap();
+        patch(&mut class.methods[0], |instructions| {
+            std::mem::replace(&mut instructions[0], Instruction::New(name))
+        });
+        let thrown = run(vec![class], "New", "run", "()V").unwrap_err();
+        assert_eq!(thrown.class_name, VERIFY_ERROR);
+    }
+}
@@ -0,0 +1,471 @@
+use std::fmt;
+
+use pest::iterators::Pair;
+use pest::Parser;
+
+use crate::error::{ErrorKind, Result};
+
+// Generic signatures, as found in Signature attributes (JVMS 4.7.9.1). Unlike descriptors these
+// keep type parameters, type arguments and wildcards.
+
+#[derive(Parser)]
+#[grammar = "signature.pest"]
+struct SignatureParser;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaseType {
+    Byte,
+    Char,
+    Double,
+    Float,
+    Int,
+    Long,
+    Short,
+    Boolean,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeSignature {
+    Base(BaseType),
+    Class(ClassTypeSignature),
+    TypeVariable(String),
+    Array(Box<TypeSignature>),
+}
+
+// e.g. java/util/Map<TK;TV;>.Entry<TK;TV;> has package "java/util" and classes Map and Entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassTypeSignature {
+    pub package: String,
+    pub classes: Vec<SimpleClassTypeSignature>, // Outermost class first
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimpleClassTypeSignature {
+    pub name: String,
+    pub type_arguments: Vec<TypeArgument>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeArgument {
+    Any, // ?
+    Exact(TypeSignature),
+    Extends(TypeSignature), // ? extends T
+    Super(TypeSignature),   // ? super T
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeParameter {
+    pub name: String,
+    pub class_bound: Option<TypeSignature>,
+    pub interface_bounds: Vec<TypeSignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassSignature {
+    pub type_parameters: Vec<TypeParameter>,
+    pub superclass: ClassTypeSignature,
+    pub interfaces: Vec<ClassTypeSignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodSignature {
+    pub type_parameters: Vec<TypeParameter>,
+    pub parameters: Vec<TypeSignature>,
+    pub result: Option<TypeSignature>, // None for void
+    pub throws: Vec<TypeSignature>,
+}
+
+pub type FieldSignature = TypeSignature;
+
+fn invalid(signature: &str) -> ErrorKind {
+    ErrorKind::InvalidSignature(signature.to_string())
+}
+
+fn parse_rule(rule: Rule, signature: &str) -> Result<Pair<'_, Rule>> {
+    let mut pairs = SignatureParser::parse(rule, signature).map_err(|_| invalid(signature))?;
+    Ok(pairs.next().ok_or_else(|| invalid(signature))?)
+}
+
+pub fn parse_class_signature(signature: &str) -> Result<ClassSignature> {
+    let mut type_parameters = vec![];
+    let mut class_types = vec![];
+    for pair in parse_rule(Rule::class, signature)?.into_inner() {
+        match pair.as_rule() {
+            Rule::type_parameters => type_parameters = parse_type_parameters(pair),
+            Rule::class_type => class_types.push(parse_class_type(pair)),
+            _ => {}
+        }
+    }
+
+    let mut class_types = class_types.into_iter();
+    let superclass = class_types.next().ok_or_else(|| invalid(signature))?;
+    Ok(ClassSignature {
+        type_parameters,
+        superclass,
+        interfaces: class_types.collect(),
+    })
+}
+
+pub fn parse_method_signature(signature: &str) -> Result<MethodSignature> {
+    let mut method = MethodSignature {
+        type_parameters: vec![],
+        parameters: vec![],
+        result: None,
+        throws: vec![],
+    };
+    for pair in parse_rule(Rule::method, signature)?.into_inner() {
+        match pair.as_rule() {
+            Rule::type_parameters => method.type_parameters = parse_type_parameters(pair),
+            Rule::java_type => method.parameters.push(parse_java_type(pair)),
+            Rule::result => {
+                let inner = first_inner(pair);
+                if inner.as_rule() == Rule::java_type {
+                    method.result = Some(parse_java_type(inner));
+                }
+            }
+            Rule::throws => method.throws.push(parse_reference_like(first_inner(pair))),
+            _ => {}
+        }
+    }
+    Ok(method)
+}
+
+pub fn parse_field_signature(signature: &str) -> Result<FieldSignature> {
+    let field = parse_rule(Rule::field, signature)?;
+    Ok(parse_reference(first_inner(field)))
+}
+
+// The grammar guarantees the structure of each pair, so walking it can't fail
+fn first_inner(pair: Pair<Rule>) -> Pair<Rule> {
+    pair.into_inner().next().unwrap()
+}
+
+fn parse_type_parameters(pair: Pair<Rule>) -> Vec<TypeParameter> {
+    pair.into_inner()
+        .map(|parameter| {
+            let mut inner = parameter.into_inner();
+            let name = inner.next().unwrap().as_str().to_string();
+            let class_bound = inner
+                .next()
+                .unwrap()
+                .into_inner()
+                .next()
+                .map(parse_reference);
+            let interface_bounds = inner
+                .map(|bound| parse_reference(first_inner(bound)))
+                .collect();
+            TypeParameter {
+                name,
+                class_bound,
+                interface_bounds,
+            }
+        })
+        .collect()
+}
+
+fn parse_java_type(pair: Pair<Rule>) -> TypeSignature {
+    let inner = first_inner(pair);
+    match inner.as_rule() {
+        Rule::base => TypeSignature::Base(match inner.as_str() {
+            "B" => BaseType::Byte,
+            "C" => BaseType::Char,
+            "D" => BaseType::Double,
+            "F" => BaseType::Float,
+            "I" => BaseType::Int,
+            "J" => BaseType::Long,
+            "S" => BaseType::Short,
+            _ => BaseType::Boolean,
+        }),
+        _ => parse_reference(inner),
+    }
+}
+
+fn parse_reference(pair: Pair<Rule>) -> TypeSignature {
+    parse_reference_like(first_inner(pair))
+}
+
+// A class type, type variable or array pair
+fn parse_reference_like(pair: Pair<Rule>) -> TypeSignature {
+    match pair.as_rule() {
+        Rule::class_type => TypeSignature::Class(parse_class_type(pair)),
+        Rule::type_variable => TypeSignature::TypeVariable(first_inner(pair).as_str().to_string()),
+        _ => TypeSignature::Array(Box::new(parse_java_type(first_inner(pair)))),
+    }
+}
+
+fn parse_class_type(pair: Pair<Rule>) -> ClassTypeSignature {
+    let mut package = String::new();
+    let mut classes = vec![];
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::package => package = inner.as_str().trim_end_matches('/').to_string(),
+            _ => {
+                let mut parts = inner.into_inner();
+                let name = parts.next().unwrap().as_str().to_string();
+                let type_arguments = match parts.next() {
+                    Some(arguments) => arguments.into_inner().map(parse_type_argument).collect(),
+                    None => vec![],
+                };
+                classes.push(SimpleClassTypeSignature {
+                    name,
+                    type_arguments,
+                });
+            }
+        }
+    }
+    ClassTypeSignature { package, classes }
+}
+
+fn parse_type_argument(pair: Pair<Rule>) -> TypeArgument {
+    let mut inner = pair.into_inner();
+    let first = inner.next().unwrap();
+    match first.as_rule() {
+        Rule::any => TypeArgument::Any,
+        Rule::extends => TypeArgument::Extends(parse_reference(inner.next().unwrap())),
+        Rule::super_ => TypeArgument::Super(parse_reference(inner.next().unwrap())),
+        _ => TypeArgument::Exact(parse_reference(first)),
+    }
+}
+
+// Signatures are displayed as they'd be written in Java source, e.g. java.util.List<java.lang.String>
+
+impl fmt::Display for BaseType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BaseType::Byte => "byte",
+            BaseType::Char => "char",
+            BaseType::Double => "double",
+            BaseType::Float => "float",
+            BaseType::Int => "int",
+            BaseType::Long => "long",
+            BaseType::Short => "short",
+            BaseType::Boolean => "boolean",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for TypeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeSignature::Base(base) => write!(f, "{}", base),
+            TypeSignature::Class(class) => write!(f, "{}", class),
+            TypeSignature::TypeVariable(name) => write!(f, "{}", name),
+            TypeSignature::Array(element) => write!(f, "{}[]", element),
+        }
+    }
+}
+
+impl fmt::Display for ClassTypeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.package.is_empty() {
+            write!(f, "{}.", self.package.replace('/', "."))?;
+        }
+        for (i, class) in self.classes.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", class.name)?;
+            write_list(f, "<", &class.type_arguments, ">")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TypeArgument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeArgument::Any => write!(f, "?"),
+            TypeArgument::Exact(signature) => write!(f, "{}", signature),
+            TypeArgument::Extends(signature) => write!(f, "? extends {}", signature),
+            TypeArgument::Super(signature) => write!(f, "? super {}", signature),
+        }
+    }
+}
+
+impl fmt::Display for TypeParameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        let bounds: Vec<_> = self
+            .class_bound
+            .iter()
+            .chain(self.interface_bounds.iter())
+            .map(|bound| bound.to_string())
+            .collect();
+        // A lone Object bound is implicit in source
+        if bounds.is_empty() || bounds == ["java.lang.Object"] {
+            return Ok(());
+        }
+        write!(f, " extends {}", bounds.join(" & "))
+    }
+}
+
+impl ClassSignature {
+    // e.g. Foo<T extends java.lang.Number> extends Bar<T> implements java.lang.Comparable<T>
+    pub fn java_declaration(&self, name: &str) -> String {
+        let mut declaration = name.replace('/', ".");
+        declaration += &list_to_string("<", &self.type_parameters, ">");
+        declaration += &format!(" extends {}", self.superclass);
+        declaration += &list_to_string(" implements ", &self.interfaces, "");
+        declaration
+    }
+}
+
+impl MethodSignature {
+    // e.g. <T> T max(java.util.List<? extends T>) throws java.io.IOException
+    pub fn java_declaration(&self, name: &str) -> String {
+        let mut declaration = list_to_string("<", &self.type_parameters, "> ");
+        match &self.result {
+            Some(result) => declaration += &result.to_string(),
+            None => declaration += "void",
+        }
+        declaration += &format!(" {}(", name);
+        declaration += &list_to_string("", &self.parameters, "");
+        declaration += ")";
+        declaration += &list_to_string(" throws ", &self.throws, "");
+        declaration
+    }
+}
+
+fn write_list<T: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    prefix: &str,
+    items: &[T],
+    suffix: &str,
+) -> fmt::Result {
+    write!(f, "{}", list_to_string(prefix, items, suffix))
+}
+
+// Comma separated, with the prefix and suffix only if the list isn't empty
+fn list_to_string<T: fmt::Display>(prefix: &str, items: &[T], suffix: &str) -> String {
+    if items.is_empty() {
+        return String::new();
+    }
+    let items: Vec<_> = items.iter().map(|item| item.to_string()).collect();
+    format!("{}{}{}", prefix, items.join(", "), suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(package: &str, classes: &[(&str, Vec<TypeArgument>)]) -> TypeSignature {
+        TypeSignature::Class(ClassTypeSignature {
+            package: package.to_string(),
+            classes: classes
+                .iter()
+                .map(|(name, type_arguments)| SimpleClassTypeSignature {
+                    name: name.to_string(),
+                    type_arguments: type_arguments.clone(),
+                })
+                .collect(),
+        })
+    }
+
+    fn variable(name: &str) -> TypeSignature {
+        TypeSignature::TypeVariable(name.to_string())
+    }
+
+    #[test]
+    fn parses_nested_generic_method_signatures() {
+        let signature = parse_method_signature(concat!(
+            "<K:Ljava/lang/Object;V::Ljava/lang/Comparable<-TV;>;:Ljava/io/Serializable;",
+            "E:Ljava/lang/Exception;>",
+            "(Ljava/util/Map<TK;+Ljava/util/List<[TV;>;>.Entry<*TK;>;[[I)TV;",
+            "^TE;^Ljava/io/IOException;",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            signature.type_parameters,
+            [
+                TypeParameter {
+                    name: "K".to_string(),
+                    class_bound: Some(class("java/lang", &[("Object", vec![])])),
+                    interface_bounds: vec![],
+                },
+                TypeParameter {
+                    name: "V".to_string(),
+                    class_bound: None,
+                    interface_bounds: vec![
+                        class(
+                            "java/lang",
+                            &[("Comparable", vec![TypeArgument::Super(variable("V"))])]
+                        ),
+                        class("java/io", &[("Serializable", vec![])]),
+                    ],
+                },
+                TypeParameter {
+                    name: "E".to_string(),
+                    class_bound: Some(class("java/lang", &[("Exception", vec![])])),
+                    interface_bounds: vec![],
+                },
+            ]
+        );
+        let list = class(
+            "java/util",
+            &[(
+                "List",
+                vec![TypeArgument::Exact(TypeSignature::Array(Box::new(
+                    variable("V"),
+                )))],
+            )],
+        );
+        assert_eq!(
+            signature.parameters,
+            [
+                class(
+                    "java/util",
+                    &[
+                        (
+                            "Map",
+                            vec![
+                                TypeArgument::Exact(variable("K")),
+                                TypeArgument::Extends(list)
+                            ]
+                        ),
+                        (
+                            "Entry",
+                            vec![TypeArgument::Any, TypeArgument::Exact(variable("K"))]
+                        ),
+                    ]
+                ),
+                TypeSignature::Array(Box::new(TypeSignature::Array(Box::new(
+                    TypeSignature::Base(BaseType::Int)
+                )))),
+            ]
+        );
+        assert_eq!(signature.result, Some(variable("V")));
+        assert_eq!(
+            signature.throws,
+            [variable("E"), class("java/io", &[("IOException", vec![])])]
+        );
+
+        assert_eq!(
+            signature.java_declaration("get"),
+            "<K, V extends java.lang.Comparable<? super V> & java.io.Serializable, \
+             E extends java.lang.Exception> \
+             V get(java.util.Map<K, ? extends java.util.List<V[]>>.Entry<?, K>, int[][]) \
+             throws E, java.io.IOException"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() {
+        for signature in [
+            "(TT;)V^",               // Nothing after ^
+            "(I)V^I",                // Only references can be thrown
+            "<T>()V",                // No bound at all
+            "(Ljava/util/List<>;)V", // Empty type arguments
+            "(Ljava/lang/String)V",  // No semicolon
+            "()V ",                  // Trailing characters
+        ] {
+            let error = parse_method_signature(signature).unwrap_err();
+            assert!(
+                matches!(&error.kind, ErrorKind::InvalidSignature(s) if s == signature),
+                "{}",
+                signature
+            );
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::io::Write;
+
+use crate::constants::*;
+use crate::error::{ErrorKind, Result};
+use crate::read::*;
+use crate::write::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerificationType {
+    Top,
+    Integer,
+    Float,
+    Double,
+    Long,
+    Null,
+    UninitializedThis,
+    Object(String),
+    Uninitialized(u16), // Offset of the `new` instruction that created the object
+}
+
+fn parse_verification_type(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<VerificationType> {
+    let tag = read_u1(reader)?;
+    Ok(match tag {
+        0 => VerificationType::Top,
+        1 => VerificationType::Integer,
+        2 => VerificationType::Float,
+        3 => VerificationType::Double,
+        4 => VerificationType::Long,
+        5 => VerificationType::Null,
+        6 => VerificationType::UninitializedThis,
+        7 => VerificationType::Object(constant_pool.class_name(read_u2(reader)?)?),
+        8 => VerificationType::Uninitialized(read_u2(reader)?),
+        _ => return Err(ErrorKind::BadVerificationTypeTag(tag).into()),
+    })
+}
+
+fn write_verification_type(
+    verification_type: &VerificationType,
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    match verification_type {
+        VerificationType::Top => write_u1(0, writer)?,
+        VerificationType::Integer => write_u1(1, writer)?,
+        VerificationType::Float => write_u1(2, writer)?,
+        VerificationType::Double => write_u1(3, writer)?,
+        VerificationType::Long => write_u1(4, writer)?,
+        VerificationType::Null => write_u1(5, writer)?,
+        VerificationType::UninitializedThis => write_u1(6, writer)?,
+        VerificationType::Object(class) => {
+            write_u1(7, writer)?;
+            write_u2(constant_pool.add_class(class)?, writer)?;
+        }
+        VerificationType::Uninitialized(offset) => {
+            write_u1(8, writer)?;
+            write_u2(*offset, writer)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_verification_types(
+    types: &[VerificationType],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    for verification_type in types {
+        write_verification_type(verification_type, writer, constant_pool)?;
+    }
+    Ok(())
+}
+
+fn parse_verification_types(
+    count: usize,
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<Vec<VerificationType>> {
+    let mut types = Vec::with_capacity(count);
+    for _ in 0..count {
+        types.push(parse_verification_type(reader, constant_pool)?);
+    }
+    Ok(types)
+}
+
+// The extended forms are kept distinct from the compact ones so frames can be written back out in
+// the same form they were read
+#[derive(Debug, Clone, PartialEq)]
+pub enum StackMapFrameKind {
+    Same,
+    SameExtended,
+    SameLocals1StackItem(VerificationType),
+    SameLocals1StackItemExtended(VerificationType),
+    Chop(u8), // Number of locals removed, 1 to 3
+    Append(Vec<VerificationType>),
+    Full {
+        locals: Vec<VerificationType>,
+        stack: Vec<VerificationType>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackMapFrame {
+    pub offset: usize, // Absolute bytecode offset the frame applies at
+    pub offset_delta: u16,
+    pub kind: StackMapFrameKind,
+}
+
+pub fn parse_stack_map_table(
+    reader: &mut ClassReader,
+    constant_pool: &ConstantPool,
+) -> Result<Vec<StackMapFrame>> {
+    let number_of_entries = read_u2(reader)?;
+    let mut entries: Vec<StackMapFrame> = Vec::with_capacity(number_of_entries as usize);
+
+    for i in 0..number_of_entries {
+        let (offset_delta, kind) = reader.within(format!("frame #{}", i), |reader| {
+            let frame_type = read_u1(reader)?;
+            let (offset_delta, kind) = match frame_type {
+                0..=63 => (frame_type as u16, StackMapFrameKind::Same),
+                64..=127 => (
+                    frame_type as u16 - 64,
+                    StackMapFrameKind::SameLocals1StackItem(parse_verification_type(
+                        reader,
+                        constant_pool,
+                    )?),
+                ),
+                247 => (
+                    read_u2(reader)?,
+                    StackMapFrameKind::SameLocals1StackItemExtended(parse_verification_type(
+                        reader,
+                        constant_pool,
+                    )?),
+                ),
+                248..=250 => (read_u2(reader)?, StackMapFrameKind::Chop(251 - frame_type)),
+                251 => (read_u2(reader)?, StackMapFrameKind::SameExtended),
+                252..=254 => {
+                    let offset_delta = read_u2(reader)?;
+                    let count = (frame_type - 251) as usize;
+                    let locals = parse_verification_types(count, reader, constant_pool)?;
+                    (offset_delta, StackMapFrameKind::Append(locals))
+                }
+                255 => {
+                    let offset_delta = read_u2(reader)?;
+                    let number_of_locals = read_u2(reader)? as usize;
+                    let locals = parse_verification_types(number_of_locals, reader, constant_pool)?;
+                    let number_of_stack_items = read_u2(reader)? as usize;
+                    let stack =
+                        parse_verification_types(number_of_stack_items, reader, constant_pool)?;
+                    (offset_delta, StackMapFrameKind::Full { locals, stack })
+                }
+                _ => return Err(ErrorKind::BadStackMapFrameType(frame_type).into()),
+            };
+            Ok((offset_delta, kind))
+        })?;
+
+        // Every frame after the first applies at least one byte after the previous one
+        let offset = match entries.last() {
+            Some(previous) => previous.offset + offset_delta as usize + 1,
+            None => offset_delta as usize,
+        };
+        entries.push(StackMapFrame {
+            offset,
+            offset_delta,
+            kind,
+        });
+    }
+
+    Ok(entries)
+}
+
+// Frames are written in the form they're stored in, using offset_delta rather than offset. Compact
+// frames whose offset_delta has grown too large for their frame type use the extended form.
+pub fn write_stack_map_table(
+    entries: &[StackMapFrame],
+    writer: &mut dyn Write,
+    constant_pool: &mut ConstantPool,
+) -> Result<()> {
+    write_count(entries.len(), writer)?;
+    for frame in entries {
+        let offset_delta = frame.offset_delta;
+        match &frame.kind {
+            StackMapFrameKind::Same if offset_delta < 64 => write_u1(offset_delta as u8, writer)?,
+            StackMapFrameKind::Same | StackMapFrameKind::SameExtended => {
+                write_u1(251, writer)?;
+                write_u2(offset_delta, writer)?;
+            }
+            StackMapFrameKind::SameLocals1StackItem(item) if offset_delta < 64 => {
+                write_u1(64 + offset_delta as u8, writer)?;
+                write_verification_type(item, writer, constant_pool)?;
+            }
+            StackMapFrameKind::SameLocals1StackItem(item)
+            | StackMapFrameKind::SameLocals1StackItemExtended(item) => {
+                write_u1(247, writer)?;
+                write_u2(offset_delta, writer)?;
+                write_verification_type(item, writer, constant_pool)?;
+            }
+            // Chopping or appending no locals is the same as the extended same frame, 251
+            StackMapFrameKind::Chop(count) => {
+                if *count > 3 {
+                    return Err(ErrorKind::TooLong(*count as usize).into());
+                }
+                write_u1(251 - count, writer)?;
+                write_u2(offset_delta, writer)?;
+            }
+            StackMapFrameKind::Append(locals) => {
+                if locals.len() > 3 {
+                    return Err(ErrorKind::TooLong(locals.len()).into());
+                }
+                write_u1(251 + locals.len() as u8, writer)?;
+                write_u2(offset_delta, writer)?;
+                write_verification_types(locals, writer, constant_pool)?;
+            }
+            StackMapFrameKind::Full { locals, stack } => {
+                write_u1(255, writer)?;
+                write_u2(offset_delta, writer)?;
+                write_count(locals.len(), writer)?;
+                write_verification_types(locals, writer, constant_pool)?;
+                write_count(stack.len(), writer)?;
+                write_verification_types(stack, writer, constant_pool)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use std::convert::TryFrom;
+use std::io::{Result, Write};
+
+use crate::error::ErrorKind;
+
+// The inverse of read.rs. Structures are written to a Vec<u8> first wherever their length has to
+// be known up front, such as attribute bodies.
+
+pub fn write_u1(value: u8, writer: &mut dyn Write) -> Result<()> {
+    writer.write_all(&value.to_be_bytes())
+}
+
+pub fn write_u2(value: u16, writer: &mut dyn Write) -> Result<()> {
+    writer.write_all(&value.to_be_bytes())
+}
+
+pub fn write_u4(value: u32, writer: &mut dyn Write) -> Result<()> {
+    writer.write_all(&value.to_be_bytes())
+}
+
+pub fn write_u8(value: u64, writer: &mut dyn Write) -> Result<()> {
+    writer.write_all(&value.to_be_bytes())
+}
+
+pub fn write_bytes(bytes: &[u8], writer: &mut dyn Write) -> Result<()> {
+    writer.write_all(bytes)
+}
+
+// Writes the length of a table as a u2, checking it fits
+pub fn write_count(count: usize, writer: &mut dyn Write) -> crate::error::Result<()> {
+    let count = u16::try_from(count).map_err(|_| ErrorKind::TooLong(count))?;
+    Ok(write_u2(count, writer)?)
+}